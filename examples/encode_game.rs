@@ -0,0 +1,57 @@
+//! Compare whole-game preprocessing from scratch with the incremental
+//! encoder.
+//!
+//! ```text
+//! cargo run --release --example encode_game
+//! ```
+
+use std::{hint::black_box, time::Instant};
+
+use maia_rust::{
+    IncrementalEncoder, preprocess,
+    shakmaty::{Chess, EnPassantMode, Move, Position, Setup, uci::UciMove},
+};
+
+/// The Opera Game, Morphy vs. Duke of Brunswick and Count Isouard, 1858.
+const MOVES: &str = "e2e4 e7e5 g1f3 d7d6 d2d4 c8g4 d4e5 g4f3 d1f3 d6e5 f1c4 g8f6 f3b3 d8e7 \
+                     b1c3 c7c6 c1g5 b7b5 c3b5 c6b5 c4b5 b8d7 e1c1 a8d8 d1d7 d8d7 h1d1 e7e6 \
+                     b5d7 f6d7 b3b8 d7b8 d1d8";
+const ROUNDS: u32 = 2000;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut pos = Chess::default();
+    let mut moves: Vec<Move> = Vec::new();
+    let mut setups: Vec<Setup> = Vec::new();
+    for uci in MOVES.split_whitespace() {
+        let m = uci.parse::<UciMove>()?.to_move(&pos)?;
+        pos.play_unchecked(m);
+        moves.push(m);
+        setups.push(pos.to_setup(EnPassantMode::Legal));
+    }
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for setup in &setups {
+            black_box(preprocess([setup.clone()], 1)?);
+        }
+    }
+    let scratch = start.elapsed() / ROUNDS;
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let mut encoder = IncrementalEncoder::new(Setup::default())?;
+        for m in &moves {
+            black_box(encoder.push_move(m)?);
+        }
+    }
+    let incremental = start.elapsed() / ROUNDS;
+
+    println!("{} plies", moves.len());
+    println!("from scratch: {scratch:?} per game");
+    println!("incremental:  {incremental:?} per game");
+    println!(
+        "speedup: {:.1}x",
+        scratch.as_secs_f64() / incremental.as_secs_f64()
+    );
+    Ok(())
+}
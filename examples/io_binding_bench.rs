@@ -0,0 +1,36 @@
+//! Per-call latency with and without IO binding, by batch size.
+//!
+//! ```text
+//! cargo run --release --example io_binding_bench
+//! ```
+
+use std::time::Instant;
+
+use maia_rust::{Maia, shakmaty::Setup};
+
+const MODEL_PATH: &str = "maia3_simplified.onnx";
+const CALLS: u32 = 200;
+
+fn mean_call(maia: &mut Maia, batch: usize) -> Result<f64, maia_rust::Error> {
+    let elos = vec![1500.0; batch];
+    // Warm up allocations and bound buffers.
+    maia.batch_evaluate(vec![Setup::default(); batch], &elos, &elos)?;
+    let start = Instant::now();
+    for _ in 0..CALLS {
+        maia.batch_evaluate(vec![Setup::default(); batch], &elos, &elos)?;
+    }
+    Ok(start.elapsed().as_secs_f64() * 1e6 / f64::from(CALLS))
+}
+
+fn main() -> Result<(), maia_rust::Error> {
+    let mut plain = Maia::from_file(MODEL_PATH)?;
+    let mut bound = Maia::from_file(MODEL_PATH)?.with_io_binding(256);
+
+    println!("batch  plain (us)  bound (us)");
+    for batch in [1, 4, 16, 64, 256] {
+        let a = mean_call(&mut plain, batch)?;
+        let b = mean_call(&mut bound, batch)?;
+        println!("{batch:>5}  {a:>10.1}  {b:>10.1}");
+    }
+    Ok(())
+}
@@ -0,0 +1,162 @@
+//! Play on lichess as a Maia bot.
+//!
+//! ```text
+//! LICHESS_TOKEN=... cargo run --release --features lichess --example lichess_bot -- 1500
+//! ```
+//!
+//! The token needs the `bot:play` scope on a bot account. The optional
+//! argument sets the rating the bot plays as; the opponent's lichess
+//! rating is used on the other side. Standard challenges are accepted,
+//! everything else is declined, and every game runs on its own thread
+//! sharing one batching `MaiaService`.
+
+use std::{
+    io::{BufRead, BufReader},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use maia_rust::{
+    Maia, MaiaService, ServiceConfig,
+    lichess::{AccountEvent, BotAction, BotConfig, GameEvent, GameSession},
+};
+use rand::{SeedableRng, rngs::StdRng};
+use reqwest::blocking::Client;
+
+const API: &str = "https://lichess.org/api";
+const MODEL_PATH: &str = "maia3_simplified.onnx";
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Iterate over the JSON lines of a streaming endpoint, skipping the
+/// empty keep-alive lines.
+fn stream(
+    client: &Client,
+    token: &str,
+    url: &str,
+) -> Result<impl Iterator<Item = String>, BoxError> {
+    let response = client
+        .get(url)
+        .bearer_auth(token)
+        .send()?
+        .error_for_status()?;
+    Ok(BufReader::new(response)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty()))
+}
+
+fn post(client: &Client, token: &str, url: &str) -> Result<(), BoxError> {
+    client
+        .post(url)
+        .bearer_auth(token)
+        .send()?
+        .error_for_status()?;
+    Ok(())
+}
+
+fn play_game(
+    client: &Client,
+    token: &str,
+    bot_id: &str,
+    game_id: &str,
+    service: &MaiaService,
+    config: BotConfig,
+) -> Result<(), BoxError> {
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut session: Option<GameSession> = None;
+
+    for line in stream(client, token, &format!("{API}/bot/game/stream/{game_id}"))? {
+        let event: GameEvent = serde_json::from_str(&line)?;
+        match (&mut session, &event) {
+            (None, GameEvent::GameFull(game)) => {
+                session = Some(GameSession::new(bot_id, game, config.clone())?);
+            }
+            (Some(session), event) => session.handle_event(event)?,
+            (None, _) => continue,
+        }
+        let session = session.as_mut().unwrap();
+
+        if let Some(status) = session.finished() {
+            println!("{game_id}: finished ({status})");
+            break;
+        }
+        if !session.needs_move() {
+            continue;
+        }
+
+        let (elo_self, elo_oppo) = session.elos();
+        let result = service.evaluate(session.setup(), elo_self, elo_oppo)?;
+        match session.decide(&result, &mut rng) {
+            BotAction::Move(uci) => post(
+                client,
+                token,
+                &format!("{API}/bot/game/{game_id}/move/{uci}"),
+            )?,
+            BotAction::Resign => post(client, token, &format!("{API}/bot/game/{game_id}/resign"))?,
+            BotAction::Wait => {}
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), BoxError> {
+    let token = std::env::var("LICHESS_TOKEN").map_err(|_| "LICHESS_TOKEN is not set")?;
+    let elo_self = match std::env::args().nth(1) {
+        Some(elo) => elo.parse()?,
+        None => 1500.0,
+    };
+    let config = BotConfig {
+        elo_self,
+        ..BotConfig::default()
+    };
+
+    // Streams stay open indefinitely, so disable the default timeout.
+    let client = Client::builder().timeout(None).build()?;
+    let account: serde_json::Value = serde_json::from_str(
+        &client
+            .get(format!("{API}/account"))
+            .bearer_auth(&token)
+            .send()?
+            .error_for_status()?
+            .text()?,
+    )?;
+    let bot_id = account["id"]
+        .as_str()
+        .ok_or("account has no id")?
+        .to_string();
+    println!("playing as {bot_id} at {elo_self}");
+
+    let service = MaiaService::spawn(Maia::from_file(MODEL_PATH)?, ServiceConfig::default());
+
+    for line in stream(&client, &token, &format!("{API}/stream/event"))? {
+        match serde_json::from_str(&line)? {
+            AccountEvent::Challenge { challenge } => {
+                let action = if challenge.is_supported() {
+                    "accept"
+                } else {
+                    "decline"
+                };
+                let url = format!("{API}/challenge/{}/{action}", challenge.id);
+                if let Err(err) = post(&client, &token, &url) {
+                    eprintln!("{}: failed to {action} challenge: {err}", challenge.id);
+                }
+            }
+            AccountEvent::GameStart { game } => {
+                let (client, token, bot_id) = (client.clone(), token.clone(), bot_id.clone());
+                let (service, config) = (service.clone(), config.clone());
+                thread::spawn(move || {
+                    let game_id = game.game_id;
+                    if let Err(err) =
+                        play_game(&client, &token, &bot_id, &game_id, &service, config)
+                    {
+                        eprintln!("{game_id}: {err}");
+                    }
+                });
+            }
+            AccountEvent::Other => {}
+        }
+    }
+    Ok(())
+}
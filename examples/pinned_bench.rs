@@ -0,0 +1,39 @@
+//! Per-call latency on CUDA with pageable and pinned bound inputs.
+//!
+//! ```text
+//! cargo run --release --features cuda --example pinned_bench
+//! ```
+
+use std::time::Instant;
+
+use maia_rust::{Device, Maia, shakmaty::Setup};
+
+const MODEL_PATH: &str = "maia3_simplified.onnx";
+const CALLS: u32 = 200;
+const MAX_BATCH: usize = 256;
+
+fn mean_call(maia: &mut Maia, batch: usize) -> Result<f64, maia_rust::Error> {
+    let elos = vec![1500.0; batch];
+    // Warm up kernels and bound buffers.
+    maia.batch_evaluate(vec![Setup::default(); batch], &elos, &elos)?;
+    let start = Instant::now();
+    for _ in 0..CALLS {
+        maia.batch_evaluate(vec![Setup::default(); batch], &elos, &elos)?;
+    }
+    Ok(start.elapsed().as_secs_f64() * 1e6 / f64::from(CALLS))
+}
+
+fn main() -> Result<(), maia_rust::Error> {
+    let mut pageable = Maia::from_file_on(MODEL_PATH, Device::Cuda(0))?.with_io_binding(MAX_BATCH);
+    let mut pinned = Maia::from_file_on(MODEL_PATH, Device::Cuda(0))?
+        .with_io_binding(MAX_BATCH)
+        .with_pinned_inputs(0)?;
+
+    println!("batch  pageable (us)  pinned (us)");
+    for batch in [1, 16, 256] {
+        let a = mean_call(&mut pageable, batch)?;
+        let b = mean_call(&mut pinned, batch)?;
+        println!("{batch:>5}  {a:>13.1}  {b:>11.1}");
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+//! Time postprocessing of a batch of 1000 positions, with and without
+//! sorting the policies.
+//!
+//! ```text
+//! cargo run --release --example postprocess_bench
+//! ```
+
+use std::{hint::black_box, time::Instant};
+
+use maia_rust::{
+    EvalOptions, postprocess, postprocess_with_options, preprocess,
+    shakmaty::{Chess, EnPassantMode, Position, Setup, uci::UciMove},
+};
+use ndarray::Array2;
+use rand::{RngExt, SeedableRng, rngs::StdRng};
+
+/// The Opera Game, Morphy vs. Duke of Brunswick and Count Isouard, 1858.
+const MOVES: &str = "e2e4 e7e5 g1f3 d7d6 d2d4 c8g4 d4e5 g4f3 d1f3 d6e5 f1c4 g8f6 f3b3 d8e7 \
+                     b1c3 c7c6 c1g5 b7b5 c3b5 c6b5 c4b5 b8d7 e1c1 a8d8 d1d7 d8d7 h1d1 e7e6 \
+                     b5d7 f6d7 b3b8 d7b8 d1d8";
+/// Size of the Maia3 move vocabulary, the width of `logits_move`.
+const VOCABULARY: usize = 4352;
+const BATCH: usize = 1000;
+const ROUNDS: u32 = 200;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut pos = Chess::default();
+    let mut game: Vec<Setup> = Vec::new();
+    for uci in MOVES.split_whitespace() {
+        game.push(pos.to_setup(EnPassantMode::Legal));
+        pos.play_unchecked(uci.parse::<UciMove>()?.to_move(&pos)?);
+    }
+    let setups: Vec<Setup> = game.iter().cycle().take(BATCH).cloned().collect();
+    let (_, data) = preprocess(setups, BATCH)?;
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let logits_move = Array2::from_shape_fn((BATCH, VOCABULARY), |_| rng.random_range(-5.0..5.0));
+    let logits_value = Array2::from_shape_fn((BATCH, 3), |_| rng.random_range(-2.0..2.0));
+
+    let results = postprocess(logits_move.view(), logits_value.view(), &data);
+    let checksum: f64 = results
+        .iter()
+        .flat_map(|r| r.policy.iter().enumerate())
+        .map(|(rank, m)| f64::from(m.probability) * (rank + 1) as f64)
+        .sum();
+
+    println!("postprocess of {BATCH} positions (checksum {checksum:.6})");
+    for sorted in [true, false] {
+        let options = EvalOptions {
+            sorted,
+            ..EvalOptions::default()
+        };
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            black_box(postprocess_with_options(
+                logits_move.view(),
+                logits_value.view(),
+                &data,
+                &options,
+            ));
+        }
+        println!("  sorted: {sorted:<5}  {:?}", start.elapsed() / ROUNDS);
+    }
+    Ok(())
+}
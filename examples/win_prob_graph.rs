@@ -0,0 +1,51 @@
+//! Plot White's win probability over a game as an SVG sparkline.
+//!
+//! ```text
+//! cargo run --release --example win_prob_graph > graph.svg
+//! ```
+
+use maia_rust::{
+    Maia,
+    analysis::{GameAnalysis, GameMoves},
+};
+
+const MODEL_PATH: &str = "maia3_simplified.onnx";
+/// The Opera Game, Morphy vs. Duke of Brunswick and Count Isouard, 1858.
+const MOVES: &str = "e2e4 e7e5 g1f3 d7d6 d2d4 c8g4 d4e5 g4f3 d1f3 d6e5 f1c4 g8f6 f3b3 d8e7 \
+                     b1c3 c7c6 c1g5 b7b5 c3b5 c6b5 c4b5 b8d7 e1c1 a8d8 d1d7 d8d7 h1d1 e7e6 \
+                     b5d7 f6d7 b3b8 d7b8 d1d8";
+
+const WIDTH: f32 = 600.0;
+const HEIGHT: f32 = 120.0;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut maia = Maia::from_file(MODEL_PATH)?;
+    let analysis = GameAnalysis::analyze(&mut maia, GameMoves::from_uci(MOVES)?, 2000.0, 1800.0)?;
+    let series = analysis.win_prob_series();
+
+    let step = WIDTH / (series.len().max(2) - 1) as f32;
+    let points: Vec<String> = series
+        .iter()
+        .enumerate()
+        .map(|(ply, p)| format!("{:.1},{:.1}", ply as f32 * step, (1.0 - p) * HEIGHT))
+        .collect();
+    let markers: String = analysis
+        .critical_moments(3)
+        .into_iter()
+        .map(|ply| {
+            let (x, y) = (ply as f32 * step, (1.0 - series[ply]) * HEIGHT);
+            format!(r#"<circle cx="{x:.1}" cy="{y:.1}" r="3" fill="crimson"/>"#)
+        })
+        .collect();
+
+    println!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}">
+<line x1="0" y1="{mid}" x2="{WIDTH}" y2="{mid}" stroke="lightgray"/>
+<polyline points="{points}" fill="none" stroke="black" stroke-width="1.5"/>
+{markers}
+</svg>"#,
+        mid = HEIGHT / 2.0,
+        points = points.join(" "),
+    );
+    Ok(())
+}
@@ -0,0 +1,82 @@
+/*
+ * C interface to maia-rust, built with
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * Kept in sync by hand with src/ffi.rs.
+ */
+#ifndef MAIA_H
+#define MAIA_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct MaiaHandle MaiaHandle;
+typedef struct MaiaResult MaiaResult;
+
+typedef enum MaiaErrorCode {
+    MAIA_OK = 0,
+    MAIA_NULL_ARGUMENT = 1,
+    MAIA_INVALID_UTF8 = 2,
+    MAIA_OUT_OF_BOUNDS = 3,
+    MAIA_BUFFER_TOO_SMALL = 4,
+    MAIA_PANIC = 5,
+    MAIA_ORT_ERROR = 10,
+    MAIA_INVALID_FEN = 11,
+    MAIA_INVALID_POSITION = 12,
+    MAIA_SHAPE_ERROR = 13,
+    MAIA_ELO_OUT_OF_RANGE = 14,
+    MAIA_SERVICE_STOPPED = 15,
+    MAIA_ILLEGAL_MOVE = 16,
+    MAIA_MODEL_NOT_FOUND = 17,
+    MAIA_ENVIRONMENT_CONFIGURED = 18,
+    MAIA_DEVICE_FAILED = 19,
+    MAIA_UNSUPPORTED_ELEMENT_TYPE = 20,
+    MAIA_TERMINAL = 21,
+    MAIA_NON_FINITE_OUTPUT = 22,
+    MAIA_BATCH_SIZE_MISMATCH = 23,
+    MAIA_MISSING_OUTPUT = 24,
+    MAIA_INCOMPATIBLE_MODEL = 25,
+    MAIA_TIMEOUT = 26,
+    MAIA_IO = 27,
+    MAIA_OUT_OF_MEMORY = 28,
+    MAIA_UNFINISHED_GAME = 29,
+    MAIA_OVERLOADED = 30,
+    MAIA_CANCELLED = 31,
+    MAIA_MISSING_EXTERNAL_DATA = 32,
+    MAIA_UNKNOWN_MODEL = 33,
+} MaiaErrorCode;
+
+/* Load a model from `path`. Returns NULL on failure. */
+MaiaHandle *maia_create(const char *path);
+
+/* Release a handle. NULL is ignored. */
+void maia_destroy(MaiaHandle *handle);
+
+/* Evaluate a FEN. On success `*out_result` must be freed with maia_free_result. */
+MaiaErrorCode maia_evaluate_fen(MaiaHandle *handle, const char *fen, float elo_self,
+                                float elo_oppo, MaiaResult **out_result);
+
+/* Number of legal moves in the policy. */
+size_t maia_result_move_count(const MaiaResult *result);
+
+/* Copy the `index`-th most probable move as a NUL-terminated UCI string
+ * (6 bytes always suffice) and its probability. */
+MaiaErrorCode maia_result_move_at(const MaiaResult *result, size_t index, char *out_uci_buf,
+                                  size_t buf_len, float *out_prob);
+
+/* White win, draw and Black win probabilities. */
+MaiaErrorCode maia_result_value(const MaiaResult *result, float *out_white_wr, float *out_draw,
+                                float *out_black_wr);
+
+/* Release a result. NULL is ignored. */
+void maia_free_result(MaiaResult *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MAIA_H */
@@ -0,0 +1,60 @@
+/*
+ * Linkage smoke test for the C interface.
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *     cc ffi/test_maia.c -Iffi -Ltarget/release -lmaia_rust -o test_maia
+ *     LD_LIBRARY_PATH=target/release ./test_maia [model.onnx]
+ *
+ * Without a model argument only the error paths are exercised.
+ */
+#include <stdio.h>
+
+#include "maia.h"
+
+int main(int argc, char **argv) {
+    MaiaResult *result = NULL;
+
+    if (maia_create(NULL) != NULL) {
+        fprintf(stderr, "maia_create(NULL) should fail\n");
+        return 1;
+    }
+    if (maia_evaluate_fen(NULL, "8/8/8/8/8/8/8/8 w - - 0 1", 1500, 1500, &result) !=
+        MAIA_NULL_ARGUMENT) {
+        fprintf(stderr, "expected MAIA_NULL_ARGUMENT\n");
+        return 1;
+    }
+    if (argc < 2) {
+        printf("ok (no model given)\n");
+        return 0;
+    }
+
+    MaiaHandle *maia = maia_create(argv[1]);
+    if (maia == NULL) {
+        fprintf(stderr, "failed to load %s\n", argv[1]);
+        return 1;
+    }
+
+    const char *fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+    MaiaErrorCode code = maia_evaluate_fen(maia, fen, 1500, 1500, &result);
+    if (code != MAIA_OK) {
+        fprintf(stderr, "evaluation failed with code %d\n", code);
+        maia_destroy(maia);
+        return 1;
+    }
+
+    float white, draw, black;
+    maia_result_value(result, &white, &draw, &black);
+    printf("white %.3f draw %.3f black %.3f\n", white, draw, black);
+
+    size_t count = maia_result_move_count(result);
+    for (size_t i = 0; i < count && i < 5; i++) {
+        char uci[6];
+        float prob;
+        maia_result_move_at(result, i, uci, sizeof uci, &prob);
+        printf("%s %.3f\n", uci, prob);
+    }
+
+    maia_free_result(result);
+    maia_destroy(maia);
+    return 0;
+}
@@ -0,0 +1,2030 @@
+//! Whole-game analysis.
+//!
+//! A [`GameAnalysis`] evaluates every position of a game in one batch and
+//! offers per-ply views of the results, such as White's win probability
+//! over the course of the game. [`move_match_profile`] compares a
+//! player's moves with Maia's predictions at every rating level and
+//! [`puzzle_difficulty`] rates tactics by the lowest level that finds them.
+//! [`likelihood_profile`] measures how likely a player's moves were at
+//! their rating, for comparison with a [`LikelihoodBaseline`] of
+//! reference games.
+//! [`sharpness`] measures how treacherous a position is for humans.
+//! [`run_epd_suite`] benchmarks rating levels against engine test suites.
+//! Per-ply accuracy can leave out forced moves, see [`AccuracyConfig`].
+//! The network sees no history, so repetitions and the fifty-move rule
+//! are tracked while replaying the game, see [`DrawStatus`] and
+//! [`DrawAdjudication`].
+//! With the `pgn` feature, [`analyze_pgn_file`] analyses every game of a
+//! PGN file in shared batches.
+
+use std::collections::HashMap;
+
+use shakmaty::{
+    CastlingMode, Chess, Color, EnPassantMode, Move, Position, uci::UciMove, zobrist::Zobrist64,
+};
+
+use crate::{
+    error::Error,
+    evaluator::Evaluator,
+    types::{EvaluationResult, Terminal},
+};
+
+/// The moves of one game and the position they start from.
+#[derive(Debug, Clone)]
+pub struct GameMoves {
+    /// Position before the first move.
+    pub initial: Chess,
+    /// Moves in playing order.
+    pub moves: Vec<UciMove>,
+}
+
+impl GameMoves {
+    /// A game from the standard starting position.
+    pub fn new(moves: Vec<UciMove>) -> Self {
+        Self {
+            initial: Chess::default(),
+            moves,
+        }
+    }
+
+    /// Parse space-separated UCI moves played from the starting position.
+    ///
+    /// # Errors
+    /// Returns [`Error::IllegalMove`] for a token that is not a UCI move.
+    pub fn from_uci(moves: &str) -> Result<Self, Error> {
+        let moves = moves
+            .split_whitespace()
+            .map(|m| m.parse().map_err(|_| Error::IllegalMove(m.to_string())))
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(moves))
+    }
+
+    /// Every position of the game, starting with [`initial`](Self::initial)
+    /// and ending with the position after the last move.
+    ///
+    /// # Errors
+    /// Returns [`Error::IllegalMove`] for the first move that is not legal.
+    pub fn positions(&self) -> Result<Vec<Chess>, Error> {
+        let mut positions = Vec::with_capacity(self.moves.len() + 1);
+        let mut pos = self.initial.clone();
+        for uci in &self.moves {
+            let m = uci
+                .to_move(&pos)
+                .map_err(|_| Error::IllegalMove(uci.to_string()))?;
+            positions.push(pos.clone());
+            pos.play_unchecked(m);
+        }
+        positions.push(pos);
+        Ok(positions)
+    }
+}
+
+/// Evaluations of every position of a game.
+#[derive(Debug, Clone)]
+pub struct GameAnalysis {
+    game: GameMoves,
+    positions: Vec<Chess>,
+    evaluations: Vec<EvaluationResult>,
+    /// White's and Black's ratings.
+    elos: (f32, f32),
+    /// Per ply, the value of every legal move for the mover, once
+    /// [`evaluate_children`](GameAnalysis::evaluate_children) has run.
+    child_values: Option<Vec<Vec<(UciMove, f32)>>>,
+    /// Tag pairs of the PGN game, empty for games not read from PGN.
+    tags: Vec<(String, String)>,
+    /// Draw status of each position.
+    draws: Vec<DrawStatus>,
+    draw_adjudication: DrawAdjudication,
+}
+
+/// Thresholds of forced-move detection and how accuracy treats forced
+/// moves.
+#[derive(Debug, Clone)]
+pub struct AccuracyConfig {
+    /// Top-move probability from which the played top move counts as the
+    /// only move.
+    pub only_move_threshold: f32,
+    /// Moves scoring more than this below the best move (in expected
+    /// score) count as losing when looking for a single non-losing move.
+    pub losing_margin: f32,
+    /// Leave forced moves out of [`GameAnalysis::accuracy`].
+    pub exclude_forced: bool,
+    /// Smallest loss in the mover's expected score that makes a move a
+    /// [`Judgement::Inaccuracy`].
+    pub inaccuracy_loss: f32,
+    /// Smallest loss that makes a move a [`Judgement::Mistake`].
+    pub mistake_loss: f32,
+    /// Smallest loss that makes a move a [`Judgement::Blunder`].
+    pub blunder_loss: f32,
+}
+
+impl Default for AccuracyConfig {
+    fn default() -> Self {
+        Self {
+            only_move_threshold: 0.9,
+            losing_margin: 0.2,
+            exclude_forced: true,
+            // The lichess thresholds, on expected score rather than
+            // winning chances.
+            inaccuracy_loss: 0.05,
+            mistake_loss: 0.1,
+            blunder_loss: 0.15,
+        }
+    }
+}
+
+/// Classification of a move that lost expected score, see
+/// [`GameAnalysis::judgements`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    /// The PGN NAG: `$6` (`?!`), `$2` (`?`) or `$4` (`??`).
+    pub fn nag(self) -> u8 {
+        match self {
+            Self::Inaccuracy => 6,
+            Self::Mistake => 2,
+            Self::Blunder => 4,
+        }
+    }
+}
+
+/// Forced-move flags of one ply.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlyFlags {
+    /// Probability of the most likely move in the position before the ply.
+    pub policy_concentration: f32,
+    /// The played move was the top move and reached
+    /// [`AccuracyConfig::only_move_threshold`].
+    pub is_only_move: bool,
+    /// The played move was the only non-losing move. `None` until
+    /// [`GameAnalysis::evaluate_children`] has run.
+    pub forced_by_value: Option<bool>,
+}
+
+impl PlyFlags {
+    /// Whether either test marks the move as forced.
+    pub fn is_forced(&self) -> bool {
+        self.is_only_move || self.forced_by_value == Some(true)
+    }
+}
+
+/// Repetition count and fifty-move clock of one position of a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DrawStatus {
+    /// Occurrences of the position so far in the game, this one
+    /// included.
+    pub repetitions: u32,
+    /// Plies since the last capture or pawn move.
+    pub halfmove_clock: u32,
+}
+
+impl DrawStatus {
+    /// Whether a draw can be claimed, by threefold repetition or the
+    /// fifty-move rule.
+    pub fn claimable(&self) -> bool {
+        self.repetitions >= 3 || self.halfmove_clock >= 100
+    }
+
+    /// Whether the game is drawn without a claim, by fivefold repetition
+    /// or the seventy-five-move rule.
+    pub fn automatic(&self) -> bool {
+        self.repetitions >= 5 || self.halfmove_clock >= 150
+    }
+}
+
+/// Counts the positions of a game as it is replayed, which is history a
+/// [`Setup`](shakmaty::Setup) cannot express.
+///
+/// Positions are told apart by their zobrist hash, which covers castling
+/// and en passant rights. Occurrences before the first position pushed
+/// are unknown.
+#[derive(Debug, Clone, Default)]
+pub struct DrawTracker {
+    seen: HashMap<Zobrist64, u32>,
+}
+
+impl DrawTracker {
+    /// A tracker that has seen no position yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that the game reached `pos` and return its status.
+    pub fn push(&mut self, pos: &Chess) -> DrawStatus {
+        let count = self
+            .seen
+            .entry(pos.zobrist_hash(EnPassantMode::Legal))
+            .or_default();
+        *count += 1;
+        DrawStatus {
+            repetitions: *count,
+            halfmove_clock: pos.halfmoves(),
+        }
+    }
+}
+
+/// Which draws [`GameAnalysis`] reports as final.
+///
+/// Once adjudicated, a position and every later one are reported as
+/// drawn: an expected score of 0.5 and no win probability, unless the
+/// drawing position itself is checkmate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DrawAdjudication {
+    /// The network's values throughout.
+    #[default]
+    Off,
+    /// From the first [automatic](DrawStatus::automatic) draw.
+    Automatic,
+    /// From the first position a draw [can be claimed](DrawStatus::claimable)
+    /// in.
+    Claimable,
+}
+
+/// The draw status of every position, in order.
+fn draw_statuses(positions: &[Chess]) -> Vec<DrawStatus> {
+    let mut tracker = DrawTracker::new();
+    positions.iter().map(|pos| tracker.push(pos)).collect()
+}
+
+impl GameAnalysis {
+    /// Evaluate all positions of `game` in one batch. Each position is
+    /// evaluated with the rating of the side to move as `elo_self`.
+    ///
+    /// # Errors
+    /// Returns [`Error::IllegalMove`] for an illegal move and propagates
+    /// evaluation errors.
+    pub fn analyze(
+        evaluator: &mut impl Evaluator,
+        game: GameMoves,
+        white_elo: f32,
+        black_elo: f32,
+    ) -> Result<Self, Error> {
+        let positions = game.positions()?;
+        let setups = positions
+            .iter()
+            .map(|p| p.to_setup(EnPassantMode::Legal))
+            .collect();
+        let (elo_selfs, elo_oppos): (Vec<f32>, Vec<f32>) = positions
+            .iter()
+            .map(|p| side_elos(p, white_elo, black_elo))
+            .unzip();
+        let evaluations = evaluator.batch_evaluate(setups, &elo_selfs, &elo_oppos)?;
+
+        Ok(Self {
+            game,
+            draws: draw_statuses(&positions),
+            positions,
+            evaluations,
+            elos: (white_elo, black_elo),
+            child_values: None,
+            tags: Vec::new(),
+            draw_adjudication: DrawAdjudication::Off,
+        })
+    }
+
+    /// The analysed game.
+    pub fn game(&self) -> &GameMoves {
+        &self.game
+    }
+
+    /// All positions, one more than there are moves.
+    pub fn positions(&self) -> &[Chess] {
+        &self.positions
+    }
+
+    /// Evaluation of each position in [`positions`](Self::positions).
+    pub fn evaluations(&self) -> &[EvaluationResult] {
+        &self.evaluations
+    }
+
+    /// Tag pairs of the game in file order, if it was read from PGN.
+    pub fn tags(&self) -> &[(String, String)] {
+        &self.tags
+    }
+
+    /// Repetition count and fifty-move clock of each position in
+    /// [`positions`](Self::positions).
+    pub fn draw_statuses(&self) -> &[DrawStatus] {
+        &self.draws
+    }
+
+    /// Report positions from the first draw of kind `adjudication` on as
+    /// drawn, in the win probabilities, expected scores and everything
+    /// derived from them. [`DrawAdjudication::Off`] by default.
+    pub fn set_draw_adjudication(&mut self, adjudication: DrawAdjudication) {
+        self.draw_adjudication = adjudication;
+    }
+
+    /// The first position adjudicated as drawn.
+    pub fn drawn_from(&self) -> Option<usize> {
+        let drawn = |status: &DrawStatus| match self.draw_adjudication {
+            DrawAdjudication::Off => false,
+            DrawAdjudication::Automatic => status.automatic(),
+            DrawAdjudication::Claimable => status.claimable(),
+        };
+        self.draws.iter().position(drawn)
+    }
+
+    /// Whether position `index` is reported as drawn.
+    fn adjudicated_draw(&self, index: usize) -> bool {
+        self.drawn_from().is_some_and(|from| {
+            index > from
+                || index == from
+                    && Terminal::of(&self.positions[index]) != Some(Terminal::Checkmate)
+        })
+    }
+
+    /// The move of ply `ply` in standard UCI notation, as used by the
+    /// policy.
+    fn played(&self, ply: usize) -> UciMove {
+        let pos = &self.positions[ply];
+        // Legality was checked when the positions were built.
+        let m = self.game.moves[ply].to_move(pos).expect("validated move");
+        m.to_uci(CastlingMode::Standard)
+    }
+
+    /// White's win probability in position `index`, pinned to 1.0 or 0.0
+    /// once a side is checkmated and to 0.0 in adjudicated draws.
+    fn white_win_prob(&self, index: usize) -> f32 {
+        if self.adjudicated_draw(index) {
+            return 0.0;
+        }
+        let pos = &self.positions[index];
+        if Terminal::of(pos) == Some(Terminal::Checkmate) {
+            return match pos.turn() {
+                Color::White => 0.0,
+                Color::Black => 1.0,
+            };
+        }
+        self.evaluations[index].white_wr
+    }
+
+    /// White's win probability after every ply, one value per move.
+    pub fn win_prob_series(&self) -> Vec<f32> {
+        (1..self.positions.len())
+            .map(|i| self.white_win_prob(i))
+            .collect()
+    }
+
+    /// Change in White's win probability caused by every ply; the first
+    /// delta is relative to the initial position.
+    pub fn win_prob_deltas(&self) -> Vec<f32> {
+        (1..self.positions.len())
+            .map(|i| self.white_win_prob(i) - self.white_win_prob(i - 1))
+            .collect()
+    }
+
+    /// Expected score of `color` in position `index`, pinned once a side
+    /// is checkmated or stalemated and in adjudicated draws.
+    pub(crate) fn expected_score(&self, index: usize, color: Color) -> f32 {
+        if self.adjudicated_draw(index) {
+            return 0.5;
+        }
+        let pos = &self.positions[index];
+        if let Some(terminal) = Terminal::of(pos) {
+            let score = terminal.score();
+            return if pos.turn() == color {
+                score
+            } else {
+                1.0 - score
+            };
+        }
+        self.evaluations[index].expected_score(color)
+    }
+
+    /// Evaluate every child of every position a move was played in, which
+    /// enables [`PlyFlags::forced_by_value`]. Children are evaluated with
+    /// the opponent's rating as `elo_self`.
+    ///
+    /// # Errors
+    /// Propagates evaluation errors, leaving the analysis unchanged.
+    pub fn evaluate_children(&mut self, evaluator: &mut impl Evaluator) -> Result<(), Error> {
+        let (white_elo, black_elo) = self.elos;
+        let mut owners = Vec::new();
+        let mut children = Vec::new();
+        for (ply, pos) in self.positions[..self.game.moves.len()].iter().enumerate() {
+            for m in &pos.legal_moves() {
+                let mut child = pos.clone();
+                child.play_unchecked(*m);
+                owners.push((ply, m.to_uci(CastlingMode::Standard)));
+                children.push(child);
+            }
+        }
+
+        let mut child_values = vec![Vec::new(); self.game.moves.len()];
+        for (chunk, owners) in children
+            .chunks(MATCH_CHUNK_SIZE)
+            .zip(owners.chunks(MATCH_CHUNK_SIZE))
+        {
+            let setups = chunk
+                .iter()
+                .map(|c| c.to_setup(EnPassantMode::Legal))
+                .collect();
+            let (elo_selfs, elo_oppos): (Vec<f32>, Vec<f32>) = chunk
+                .iter()
+                .map(|c| {
+                    c.turn()
+                        .fold_wb((white_elo, black_elo), (black_elo, white_elo))
+                })
+                .unzip();
+            let results = evaluator.batch_evaluate(setups, &elo_selfs, &elo_oppos)?;
+            for ((child, &(ply, uci)), result) in chunk.iter().zip(owners).zip(&results) {
+                let mover = self.positions[ply].turn();
+                child_values[ply].push((uci, move_value(child, result, mover)));
+            }
+        }
+        self.child_values = Some(child_values);
+        Ok(())
+    }
+
+    /// Forced-move flags of every ply. [`PlyFlags::forced_by_value`] is
+    /// only set after [`evaluate_children`](Self::evaluate_children).
+    pub fn ply_flags(&self, config: &AccuracyConfig) -> Vec<PlyFlags> {
+        (0..self.game.moves.len())
+            .map(|ply| {
+                let played = self.played(ply);
+                let top = self.evaluations[ply].best_move();
+                let policy_concentration = top.map_or(0.0, |m| m.probability);
+                let is_only_move = top.is_some_and(|m| m.uci == played)
+                    && policy_concentration >= config.only_move_threshold;
+                let forced_by_value = self.child_values.as_ref().map(|values| {
+                    let values = &values[ply];
+                    let best = values
+                        .iter()
+                        .map(|&(_, v)| v)
+                        .fold(f32::NEG_INFINITY, f32::max);
+                    let mut holding = values
+                        .iter()
+                        .filter(|&&(_, v)| v >= best - config.losing_margin);
+                    holding.next().is_some_and(|&(m, _)| m == played) && holding.next().is_none()
+                });
+                PlyFlags {
+                    policy_concentration,
+                    is_only_move,
+                    forced_by_value,
+                }
+            })
+            .collect()
+    }
+
+    /// Accuracy of every ply from 0 to 100, using the lichess formula on
+    /// the mover's expected score before and after the move.
+    pub fn move_accuracies(&self) -> Vec<f32> {
+        (0..self.game.moves.len())
+            .map(|ply| {
+                let loss = 100.0 * self.score_loss(ply);
+                (103.1668 * (-0.04354 * loss).exp() - 3.1669).clamp(0.0, 100.0)
+            })
+            .collect()
+    }
+
+    /// Loss in the mover's expected score caused by ply `ply`, zero for
+    /// moves that did not lose.
+    fn score_loss(&self, ply: usize) -> f32 {
+        let mover = self.positions[ply].turn();
+        (self.expected_score(ply, mover) - self.expected_score(ply + 1, mover)).max(0.0)
+    }
+
+    /// Judgement of every ply by its loss in expected score, against the
+    /// thresholds of `config`; `None` for moves losing less than
+    /// [`AccuracyConfig::inaccuracy_loss`].
+    pub fn judgements(&self, config: &AccuracyConfig) -> Vec<Option<Judgement>> {
+        (0..self.game.moves.len())
+            .map(|ply| {
+                let loss = self.score_loss(ply);
+                if loss >= config.blunder_loss {
+                    Some(Judgement::Blunder)
+                } else if loss >= config.mistake_loss {
+                    Some(Judgement::Mistake)
+                } else if loss >= config.inaccuracy_loss {
+                    Some(Judgement::Inaccuracy)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Mean [`move_accuracies`](Self::move_accuracies) of `color`,
+    /// leaving out forced moves if [`AccuracyConfig::exclude_forced`] is
+    /// set. `None` if no move is left.
+    pub fn accuracy(&self, color: Color, config: &AccuracyConfig) -> Option<f32> {
+        let flags = self.ply_flags(config);
+        let counted: Vec<f32> = self
+            .move_accuracies()
+            .into_iter()
+            .enumerate()
+            .filter(|&(ply, _)| self.positions[ply].turn() == color)
+            .filter(|&(ply, _)| !(config.exclude_forced && flags[ply].is_forced()))
+            .map(|(_, accuracy)| accuracy)
+            .collect();
+        if counted.is_empty() {
+            return None;
+        }
+        Some(counted.iter().sum::<f32>() / counted.len() as f32)
+    }
+
+    /// Ply indices (into [`GameMoves::moves`]) of the `k` largest swings
+    /// in either direction, largest first.
+    pub fn critical_moments(&self, k: usize) -> Vec<usize> {
+        let deltas = self.win_prob_deltas();
+        let mut plies: Vec<usize> = (0..deltas.len()).collect();
+        plies.sort_by(|&a, &b| deltas[b].abs().total_cmp(&deltas[a].abs()));
+        plies.truncate(k);
+        plies
+    }
+}
+
+/// Expected score for `mover` of the move leading to `child`, with a
+/// delivered mate worth 1 and a stalemate 0.5.
+fn move_value(child: &Chess, result: &EvaluationResult, mover: Color) -> f32 {
+    match Terminal::of(child) {
+        Some(terminal) => 1.0 - terminal.score(),
+        None => result.expected_score(mover),
+    }
+}
+
+/// `(elo_self, elo_oppo)` of the side to move in `pos`.
+fn side_elos(pos: &Chess, white_elo: f32, black_elo: f32) -> (f32, f32) {
+    match pos.turn() {
+        Color::White => (white_elo, black_elo),
+        Color::Black => (black_elo, white_elo),
+    }
+}
+
+/// How [`analyze_pgn_file`] batches and rates games.
+#[cfg(feature = "pgn")]
+#[derive(Debug, Clone)]
+pub struct PgnAnalysisConfig {
+    /// Most positions sent to the evaluator in one call.
+    pub max_batch_size: usize,
+    /// Rating of players without a usable `WhiteElo` or `BlackElo` tag.
+    pub default_elo: f32,
+}
+
+#[cfg(feature = "pgn")]
+impl Default for PgnAnalysisConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: MATCH_CHUNK_SIZE,
+            default_elo: 1500.0,
+        }
+    }
+}
+
+/// Analyse every game of a PGN file, as [`GameAnalysis::analyze`] with
+/// the players' tagged ratings, pooling the positions of all games into
+/// shared batches.
+///
+/// Positions are keyed by zobrist hash and rating pair, as in
+/// [`TreeEvaluator`](crate::tree::TreeEvaluator), so a position reached
+/// in several games (most of all, opening positions) is evaluated once
+/// per call. The distinct positions go to the evaluator in batches of at
+/// most [`max_batch_size`](PgnAnalysisConfig::max_batch_size); the
+/// analyses come back in file order, with the tags of their games.
+///
+/// # Errors
+/// A game with corrupt movetext or a bad `FEN` tag gets its error in
+/// place, see [`PgnGame::moves`](crate::pgn::PgnGame::moves), and the
+/// other games are analysed. The whole call fails only if `reader`
+/// fails ([`Error::Io`]) or the evaluator does.
+#[cfg(feature = "pgn")]
+pub fn analyze_pgn_file(
+    evaluator: &mut impl Evaluator,
+    mut reader: impl std::io::Read,
+    config: &PgnAnalysisConfig,
+) -> Result<Vec<Result<GameAnalysis, Error>>, Error> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let games = crate::pgn::parse_games(&String::from_utf8_lossy(&bytes));
+    let games = games.into_iter().map(|mut game| {
+        let elo = |tag| {
+            game.tag(tag)
+                .and_then(|v| v.parse::<f32>().ok())
+                .filter(|elo| elo.is_finite() && *elo > 0.0)
+                .unwrap_or(config.default_elo)
+        };
+        let elos = (elo("WhiteElo"), elo("BlackElo"));
+        let tags = std::mem::take(&mut game.tags);
+        game.moves.map(|moves| (moves, elos, tags))
+    });
+    analyze_batched(evaluator, games, config.max_batch_size)
+}
+
+/// Analyse `games`, each its moves, `(white_elo, black_elo)` and tags,
+/// pooling their distinct positions into batches of at most
+/// `max_batch_size`. Games that are already errors stay in place.
+#[cfg(feature = "pgn")]
+pub(crate) fn analyze_batched(
+    evaluator: &mut impl Evaluator,
+    games: impl IntoIterator<Item = Result<(GameMoves, (f32, f32), Vec<(String, String)>), Error>>,
+    max_batch_size: usize,
+) -> Result<Vec<Result<GameAnalysis, Error>>, Error> {
+    use std::collections::HashMap;
+
+    use shakmaty::zobrist::Zobrist64;
+
+    // Distinct positions in order of first appearance, and per game the
+    // index of each of its positions among them.
+    let mut slots: HashMap<(Zobrist64, u32, u32), usize> = HashMap::new();
+    let mut unique = Vec::new();
+    let mut prepared = Vec::new();
+    for game in games {
+        prepared.push(game.and_then(|(moves, (white_elo, black_elo), tags)| {
+            let positions = moves.positions()?;
+            let indices: Vec<usize> = positions
+                .iter()
+                .map(|pos| {
+                    let (elo_self, elo_oppo) = side_elos(pos, white_elo, black_elo);
+                    let key = (
+                        pos.zobrist_hash(EnPassantMode::Legal),
+                        elo_self.to_bits(),
+                        elo_oppo.to_bits(),
+                    );
+                    *slots.entry(key).or_insert_with(|| {
+                        unique.push((pos.to_setup(EnPassantMode::Legal), elo_self, elo_oppo));
+                        unique.len() - 1
+                    })
+                })
+                .collect();
+            Ok((moves, positions, (white_elo, black_elo), indices, tags))
+        }));
+    }
+
+    let mut results = Vec::with_capacity(unique.len());
+    for chunk in unique.chunks(max_batch_size.max(1)) {
+        let setups = chunk.iter().map(|(setup, ..)| setup.clone()).collect();
+        let (elo_selfs, elo_oppos): (Vec<f32>, Vec<f32>) =
+            chunk.iter().map(|&(_, s, o)| (s, o)).unzip();
+        results.extend(evaluator.batch_evaluate(setups, &elo_selfs, &elo_oppos)?);
+    }
+
+    Ok(prepared
+        .into_iter()
+        .map(|game| {
+            game.map(|(game, positions, elos, indices, tags)| GameAnalysis {
+                game,
+                draws: draw_statuses(&positions),
+                positions,
+                evaluations: indices.iter().map(|&i| results[i].clone()).collect(),
+                elos,
+                child_values: None,
+                tags,
+                draw_adjudication: DrawAdjudication::Off,
+            })
+        })
+        .collect())
+}
+
+/// Representative ratings of the 11 elo buckets Maia models were
+/// originally trained on (below 1100, 1100s, ..., 1900s, 2000 and up).
+pub const ELO_BUCKETS: [f32; 11] = [
+    1000.0, 1100.0, 1200.0, 1300.0, 1400.0, 1500.0, 1600.0, 1700.0, 1800.0, 1900.0, 2000.0,
+];
+
+/// Positions per evaluator call in [`move_match_profile`].
+const MATCH_CHUNK_SIZE: usize = 512;
+
+/// Which moves [`move_match_profile_with`] takes into account.
+#[derive(Debug, Clone)]
+pub struct MatchProfileConfig {
+    /// Ignore the first this many plies of every game (opening theory).
+    pub skip_plies: usize,
+    /// Ignore positions with a single legal move.
+    pub skip_forced: bool,
+}
+
+impl Default for MatchProfileConfig {
+    fn default() -> Self {
+        Self {
+            skip_plies: 10,
+            skip_forced: true,
+        }
+    }
+}
+
+/// Move-match counts at one rating.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BucketMatch {
+    /// Rating the predictions were made at.
+    pub elo: f32,
+    /// Moves considered.
+    pub moves: usize,
+    /// Moves that were Maia's top prediction.
+    pub top1: usize,
+    /// Moves among Maia's three most likely predictions.
+    pub top3: usize,
+}
+
+impl BucketMatch {
+    /// Fraction of moves matching the top prediction.
+    pub fn top1_rate(&self) -> f32 {
+        self.top1 as f32 / self.moves.max(1) as f32
+    }
+
+    /// Fraction of moves among the top three predictions.
+    pub fn top3_rate(&self) -> f32 {
+        self.top3 as f32 / self.moves.max(1) as f32
+    }
+}
+
+/// Result of [`move_match_profile`]: one entry per [`ELO_BUCKETS`] rating.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveMatchProfile {
+    pub buckets: Vec<BucketMatch>,
+}
+
+/// How often `color`'s moves in `games` match Maia's predictions at each
+/// of the [`ELO_BUCKETS`], with the default [`MatchProfileConfig`].
+///
+/// # Errors
+/// See [`move_match_profile_with`].
+pub fn move_match_profile(
+    evaluator: &mut impl Evaluator,
+    games: &[GameMoves],
+    color: Color,
+) -> Result<MoveMatchProfile, Error> {
+    move_match_profile_with(evaluator, games, color, &MatchProfileConfig::default())
+}
+
+/// [`move_match_profile`] with explicit move filtering.
+///
+/// Every bucket is evaluated with that rating on both sides, since the
+/// point is to find the level the player's moves resemble most.
+///
+/// # Errors
+/// Returns [`Error::IllegalMove`] for an illegal move and propagates
+/// evaluation errors.
+pub fn move_match_profile_with(
+    evaluator: &mut impl Evaluator,
+    games: &[GameMoves],
+    color: Color,
+    config: &MatchProfileConfig,
+) -> Result<MoveMatchProfile, Error> {
+    let mut samples: Vec<(Chess, UciMove)> = Vec::new();
+    for game in games {
+        let positions = game.positions()?;
+        for (ply, (pos, uci)) in positions.into_iter().zip(&game.moves).enumerate() {
+            let forced = config.skip_forced && pos.legal_moves().len() == 1;
+            if pos.turn() == color && ply >= config.skip_plies && !forced {
+                samples.push((pos, *uci));
+            }
+        }
+    }
+
+    let mut buckets = Vec::with_capacity(ELO_BUCKETS.len());
+    for elo in ELO_BUCKETS {
+        let mut bucket = BucketMatch {
+            elo,
+            moves: samples.len(),
+            top1: 0,
+            top3: 0,
+        };
+        for chunk in samples.chunks(MATCH_CHUNK_SIZE) {
+            let setups = chunk
+                .iter()
+                .map(|(pos, _)| pos.to_setup(EnPassantMode::Legal))
+                .collect();
+            let elos = vec![elo; chunk.len()];
+            let results = evaluator.batch_evaluate(setups, &elos, &elos)?;
+
+            for ((_, played), result) in chunk.iter().zip(&results) {
+                let rank = result.policy.iter().position(|m| m.uci == *played);
+                bucket.top1 += usize::from(rank == Some(0));
+                bucket.top3 += usize::from(rank.is_some_and(|r| r < 3));
+            }
+        }
+        buckets.push(bucket);
+    }
+    Ok(MoveMatchProfile { buckets })
+}
+
+/// Probability assumed for a played move missing from the policy in
+/// [`likelihood_profiles`], so that its log-probability stays finite.
+const MIN_MOVE_PROBABILITY: f64 = 1e-6;
+
+/// Which moves [`likelihood_profile_with`] counts.
+#[derive(Debug, Clone)]
+pub struct LikelihoodConfig {
+    /// Ignore the first this many plies of every game (opening theory).
+    pub skip_plies: usize,
+    /// Top-move probability from which a position counts as forced: the
+    /// move is listed but left out of the statistics, since nearly
+    /// everyone plays it.
+    pub only_move_threshold: f32,
+}
+
+impl Default for LikelihoodConfig {
+    fn default() -> Self {
+        Self {
+            skip_plies: 10,
+            only_move_threshold: 0.9,
+        }
+    }
+}
+
+/// One side of a game and the rating to evaluate its moves at, for
+/// [`likelihood_profiles`].
+#[derive(Debug, Clone)]
+pub struct PlayerGame {
+    pub game: GameMoves,
+    pub elo: f32,
+    pub color: Color,
+}
+
+/// How likely one played move was at the player's rating.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveLikelihood {
+    /// Index into [`GameMoves::moves`].
+    pub ply: usize,
+    pub uci: UciMove,
+    /// Natural logarithm of the move's policy probability.
+    pub log_probability: f64,
+    /// 1-based policy rank of the move, `None` if it is not in the policy.
+    pub rank: Option<usize>,
+    /// The top move reached [`LikelihoodConfig::only_move_threshold`].
+    pub forced: bool,
+}
+
+/// Result of [`likelihood_profile`]: the likelihood of every move of one
+/// player in a game, leaving out opening plies and positions with a single
+/// legal move.
+///
+/// The statistics describe how typical the moves are for the rating, and
+/// [`compare`](Self::compare) how far they are from a reference set; they
+/// are no judgement of why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LikelihoodProfile {
+    pub elo: f32,
+    pub color: Color,
+    pub moves: Vec<MoveLikelihood>,
+}
+
+impl LikelihoodProfile {
+    /// The moves that count towards the statistics, those not forced.
+    pub fn counted(&self) -> impl Iterator<Item = &MoveLikelihood> {
+        self.moves.iter().filter(|m| !m.forced)
+    }
+
+    /// Number of [`counted`](Self::counted) moves.
+    pub fn counted_moves(&self) -> usize {
+        self.counted().count()
+    }
+
+    /// Mean log-probability of the counted moves, `None` without any.
+    pub fn mean_log_probability(&self) -> Option<f64> {
+        let n = self.counted_moves();
+        (n > 0).then(|| self.counted().map(|m| m.log_probability).sum::<f64>() / n as f64)
+    }
+
+    /// Fraction of the counted moves that were the top prediction, `None`
+    /// without any.
+    pub fn top1_share(&self) -> Option<f64> {
+        let n = self.counted_moves();
+        let top1 = self.counted().filter(|m| m.rank == Some(1)).count();
+        (n > 0).then(|| top1 as f64 / n as f64)
+    }
+
+    /// The statistics of this game against `baseline`.
+    pub fn compare(&self, baseline: &LikelihoodBaseline) -> LikelihoodComparison {
+        let moves = self.counted_moves();
+        // Standard error of a mean of `moves` moves drawn like the
+        // baseline's.
+        let z = |value: Option<f64>, mean: f64, sd: f64| {
+            let value = value?;
+            (sd > 0.0).then(|| (value - mean) / (sd / (moves as f64).sqrt()))
+        };
+        let top1_sd = (baseline.top1_share * (1.0 - baseline.top1_share)).sqrt();
+        LikelihoodComparison {
+            moves,
+            log_probability_z: z(
+                self.mean_log_probability(),
+                baseline.mean_log_probability,
+                baseline.log_probability_sd,
+            ),
+            top1_z: z(self.top1_share(), baseline.top1_share, top1_sd),
+        }
+    }
+}
+
+/// Distribution of counted moves over a reference set of games, against
+/// which single games are compared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LikelihoodBaseline {
+    pub games: usize,
+    /// Counted moves over all games.
+    pub moves: usize,
+    pub mean_log_probability: f64,
+    /// Standard deviation of the log-probability of one move.
+    pub log_probability_sd: f64,
+    /// Fraction of moves that were the top prediction.
+    pub top1_share: f64,
+}
+
+impl LikelihoodBaseline {
+    /// The baseline of `profiles`, `None` if they have no counted move.
+    pub fn from_profiles(profiles: &[LikelihoodProfile]) -> Option<Self> {
+        let counted = || profiles.iter().flat_map(LikelihoodProfile::counted);
+        let moves = counted().count();
+        if moves == 0 {
+            return None;
+        }
+        let n = moves as f64;
+        let mean = counted().map(|m| m.log_probability).sum::<f64>() / n;
+        let variance = counted()
+            .map(|m| (m.log_probability - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        let top1 = counted().filter(|m| m.rank == Some(1)).count();
+        Some(Self {
+            games: profiles.len(),
+            moves,
+            mean_log_probability: mean,
+            log_probability_sd: variance.sqrt(),
+            top1_share: top1 as f64 / n,
+        })
+    }
+
+    /// Evaluate `reference` and take its baseline, `None` if it has no
+    /// counted move. The reference should be games by players like the
+    /// ones compared, at the same ratings.
+    ///
+    /// # Errors
+    /// See [`likelihood_profiles`].
+    pub fn calibrate(
+        evaluator: &mut impl Evaluator,
+        reference: &[PlayerGame],
+        config: &LikelihoodConfig,
+    ) -> Result<Option<Self>, Error> {
+        let profiles = likelihood_profiles(evaluator, reference, config)?;
+        Ok(Self::from_profiles(&profiles))
+    }
+}
+
+/// How one game's statistics relate to a [`LikelihoodBaseline`], as
+/// z-scores of the game's means: positive where the moves were more
+/// likely, or more often the top prediction, than in the baseline.
+/// `None` without counted moves or spread in the baseline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LikelihoodComparison {
+    /// Counted moves of the game.
+    pub moves: usize,
+    pub log_probability_z: Option<f64>,
+    pub top1_z: Option<f64>,
+}
+
+/// The likelihood of `color`'s moves in `game` at `player_elo`, with the
+/// default [`LikelihoodConfig`].
+///
+/// # Errors
+/// See [`likelihood_profiles`].
+pub fn likelihood_profile(
+    evaluator: &mut impl Evaluator,
+    game: &GameMoves,
+    player_elo: f32,
+    color: Color,
+) -> Result<LikelihoodProfile, Error> {
+    likelihood_profile_with(
+        evaluator,
+        game,
+        player_elo,
+        color,
+        &LikelihoodConfig::default(),
+    )
+}
+
+/// [`likelihood_profile`] with explicit move filtering.
+///
+/// # Errors
+/// See [`likelihood_profiles`].
+pub fn likelihood_profile_with(
+    evaluator: &mut impl Evaluator,
+    game: &GameMoves,
+    player_elo: f32,
+    color: Color,
+    config: &LikelihoodConfig,
+) -> Result<LikelihoodProfile, Error> {
+    let mut profiles = collect_likelihoods(evaluator, [(game, player_elo, color)], config)?;
+    Ok(profiles.remove(0))
+}
+
+/// [`likelihood_profile_with`] for many games, evaluated in shared
+/// batches. Profiles are in the order of `games`.
+///
+/// Every position is evaluated with the player's rating on both sides,
+/// as the opponent's rating says little about the player's own moves.
+///
+/// # Errors
+/// Returns [`Error::IllegalMove`] for an illegal move and propagates
+/// evaluation errors.
+pub fn likelihood_profiles(
+    evaluator: &mut impl Evaluator,
+    games: &[PlayerGame],
+    config: &LikelihoodConfig,
+) -> Result<Vec<LikelihoodProfile>, Error> {
+    collect_likelihoods(
+        evaluator,
+        games.iter().map(|g| (&g.game, g.elo, g.color)),
+        config,
+    )
+}
+
+fn collect_likelihoods<'a>(
+    evaluator: &mut impl Evaluator,
+    games: impl IntoIterator<Item = (&'a GameMoves, f32, Color)>,
+    config: &LikelihoodConfig,
+) -> Result<Vec<LikelihoodProfile>, Error> {
+    let mut profiles = Vec::new();
+    // Game index, ply, position and played move of every counted ply.
+    let mut samples: Vec<(usize, usize, Chess, UciMove)> = Vec::new();
+    for (i, (game, elo, color)) in games.into_iter().enumerate() {
+        let positions = game.positions()?;
+        for (ply, (pos, uci)) in positions.into_iter().zip(&game.moves).enumerate() {
+            if pos.turn() == color && ply >= config.skip_plies && pos.legal_moves().len() > 1 {
+                samples.push((i, ply, pos, *uci));
+            }
+        }
+        profiles.push(LikelihoodProfile {
+            elo,
+            color,
+            moves: Vec::new(),
+        });
+    }
+
+    for chunk in samples.chunks(MATCH_CHUNK_SIZE) {
+        let setups = chunk
+            .iter()
+            .map(|(_, _, pos, _)| pos.to_setup(EnPassantMode::Legal))
+            .collect();
+        let elos: Vec<f32> = chunk.iter().map(|&(i, ..)| profiles[i].elo).collect();
+        let results = evaluator.batch_evaluate(setups, &elos, &elos)?;
+
+        for (&(i, ply, _, uci), result) in chunk.iter().zip(&results) {
+            let rank = result.policy.iter().position(|m| m.uci == uci);
+            let probability = rank.map_or(0.0, |r| f64::from(result.policy[r].probability));
+            let forced = result
+                .best_move()
+                .is_some_and(|m| m.probability >= config.only_move_threshold);
+            profiles[i].moves.push(MoveLikelihood {
+                ply,
+                uci,
+                log_probability: probability.max(MIN_MOVE_PROBABILITY).ln(),
+                rank: rank.map(|r| r + 1),
+                forced,
+            });
+        }
+    }
+    Ok(profiles)
+}
+
+/// How Maia handles a puzzle at one rating.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PuzzleBucket {
+    /// Rating of the solving side (the defender is evaluated at the same
+    /// rating).
+    pub elo: f32,
+    /// Probability of the first solution move.
+    pub first_move_probability: f32,
+    /// 1-based rank of the first solution move in the policy.
+    pub first_move_rank: Option<usize>,
+    /// Mean probability of the solver's moves, each weighted by the
+    /// probability of the defender replies leading to it.
+    pub weighted_probability: f32,
+    /// Probability of playing every solver move of the line.
+    pub line_probability: f32,
+    /// Whether every solver move is the top prediction.
+    pub solved: bool,
+}
+
+/// Result of [`puzzle_difficulty`]: one entry per [`ELO_BUCKETS`] rating.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PuzzleDifficulty {
+    pub buckets: Vec<PuzzleBucket>,
+}
+
+impl PuzzleDifficulty {
+    /// One minus the mean [`PuzzleBucket::weighted_probability`] over all
+    /// buckets: near 0 for puzzles every level solves, near 1 for puzzles
+    /// no level sees.
+    pub fn score(&self) -> f32 {
+        let total: f32 = self.buckets.iter().map(|b| b.weighted_probability).sum();
+        1.0 - total / self.buckets.len().max(1) as f32
+    }
+
+    /// Lowest bucket rating whose top predictions play the whole
+    /// solution, if any.
+    pub fn solved_from_elo(&self) -> Option<f32> {
+        self.buckets.iter().find(|b| b.solved).map(|b| b.elo)
+    }
+}
+
+/// Rate how hard `solution` is to find in `pos` for each of the
+/// [`ELO_BUCKETS`].
+///
+/// `solution` alternates the solver's moves and the defender's replies,
+/// starting and normally ending with a solver move. All positions of the
+/// line are evaluated at all ratings in a single batch. Later solver
+/// moves count in proportion to the probability that the defender
+/// actually plays the line's replies, so a step behind an unlikely
+/// defence matters less.
+///
+/// # Errors
+/// Returns [`Error::IllegalMove`] if the line is not legal and
+/// propagates evaluation errors.
+///
+/// # Panics
+/// Panics if `solution` is empty.
+pub fn puzzle_difficulty(
+    evaluator: &mut impl Evaluator,
+    pos: &Chess,
+    solution: &[Move],
+) -> Result<PuzzleDifficulty, Error> {
+    assert!(!solution.is_empty(), "puzzle solution is empty");
+
+    let mut positions = Vec::with_capacity(solution.len());
+    let mut current = pos.clone();
+    for m in solution {
+        if !current.is_legal(*m) {
+            return Err(Error::IllegalMove(
+                m.to_uci(CastlingMode::Standard).to_string(),
+            ));
+        }
+        positions.push(current.clone());
+        current.play_unchecked(*m);
+    }
+    let ucis: Vec<UciMove> = solution
+        .iter()
+        .map(|m| m.to_uci(CastlingMode::Standard))
+        .collect();
+
+    let setups = ELO_BUCKETS
+        .iter()
+        .flat_map(|_| positions.iter().map(|p| p.to_setup(EnPassantMode::Legal)))
+        .collect();
+    let elos: Vec<f32> = ELO_BUCKETS
+        .iter()
+        .flat_map(|&elo| std::iter::repeat_n(elo, positions.len()))
+        .collect();
+    let results = evaluator.batch_evaluate(setups, &elos, &elos)?;
+
+    let buckets = ELO_BUCKETS
+        .iter()
+        .zip(results.chunks(positions.len()))
+        .map(|(&elo, line)| {
+            let ranked: Vec<(f32, Option<usize>)> = line
+                .iter()
+                .zip(&ucis)
+                .map(|(result, uci)| {
+                    let rank = result.policy.iter().position(|m| m.uci == *uci);
+                    (rank.map_or(0.0, |r| result.policy[r].probability), rank)
+                })
+                .collect();
+
+            let (mut weight, mut weighted_sum, mut weight_sum) = (1.0, 0.0, 0.0);
+            let mut line_probability = 1.0;
+            let mut solved = true;
+            for (step, &(probability, rank)) in ranked.iter().enumerate() {
+                if step % 2 == 0 {
+                    weighted_sum += weight * probability;
+                    weight_sum += weight;
+                    line_probability *= probability;
+                    solved &= rank == Some(0);
+                } else {
+                    weight *= probability;
+                }
+            }
+
+            PuzzleBucket {
+                elo,
+                first_move_probability: ranked[0].0,
+                first_move_rank: ranked[0].1.map(|r| r + 1),
+                weighted_probability: weighted_sum / weight_sum,
+                line_probability,
+                solved,
+            }
+        })
+        .collect();
+    Ok(PuzzleDifficulty { buckets })
+}
+
+/// Rating the children are evaluated at in [`sharpness`].
+pub const SHARPNESS_REFERENCE_ELO: f32 = 1500.0;
+
+/// Expected-score drop below the best move that makes a move count as a
+/// mistake in [`SharpnessReport::mistake_mass`].
+pub const SHARPNESS_MISTAKE_MARGIN: f32 = 0.1;
+
+/// Result of [`sharpness`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharpnessReport {
+    /// Shannon entropy of the policy in bits at each of the
+    /// [`ELO_BUCKETS`]. Higher means the level has no clear move.
+    pub entropy: Vec<f32>,
+    /// Per bucket, the policy mass on moves whose expected score for the
+    /// mover is more than [`SHARPNESS_MISTAKE_MARGIN`] below the best
+    /// move's. Move values come from evaluating every child once at
+    /// [`SHARPNESS_REFERENCE_ELO`]; a mate scores 1 and a stalemate 0.5.
+    pub mistake_mass: Vec<f32>,
+    /// Whether the top move at 1100 differs from the top move at 2000.
+    pub top_move_changes: bool,
+    /// Total variation distance between the 1100 and 2000 policies, from
+    /// 0 (identical) to 1 (disjoint).
+    pub policy_shift: f32,
+}
+
+/// Sharpness metrics of `pos`, computed from a single batch holding the
+/// position at every [`ELO_BUCKETS`] rating plus all of its children.
+///
+/// Positions without legal moves report zeros everywhere.
+///
+/// # Errors
+/// Propagates evaluation errors.
+pub fn sharpness(evaluator: &mut impl Evaluator, pos: &Chess) -> Result<SharpnessReport, Error> {
+    let mover = pos.turn();
+    let children: Vec<(UciMove, Chess)> = pos
+        .legal_moves()
+        .iter()
+        .map(|m| {
+            let mut child = pos.clone();
+            child.play_unchecked(*m);
+            (m.to_uci(CastlingMode::Standard), child)
+        })
+        .collect();
+
+    let mut setups: Vec<_> = ELO_BUCKETS
+        .iter()
+        .map(|_| pos.to_setup(EnPassantMode::Legal))
+        .collect();
+    setups.extend(
+        children
+            .iter()
+            .map(|(_, c)| c.to_setup(EnPassantMode::Legal)),
+    );
+    let mut elos = ELO_BUCKETS.to_vec();
+    elos.resize(setups.len(), SHARPNESS_REFERENCE_ELO);
+    let results = evaluator.batch_evaluate(setups, &elos, &elos)?;
+    let (roots, child_results) = results.split_at(ELO_BUCKETS.len());
+
+    let values: Vec<(UciMove, f32)> = children
+        .iter()
+        .zip(child_results)
+        .map(|((uci, child), result)| (*uci, move_value(child, result, mover)))
+        .collect();
+    let best = values
+        .iter()
+        .map(|&(_, v)| v)
+        .fold(f32::NEG_INFINITY, f32::max);
+    let is_mistake = |uci: &UciMove| {
+        values
+            .iter()
+            .any(|(m, v)| m == uci && *v < best - SHARPNESS_MISTAKE_MARGIN)
+    };
+
+    let entropy = roots.iter().map(EvaluationResult::entropy).collect();
+    let mistake_mass = roots
+        .iter()
+        .map(|r| {
+            r.policy
+                .iter()
+                .filter(|m| is_mistake(&m.uci))
+                .map(|m| m.probability)
+                .sum()
+        })
+        .collect();
+
+    let (low, high) = (&roots[1], &roots[ELO_BUCKETS.len() - 1]);
+    let top = |r: &EvaluationResult| r.best_move().map(|m| m.uci);
+    let probability_in = |r: &EvaluationResult, uci: &UciMove| {
+        r.policy
+            .iter()
+            .find(|m| m.uci == *uci)
+            .map_or(0.0, |m| m.probability)
+    };
+    let policy_shift = 0.5
+        * values
+            .iter()
+            .map(|(uci, _)| (probability_in(low, uci) - probability_in(high, uci)).abs())
+            .sum::<f32>();
+
+    Ok(SharpnessReport {
+        entropy,
+        mistake_mass,
+        top_move_changes: top(low) != top(high),
+        policy_shift,
+    })
+}
+
+/// One position of an EPD test suite, see [`run_epd_suite`].
+#[derive(Debug, Clone)]
+pub struct EpdEntry {
+    /// 1-based line number in the suite.
+    pub line: usize,
+    /// Operand of the `id` opcode, if any.
+    pub id: Option<String>,
+    pub position: Chess,
+    /// Moves of the `bm` opcode in standard UCI, empty without one.
+    pub best_moves: Vec<UciMove>,
+    /// Moves of the `am` opcode in standard UCI, empty without one.
+    pub avoid_moves: Vec<UciMove>,
+}
+
+/// A suite line [`run_epd_suite`] could not use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedEpdLine {
+    /// 1-based line number.
+    pub line: usize,
+    pub text: String,
+    pub reason: String,
+}
+
+/// Maia's answer to one suite position at one rating.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpdOutcome {
+    pub elo: f32,
+    /// The model's most likely move.
+    pub top_move: Option<UciMove>,
+    /// 1-based policy rank of the best-ranked `bm` move. `None` without
+    /// a `bm` opcode.
+    pub best_move_rank: Option<usize>,
+    /// The top move is one of the `am` moves.
+    pub avoid_move_played: bool,
+}
+
+/// A suite position with its outcome at every rating.
+#[derive(Debug, Clone)]
+pub struct EpdPositionReport {
+    pub entry: EpdEntry,
+    /// One outcome per rating, in the order given.
+    pub outcomes: Vec<EpdOutcome>,
+}
+
+/// Suite totals at one rating.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SuiteBucket {
+    pub elo: f32,
+    /// Positions with a `bm` opcode.
+    pub best_move_positions: usize,
+    /// Positions whose top move is a `bm` move.
+    pub top1: usize,
+    /// Positions with a `bm` move among the three most likely moves.
+    pub top3: usize,
+    /// Positions with an `am` opcode.
+    pub avoid_move_positions: usize,
+    /// Positions whose top move is an `am` move.
+    pub avoid_violations: usize,
+}
+
+impl SuiteBucket {
+    /// Fraction of `bm` positions whose top move is a best move.
+    pub fn top1_rate(&self) -> f32 {
+        self.top1 as f32 / self.best_move_positions.max(1) as f32
+    }
+
+    /// Fraction of `bm` positions with a best move in the top three.
+    pub fn top3_rate(&self) -> f32 {
+        self.top3 as f32 / self.best_move_positions.max(1) as f32
+    }
+
+    /// Fraction of `am` positions whose top move is to be avoided.
+    pub fn violation_rate(&self) -> f32 {
+        self.avoid_violations as f32 / self.avoid_move_positions.max(1) as f32
+    }
+}
+
+/// Result of [`run_epd_suite`].
+#[derive(Debug, Clone)]
+pub struct SuiteReport {
+    /// Totals per rating, in the order given.
+    pub buckets: Vec<SuiteBucket>,
+    /// Every usable position, in suite order.
+    pub positions: Vec<EpdPositionReport>,
+    /// Lines that were malformed or had neither `bm` nor `am`.
+    pub skipped: Vec<SkippedEpdLine>,
+}
+
+/// How often Maia's top move at each rating in `buckets` (e.g.
+/// [`ELO_BUCKETS`]) matches the `bm` moves of an EPD test suite and
+/// avoids its `am` moves.
+///
+/// Every position is evaluated once per rating, with that rating on both
+/// sides; all pairs go to the evaluator in shared batches. Moves may be
+/// given in SAN, as suites do, or in UCI. Blank lines are ignored;
+/// malformed lines and lines without `bm` or `am` end up in
+/// [`SuiteReport::skipped`].
+///
+/// # Errors
+/// Propagates evaluation errors.
+pub fn run_epd_suite(
+    evaluator: &mut impl Evaluator,
+    epd_text: &str,
+    buckets: &[f32],
+) -> Result<SuiteReport, Error> {
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    for (i, text) in epd_text.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        match parse_epd_line(i + 1, text) {
+            Ok(entry) => entries.push(entry),
+            Err(reason) => skipped.push(SkippedEpdLine {
+                line: i + 1,
+                text: text.to_string(),
+                reason,
+            }),
+        }
+    }
+
+    let pairs: Vec<(usize, f32)> = (0..entries.len())
+        .flat_map(|e| buckets.iter().map(move |&elo| (e, elo)))
+        .collect();
+    let mut results = Vec::with_capacity(pairs.len());
+    for chunk in pairs.chunks(MATCH_CHUNK_SIZE) {
+        let setups = chunk
+            .iter()
+            .map(|&(e, _)| entries[e].position.to_setup(EnPassantMode::Legal))
+            .collect();
+        let elos: Vec<f32> = chunk.iter().map(|&(_, elo)| elo).collect();
+        results.extend(evaluator.batch_evaluate(setups, &elos, &elos)?);
+    }
+
+    let mut totals: Vec<SuiteBucket> = buckets
+        .iter()
+        .map(|&elo| SuiteBucket {
+            elo,
+            best_move_positions: 0,
+            top1: 0,
+            top3: 0,
+            avoid_move_positions: 0,
+            avoid_violations: 0,
+        })
+        .collect();
+    let mut results = results.into_iter();
+    let positions = entries
+        .into_iter()
+        .map(|entry| {
+            let outcomes = totals
+                .iter_mut()
+                .zip(results.by_ref())
+                .map(|(total, result)| {
+                    let top_move = result.policy.first().map(|m| m.uci);
+                    let best_move_rank = (!entry.best_moves.is_empty()).then(|| {
+                        result
+                            .policy
+                            .iter()
+                            .position(|m| entry.best_moves.contains(&m.uci))
+                            .map(|rank| rank + 1)
+                    });
+                    let avoid_move_played =
+                        top_move.is_some_and(|m| entry.avoid_moves.contains(&m));
+                    if let Some(rank) = best_move_rank {
+                        total.best_move_positions += 1;
+                        total.top1 += usize::from(rank == Some(1));
+                        total.top3 += usize::from(rank.is_some_and(|r| r <= 3));
+                    }
+                    if !entry.avoid_moves.is_empty() {
+                        total.avoid_move_positions += 1;
+                        total.avoid_violations += usize::from(avoid_move_played);
+                    }
+                    EpdOutcome {
+                        elo: total.elo,
+                        top_move,
+                        best_move_rank: best_move_rank.flatten(),
+                        avoid_move_played,
+                    }
+                })
+                .collect();
+            EpdPositionReport { entry, outcomes }
+        })
+        .collect();
+
+    Ok(SuiteReport {
+        buckets: totals,
+        positions,
+        skipped,
+    })
+}
+
+/// The position and opcodes of an EPD line, or why it cannot be used.
+fn parse_epd_line(line: usize, text: &str) -> Result<EpdEntry, String> {
+    let mut rest = text.trim();
+    let mut fields = Vec::with_capacity(4);
+    for _ in 0..4 {
+        let (field, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        fields.push(field);
+        rest = tail.trim_start();
+    }
+    let epd = fields.join(" ");
+    let position: Chess = epd
+        .parse::<shakmaty::fen::Epd>()
+        .map_err(|e| Error::invalid_fen(&epd, e).to_string())?
+        .into_position(CastlingMode::Standard)
+        .map_err(|e| Error::from(e).to_string())?;
+
+    let mut entry = EpdEntry {
+        line,
+        id: None,
+        position,
+        best_moves: Vec::new(),
+        avoid_moves: Vec::new(),
+    };
+    for operation in epd_operations(rest) {
+        let (opcode, operands) = operation
+            .split_once(char::is_whitespace)
+            .unwrap_or((operation, ""));
+        match opcode {
+            "id" => entry.id = Some(operands.trim().trim_matches('"').to_string()),
+            "bm" | "am" => {
+                let moves = operands
+                    .split_whitespace()
+                    .map(|m| epd_move(&entry.position, m))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match opcode {
+                    "bm" => entry.best_moves = moves,
+                    _ => entry.avoid_moves = moves,
+                }
+            }
+            _ => {}
+        }
+    }
+    if entry.best_moves.is_empty() && entry.avoid_moves.is_empty() {
+        return Err("no bm or am opcode".to_string());
+    }
+    Ok(entry)
+}
+
+/// The `;`-terminated operations of an EPD line, trimmed. Semicolons in
+/// quoted operands do not end an operation.
+fn epd_operations(text: &str) -> Vec<&str> {
+    let mut operations = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                operations.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    operations.push(text[start..].trim());
+    operations.retain(|op| !op.is_empty());
+    operations
+}
+
+/// A SAN or UCI move of an EPD operand, in standard UCI.
+fn epd_move(pos: &Chess, text: &str) -> Result<UciMove, String> {
+    let m = match text.parse::<shakmaty::san::SanPlus>() {
+        Ok(san) => san.san.to_move(pos).ok(),
+        Err(_) => None,
+    }
+    .or_else(|| text.parse::<UciMove>().ok()?.to_move(pos).ok())
+    .ok_or_else(|| Error::IllegalMove(text.to_string()).to_string())?;
+    Ok(m.to_uci(CastlingMode::Standard))
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::fen::Fen;
+
+    use super::*;
+    use crate::testing::{ScriptedEvaluator, UniformEvaluator};
+
+    /// Position from `fen` and the solution line in UCI.
+    fn puzzle(fen: &str, line: &str) -> (Chess, Vec<Move>) {
+        let mut pos: Chess = fen
+            .parse::<Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let start = pos.clone();
+        let moves = line
+            .split_whitespace()
+            .map(|uci| {
+                let m = uci.parse::<UciMove>().unwrap().to_move(&pos).unwrap();
+                pos.play_unchecked(m);
+                m
+            })
+            .collect();
+        (start, moves)
+    }
+
+    /// Ladder mate: two rooks, quiet first move.
+    const LADDER: (&str, &str) = ("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", "b1b7 h8g8 a2a8");
+    /// Légal's mate: knight check and bishop mate after a queen sacrifice.
+    const LEGAL: (&str, &str) = (
+        "r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1",
+        "d5f6 g7f6 c4f7",
+    );
+
+    #[test]
+    fn win_prob_series_pins_checkmate() {
+        // Fool's mate.
+        let game = GameMoves::from_uci("f2f3 e7e5 g2g4 d8h4").unwrap();
+        let analysis = GameAnalysis::analyze(&mut UniformEvaluator, game, 1500.0, 1500.0).unwrap();
+
+        let series = analysis.win_prob_series();
+        assert_eq!(series, vec![0.4, 0.4, 0.4, 0.0]);
+        assert_eq!(analysis.win_prob_deltas()[3], -0.4);
+        assert_eq!(analysis.critical_moments(2)[0], 3);
+        assert_eq!(analysis.evaluations().len(), 5);
+    }
+
+    #[test]
+    fn forced_moves_are_flagged_and_excluded() {
+        // Fool's mate; d8h4 is the only move that wins on the spot.
+        let game = GameMoves::from_uci("f2f3 e7e5 g2g4 d8h4").unwrap();
+        let mut analysis =
+            GameAnalysis::analyze(&mut UniformEvaluator, game, 1500.0, 1500.0).unwrap();
+        let config = AccuracyConfig::default();
+
+        let flags = analysis.ply_flags(&config);
+        assert_eq!(flags[0].policy_concentration, 0.05);
+        assert!(
+            flags
+                .iter()
+                .all(|f| !f.is_only_move && f.forced_by_value.is_none())
+        );
+
+        analysis.evaluate_children(&mut UniformEvaluator).unwrap();
+        let flags = analysis.ply_flags(&config);
+        assert_eq!(flags[3].forced_by_value, Some(true));
+        assert_eq!(flags[1].forced_by_value, Some(false));
+
+        assert_eq!(analysis.move_accuracies().len(), 4);
+        assert!(analysis.accuracy(Color::Black, &config).unwrap() > 99.9);
+
+        // Black's only reply in the ladder mate is forced by the policy.
+        let (initial, line) = puzzle(LADDER.0, LADDER.1);
+        let game = GameMoves {
+            initial,
+            moves: line
+                .iter()
+                .map(|m| m.to_uci(CastlingMode::Standard))
+                .collect(),
+        };
+        let analysis = GameAnalysis::analyze(&mut UniformEvaluator, game, 1500.0, 1500.0).unwrap();
+        assert!(analysis.ply_flags(&config)[1].is_only_move);
+        assert_eq!(analysis.accuracy(Color::Black, &config), None);
+        let counted = AccuracyConfig {
+            exclude_forced: false,
+            ..config
+        };
+        assert!(analysis.accuracy(Color::Black, &counted).is_some());
+    }
+
+    #[test]
+    fn moves_are_judged_by_score_loss() {
+        let game = GameMoves::from_uci("e2e4 e7e5 g1f3 b8c6 f1c4").unwrap();
+        let mut evaluator = ScriptedEvaluator(vec![0.5, 0.44, 0.4, 0.29, 0.45, 0.45]);
+        let analysis = GameAnalysis::analyze(&mut evaluator, game, 1500.0, 1500.0).unwrap();
+        let judgements = analysis.judgements(&AccuracyConfig::default());
+        assert_eq!(
+            judgements,
+            [
+                Some(Judgement::Inaccuracy),
+                None,
+                Some(Judgement::Mistake),
+                Some(Judgement::Blunder),
+                None
+            ]
+        );
+        assert_eq!(Judgement::Blunder.nag(), 4);
+    }
+
+    #[test]
+    fn repetitions_and_the_fifty_move_rule_draw_the_game() {
+        // The knights shuffle back four times: the start position occurs
+        // for the third time at ply 8 and the fifth at ply 16.
+        let game = GameMoves::from_uci(&"g1f3 g8f6 f3g1 f6g8 ".repeat(4)).unwrap();
+        let mut evaluator = ScriptedEvaluator(vec![0.9; 17]);
+        let mut analysis = GameAnalysis::analyze(&mut evaluator, game, 1500.0, 1500.0).unwrap();
+
+        let draws = analysis.draw_statuses();
+        let repetitions: Vec<u32> = draws.iter().map(|d| d.repetitions).collect();
+        assert_eq!(
+            repetitions,
+            [1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5]
+        );
+        assert_eq!(draws[8].halfmove_clock, 8);
+        assert!(!draws[7].claimable() && draws[8].claimable());
+        assert!(!draws[15].automatic() && draws[16].automatic());
+
+        assert_eq!(analysis.drawn_from(), None);
+        assert!(analysis.win_prob_series().iter().all(|&p| p == 0.9));
+
+        analysis.set_draw_adjudication(DrawAdjudication::Claimable);
+        assert_eq!(analysis.drawn_from(), Some(8));
+        let series = analysis.win_prob_series();
+        assert_eq!(series[6], 0.9);
+        assert!(series[7..].iter().all(|&p| p == 0.0));
+        assert_eq!(analysis.expected_score(8, Color::White), 0.5);
+        assert_eq!(analysis.expected_score(12, Color::Black), 0.5);
+
+        analysis.set_draw_adjudication(DrawAdjudication::Automatic);
+        assert_eq!(analysis.drawn_from(), Some(16));
+        assert_eq!(analysis.expected_score(15, Color::White), 0.9);
+
+        let initial: Chess = "8/8/4k3/8/8/4K3/8/4R3 w - - 99 80"
+            .parse::<shakmaty::fen::Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let game = GameMoves {
+            initial,
+            moves: vec!["e1e2".parse().unwrap()],
+        };
+        let analysis = GameAnalysis::analyze(&mut UniformEvaluator, game, 1500.0, 1500.0).unwrap();
+        let draws = analysis.draw_statuses();
+        assert!(!draws[0].claimable());
+        assert_eq!(draws[1].halfmove_clock, 100);
+        assert!(draws[1].claimable() && !draws[1].automatic());
+    }
+
+    #[test]
+    fn illegal_moves_are_reported() {
+        let game = GameMoves::from_uci("e2e4 e2e4").unwrap();
+        let err = GameAnalysis::analyze(&mut UniformEvaluator, game, 1500.0, 1500.0).unwrap_err();
+        assert!(matches!(err, Error::IllegalMove(m) if m == "e2e4"));
+    }
+
+    #[test]
+    #[cfg(feature = "pgn")]
+    fn pgn_games_share_batches() {
+        /// [`UniformEvaluator`] recording its batch sizes.
+        struct Counting(Vec<usize>);
+
+        impl Evaluator for Counting {
+            fn batch_evaluate(
+                &mut self,
+                setups: Vec<shakmaty::Setup>,
+                elo_selfs: &[f32],
+                elo_oppos: &[f32],
+            ) -> Result<Vec<EvaluationResult>, Error> {
+                self.0.push(setups.len());
+                UniformEvaluator.batch_evaluate(setups, elo_selfs, elo_oppos)
+            }
+        }
+
+        // The second game transposes into the first; the fourth starts
+        // from the same position with other ratings.
+        let pgn = "1. e4 e5 2. Nf3 Nc6 1-0\n\n\
+                   1. Nf3 Nc6 2. e4 e5 0-1\n\n\
+                   1. e4 e5 2. Ke3 *\n\n\
+                   [WhiteElo \"1800\"]\n\n1. e4 *\n";
+        let config = PgnAnalysisConfig {
+            max_batch_size: 3,
+            ..PgnAnalysisConfig::default()
+        };
+        let mut counting = Counting(Vec::new());
+        let games = analyze_pgn_file(&mut counting, pgn.as_bytes(), &config).unwrap();
+
+        assert_eq!(games.len(), 4);
+        assert!(matches!(&games[2], Err(Error::IllegalMove(m)) if m == "Ke3"));
+        // 5 + 5 + 2 positions, of which the start and the final position
+        // of the first two games repeat.
+        assert_eq!(counting.0, [3, 3, 3, 1]);
+
+        let first = games[0].as_ref().unwrap();
+        let alone =
+            GameAnalysis::analyze(&mut UniformEvaluator, first.game().clone(), 1500.0, 1500.0)
+                .unwrap();
+        assert_eq!(
+            format!("{:?}", first.evaluations()),
+            format!("{:?}", alone.evaluations())
+        );
+        assert_eq!(games[3].as_ref().unwrap().elos, (1800.0, 1500.0));
+    }
+
+    #[test]
+    fn move_match_profile_filters_moves() {
+        let games = [GameMoves::from_uci("e2e4 e7e5 g1f3 b8c6 f1b5 a7a6").unwrap()];
+        let config = MatchProfileConfig {
+            skip_plies: 2,
+            skip_forced: true,
+        };
+        let profile =
+            move_match_profile_with(&mut UniformEvaluator, &games, Color::White, &config).unwrap();
+
+        assert_eq!(profile.buckets.len(), ELO_BUCKETS.len());
+        // Only g1f3 and f1b5 remain. The uniform policy lists moves in
+        // generation order, so the counts are identical across buckets.
+        let first = &profile.buckets[0];
+        assert_eq!(first.moves, 2);
+        assert!(first.top3 >= first.top1);
+        assert!(profile.buckets.iter().all(|b| b.top1 == first.top1));
+    }
+
+    #[test]
+    fn likelihood_profiles_skip_and_flag_moves() {
+        let (initial, line) = puzzle(LADDER.0, LADDER.1);
+        let ladder = GameMoves {
+            initial,
+            moves: line
+                .iter()
+                .map(|m| m.to_uci(CastlingMode::Standard))
+                .collect(),
+        };
+        let opening = GameMoves::from_uci("e2e4 e7e5 g1f3 b8c6").unwrap();
+        let config = LikelihoodConfig {
+            skip_plies: 0,
+            // Forces the 20-move start position but not the 29 moves
+            // after 1. e4 e5.
+            only_move_threshold: 0.05,
+        };
+        let games = [
+            PlayerGame {
+                game: opening.clone(),
+                elo: 1500.0,
+                color: Color::White,
+            },
+            PlayerGame {
+                game: ladder,
+                elo: 1100.0,
+                color: Color::Black,
+            },
+        ];
+        let profiles = likelihood_profiles(&mut UniformEvaluator, &games, &config).unwrap();
+
+        let white = &profiles[0];
+        let plies: Vec<usize> = white.moves.iter().map(|m| m.ply).collect();
+        assert_eq!(plies, [0, 2]);
+        assert!(white.moves[0].forced && !white.moves[1].forced);
+        assert!((white.moves[1].log_probability + 29f64.ln()).abs() < 1e-6);
+        assert!(white.moves[1].rank.is_some());
+        assert_eq!(white.counted_moves(), 1);
+        assert_eq!(
+            white.mean_log_probability(),
+            Some(white.moves[1].log_probability)
+        );
+
+        // Black's only reply has a single legal move.
+        let black = &profiles[1];
+        assert_eq!((black.elo, black.color), (1100.0, Color::Black));
+        assert!(black.moves.is_empty());
+        assert_eq!(
+            (black.mean_log_probability(), black.top1_share()),
+            (None, None)
+        );
+
+        let alone = likelihood_profile_with(
+            &mut UniformEvaluator,
+            &opening,
+            1500.0,
+            Color::White,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(&alone, white);
+        let skipped = likelihood_profile(&mut UniformEvaluator, &opening, 1500.0, Color::White);
+        assert!(skipped.unwrap().moves.is_empty());
+    }
+
+    #[test]
+    fn games_are_compared_with_the_baseline() {
+        let profile = |moves: &[(f64, usize)]| LikelihoodProfile {
+            elo: 1500.0,
+            color: Color::White,
+            moves: moves
+                .iter()
+                .enumerate()
+                .map(|(ply, &(log_probability, rank))| MoveLikelihood {
+                    ply,
+                    uci: "e2e4".parse().unwrap(),
+                    log_probability,
+                    rank: Some(rank),
+                    forced: false,
+                })
+                .collect(),
+        };
+        let reference = [profile(&[(-1.0, 1)]), profile(&[(-3.0, 2)])];
+        let baseline = LikelihoodBaseline::from_profiles(&reference).unwrap();
+        assert_eq!((baseline.games, baseline.moves), (2, 2));
+        assert_eq!(baseline.mean_log_probability, -2.0);
+        assert_eq!(baseline.log_probability_sd, 1.0);
+        assert_eq!(baseline.top1_share, 0.5);
+
+        let comparison = profile(&[(-1.0, 1), (-1.0, 1)]).compare(&baseline);
+        assert_eq!(comparison.moves, 2);
+        assert!((comparison.log_probability_z.unwrap() - 2f64.sqrt()).abs() < 1e-9);
+        assert!((comparison.top1_z.unwrap() - 2f64.sqrt()).abs() < 1e-9);
+        assert_eq!(profile(&[]).compare(&baseline).top1_z, None);
+
+        let flat = LikelihoodBaseline::from_profiles(&reference[..1]).unwrap();
+        assert_eq!(profile(&[(-1.0, 1)]).compare(&flat).log_probability_z, None);
+        assert_eq!(LikelihoodBaseline::from_profiles(&[profile(&[])]), None);
+    }
+
+    #[test]
+    fn puzzle_difficulty_aggregates_the_line() {
+        let (pos, line) = puzzle(LADDER.0, LADDER.1);
+        let difficulty = puzzle_difficulty(&mut UniformEvaluator, &pos, &line).unwrap();
+
+        let bucket = &difficulty.buckets[0];
+        let first = 1.0 / pos.legal_moves().len() as f32;
+        assert_eq!(bucket.first_move_probability, first);
+        assert!(bucket.first_move_rank.is_some());
+        // The only defender reply is forced, so both solver moves weigh
+        // the same and the line is their product.
+        assert!(bucket.line_probability < first);
+        assert!(difficulty.score() > 0.5);
+
+        let (_, legal) = puzzle(LEGAL.0, LEGAL.1);
+        assert!(matches!(
+            puzzle_difficulty(&mut UniformEvaluator, &pos, &legal),
+            Err(Error::IllegalMove(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "ort")]
+    #[ignore = "requires local Maia3 ONNX model file"]
+    fn model_rates_classic_mates() {
+        let mut maia = crate::Maia::from_file("maia3_simplified.onnx").expect("load model");
+        for (fen, line) in [LADDER, LEGAL] {
+            let (pos, solution) = puzzle(fen, line);
+            let difficulty = puzzle_difficulty(&mut maia, &pos, &solution).expect("rate puzzle");
+            assert_eq!(difficulty.buckets.len(), ELO_BUCKETS.len());
+            assert!((0.0..=1.0).contains(&difficulty.score()));
+        }
+    }
+
+    #[test]
+    fn epd_suites_are_scored_per_bucket() {
+        /// [`UniformEvaluator`] ranking h5f7 and a2a3 first from 1500 on
+        /// and last below.
+        struct Preferring;
+
+        impl Evaluator for Preferring {
+            fn batch_evaluate(
+                &mut self,
+                setups: Vec<shakmaty::Setup>,
+                elo_selfs: &[f32],
+                elo_oppos: &[f32],
+            ) -> Result<Vec<EvaluationResult>, Error> {
+                let mut results = UniformEvaluator.batch_evaluate(setups, elo_selfs, elo_oppos)?;
+                for (result, &elo) in results.iter_mut().zip(elo_selfs) {
+                    result.policy.sort_by_key(|m| {
+                        let uci = m.uci.to_string();
+                        let rank = ["h5f7", "a2a3"].iter().position(|&p| p == uci);
+                        let rank = rank.unwrap_or(2);
+                        if elo < 1500.0 { 2 - rank } else { rank }
+                    });
+                }
+                Ok(results)
+            }
+        }
+
+        let suite = "\
+r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - bm Qxf7#; id \"mate\";
+not an epd line
+
+4k3/8/8/8/8/8/8/4K3 w - - id \"no opcodes\";
+rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - am a3; id \"semi; colon\";
+rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm Ke2;
+";
+        let report = run_epd_suite(&mut Preferring, suite, &[1100.0, 1900.0]).unwrap();
+
+        let skipped: Vec<usize> = report.skipped.iter().map(|s| s.line).collect();
+        assert_eq!(skipped, [2, 4, 6]);
+        assert!(report.skipped[2].reason.contains("Ke2"));
+
+        assert_eq!(report.positions.len(), 2);
+        let mate = &report.positions[0];
+        assert_eq!(mate.entry.id.as_deref(), Some("mate"));
+        assert_eq!(mate.entry.best_moves, ["h5f7".parse::<UciMove>().unwrap()]);
+        assert_eq!(mate.outcomes[1].best_move_rank, Some(1));
+        assert!(mate.outcomes[0].best_move_rank.unwrap() > 3);
+        assert_eq!(report.positions[1].entry.id.as_deref(), Some("semi; colon"));
+
+        let [low, high] = &report.buckets[..] else {
+            panic!("two buckets");
+        };
+        assert_eq!((low.best_move_positions, low.top1, low.top3), (1, 0, 0));
+        assert_eq!((high.top1, high.top3), (1, 1));
+        assert_eq!((low.avoid_move_positions, low.avoid_violations), (1, 0));
+        assert_eq!(high.violation_rate(), 1.0);
+    }
+
+    #[test]
+    fn sharpness_of_uniform_policies() {
+        let report = sharpness(&mut UniformEvaluator, &Chess::default()).unwrap();
+        assert!((report.entropy[0] - 20f32.log2()).abs() < 1e-4);
+        assert_eq!(report.mistake_mass[0], 0.0);
+        assert!(!report.top_move_changes);
+        assert_eq!(report.policy_shift, 0.0);
+
+        // With a mate on the board every other move is a mistake.
+        let (pos, _) = puzzle("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1", "d1d8");
+        let report = sharpness(&mut UniformEvaluator, &pos).unwrap();
+        let n = pos.legal_moves().len() as f32;
+        assert!((report.mistake_mass[5] - (n - 1.0) / n).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,748 @@
+//! Inference backends that run the Maia3 network.
+//!
+//! [`Maia`](crate::Maia) owns the pre- and postprocessing and hands the
+//! encoded batch to an [`InferenceBackend`], which only has to execute the
+//! graph. The ONNX Runtime backend is the default; other runtimes (or test
+//! doubles) plug in by implementing the trait.
+
+use std::time::Instant;
+#[cfg(feature = "ort")]
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+};
+
+use ndarray::{Array2, Array3};
+#[cfg(feature = "ort")]
+use ort::{
+    memory::Allocator,
+    session::{
+        IoBinding, OutputSelector, RunOptions, SelectedOutputMarker, Session, SessionOutputs,
+    },
+    value::{DynTensor, DynValue, Outlet, Tensor, TensorElementType},
+};
+
+#[cfg(feature = "ort")]
+use crate::registry::ModelSource;
+use crate::{error::Error, postprocess::OutputSelection};
+
+/// Raw outputs of one forward pass over a batch. A head left out by
+/// [`InferenceBackend::select_outputs`] may be empty.
+#[derive(Debug, Clone)]
+pub struct ModelOutputs {
+    /// Policy logits over the move vocabulary, shape `[B, vocabulary]`.
+    pub logits_move: Array2<f32>,
+    /// Loss/draw/win logits from the side to move, shape `[B, 3]`.
+    pub logits_value: Array2<f32>,
+}
+
+/// A runtime capable of executing the Maia3 graph.
+pub trait InferenceBackend {
+    /// Run the network on a batch.
+    ///
+    /// `tokens` has shape `[B, 64, 12]` (see [`preprocess`](crate::preprocess))
+    /// and `elo_self`/`elo_oppo` hold `B` raw ratings each.
+    fn run(
+        &mut self,
+        tokens: Array3<f32>,
+        elo_self: &[f32],
+        elo_oppo: &[f32],
+    ) -> Result<ModelOutputs, Error>;
+
+    /// [`run`](Self::run), giving up once `deadline` passes.
+    ///
+    /// The provided implementation cannot interrupt a run and returns its
+    /// outputs however late; [`Maia`](crate::Maia) then reports the
+    /// overrun. Backends able to abort a run in progress override this
+    /// and return [`Error::Timeout`] when they do.
+    fn run_until(
+        &mut self,
+        tokens: Array3<f32>,
+        elo_self: &[f32],
+        elo_oppo: &[f32],
+        _deadline: Instant,
+    ) -> Result<ModelOutputs, Error> {
+        self.run(tokens, elo_self, elo_oppo)
+    }
+
+    /// Compute only the heads `outputs` selects on the following runs.
+    ///
+    /// [`Maia`](crate::Maia) calls this before every run with its
+    /// [`EvalOptions::outputs`](crate::EvalOptions::outputs) and does
+    /// not read the other head, so the provided implementation, which
+    /// keeps computing both, is always correct.
+    fn select_outputs(&mut self, _outputs: OutputSelection) {}
+}
+
+impl<B: InferenceBackend + ?Sized> InferenceBackend for Box<B> {
+    fn run(
+        &mut self,
+        tokens: Array3<f32>,
+        elo_self: &[f32],
+        elo_oppo: &[f32],
+    ) -> Result<ModelOutputs, Error> {
+        (**self).run(tokens, elo_self, elo_oppo)
+    }
+
+    fn run_until(
+        &mut self,
+        tokens: Array3<f32>,
+        elo_self: &[f32],
+        elo_oppo: &[f32],
+        deadline: Instant,
+    ) -> Result<ModelOutputs, Error> {
+        (**self).run_until(tokens, elo_self, elo_oppo, deadline)
+    }
+
+    fn select_outputs(&mut self, outputs: OutputSelection) {
+        (**self).select_outputs(outputs);
+    }
+}
+
+/// Whether `err` is a backend failing to allocate memory for a run.
+///
+/// ONNX Runtime reports allocation failures without a dedicated error
+/// code, so its errors are matched on the messages of the CPU arena,
+/// CUDA and ROCm.
+pub(crate) fn is_out_of_memory(err: &Error) -> bool {
+    if let Error::OutOfMemory { .. } = err {
+        return true;
+    }
+    #[cfg(feature = "ort")]
+    if let Error::OrtError(err) = err {
+        let message = err.message().to_ascii_lowercase();
+        return [
+            "out of memory",
+            "failed to allocate",
+            "bad_alloc",
+            "alloc_failed",
+            "cudaerrormemoryallocation",
+            "hiperroroutofmemory",
+        ]
+        .iter()
+        .any(|pattern| message.contains(pattern));
+    }
+    false
+}
+
+/// Backend used by [`Maia`](crate::Maia) when none is named: ONNX
+/// Runtime with the `ort` feature, otherwise any boxed backend.
+#[cfg(feature = "ort")]
+pub type DefaultBackend = OrtBackend;
+/// Backend used by [`Maia`](crate::Maia) when none is named: ONNX
+/// Runtime with the `ort` feature, otherwise any boxed backend.
+#[cfg(not(feature = "ort"))]
+pub type DefaultBackend = Box<dyn InferenceBackend + Send>;
+
+/// ONNX Runtime backend wrapping a [`Session`] loaded with Maia3.
+#[cfg(feature = "ort")]
+pub struct OrtBackend {
+    pub(crate) session: Session,
+    io_binding: Option<BindingCache>,
+    /// Element types the model declares for its inputs.
+    input_types: Vec<(String, TensorElementType)>,
+    /// Every input and output is f32, as the bound buffers are.
+    f32_io: bool,
+    /// Allocator of the bound inputs; declared after `io_binding` so it
+    /// outlives the buffers.
+    input_allocator: Allocator,
+    /// What the model was loaded from.
+    source: ModelSource,
+    /// Heads requested from the session, see
+    /// [`InferenceBackend::select_outputs`].
+    outputs: OutputSelection,
+}
+
+/// Bound buffers per padded batch size, see
+/// [`OrtBackend::with_io_binding`].
+#[cfg(feature = "ort")]
+struct BindingCache {
+    max_batch: usize,
+    buffers: HashMap<usize, BoundBuffers>,
+}
+
+/// Input tensors and an [`IoBinding`] with pre-allocated outputs for one
+/// batch size.
+#[cfg(feature = "ort")]
+struct BoundBuffers {
+    binding: IoBinding,
+    tokens: Tensor<f32>,
+    elo_self: Tensor<f32>,
+    elo_oppo: Tensor<f32>,
+}
+
+#[cfg(feature = "ort")]
+impl BoundBuffers {
+    /// Buffers for `batch` positions, with the inputs allocated by
+    /// `input_allocator`.
+    fn new(session: &Session, batch: usize, input_allocator: &Allocator) -> Result<Self, Error> {
+        let allocator = Allocator::default();
+        let vocabulary = crate::moves::ALL_MOVES.len();
+        let mut binding = session.create_binding()?;
+        binding.bind_output(
+            "logits_move",
+            Tensor::<f32>::new(&allocator, [batch, vocabulary])?,
+        )?;
+        binding.bind_output("logits_value", Tensor::<f32>::new(&allocator, [batch, 3])?)?;
+        Ok(Self {
+            binding,
+            tokens: Tensor::new(input_allocator, [batch, 64, 12])?,
+            elo_self: Tensor::new(input_allocator, [batch])?,
+            elo_oppo: Tensor::new(input_allocator, [batch])?,
+        })
+    }
+}
+
+/// Batch size a call with `n` positions is padded to: the next power of
+/// two, capped at `max_batch`.
+#[cfg(feature = "ort")]
+fn padded_batch(n: usize, max_batch: usize) -> usize {
+    n.next_power_of_two().min(max_batch)
+}
+
+/// Copy `values` to the front of `dst` and fill the rest with `pad`.
+#[cfg(feature = "ort")]
+fn fill_padded<'a>(dst: &mut [f32], values: impl IntoIterator<Item = &'a f32>, pad: f32) {
+    let mut dst = dst.iter_mut();
+    for (v, d) in values.into_iter().zip(dst.by_ref()) {
+        *d = *v;
+    }
+    dst.for_each(|d| *d = pad);
+}
+
+#[cfg(feature = "ort")]
+impl OrtBackend {
+    /// Wrap an existing session.
+    ///
+    /// Inputs and outputs are converted from and to `f32` at the boundary
+    /// according to the element types the model declares, so
+    /// half-precision exports and quantized exports with float16, float64
+    /// or integer inputs work unchanged. Integer inputs are rounded.
+    /// Other element types fail at run time with
+    /// [`Error::UnsupportedElementType`].
+    pub fn new(session: Session) -> Self {
+        let input_types = session
+            .inputs()
+            .iter()
+            .filter_map(|outlet| Some((outlet.name().to_string(), outlet.dtype().tensor_type()?)))
+            .collect();
+        let f32_io = session
+            .inputs()
+            .iter()
+            .chain(session.outputs())
+            .all(|outlet| outlet.dtype().tensor_type() == Some(TensorElementType::Float32));
+        Self {
+            session,
+            io_binding: None,
+            input_types,
+            f32_io,
+            input_allocator: Allocator::default(),
+            source: ModelSource::Unknown,
+            outputs: OutputSelection::Both,
+        }
+    }
+
+    /// Record what the session was loaded from.
+    pub(crate) fn with_source(mut self, source: ModelSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub(crate) fn source(&self) -> &ModelSource {
+        &self.source
+    }
+
+    /// Whether any input or output of the model is float16.
+    pub fn is_half_precision(&self) -> bool {
+        let session = &self.session;
+        session
+            .inputs()
+            .iter()
+            .chain(session.outputs())
+            .any(is_float16)
+    }
+
+    /// The three model inputs for a batch, in `tokens`, `elo_self`,
+    /// `elo_oppo` order, each in the element type the model declares.
+    pub(crate) fn inputs(
+        &self,
+        tokens: Array3<f32>,
+        elo_self: &[f32],
+        elo_oppo: &[f32],
+    ) -> Result<[DynValue; 3], Error> {
+        let batch_size = elo_self.len();
+        let tensor = |name: &str, shape: Vec<usize>, values: Vec<f32>| {
+            let dtype = self
+                .input_types
+                .iter()
+                .find(|(n, _)| n == name)
+                .map_or(TensorElementType::Float32, |&(_, dtype)| dtype);
+            input_tensor(name, dtype, shape, values)
+        };
+        let shape = tokens.shape().to_vec();
+        Ok([
+            tensor("tokens", shape, tokens.into_iter().collect())?,
+            tensor("elo_self", vec![batch_size], elo_self.to_vec())?,
+            tensor("elo_oppo", vec![batch_size], elo_oppo.to_vec())?,
+        ])
+    }
+
+    /// Run batches of up to `max_batch` positions through pre-allocated,
+    /// bound input and output buffers instead of allocating tensors on
+    /// every call.
+    ///
+    /// Batches are padded to the next power of two (at most `max_batch`)
+    /// and one set of buffers is kept per padded size, so memory stays
+    /// within about twice that of a single `max_batch` call. Larger
+    /// batches take the regular path.
+    pub fn with_io_binding(mut self, max_batch: usize) -> Self {
+        self.io_binding = Some(BindingCache {
+            max_batch,
+            buffers: HashMap::new(),
+        });
+        self
+    }
+
+    /// Allocate the bound inputs of [`with_io_binding`](Self::with_io_binding)
+    /// in page-locked host memory of CUDA device `device_id`, so the
+    /// host-to-device copy runs at full speed and can overlap.
+    ///
+    /// Only affects the IO-binding path.
+    ///
+    /// # Errors
+    /// Returns an [`Error::OrtError`] if the session cannot allocate
+    /// pinned memory, e.g. because it does not run on the CUDA execution
+    /// provider.
+    #[cfg(feature = "cuda")]
+    pub fn with_pinned_inputs(mut self, device_id: i32) -> Result<Self, Error> {
+        use ort::memory::{AllocationDevice, AllocatorType, MemoryInfo, MemoryType};
+        let info = MemoryInfo::new(
+            AllocationDevice::CUDA_PINNED,
+            device_id,
+            AllocatorType::Device,
+            MemoryType::CPUInput,
+        )?;
+        self.input_allocator = Allocator::new(&self.session, info)?;
+        // Buffers allocated so far live in pageable memory.
+        if let Some(cache) = &mut self.io_binding {
+            cache.buffers.clear();
+        }
+        Ok(self)
+    }
+
+    /// The underlying ONNX Runtime session.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Mutable access to the underlying session.
+    pub fn session_mut(&mut self) -> &mut Session {
+        &mut self.session
+    }
+}
+
+#[cfg(feature = "ort")]
+impl InferenceBackend for OrtBackend {
+    fn run(
+        &mut self,
+        tokens: Array3<f32>,
+        elo_self: &[f32],
+        elo_oppo: &[f32],
+    ) -> Result<ModelOutputs, Error> {
+        match self.output_selector() {
+            None => self.execute(tokens, elo_self, elo_oppo, None),
+            Some(selector) => {
+                let options = RunOptions::new()?.with_outputs(selector);
+                self.execute_unbound(tokens, elo_self, elo_oppo, Some(&options))
+            }
+        }
+    }
+
+    /// Runs under fresh [`RunOptions`] that a watchdog thread terminates
+    /// at `deadline`, see [`with_deadline`].
+    fn run_until(
+        &mut self,
+        tokens: Array3<f32>,
+        elo_self: &[f32],
+        elo_oppo: &[f32],
+        deadline: Instant,
+    ) -> Result<ModelOutputs, Error> {
+        // Options with selected outputs cannot be terminated from the
+        // watchdog thread, so runs with a deadline compute both heads.
+        let options = RunOptions::new()?;
+        with_deadline(&options, deadline, elo_self.len(), || {
+            self.execute(tokens, elo_self, elo_oppo, Some(&options))
+        })
+    }
+
+    /// Asks the session for the selected outputs by name, except on the
+    /// IO-binding path, whose bound outputs are always both heads, and
+    /// for runs with a deadline.
+    fn select_outputs(&mut self, outputs: OutputSelection) {
+        self.outputs = outputs;
+    }
+}
+
+#[cfg(feature = "ort")]
+impl OrtBackend {
+    /// The outputs to request when not both heads are selected.
+    fn output_selector(&self) -> Option<OutputSelector> {
+        let name = match self.outputs {
+            OutputSelection::Both => return None,
+            OutputSelection::PolicyOnly => "logits_move",
+            OutputSelection::ValueOnly => "logits_value",
+        };
+        Some(OutputSelector::no_default().with(name))
+    }
+
+    /// One forward pass, under `options` if given, through the bound
+    /// buffers when IO binding applies.
+    fn execute(
+        &mut self,
+        tokens: Array3<f32>,
+        elo_self: &[f32],
+        elo_oppo: &[f32],
+        options: Option<&RunOptions>,
+    ) -> Result<ModelOutputs, Error> {
+        let batch_size = elo_self.len();
+        // Bound buffers are f32; models with other element types take
+        // the regular path.
+        if let Some(cache) = &mut self.io_binding
+            && self.f32_io
+            && batch_size > 0
+            && batch_size <= cache.max_batch
+        {
+            let padded = padded_batch(batch_size, cache.max_batch);
+            let buffers = match cache.buffers.entry(padded) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    let allocator = &self.input_allocator;
+                    entry.insert(BoundBuffers::new(&self.session, padded, allocator)?)
+                }
+            };
+            // Padding rows repeat the last rating so strict models see
+            // valid inputs; their outputs are dropped.
+            let pad_self = elo_self[batch_size - 1];
+            let pad_oppo = elo_oppo[batch_size - 1];
+            fill_padded(buffers.tokens.extract_tensor_mut().1, tokens.iter(), 0.0);
+            fill_padded(buffers.elo_self.extract_tensor_mut().1, elo_self, pad_self);
+            fill_padded(buffers.elo_oppo.extract_tensor_mut().1, elo_oppo, pad_oppo);
+            buffers.binding.bind_input("tokens", &buffers.tokens)?;
+            buffers.binding.bind_input("elo_self", &buffers.elo_self)?;
+            buffers.binding.bind_input("elo_oppo", &buffers.elo_oppo)?;
+
+            let outputs = match options {
+                Some(options) => self
+                    .session
+                    .run_binding_with_options(&buffers.binding, options)?,
+                None => self.session.run_binding(&buffers.binding)?,
+            };
+            return extract_rows(&outputs, batch_size, OutputSelection::Both);
+        }
+        self.execute_unbound(tokens, elo_self, elo_oppo, options)
+    }
+
+    /// One forward pass without IO binding, under `options` if given,
+    /// reading the heads [`select_outputs`](InferenceBackend::select_outputs)
+    /// asked for.
+    fn execute_unbound<O: SelectedOutputMarker>(
+        &mut self,
+        tokens: Array3<f32>,
+        elo_self: &[f32],
+        elo_oppo: &[f32],
+        options: Option<&RunOptions<O>>,
+    ) -> Result<ModelOutputs, Error> {
+        let [tokens, elo_self, elo_oppo] = self.inputs(tokens, elo_self, elo_oppo)?;
+        let inputs = ort::inputs! {
+            "tokens" => tokens,
+            "elo_self" => elo_self,
+            "elo_oppo" => elo_oppo,
+        };
+        let outputs = match options {
+            Some(options) => self.session.run_with_options(inputs, options)?,
+            None => self.session.run(inputs)?,
+        };
+
+        extract_rows(&outputs, usize::MAX, self.outputs)
+    }
+}
+
+/// Call `run`, terminating the ONNX Runtime runs under `options` once
+/// `deadline` passes.
+///
+/// A watchdog thread waits for `run` to return or for the deadline,
+/// whichever comes first. A terminated run fails inside ONNX Runtime and
+/// is reported as [`Error::Timeout`] of a batch of `total` positions;
+/// `options` are reset afterwards so they can be reused.
+#[cfg(feature = "ort")]
+pub(crate) fn with_deadline<T>(
+    options: &RunOptions,
+    deadline: Instant,
+    total: usize,
+    run: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let fired = AtomicBool::new(false);
+    let (done, finished) = mpsc::channel::<()>();
+    let result = thread::scope(|scope| {
+        let fired = &fired;
+        scope.spawn(move || {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(wait) {
+                fired.store(true, Ordering::Relaxed);
+                // Should termination fail, the run completes and the
+                // caller still sees the overrun.
+                let _ = options.terminate();
+            }
+        });
+        let result = run();
+        drop(done);
+        result
+    });
+    if fired.load(Ordering::Relaxed) {
+        options.unterminate()?;
+        return Err(Error::Timeout {
+            completed: 0,
+            total,
+        });
+    }
+    result
+}
+
+/// Copy the two Maia3 output heads out of a session result.
+#[cfg(feature = "ort")]
+pub(crate) fn extract_outputs(outputs: &SessionOutputs) -> Result<ModelOutputs, Error> {
+    extract_rows(outputs, usize::MAX, OutputSelection::Both)
+}
+
+/// Copy the first `rows` rows of the output heads `selection` reads; the
+/// other head is left empty.
+#[cfg(feature = "ort")]
+fn extract_rows(
+    outputs: &SessionOutputs,
+    rows: usize,
+    selection: OutputSelection,
+) -> Result<ModelOutputs, Error> {
+    let head = |name: &str, read: bool| -> Result<Array2<f32>, Error> {
+        if !read {
+            return Ok(Array2::zeros((0, 0)));
+        }
+        let value = outputs.get(name).ok_or_else(|| Error::MissingOutput {
+            name: name.to_string(),
+        })?;
+        let array = match *value.data_type() {
+            TensorElementType::Float32 => value.try_extract_array::<f32>()?.to_owned(),
+            TensorElementType::Float16 => half_array(value)?,
+            TensorElementType::Float64 => value.try_extract_array::<f64>()?.mapv(|v| v as f32),
+            dtype => {
+                return Err(Error::UnsupportedElementType {
+                    name: name.to_string(),
+                    dtype,
+                });
+            }
+        };
+        let array = array.into_dimensionality::<ndarray::Ix2>()?;
+        let rows = rows.min(array.nrows());
+        Ok(array.slice_move(ndarray::s![..rows, ..]))
+    };
+    let logits_move = head("logits_move", selection.policy())?;
+    let logits_value = head("logits_value", selection.value())?;
+
+    Ok(ModelOutputs {
+        logits_move,
+        logits_value,
+    })
+}
+
+/// Whether `outlet` is a float16 tensor.
+#[cfg(feature = "ort")]
+fn is_float16(outlet: &Outlet) -> bool {
+    outlet.dtype().tensor_type() == Some(TensorElementType::Float16)
+}
+
+/// An input tensor of element type `dtype` holding `values`.
+#[cfg(feature = "ort")]
+fn input_tensor(
+    name: &str,
+    dtype: TensorElementType,
+    shape: Vec<usize>,
+    values: Vec<f32>,
+) -> Result<DynValue, Error> {
+    let value = match dtype {
+        TensorElementType::Float32 => Tensor::from_array((shape, values))?.into_dyn(),
+        TensorElementType::Float16 => half_tensor(shape, &values)?,
+        TensorElementType::Float64 => {
+            let values: Vec<f64> = values.into_iter().map(f64::from).collect();
+            Tensor::from_array((shape, values))?.into_dyn()
+        }
+        TensorElementType::Int64 => {
+            let values: Vec<i64> = values.into_iter().map(|v| v.round() as i64).collect();
+            Tensor::from_array((shape, values))?.into_dyn()
+        }
+        TensorElementType::Int32 => {
+            let values: Vec<i32> = values.into_iter().map(|v| v.round() as i32).collect();
+            Tensor::from_array((shape, values))?.into_dyn()
+        }
+        dtype => {
+            return Err(Error::UnsupportedElementType {
+                name: name.to_string(),
+                dtype,
+            });
+        }
+    };
+    Ok(value)
+}
+
+/// A CPU float16 tensor holding `values`.
+#[cfg(feature = "ort")]
+fn half_tensor(shape: Vec<usize>, values: &[f32]) -> Result<DynValue, Error> {
+    let mut tensor = DynTensor::new(&Allocator::default(), TensorElementType::Float16, shape)?;
+    // SAFETY: the tensor was just allocated on the CPU with one 2-byte
+    // element per value, and nothing else references it yet.
+    let data = unsafe {
+        std::slice::from_raw_parts_mut(tensor.data_ptr_mut().cast::<u16>(), values.len())
+    };
+    for (d, v) in data.iter_mut().zip(values) {
+        *d = f32_to_f16(*v);
+    }
+    Ok(tensor.into_dyn())
+}
+
+/// Convert a CPU float16 output to `f32`.
+#[cfg(feature = "ort")]
+fn half_array(value: &DynValue) -> Result<ndarray::ArrayD<f32>, Error> {
+    let shape: Vec<usize> = value.shape().iter().map(|&d| d as usize).collect();
+    let len = shape.iter().product();
+    // SAFETY: float16 tensors store 2-byte elements, `len` of them for
+    // this shape, and session outputs live in CPU memory.
+    let data = unsafe { std::slice::from_raw_parts(value.data_ptr().cast::<u16>(), len) };
+    let values = data.iter().map(|&bits| f16_to_f32(bits)).collect();
+    Ok(ndarray::ArrayD::from_shape_vec(shape, values)?)
+}
+
+/// IEEE 754 binary16 bits of `value`, rounding to nearest even.
+#[cfg(feature = "ort")]
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        // Infinity stays infinity, NaN stays a (quiet) NaN.
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    let (half, shift, mantissa) = if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        // Subnormal: shift the implicit leading bit in.
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        (mantissa >> shift, shift, mantissa)
+    } else {
+        (((exponent as u32) << 10) | (mantissa >> 13), 13, mantissa)
+    };
+    let rest = mantissa & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    let round_up = rest > halfway || (rest == halfway && half & 1 == 1);
+    // A carry out of the mantissa correctly bumps the exponent.
+    sign | (half + u32::from(round_up)) as u16
+}
+
+/// `f32` value of IEEE 754 binary16 `bits`.
+#[cfg(feature = "ort")]
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = u32::from(bits & 0x8000) << 16;
+    let exponent = u32::from((bits >> 10) & 0x1f);
+    let mantissa = u32::from(bits & 0x3ff);
+    match exponent {
+        0 => {
+            let magnitude = mantissa as f32 / (1 << 24) as f32;
+            if sign == 0 { magnitude } else { -magnitude }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
+
+#[cfg(all(test, feature = "ort"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_pad_to_powers_of_two() {
+        assert_eq!(padded_batch(1, 64), 1);
+        assert_eq!(padded_batch(3, 64), 4);
+        assert_eq!(padded_batch(33, 64), 64);
+        assert_eq!(padded_batch(40, 48), 48);
+
+        let mut buffer = [9.0; 4];
+        fill_padded(&mut buffer, &[1.0, 2.0], 5.0);
+        assert_eq!(buffer, [1.0, 2.0, 5.0, 5.0]);
+    }
+
+    #[test]
+    fn half_precision_conversions() {
+        for (value, bits) in [
+            (1.0, 0x3c00),
+            (-2.0, 0xc000),
+            (65504.0, 0x7bff),
+            (0.0, 0x0000),
+            (f32::INFINITY, 0x7c00),
+        ] {
+            assert_eq!(f32_to_f16(value), bits, "{value}");
+            assert_eq!(f16_to_f32(bits), value);
+        }
+        assert_eq!(f32_to_f16(0.1), 0x2e66);
+        assert_eq!(f16_to_f32(0x2e66), 0.099975586);
+        // Overflow, subnormals and underflow.
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert_eq!(f32_to_f16(1e-7), 0x0002);
+        assert_eq!(f32_to_f16(1e-9), 0x0000);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+
+        // Every finite half survives the round trip.
+        for bits in (0..=u16::MAX).filter(|b| b & 0x7c00 != 0x7c00) {
+            assert_eq!(f32_to_f16(f16_to_f32(bits)), bits, "{bits:#06x}");
+        }
+    }
+
+    #[test]
+    #[ignore = "requires local Maia3 ONNX model file"]
+    fn terminated_runs_time_out() {
+        let session = Session::builder()
+            .unwrap()
+            .commit_from_file("maia3_simplified.onnx")
+            .expect("load model");
+        let mut backend = OrtBackend::new(session);
+        let tokens = Array3::zeros((1, 64, 12));
+
+        let err = backend
+            .run_until(tokens.clone(), &[1500.0], &[1500.0], Instant::now())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Timeout {
+                completed: 0,
+                total: 1
+            }
+        ));
+
+        // The backend is usable again once a run was terminated.
+        let later = Instant::now() + std::time::Duration::from_secs(60);
+        assert!(
+            backend
+                .run_until(tokens, &[1500.0], &[1500.0], later)
+                .is_ok()
+        );
+    }
+}
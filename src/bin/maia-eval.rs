@@ -0,0 +1,356 @@
+//! Evaluate FEN strings read from stdin, one per line, and print one JSON
+//! object per non-blank input line; blank lines are skipped.
+//!
+//! ```text
+//! echo "<fen>" | maia-eval --elo 1500 --top-k 3
+//! ```
+//!
+//! Input is batched internally; lines that fail to parse are reported as
+//! `{"fen": ..., "error": ...}` objects in place and make the process exit
+//! with a non-zero status once all input has been processed. With
+//! `--summary`, the results are aggregated as they come into one
+//! [`BatchSummary`](maia_rust::stats::BatchSummary) object printed at
+//! the end, and only failed lines are printed in place.
+
+use std::{
+    io::{self, BufRead, Write},
+    process::ExitCode,
+    str::FromStr,
+};
+
+use maia_rust::{
+    EvaluationResult, Evaluator, Maia,
+    shakmaty::{CastlingMode, Chess, Setup, fen::Fen},
+    stats::BatchSummaryBuilder,
+};
+use serde_json::{Value, json};
+
+const USAGE: &str = "\
+usage: maia-eval [options] < fens.txt
+
+options:
+  --model <path>       ONNX model file, or a URL to download it from with the
+                       download feature (default: maia3_simplified.onnx)
+  --elo <elo>          set both --elo-self and --elo-oppo
+  --elo-self <elo>     rating of the side to move (default: 1500)
+  --elo-oppo <elo>     rating of the opponent (default: 1500)
+  --batch-size <n>     positions per inference call (default: 256)
+  --top-k <n>          moves to include per position (default: 5)
+  --summary            print one summary of all positions instead of their results";
+
+struct Args {
+    model: String,
+    elo_self: f32,
+    elo_oppo: f32,
+    batch_size: usize,
+    top_k: usize,
+    summary: bool,
+}
+
+impl Args {
+    /// Parse the options in `iter`, the command line without the program
+    /// name.
+    fn parse(iter: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = Args {
+            model: "maia3_simplified.onnx".to_string(),
+            elo_self: 1500.0,
+            elo_oppo: 1500.0,
+            batch_size: 256,
+            top_k: 5,
+            summary: false,
+        };
+
+        let mut iter = iter.into_iter();
+        while let Some(flag) = iter.next() {
+            if flag == "-h" || flag == "--help" {
+                return Err(USAGE.to_string());
+            }
+            if flag == "--summary" {
+                args.summary = true;
+                continue;
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("missing value for {flag}\n\n{USAGE}"))?;
+
+            match flag.as_str() {
+                "--model" => args.model = value,
+                "--elo" => {
+                    args.elo_self = parse_value(&flag, &value)?;
+                    args.elo_oppo = args.elo_self;
+                }
+                "--elo-self" => args.elo_self = parse_value(&flag, &value)?,
+                "--elo-oppo" => args.elo_oppo = parse_value(&flag, &value)?,
+                "--batch-size" => args.batch_size = parse_value::<usize>(&flag, &value)?.max(1),
+                "--top-k" => args.top_k = parse_value(&flag, &value)?,
+                _ => return Err(format!("unknown option {flag}\n\n{USAGE}")),
+            }
+        }
+        Ok(args)
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {flag}: {value}"))
+}
+
+/// Parse and validate a single input line.
+fn parse_line(line: &str) -> Result<Setup, String> {
+    let setup = line.parse::<Fen>().map_err(|e| e.to_string())?.into_setup();
+    // Validate up front so one bad position doesn't fail the whole batch.
+    setup
+        .clone()
+        .position::<Chess>(CastlingMode::Standard)
+        .map_err(|e| e.to_string())?;
+    Ok(setup)
+}
+
+fn result_json(fen: &str, result: &EvaluationResult, top_k: usize) -> Value {
+    let policy: Vec<Value> = result
+        .policy
+        .iter()
+        .take(top_k)
+        .map(|m| json!({ "uci": m.uci.to_string(), "probability": m.probability }))
+        .collect();
+
+    json!({
+        "fen": fen,
+        "white_wr": result.white_wr,
+        "draw": result.draw,
+        "black_wr": result.black_wr,
+        "policy": policy,
+    })
+}
+
+/// Evaluate one chunk of input lines, returning one JSON value per line,
+/// or with `--summary` one per failed line after adding the results to
+/// `summary`.
+fn evaluate_chunk(
+    evaluator: &mut impl Evaluator,
+    args: &Args,
+    lines: &[String],
+    summary: &mut BatchSummaryBuilder,
+) -> (Vec<Value>, bool) {
+    let parsed: Vec<Result<Setup, String>> = lines.iter().map(|l| parse_line(l)).collect();
+    let setups: Vec<Setup> = parsed.iter().filter_map(|p| p.clone().ok()).collect();
+    let n = setups.len();
+
+    // A chunk without a single valid line needs no inference.
+    let results: Vec<Result<EvaluationResult, String>> = if n == 0 {
+        Vec::new()
+    } else {
+        match evaluator.batch_evaluate(setups, &vec![args.elo_self; n], &vec![args.elo_oppo; n]) {
+            Ok(results) => results.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err.to_string()); n],
+        }
+    };
+    let mut results = results.into_iter();
+
+    let mut failed = false;
+    let values = lines
+        .iter()
+        .zip(parsed)
+        .filter_map(|(line, parsed)| {
+            let outcome = parsed.and_then(|_| results.next().unwrap());
+            match outcome {
+                Ok(result) if args.summary => {
+                    summary.add(&result);
+                    None
+                }
+                Ok(result) => Some(result_json(line, &result, args.top_k)),
+                Err(error) => {
+                    failed = true;
+                    Some(json!({ "fen": line, "error": error }))
+                }
+            }
+        })
+        .collect();
+    (values, failed)
+}
+
+/// Load `model`, which with the `download` feature may be an `http(s)`
+/// URL; downloads show their progress on a terminal.
+fn load_model(model: &str) -> Result<Maia, maia_rust::Error> {
+    #[cfg(feature = "download")]
+    if model.starts_with("https://") || model.starts_with("http://") {
+        return Maia::from_url_with_progress(
+            model,
+            maia_rust::CacheConfig::default(),
+            maia_rust::stderr_progress(),
+        );
+    }
+    Maia::from_file(model)
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{msg}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut maia = match load_model(&args.model) {
+        Ok(maia) => maia,
+        Err(err) => {
+            eprintln!("failed to load {}: {err}", args.model);
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut any_failed = false;
+    let mut summary = BatchSummaryBuilder::new();
+    let mut chunk = Vec::with_capacity(args.batch_size);
+    let mut stdout = io::stdout().lock();
+    let mut flush = |chunk: &mut Vec<String>| -> io::Result<()> {
+        let (values, failed) = evaluate_chunk(&mut maia, &args, chunk, &mut summary);
+        any_failed |= failed;
+        for value in values {
+            writeln!(stdout, "{value}")?;
+        }
+        chunk.clear();
+        stdout.flush()
+    };
+
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("failed to read input: {err}");
+                return ExitCode::from(2);
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        chunk.push(line.trim().to_string());
+        if chunk.len() == args.batch_size && flush(&mut chunk).is_err() {
+            return ExitCode::FAILURE;
+        }
+    }
+    if !chunk.is_empty() && flush(&mut chunk).is_err() {
+        return ExitCode::FAILURE;
+    }
+    if args.summary {
+        let summary = serde_json::to_value(summary.build()).expect("BatchSummary serializes");
+        if writeln!(io::stdout(), "{summary}").is_err() {
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if any_failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maia_rust::testing::{ScriptedEvaluator, ZeroBackend};
+
+    use super::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    fn args(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let parsed = args(&[
+            "--elo",
+            "1800",
+            "--elo-oppo",
+            "1200",
+            "--summary",
+            "--top-k",
+            "2",
+        ]);
+        let parsed = parsed.unwrap();
+        assert_eq!((parsed.elo_self, parsed.elo_oppo), (1800.0, 1200.0));
+        assert_eq!((parsed.top_k, parsed.batch_size), (2, 256));
+        assert!(parsed.summary);
+        assert_eq!(args(&["--batch-size", "0"]).unwrap().batch_size, 1);
+
+        assert_eq!(
+            args(&["--top-k", "many"]).err().unwrap(),
+            "invalid value for --top-k: many"
+        );
+        assert!(
+            args(&["--elo"])
+                .err()
+                .unwrap()
+                .starts_with("missing value for --elo")
+        );
+        assert!(
+            args(&["--depth", "3"])
+                .err()
+                .unwrap()
+                .starts_with("unknown option --depth")
+        );
+        assert_eq!(args(&["--help"]).err().unwrap(), USAGE);
+    }
+
+    #[test]
+    fn chunks_report_results_and_errors_in_place() {
+        let parsed = args(&["--top-k", "2"]).unwrap();
+        let lines = [
+            START.to_string(),
+            "not a fen".to_string(),
+            START.to_string(),
+        ];
+        let mut summary = BatchSummaryBuilder::new();
+        let (values, failed) = evaluate_chunk(
+            &mut ScriptedEvaluator(vec![0.75]),
+            &parsed,
+            &lines,
+            &mut summary,
+        );
+        assert!(failed);
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0]["white_wr"], 0.75);
+        assert_eq!(values[0]["policy"].as_array().unwrap().len(), 2);
+        assert_eq!(values[1]["fen"], "not a fen");
+        assert!(values[1]["error"].is_string());
+        assert_eq!(values[2], values[0]);
+        assert_eq!(summary.positions(), 0);
+
+        // With --summary only the failed line is printed.
+        let parsed = args(&["--summary"]).unwrap();
+        let (values, failed) = evaluate_chunk(
+            &mut ScriptedEvaluator(vec![0.75]),
+            &parsed,
+            &lines,
+            &mut summary,
+        );
+        assert!(failed);
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0]["fen"], "not a fen");
+        assert_eq!(summary.build().mean_value, 0.75);
+        assert_eq!(summary.positions(), 2);
+    }
+
+    #[test]
+    fn chunks_without_valid_lines_skip_inference() {
+        let parsed = args(&[]).unwrap();
+        let lines = [
+            "garbage".to_string(),
+            "8/8/8/8/8/8/8/8 w - - 0 1".to_string(),
+        ];
+        let backend = ZeroBackend::default();
+        let mut maia = Maia::from_backend(backend.clone());
+        let (values, failed) =
+            evaluate_chunk(&mut maia, &parsed, &lines, &mut BatchSummaryBuilder::new());
+        assert!(failed);
+        assert_eq!(values.len(), 2);
+        assert!(values.iter().all(|v| v["error"].is_string()));
+        assert_eq!(values[0]["fen"], "garbage");
+        assert!(backend.batch_sizes().is_empty());
+    }
+}
@@ -0,0 +1,815 @@
+//! HTTP evaluation server on top of [`MaiaService`].
+//!
+//! ```text
+//! maia-server --model maia3_simplified.onnx --bind 127.0.0.1:8080
+//! curl -d '{"fen": "<fen>", "elo_self": 1500, "elo_oppo": 1500}' localhost:8080/evaluate
+//! ```
+//!
+//! Endpoints:
+//! - `POST /evaluate` takes `{"fen", "elo_self", "elo_oppo"}` and returns
+//!   the serialized [`EvaluationResult`]. An optional `"model"` field
+//!   names one of the `--models` to evaluate on instead of `--model`.
+//! - `POST /batch` takes a list of such objects and returns a list of
+//!   results, with `{"error": ...}` in place of positions that failed.
+//! - `GET /health` answers as soon as the server is listening, `GET /ready`
+//!   only once the model has loaded.
+//! - `GET /metrics` reports request counts and latencies per endpoint,
+//!   followed by the library's metrics (see [`maia_rust::metrics`]).
+//!
+//! Up to `--max-connections` threads serve connections, and further ones
+//! wait to be accepted; a client read or write that stalls for
+//! `--io-timeout-ms` closes its connection. The threads submit to the
+//! shared service, which coalesces concurrent requests into batches. Named
+//! models live in a [`MaiaRegistry`], load on their first request and
+//! evaluate one request at a time; `--max-loaded-models` bounds how many
+//! of them stay in memory. Responses
+//! close the connection; there is no keep-alive.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    process::ExitCode,
+    str::FromStr,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use maia_rust::{
+    DefaultBackend, Error, EvaluationResult, InferenceBackend, Maia, MaiaRegistry, MaiaService,
+    PendingEvaluation, ServiceConfig,
+    shakmaty::{Setup, fen::Fen},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+const USAGE: &str = "\
+usage: maia-server [options]
+
+options (environment variable in brackets):
+  --model <path>          ONNX model file, or a URL to download it from with the
+                          download feature [MAIA_MODEL] (default: maia3_simplified.onnx)
+  --models <key=path,..>  further models, chosen by a request's model field [MAIA_MODELS]
+  --max-loaded-models <n> most named models kept in memory [MAIA_MAX_LOADED_MODELS]
+  --bind <addr>           listen address [MAIA_BIND] (default: 127.0.0.1:8080)
+  --max-batch-size <n>    largest coalesced batch [MAIA_MAX_BATCH_SIZE] (default: 256)
+  --max-delay-ms <ms>     time to wait for a batch to fill [MAIA_MAX_DELAY_MS] (default: 2)
+  --max-connections <n>   connections served at once [MAIA_MAX_CONNECTIONS] (default: 64)
+  --io-timeout-ms <ms>    longest wait on a client read or write [MAIA_IO_TIMEOUT_MS]
+                          (default: 10000)";
+
+/// Request bodies larger than this are rejected.
+const MAX_BODY_BYTES: usize = 16 << 20;
+
+struct Args {
+    model: String,
+    /// Named models as keys and paths.
+    models: Vec<(String, String)>,
+    max_loaded_models: Option<usize>,
+    bind: String,
+    config: ServiceConfig,
+    connections: ConnectionConfig,
+}
+
+/// How many connections are served at once and how long they may stall.
+#[derive(Clone, Copy)]
+struct ConnectionConfig {
+    max_connections: usize,
+    io_timeout: Duration,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 64,
+            io_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Args {
+    /// Parse the options in `env`, the environment variables, and then
+    /// `iter`, the command line without the program name.
+    fn parse(
+        env: impl Fn(&str) -> Option<String>,
+        iter: impl IntoIterator<Item = String>,
+    ) -> Result<Self, String> {
+        let mut args = Args {
+            model: "maia3_simplified.onnx".to_string(),
+            models: Vec::new(),
+            max_loaded_models: None,
+            bind: "127.0.0.1:8080".to_string(),
+            config: ServiceConfig::default(),
+            connections: ConnectionConfig::default(),
+        };
+
+        for (var, flag) in [
+            ("MAIA_MODEL", "--model"),
+            ("MAIA_MODELS", "--models"),
+            ("MAIA_MAX_LOADED_MODELS", "--max-loaded-models"),
+            ("MAIA_BIND", "--bind"),
+            ("MAIA_MAX_BATCH_SIZE", "--max-batch-size"),
+            ("MAIA_MAX_DELAY_MS", "--max-delay-ms"),
+            ("MAIA_MAX_CONNECTIONS", "--max-connections"),
+            ("MAIA_IO_TIMEOUT_MS", "--io-timeout-ms"),
+        ] {
+            if let Some(value) = env(var) {
+                args.set(flag, value)?;
+            }
+        }
+
+        let mut iter = iter.into_iter();
+        while let Some(flag) = iter.next() {
+            if flag == "-h" || flag == "--help" {
+                return Err(USAGE.to_string());
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("missing value for {flag}\n\n{USAGE}"))?;
+            args.set(&flag, value)?;
+        }
+        Ok(args)
+    }
+
+    fn set(&mut self, flag: &str, value: String) -> Result<(), String> {
+        match flag {
+            "--model" => self.model = value,
+            "--models" => {
+                self.models = value
+                    .split(',')
+                    .filter(|entry| !entry.is_empty())
+                    .map(|entry| match entry.split_once('=') {
+                        Some((key, path)) => Ok((key.to_string(), path.to_string())),
+                        None => Err(format!("invalid value for {flag}: {entry}")),
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "--max-loaded-models" => self.max_loaded_models = Some(parse_value(flag, &value)?),
+            "--bind" => self.bind = value,
+            "--max-batch-size" => {
+                self.config.max_batch_size = parse_value::<usize>(flag, &value)?.max(1)
+            }
+            "--max-delay-ms" => {
+                self.config.max_delay = Duration::from_millis(parse_value(flag, &value)?)
+            }
+            "--max-connections" => {
+                self.connections.max_connections = parse_value::<usize>(flag, &value)?.max(1)
+            }
+            "--io-timeout-ms" => {
+                // A zero timeout is rejected by the socket, so wait at least 1 ms.
+                let millis = parse_value::<u64>(flag, &value)?.max(1);
+                self.connections.io_timeout = Duration::from_millis(millis)
+            }
+            _ => return Err(format!("unknown option {flag}\n\n{USAGE}")),
+        }
+        Ok(())
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {flag}: {value}"))
+}
+
+/// Counters for one endpoint.
+#[derive(Default)]
+struct EndpointMetrics {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    latency_micros_sum: AtomicU64,
+    latency_micros_max: AtomicU64,
+}
+
+impl EndpointMetrics {
+    fn record(&self, latency: Duration, ok: bool) {
+        let micros = latency.as_micros() as u64;
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency_micros_sum.fetch_add(micros, Ordering::Relaxed);
+        self.latency_micros_max.fetch_max(micros, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct Metrics {
+    evaluate: EndpointMetrics,
+    batch: EndpointMetrics,
+}
+
+impl Metrics {
+    /// Render in the Prometheus text format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, m) in [("evaluate", &self.evaluate), ("batch", &self.batch)] {
+            let requests = m.requests.load(Ordering::Relaxed);
+            let errors = m.errors.load(Ordering::Relaxed);
+            let sum = m.latency_micros_sum.load(Ordering::Relaxed) as f64 / 1e6;
+            let max = m.latency_micros_max.load(Ordering::Relaxed) as f64 / 1e6;
+            out += &format!("maia_requests_total{{endpoint=\"{name}\"}} {requests}\n");
+            out += &format!("maia_request_errors_total{{endpoint=\"{name}\"}} {errors}\n");
+            out += &format!("maia_request_latency_seconds_sum{{endpoint=\"{name}\"}} {sum}\n");
+            out += &format!("maia_request_latency_seconds_max{{endpoint=\"{name}\"}} {max}\n");
+        }
+        out
+    }
+}
+
+/// Per-model lines of the named models, in the Prometheus text format.
+fn render_models<B: InferenceBackend + Send + 'static>(registry: &MaiaRegistry<B>) -> String {
+    let mut out = String::new();
+    for info in registry.infos() {
+        let key = &info.key;
+        out += &format!(
+            "maia_model_loaded{{model=\"{key}\"}} {}\n",
+            u8::from(info.loaded)
+        );
+        out += &format!("maia_model_loads_total{{model=\"{key}\"}} {}\n", info.loads);
+        out += &format!(
+            "maia_model_evaluations_total{{model=\"{key}\"}} {}\n",
+            info.evaluations
+        );
+    }
+    out
+}
+
+struct State<B = DefaultBackend> {
+    service: OnceLock<MaiaService>,
+    registry: MaiaRegistry<B>,
+    metrics: Metrics,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, value: &Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: value.to_string(),
+        }
+    }
+
+    fn text(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+        }
+    }
+
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Self::json(status, &json!({ "error": message.to_string() }))
+    }
+}
+
+#[derive(Deserialize)]
+struct EvaluateRequest {
+    fen: String,
+    elo_self: f32,
+    elo_oppo: f32,
+    /// Key of a named model, `None` for the default one.
+    #[serde(default)]
+    model: Option<String>,
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request, Response> {
+    let bad_request = |msg: &str| Response::error(400, msg);
+
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .map_err(|_| bad_request("unreadable request line"))?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(bad_request("malformed request line"));
+    };
+    let path = target.split('?').next().unwrap_or(target).to_string();
+    let method = method.to_string();
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        reader
+            .read_line(&mut line)
+            .map_err(|_| bad_request("unreadable header"))?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = value
+                .trim()
+                .parse()
+                .map_err(|_| bad_request("invalid Content-Length"))?;
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(Response::error(413, "request body too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|_| bad_request("truncated body"))?;
+    Ok(Request { method, path, body })
+}
+
+fn write_response(stream: &mut impl Write, response: &Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(response.body.as_bytes())?;
+    stream.flush()
+}
+
+/// HTTP status for a failed evaluation: caller mistakes are 400, the
+/// rest are server-side.
+fn error_status(err: &Error) -> u16 {
+    match err {
+        Error::InvalidFen { .. }
+        | Error::InvalidPosition(_)
+        | Error::EloOutOfRange { .. }
+        | Error::UnknownModel { .. } => 400,
+        Error::AtIndex { source, .. } => error_status(source),
+        Error::Overloaded { .. } => 503,
+        Error::Timeout { .. } => 504,
+        _ => 500,
+    }
+}
+
+fn parse_setup(fen: &str) -> Result<Setup, Error> {
+    let fen: Fen = fen.parse().map_err(|e| Error::invalid_fen(fen, e))?;
+    Ok(fen.into_setup())
+}
+
+fn result_json(result: &EvaluationResult) -> Value {
+    serde_json::to_value(result).expect("EvaluationResult serializes to JSON")
+}
+
+fn evaluate<B: InferenceBackend + Send + 'static>(
+    state: &State<B>,
+    service: &MaiaService,
+    body: &[u8],
+) -> Response {
+    let req: EvaluateRequest = match serde_json::from_slice(body) {
+        Ok(req) => req,
+        Err(err) => return Response::error(400, err),
+    };
+    let outcome = parse_setup(&req.fen).and_then(|setup| match &req.model {
+        Some(key) => state
+            .registry
+            .evaluate(key, setup, req.elo_self, req.elo_oppo),
+        None => service.evaluate(setup, req.elo_self, req.elo_oppo),
+    });
+
+    match outcome {
+        Ok(result) => Response::json(200, &result_json(&result)),
+        Err(err) => Response::error(error_status(&err), err),
+    }
+}
+
+/// A `/batch` item for the default model, submitted to its service, or
+/// for a named model, evaluated once everything else is submitted.
+enum BatchItem<'a> {
+    Submitted(PendingEvaluation),
+    Named(&'a EvaluateRequest, Setup),
+}
+
+fn batch<B: InferenceBackend + Send + 'static>(
+    state: &State<B>,
+    service: &MaiaService,
+    body: &[u8],
+) -> Response {
+    let reqs: Vec<EvaluateRequest> = match serde_json::from_slice(body) {
+        Ok(reqs) => reqs,
+        Err(err) => return Response::error(400, err),
+    };
+
+    // Submit everything before waiting so the whole list can share batches.
+    let items: Vec<_> = reqs
+        .iter()
+        .map(|req| {
+            parse_setup(&req.fen).map(|setup| match req.model {
+                Some(_) => BatchItem::Named(req, setup),
+                None => BatchItem::Submitted(service.submit(setup, req.elo_self, req.elo_oppo)),
+            })
+        })
+        .collect();
+    let values: Vec<Value> = items
+        .into_iter()
+        .map(|item| match item.and_then(|item| item.finish(state)) {
+            Ok(result) => result_json(&result),
+            Err(err) => json!({ "error": err.to_string() }),
+        })
+        .collect();
+    Response::json(200, &Value::Array(values))
+}
+
+impl BatchItem<'_> {
+    fn finish<B: InferenceBackend + Send + 'static>(
+        self,
+        state: &State<B>,
+    ) -> Result<EvaluationResult, Error> {
+        match self {
+            BatchItem::Submitted(pending) => pending.wait(),
+            BatchItem::Named(req, setup) => {
+                let key = req.model.as_deref().unwrap_or_default();
+                state
+                    .registry
+                    .evaluate(key, setup, req.elo_self, req.elo_oppo)
+            }
+        }
+    }
+}
+
+/// An endpoint evaluating on the default model's service or the registry.
+type Handler<B> = fn(&State<B>, &MaiaService, &[u8]) -> Response;
+
+fn route<B: InferenceBackend + Send + 'static>(state: &State<B>, req: &Request) -> Response {
+    let start = Instant::now();
+    let (metrics, handler): (_, Handler<B>) = match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/health") => return Response::text(200, "ok\n"),
+        ("GET", "/ready") => {
+            return match state.service.get() {
+                Some(_) => Response::text(200, "ready\n"),
+                None => Response::text(503, "loading\n"),
+            };
+        }
+        ("GET", "/metrics") => {
+            let body = state.metrics.render()
+                + &render_models(&state.registry)
+                + &maia_rust::metrics::render();
+            return Response::text(200, body);
+        }
+        ("POST", "/evaluate") => (&state.metrics.evaluate, evaluate),
+        ("POST", "/batch") => (&state.metrics.batch, batch),
+        (_, "/health" | "/ready" | "/metrics" | "/evaluate" | "/batch") => {
+            return Response::error(405, "method not allowed");
+        }
+        _ => return Response::error(404, "not found"),
+    };
+
+    let response = match state.service.get() {
+        Some(service) => handler(state, service, &req.body),
+        None => Response::error(503, "model is still loading"),
+    };
+    metrics.record(start.elapsed(), response.status == 200);
+    response
+}
+
+fn handle_connection<B: InferenceBackend + Send + 'static>(
+    state: &State<B>,
+    mut stream: TcpStream,
+    io_timeout: Duration,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(io_timeout))?;
+    stream.set_write_timeout(Some(io_timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader) {
+        Ok(req) => route(state, &req),
+        Err(response) => response,
+    };
+    write_response(&mut stream, &response)
+}
+
+/// Load `model`, which with the `download` feature may be an `http(s)`
+/// URL; downloads show their progress on a terminal.
+fn load_model(model: &str) -> Result<Maia, Error> {
+    #[cfg(feature = "download")]
+    if model.starts_with("https://") || model.starts_with("http://") {
+        return Maia::from_url_with_progress(
+            model,
+            maia_rust::CacheConfig::default(),
+            maia_rust::stderr_progress(),
+        );
+    }
+    Maia::from_file(model)
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(|var| std::env::var(var).ok(), std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{msg}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let listener = match TcpListener::bind(&args.bind) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("failed to bind {}: {err}", args.bind);
+            return ExitCode::from(2);
+        }
+    };
+    eprintln!("listening on {}", args.bind);
+
+    let mut registry = MaiaRegistry::new();
+    if let Some(max) = args.max_loaded_models {
+        registry = registry.with_max_loaded(max);
+    }
+    for (key, path) in &args.models {
+        let path = path.clone();
+        registry.register(key.as_str(), move || load_model(&path));
+    }
+    let state = Arc::new(State {
+        service: OnceLock::new(),
+        registry,
+        metrics: Metrics::default(),
+    });
+
+    // Load in the background so /health answers (and /ready reports
+    // 503) while the session is being built.
+    let loader_state = state.clone();
+    thread::spawn(move || match load_model(&args.model) {
+        Ok(maia) => {
+            let _ = loader_state
+                .service
+                .set(MaiaService::spawn(maia, args.config));
+            eprintln!("loaded {}", args.model);
+        }
+        Err(err) => {
+            eprintln!("failed to load {}: {err}", args.model);
+            std::process::exit(2);
+        }
+    });
+
+    serve(&listener, &state, args.connections);
+    ExitCode::SUCCESS
+}
+
+/// Accept connections on `listener` forever, handing them to
+/// `config.max_connections` worker threads. Accepting waits while every
+/// worker is busy and as many connections are queued.
+fn serve<B: InferenceBackend + Send + 'static>(
+    listener: &TcpListener,
+    state: &Arc<State<B>>,
+    config: ConnectionConfig,
+) {
+    let (sender, receiver) = mpsc::sync_channel::<TcpStream>(config.max_connections);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..config.max_connections {
+        let (state, receiver) = (state.clone(), receiver.clone());
+        thread::spawn(move || {
+            loop {
+                // Release the lock before handling, so the others can take
+                // the next connection.
+                let next = receiver.lock().unwrap().recv();
+                let Ok(stream) = next else {
+                    return;
+                };
+                if let Err(err) = handle_connection(&state, stream, config.io_timeout) {
+                    eprintln!("connection error: {err}");
+                }
+            }
+        });
+    }
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if sender.send(stream).is_err() {
+                    return;
+                }
+            }
+            Err(err) => eprintln!("failed to accept connection: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maia_rust::testing::ZeroBackend;
+
+    use super::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    /// A state with a `blitz` named model, and with the default model
+    /// loaded if `loaded`.
+    fn state(loaded: bool) -> State<ZeroBackend> {
+        let registry = MaiaRegistry::new();
+        registry.register("blitz", || Ok(Maia::from_backend(ZeroBackend::default())));
+        let state = State {
+            service: OnceLock::new(),
+            registry,
+            metrics: Metrics::default(),
+        };
+        if loaded {
+            let maia = Maia::from_backend(ZeroBackend::default());
+            let _ = state
+                .service
+                .set(MaiaService::spawn(maia, ServiceConfig::default()));
+        }
+        state
+    }
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn body_json(response: &Response) -> Value {
+        serde_json::from_str(&response.body).unwrap()
+    }
+
+    #[test]
+    fn options_come_from_the_environment_and_the_command_line() {
+        let env = |var: &str| match var {
+            "MAIA_BIND" => Some("0.0.0.0:9000".to_string()),
+            "MAIA_MAX_BATCH_SIZE" => Some("64".to_string()),
+            _ => None,
+        };
+        let flags = ["--max-batch-size", "0", "--models", "a=x.onnx,b=y.onnx"];
+        let args = Args::parse(env, flags.map(String::from)).unwrap();
+        assert_eq!(args.bind, "0.0.0.0:9000");
+        assert_eq!(args.config.max_batch_size, 1);
+        assert_eq!(
+            args.models,
+            [
+                ("a".to_string(), "x.onnx".to_string()),
+                ("b".to_string(), "y.onnx".to_string())
+            ]
+        );
+
+        let env = |var: &str| (var == "MAIA_IO_TIMEOUT_MS").then(|| "250".to_string());
+        let args = Args::parse(env, ["--max-connections", "0"].map(String::from)).unwrap();
+        assert_eq!(args.connections.max_connections, 1);
+        assert_eq!(args.connections.io_timeout, Duration::from_millis(250));
+
+        let no_env = |_: &str| None;
+        let err = Args::parse(no_env, ["--models", "a"].map(String::from));
+        assert_eq!(err.err().unwrap(), "invalid value for --models: a");
+        let env = |var: &str| (var == "MAIA_MAX_LOADED_MODELS").then(|| "soon".to_string());
+        let err = Args::parse(env, []);
+        assert_eq!(
+            err.err().unwrap(),
+            "invalid value for --max-loaded-models: soon"
+        );
+    }
+
+    #[test]
+    fn requests_are_read() {
+        let raw = "POST /evaluate?pretty HTTP/1.1\r\nHost: x\r\ncontent-length: 4\r\n\r\nbodyrest";
+        let req = read_request(&mut raw.as_bytes()).ok().unwrap();
+        assert_eq!(
+            (req.method.as_str(), req.path.as_str()),
+            ("POST", "/evaluate")
+        );
+        assert_eq!(req.body, b"body");
+
+        let status = |raw: &str| read_request(&mut raw.as_bytes()).err().unwrap().status;
+        assert_eq!(status("\r\n"), 400);
+        assert_eq!(status("POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n"), 400);
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nshort"),
+            400
+        );
+        let huge = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_BYTES + 1
+        );
+        assert_eq!(status(&huge), 413);
+    }
+
+    #[test]
+    fn endpoints_evaluate_and_report_errors() {
+        let loading = state(false);
+        assert_eq!(route(&loading, &request("GET", "/health", "")).status, 200);
+        assert_eq!(route(&loading, &request("GET", "/ready", "")).status, 503);
+        let body = format!(r#"{{"fen": "{START}", "elo_self": 1500, "elo_oppo": 1500}}"#);
+        assert_eq!(
+            route(&loading, &request("POST", "/evaluate", &body)).status,
+            503
+        );
+
+        let state = state(true);
+        assert_eq!(route(&state, &request("GET", "/ready", "")).status, 200);
+        let response = route(&state, &request("POST", "/evaluate", &body));
+        assert_eq!(response.status, 200);
+        assert_eq!(body_json(&response)["policy"].as_array().unwrap().len(), 20);
+
+        let named = body.replace('}', r#", "model": "blitz"}"#);
+        assert_eq!(
+            route(&state, &request("POST", "/evaluate", &named)).status,
+            200
+        );
+        let unknown = body.replace('}', r#", "model": "bullet"}"#);
+        assert_eq!(
+            route(&state, &request("POST", "/evaluate", &unknown)).status,
+            400
+        );
+        let bad_fen = body.replace(START, "8/8/8");
+        assert_eq!(
+            route(&state, &request("POST", "/evaluate", &bad_fen)).status,
+            400
+        );
+        assert_eq!(
+            route(&state, &request("POST", "/evaluate", "{")).status,
+            400
+        );
+
+        let list = format!("[{body}, {bad_fen}, {named}]");
+        let response = route(&state, &request("POST", "/batch", &list));
+        assert_eq!(response.status, 200);
+        let results = body_json(&response);
+        assert!(results[0]["policy"].is_array());
+        assert!(results[1]["error"].is_string());
+        assert_eq!(results[2], results[0]);
+
+        assert_eq!(route(&state, &request("GET", "/evaluate", "")).status, 405);
+        assert_eq!(route(&state, &request("GET", "/nowhere", "")).status, 404);
+        let metrics = route(&state, &request("GET", "/metrics", "")).body;
+        assert!(metrics.contains("maia_requests_total{endpoint=\"evaluate\"} 5\n"));
+        assert!(metrics.contains("maia_request_errors_total{endpoint=\"evaluate\"} 3\n"));
+        assert!(metrics.contains("maia_model_evaluations_total{model=\"blitz\"} 2\n"));
+    }
+
+    #[test]
+    fn errors_map_to_statuses() {
+        assert_eq!(error_status(&Error::IllegalMove("e2e5".into())), 500);
+        assert_eq!(
+            error_status(&Error::UnknownModel {
+                key: "bullet".into()
+            }),
+            400
+        );
+        let mut out = Vec::new();
+        write_response(&mut out, &Response::text(404, "gone")).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(out.ends_with("Content-Length: 4\r\nConnection: close\r\n\r\ngone"));
+
+        let timeout = Error::Timeout {
+            completed: 1,
+            total: 2,
+        };
+        let mut out = Vec::new();
+        write_response(&mut out, &Response::error(error_status(&timeout), "late")).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"));
+    }
+
+    #[test]
+    fn idle_connections_time_out_and_free_their_thread() {
+        use std::io::Read;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = ConnectionConfig {
+            max_connections: 1,
+            io_timeout: Duration::from_millis(100),
+        };
+        let state = Arc::new(state(false));
+        thread::spawn(move || serve(&listener, &state, config));
+
+        // The idle client holds the only thread until its read times out.
+        let mut idle = TcpStream::connect(addr).unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET /health HTTP/1.1\r\n\r\n").unwrap();
+        let mut out = String::new();
+        client.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.ends_with("ok\n"));
+
+        let mut out = String::new();
+        idle.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
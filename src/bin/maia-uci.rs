@@ -0,0 +1,434 @@
+//! Minimal UCI engine backed by the Maia3 policy.
+//!
+//! Each `go` runs one forward pass for the current position. With
+//! `Humanize` on, the default, the best move is sampled as a player of
+//! `MaiaSelfElo` would choose it, see [`HumanizationProfile`]; with it off
+//! it is the top policy move or, with a positive `Temperature`, sampled
+//! from the policy. A second batched pass over the children of
+//! the `MultiPV` reported moves provides their scores and a one-move
+//! continuation. Time controls are accepted but ignored since there is
+//! no search to budget.
+
+use std::{
+    io::{self, BufRead, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use maia_rust::{
+    Error, EvaluationResult, Evaluator, HumanizationProfile, Maia, Terminal,
+    selection::{Greedy, MoveSelector, Temperature},
+    shakmaty::{CastlingMode, Chess, EnPassantMode, Position, Setup, fen::Fen, uci::UciMove},
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+const DEFAULT_MODEL_PATH: &str = "maia3_simplified.onnx";
+
+/// The engine state, evaluating with an `E` loaded from `ModelPath` on
+/// the first `isready` or `go` after it is set.
+struct Engine<E> {
+    model_path: String,
+    evaluator: Option<E>,
+    load: fn(&str) -> Result<E, Error>,
+    self_elo: f32,
+    oppo_elo: f32,
+    humanize: bool,
+    profile: HumanizationProfile,
+    temperature: f32,
+    multipv: usize,
+    position: Chess,
+    rng: StdRng,
+}
+
+/// Score of a reported line from the engine's point of view.
+enum Score {
+    Cp(i32),
+    Mate(i32),
+}
+
+/// One `info multipv` line: a candidate move, its score and the
+/// continuation shown as the principal variation.
+struct PvLine {
+    score: Score,
+    pv: Vec<UciMove>,
+}
+
+impl<E: Evaluator> Engine<E> {
+    fn new(load: fn(&str) -> Result<E, Error>) -> Self {
+        // Sampling only needs to differ between runs, not be unpredictable.
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self {
+            model_path: DEFAULT_MODEL_PATH.to_string(),
+            evaluator: None,
+            load,
+            self_elo: 1500.0,
+            oppo_elo: 1500.0,
+            humanize: true,
+            profile: HumanizationProfile::default(),
+            temperature: 0.0,
+            multipv: 1,
+            position: Chess::default(),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    fn handle(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("uci") => {
+                writeln!(out, "id name Maia3")?;
+                writeln!(out, "id author maia-rust")?;
+                writeln!(
+                    out,
+                    "option name ModelPath type string default {DEFAULT_MODEL_PATH}"
+                )?;
+                writeln!(
+                    out,
+                    "option name MaiaSelfElo type spin default 1500 min 0 max 4000"
+                )?;
+                writeln!(
+                    out,
+                    "option name MaiaOppoElo type spin default 1500 min 0 max 4000"
+                )?;
+                writeln!(out, "option name Humanize type check default true")?;
+                writeln!(out, "option name Temperature type string default 0")?;
+                writeln!(out, "option name MultiPV type spin default 1 min 1 max 500")?;
+                writeln!(out, "uciok")?;
+            }
+            Some("isready") => {
+                if let Err(err) = self.ensure_loaded() {
+                    writeln!(out, "info string {err}")?;
+                }
+                writeln!(out, "readyok")?;
+            }
+            Some("setoption") => self.set_option(tokens.collect::<Vec<_>>().join(" ")),
+            Some("ucinewgame") => self.position = Chess::default(),
+            Some("position") => {
+                if let Err(err) = self.set_position(tokens) {
+                    writeln!(out, "info string {err}")?;
+                }
+            }
+            Some("go") => self.go(out)?,
+            Some("quit") => return Ok(false),
+            // `go` answers synchronously, so there is never a search to stop.
+            Some("stop") | None => {}
+            Some(other) => writeln!(out, "info string unknown command: {other}")?,
+        }
+        Ok(true)
+    }
+
+    fn set_option(&mut self, args: String) {
+        // `name <id...> value <x...>`; option names may contain spaces.
+        let Some(rest) = args.strip_prefix("name ") else {
+            return;
+        };
+        let (name, value) = match rest.split_once(" value ") {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => (rest.trim(), ""),
+        };
+
+        match name.to_ascii_lowercase().as_str() {
+            "modelpath" => {
+                self.model_path = value.to_string();
+                self.evaluator = None;
+            }
+            "maiaselfelo" => self.self_elo = value.parse().unwrap_or(self.self_elo),
+            "maiaoppoelo" => self.oppo_elo = value.parse().unwrap_or(self.oppo_elo),
+            "humanize" => self.humanize = value.eq_ignore_ascii_case("true"),
+            "temperature" => self.temperature = value.parse().unwrap_or(self.temperature),
+            "multipv" => self.multipv = value.parse().unwrap_or(self.multipv).max(1),
+            _ => {}
+        }
+    }
+
+    fn set_position<'a>(
+        &mut self,
+        mut tokens: impl Iterator<Item = &'a str>,
+    ) -> Result<(), String> {
+        let mut position = match tokens.next() {
+            Some("startpos") => {
+                // Skip past the `moves` keyword if present.
+                tokens.next();
+                Chess::default()
+            }
+            Some("fen") => {
+                let fen: Vec<&str> = tokens.by_ref().take_while(|&t| t != "moves").collect();
+                let fen: Fen = fen
+                    .join(" ")
+                    .parse()
+                    .map_err(|e| format!("invalid fen: {e}"))?;
+                fen.into_position(CastlingMode::Standard)
+                    .map_err(|e| format!("invalid position: {e}"))?
+            }
+            _ => return Err("expected `startpos` or `fen`".to_string()),
+        };
+
+        for token in tokens {
+            let m = token
+                .parse::<UciMove>()
+                .ok()
+                .and_then(|uci| uci.to_move(&position).ok())
+                .ok_or_else(|| format!("illegal move: {token}"))?;
+            position.play_unchecked(m);
+        }
+
+        self.position = position;
+        Ok(())
+    }
+
+    fn ensure_loaded(&mut self) -> Result<&mut E, String> {
+        if self.evaluator.is_none() {
+            let evaluator = (self.load)(&self.model_path)
+                .map_err(|e| format!("failed to load {}: {e}", self.model_path))?;
+            self.evaluator = Some(evaluator);
+        }
+        Ok(self.evaluator.as_mut().unwrap())
+    }
+
+    fn evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        self_elo: f32,
+        oppo_elo: f32,
+    ) -> Result<Vec<EvaluationResult>, String> {
+        let n = setups.len();
+        let evaluator = self.ensure_loaded()?;
+
+        evaluator
+            .batch_evaluate(setups, &vec![self_elo; n], &vec![oppo_elo; n])
+            .map_err(|e| e.to_string())
+    }
+
+    /// Evaluate the current position and build up to `MultiPV` lines.
+    ///
+    /// Lines follow policy order, except that a sampled best move is
+    /// promoted to the first line so `bestmove` always agrees with
+    /// `multipv 1`.
+    fn analyse(&mut self) -> Result<Vec<PvLine>, String> {
+        let us = self.position.turn();
+        let setup = self.position.to_setup(EnPassantMode::Legal);
+        let root = self
+            .evaluate(vec![setup], self.self_elo, self.oppo_elo)?
+            .remove(0);
+
+        let selector: Box<dyn MoveSelector> = if self.humanize {
+            Box::new(self.profile.params(self.self_elo))
+        } else {
+            Box::new(Temperature(self.temperature))
+        };
+        let Some(best) = selector.select(&root, &mut self.rng) else {
+            return Ok(Vec::new());
+        };
+        let candidates: Vec<UciMove> = std::iter::once(best)
+            .chain(root.policy.iter().map(|m| m.uci).filter(|uci| *uci != best))
+            .take(self.multipv)
+            .collect();
+
+        let mut children = Vec::with_capacity(candidates.len());
+        for uci in &candidates {
+            let m = uci.to_move(&self.position).map_err(|e| e.to_string())?;
+            let mut child = self.position.clone();
+            child.play_unchecked(m);
+            children.push(child);
+        }
+
+        // In the children the opponent is to move, so the elo roles swap.
+        let setups = children
+            .iter()
+            .map(|c| c.to_setup(EnPassantMode::Legal))
+            .collect();
+        let replies = self.evaluate(setups, self.oppo_elo, self.self_elo)?;
+
+        let lines = candidates
+            .into_iter()
+            .zip(children.iter().zip(replies))
+            .map(|(uci, (child, reply))| {
+                let score = match Terminal::of(child) {
+                    Some(Terminal::Checkmate) => Score::Mate(1),
+                    Some(Terminal::Stalemate) => Score::Cp(0),
+                    None => Score::Cp(reply.centipawns(us)),
+                };
+                let mut pv = vec![uci];
+                pv.extend(Greedy.select(&reply, &mut self.rng));
+                PvLine { score, pv }
+            })
+            .collect();
+        Ok(lines)
+    }
+
+    fn go(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let lines = match self.analyse() {
+            Ok(lines) => lines,
+            Err(err) => {
+                writeln!(out, "info string {err}")?;
+                return writeln!(out, "bestmove 0000");
+            }
+        };
+
+        for (k, line) in lines.iter().enumerate() {
+            let score = match line.score {
+                Score::Cp(cp) => format!("cp {cp}"),
+                Score::Mate(n) => format!("mate {n}"),
+            };
+            let pv: Vec<String> = line.pv.iter().map(ToString::to_string).collect();
+            writeln!(
+                out,
+                "info depth 1 multipv {} nodes {} score {score} pv {}",
+                k + 1,
+                lines.len() + 1,
+                pv.join(" ")
+            )?;
+        }
+
+        match lines.first() {
+            Some(line) => writeln!(out, "bestmove {}", line.pv[0]),
+            None => writeln!(out, "bestmove 0000"),
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut engine = Engine::new(|path| Maia::from_file(path));
+    let mut stdout = io::stdout().lock();
+
+    for line in io::stdin().lock().lines() {
+        if !engine.handle(&line?, &mut stdout)? {
+            break;
+        }
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use maia_rust::testing::ScriptedEvaluator;
+
+    use super::*;
+
+    fn engine() -> Engine<ScriptedEvaluator> {
+        Engine::new(|_| Ok(ScriptedEvaluator(vec![0.5; 64])))
+    }
+
+    /// Send `commands` and return the output lines.
+    fn run(engine: &mut Engine<ScriptedEvaluator>, commands: &[&str]) -> Vec<String> {
+        let mut out = Vec::new();
+        for command in commands {
+            assert!(engine.handle(command, &mut out).unwrap());
+        }
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn positions_are_set_up() {
+        let mut engine = engine();
+        assert!(run(&mut engine, &["position startpos moves e2e4 e7e5 g1f3"]).is_empty());
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+        assert_eq!(
+            Fen::from_position(&engine.position, EnPassantMode::Legal).to_string(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+
+        run(&mut engine, &[&format!("position fen {fen} moves f1b5")]);
+        assert_eq!(engine.position.turn(), shakmaty::Color::Black);
+        assert_eq!(engine.position.fullmoves().get(), 3);
+
+        // A bad command leaves the position as it was.
+        let before = engine.position.clone();
+        let out = run(&mut engine, &["position startpos moves e2e5"]);
+        assert_eq!(out, ["info string illegal move: e2e5"]);
+        let out = run(&mut engine, &["position fen not a fen"]);
+        assert!(out[0].starts_with("info string invalid fen"));
+        let out = run(&mut engine, &["position"]);
+        assert_eq!(out, ["info string expected `startpos` or `fen`"]);
+        assert_eq!(engine.position, before);
+
+        run(&mut engine, &["ucinewgame"]);
+        assert_eq!(engine.position, Chess::default());
+    }
+
+    #[test]
+    fn options_are_set() {
+        let mut engine = engine();
+        run(
+            &mut engine,
+            &[
+                "isready",
+                "setoption name MaiaSelfElo value 1900",
+                "setoption name maiaoppoelo value 1100",
+                "setoption name Humanize value false",
+                "setoption name Temperature value 0.5",
+                "setoption name MultiPV value 0",
+                "setoption name MaiaSelfElo value high",
+                "setoption name Unknown value 3",
+            ],
+        );
+        assert_eq!((engine.self_elo, engine.oppo_elo), (1900.0, 1100.0));
+        assert!(!engine.humanize);
+        assert_eq!((engine.temperature, engine.multipv), (0.5, 1));
+        assert!(engine.evaluator.is_some());
+
+        run(
+            &mut engine,
+            &["setoption name ModelPath value other model.onnx"],
+        );
+        assert_eq!(engine.model_path, "other model.onnx");
+        assert!(engine.evaluator.is_none());
+    }
+
+    #[test]
+    fn bestmove_agrees_with_the_first_line() {
+        for humanize in ["true", "false"] {
+            let mut engine = engine();
+            let out = run(
+                &mut engine,
+                &[
+                    &format!("setoption name Humanize value {humanize}"),
+                    "setoption name Temperature value 1",
+                    "setoption name MultiPV value 3",
+                    "position startpos moves e2e4",
+                    "go wtime 1000 btime 1000",
+                ],
+            );
+            assert_eq!(out.len(), 4, "{out:?}");
+            for (k, line) in out[..3].iter().enumerate() {
+                assert!(line.starts_with(&format!(
+                    "info depth 1 multipv {} nodes 4 score cp 0 pv ",
+                    k + 1
+                )));
+            }
+            let first_move = out[0]
+                .split(" pv ")
+                .nth(1)
+                .unwrap()
+                .split(' ')
+                .next()
+                .unwrap();
+            assert_eq!(out[3], format!("bestmove {first_move}"));
+        }
+
+        // Without legal moves there is nothing to report.
+        let mut engine = engine();
+        let out = run(
+            &mut engine,
+            &["position startpos moves f2f3 e7e5 g2g4 d8h4", "go"],
+        );
+        assert_eq!(out, ["bestmove 0000"]);
+    }
+
+    #[test]
+    fn load_failures_are_reported() {
+        let mut engine: Engine<ScriptedEvaluator> =
+            Engine::new(|path| Err(Error::ModelNotFound(vec![path.into()])));
+        let out = run(&mut engine, &["isready", "go"]);
+        assert!(out[0].starts_with("info string failed to load maia3_simplified.onnx"));
+        assert_eq!(out[1], "readyok");
+        assert_eq!(out.last().unwrap(), "bestmove 0000");
+        assert!(!engine.handle("quit", &mut Vec::new()).unwrap());
+    }
+}
@@ -0,0 +1,298 @@
+//! Polyglot opening books and blending them with the Maia policy.
+//!
+//! Keys and move encoding are shared with the book writer in
+//! [`export`](crate::export), so a book written from Maia policies
+//! reads back exactly.
+
+use std::{fs, io, path::Path};
+
+use shakmaty::{CastlingMode, Chess, Position, Role, Setup, Square, uci::UciMove};
+
+use crate::{
+    error::Error,
+    evaluator::Evaluator,
+    export::{PolyglotEntry, polyglot_key},
+    moves::vocabulary_index,
+    postprocess::sort_policy,
+    types::{EvaluationResult, MoveProbability},
+};
+
+/// A Polyglot `.bin` book held in memory.
+#[derive(Debug, Clone, Default)]
+pub struct PolyglotBook {
+    entries: Vec<PolyglotEntry>,
+}
+
+impl PolyglotBook {
+    /// Read a book from disk.
+    ///
+    /// # Errors
+    /// Fails if the file cannot be read or its size is not a multiple of
+    /// 16 bytes.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Parse book contents.
+    ///
+    /// # Errors
+    /// Returns [`io::ErrorKind::InvalidData`] if the length is not a
+    /// multiple of 16 bytes.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if !bytes.len().is_multiple_of(PolyglotEntry::SIZE) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "polyglot book size is not a multiple of 16 bytes",
+            ));
+        }
+        let mut entries: Vec<PolyglotEntry> = bytes
+            .chunks_exact(PolyglotEntry::SIZE)
+            .map(|chunk| PolyglotEntry::from_bytes(chunk.try_into().unwrap()))
+            .collect();
+        // Books are sorted by key already; make sure of it for lookups.
+        entries.sort_by_key(|e| e.key);
+        Ok(Self { entries })
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the book has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Raw entries for the position with Polyglot hash `key`.
+    pub fn entries(&self, key: u64) -> &[PolyglotEntry] {
+        let start = self.entries.partition_point(|e| e.key < key);
+        let end = self.entries.partition_point(|e| e.key <= key);
+        &self.entries[start..end]
+    }
+
+    /// Book moves of `pos` with their weights, skipping entries that do
+    /// not decode to a legal move (hash collisions).
+    pub fn moves(&self, pos: &Chess) -> Vec<(UciMove, u16)> {
+        self.entries(polyglot_key(pos))
+            .iter()
+            .filter_map(|e| Some((decode_move(pos, e.mv)?, e.weight)))
+            .collect()
+    }
+}
+
+/// Decode a Polyglot move into standard UCI notation (`e1g1` for
+/// castling, as in the Maia policy). `None` if it is illegal in `pos`.
+fn decode_move(pos: &Chess, mv: u16) -> Option<UciMove> {
+    let square = |bits: u16| Square::new(u32::from(bits & 0x3f));
+    let promotion = match (mv >> 12) & 0x7 {
+        0 => None,
+        1 => Some(Role::Knight),
+        2 => Some(Role::Bishop),
+        3 => Some(Role::Rook),
+        4 => Some(Role::Queen),
+        _ => return None,
+    };
+    let uci = UciMove::Normal {
+        from: square(mv >> 6),
+        to: square(mv),
+        promotion,
+    };
+    let m = uci.to_move(pos).ok()?;
+    Some(m.to_uci(CastlingMode::Standard))
+}
+
+/// How [`BookBlendedEvaluator`] combines book and model in book positions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    /// Only book moves, with probabilities proportional to their weights.
+    Book,
+    /// Ignore the book.
+    Model,
+    /// `lambda * book + (1 - lambda) * model`, with `lambda` in [0, 1].
+    Blend(f32),
+}
+
+/// Evaluator that mixes a Polyglot book into the policy of another
+/// evaluator.
+///
+/// Positions found in the book get a policy combined according to the
+/// [`BlendMode`]; the value head and positions outside the book come
+/// from the wrapped evaluator unchanged.
+pub struct BookBlendedEvaluator<E> {
+    inner: E,
+    book: PolyglotBook,
+    mode: BlendMode,
+}
+
+impl<E: Evaluator> BookBlendedEvaluator<E> {
+    /// Wrap `inner`, consulting `book` according to `mode`.
+    pub fn new(inner: E, book: PolyglotBook, mode: BlendMode) -> Self {
+        Self { inner, book, mode }
+    }
+
+    /// Change the blend mode.
+    pub fn set_mode(&mut self, mode: BlendMode) {
+        self.mode = mode;
+    }
+
+    /// The wrapped evaluator.
+    pub fn inner_mut(&mut self) -> &mut E {
+        &mut self.inner
+    }
+
+    /// Unwrap, returning the inner evaluator and the book.
+    pub fn into_parts(self) -> (E, PolyglotBook) {
+        (self.inner, self.book)
+    }
+
+    fn blend(&self, pos: &Chess, result: &mut EvaluationResult) {
+        let lambda = match self.mode {
+            BlendMode::Model => return,
+            BlendMode::Book => 1.0,
+            BlendMode::Blend(lambda) => lambda.clamp(0.0, 1.0),
+        };
+        let book_moves = self.book.moves(pos);
+        let total: f32 = book_moves.iter().map(|&(_, w)| f32::from(w)).sum();
+        if total <= 0.0 {
+            return;
+        }
+        let book_probability = |uci: &UciMove| {
+            book_moves
+                .iter()
+                .find(|(m, _)| m == uci)
+                .map_or(0.0, |&(_, w)| f32::from(w) / total)
+        };
+
+        for m in &mut result.policy {
+            m.probability = lambda * book_probability(&m.uci) + (1.0 - lambda) * m.probability;
+        }
+        // Book moves missing from the model policy only happen with a
+        // truncated policy; give them their book share. Every legal move
+        // is in the vocabulary.
+        for (uci, _) in &book_moves {
+            if !result.policy.iter().any(|m| m.uci == *uci)
+                && let Some(index) = vocabulary_index(uci, pos.turn())
+            {
+                result.policy.push(MoveProbability {
+                    uci: *uci,
+                    probability: lambda * book_probability(uci),
+                    index,
+                });
+            }
+        }
+        if self.mode == BlendMode::Book {
+            result.policy.retain(|m| m.probability > 0.0);
+        }
+        sort_policy(&mut result.policy);
+    }
+}
+
+impl<E: Evaluator> Evaluator for BookBlendedEvaluator<E> {
+    fn batch_evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        let positions = setups
+            .iter()
+            .map(|s| Ok(s.clone().position::<Chess>(CastlingMode::Standard)?))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut results = self.inner.batch_evaluate(setups, elo_selfs, elo_oppos)?;
+
+        for (pos, result) in positions.iter().zip(&mut results) {
+            self.blend(pos, result);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        export::{polyglot_entries, write_polyglot},
+        testing::{UniformEvaluator, white_move},
+    };
+
+    /// Book with e2e4 (weight 3) and d2d4 (weight 1) at the start.
+    fn book() -> PolyglotBook {
+        let book_result = EvaluationResult {
+            policy: vec![white_move("e2e4", 0.75), white_move("d2d4", 0.25)],
+            white_wr: 0.0,
+            draw: 0.0,
+            black_wr: 0.0,
+            outcome: None,
+        };
+        let mut bytes = Vec::new();
+        write_polyglot(
+            polyglot_entries(&Chess::default(), &book_result),
+            &mut bytes,
+        )
+        .unwrap();
+        PolyglotBook::from_bytes(&bytes).unwrap()
+    }
+
+    fn probability(result: &EvaluationResult, uci: &str) -> f32 {
+        let uci: UciMove = uci.parse().unwrap();
+        result
+            .policy
+            .iter()
+            .find(|m| m.uci == uci)
+            .map_or(0.0, |m| m.probability)
+    }
+
+    #[test]
+    fn book_moves_round_trip() {
+        let moves = book().moves(&Chess::default());
+        assert_eq!(moves[0].0, "e2e4".parse::<UciMove>().unwrap());
+        assert_eq!(moves.len(), 2);
+    }
+
+    #[test]
+    fn blend_modes_mix_book_and_model() {
+        let start = Setup::default();
+        let mut evaluator = BookBlendedEvaluator::new(UniformEvaluator, book(), BlendMode::Book);
+
+        let result = evaluator.evaluate(start.clone(), 1500.0, 1500.0).unwrap();
+        assert_eq!(result.policy.len(), 2);
+        assert!((probability(&result, "e2e4") - 0.75).abs() < 1e-3);
+
+        evaluator.set_mode(BlendMode::Blend(0.5));
+        let result = evaluator.evaluate(start.clone(), 1500.0, 1500.0).unwrap();
+        assert_eq!(result.policy.len(), 20);
+        assert!((probability(&result, "e2e4") - (0.375 + 0.025)).abs() < 1e-3);
+        assert!((result.policy.iter().map(|m| m.probability).sum::<f32>() - 1.0).abs() < 1e-3);
+        // The model-only moves tie and keep UCI order.
+        let rest: Vec<String> = result.policy[2..]
+            .iter()
+            .map(|m| m.uci.to_string())
+            .collect();
+        let mut sorted = rest.clone();
+        sorted.sort();
+        assert_eq!(rest, sorted);
+
+        // A NaN probability sorts without panicking.
+        let mut nan = evaluator.evaluate(start.clone(), 1500.0, 1500.0).unwrap();
+        nan.policy[5].probability = f32::NAN;
+        evaluator.blend(&Chess::default(), &mut nan);
+        assert_eq!(nan.policy.len(), 20);
+
+        evaluator.set_mode(BlendMode::Model);
+        let result = evaluator.evaluate(start, 1500.0, 1500.0).unwrap();
+        assert!((probability(&result, "e2e4") - 0.05).abs() < 1e-6);
+    }
+
+    #[test]
+    fn positions_outside_the_book_use_the_model() {
+        let mut evaluator = BookBlendedEvaluator::new(UniformEvaluator, book(), BlendMode::Book);
+        let fen: shakmaty::fen::Fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"
+            .parse()
+            .unwrap();
+        let result = evaluator
+            .evaluate(fen.into_setup(), 1500.0, 1500.0)
+            .unwrap();
+        assert_eq!(result.policy.len(), 20);
+    }
+}
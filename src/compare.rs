@@ -0,0 +1,291 @@
+//! Two evaluators on the same positions, side by side.
+//!
+//! [`paired_evaluate`] runs a candidate, such as a half-precision or
+//! quantized export or another backend, and a reference on identical
+//! inputs and pairs their results position by position with the
+//! differences that matter for validation. [`ComparisonSummary`]
+//! aggregates them over the batch:
+//!
+#![cfg_attr(feature = "ort", doc = "```no_run")]
+#![cfg_attr(not(feature = "ort"), doc = "```ignore")]
+//! # fn main() -> Result<(), maia_rust::Error> {
+//! use maia_rust::{Maia, compare::{ComparisonSummary, paired_evaluate}, shakmaty::Setup};
+//!
+//! let mut reference = Maia::from_file("maia3_simplified.onnx")?;
+//! let mut candidate = Maia::from_file("maia3_fp16.onnx")?;
+//! let setups = vec![Setup::initial(); 64];
+//! let pairs = paired_evaluate(&mut reference, &mut candidate, setups, &[1500.0; 64], &[1500.0; 64])?;
+//! println!("{}", ComparisonSummary::of(&pairs));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{fmt, thread};
+
+use shakmaty::Setup;
+
+use crate::{error::Error, evaluator::Evaluator, types::EvaluationResult};
+
+/// The results of one position from both evaluators.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairedResult {
+    pub a: EvaluationResult,
+    pub b: EvaluationResult,
+    /// Whether both have the same most likely move, also when neither
+    /// has one.
+    pub top_move_agrees: bool,
+    /// Largest difference of a move's probability; a move in only one
+    /// policy counts with its full probability.
+    pub max_probability_diff: f32,
+    /// Largest difference of the White win, draw and Black win
+    /// probabilities.
+    pub value_diff: f32,
+}
+
+impl PairedResult {
+    /// Pair `a` and `b`, results for the same position.
+    pub fn new(a: EvaluationResult, b: EvaluationResult) -> Self {
+        let top_move_agrees = a.best_move().map(|m| m.uci) == b.best_move().map(|m| m.uci);
+        let b_moves = b.to_map();
+        let mut max_probability_diff = a
+            .policy
+            .iter()
+            .map(|m| (m.probability - b_moves.get(&m.uci).copied().unwrap_or(0.0)).abs())
+            .fold(0.0, f32::max);
+        for m in &b.policy {
+            if !a.policy.iter().any(|other| other.uci == m.uci) {
+                max_probability_diff = max_probability_diff.max(m.probability);
+            }
+        }
+        let value_diff = [
+            a.white_wr - b.white_wr,
+            a.draw - b.draw,
+            a.black_wr - b.black_wr,
+        ]
+        .into_iter()
+        .map(f32::abs)
+        .fold(0.0, f32::max);
+        Self {
+            a,
+            b,
+            top_move_agrees,
+            max_probability_diff,
+            value_diff,
+        }
+    }
+}
+
+/// Aggregate of the [`PairedResult`]s of a batch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComparisonSummary {
+    pub positions: usize,
+    /// Fraction of positions whose most likely moves agree, 1 for an
+    /// empty batch.
+    pub top_move_agreement: f64,
+    pub mean_probability_diff: f64,
+    pub max_probability_diff: f32,
+    pub mean_value_diff: f64,
+    pub max_value_diff: f32,
+    /// Index of the position with the largest probability difference.
+    pub worst_position: Option<usize>,
+}
+
+impl ComparisonSummary {
+    /// Summarize `pairs`.
+    pub fn of(pairs: &[PairedResult]) -> Self {
+        let n = pairs.len();
+        let mean = |sum: f64| if n == 0 { 0.0 } else { sum / n as f64 };
+        let agreeing = pairs.iter().filter(|p| p.top_move_agrees).count();
+        let worst_position = (0..n).reduce(|worst, i| {
+            if pairs[i].max_probability_diff > pairs[worst].max_probability_diff {
+                i
+            } else {
+                worst
+            }
+        });
+        Self {
+            positions: n,
+            top_move_agreement: if n == 0 {
+                1.0
+            } else {
+                agreeing as f64 / n as f64
+            },
+            mean_probability_diff: mean(
+                pairs
+                    .iter()
+                    .map(|p| f64::from(p.max_probability_diff))
+                    .sum(),
+            ),
+            max_probability_diff: pairs
+                .iter()
+                .map(|p| p.max_probability_diff)
+                .fold(0.0, f32::max),
+            mean_value_diff: mean(pairs.iter().map(|p| f64::from(p.value_diff)).sum()),
+            max_value_diff: pairs.iter().map(|p| p.value_diff).fold(0.0, f32::max),
+            worst_position,
+        }
+    }
+}
+
+impl fmt::Display for ComparisonSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} positions: top move agrees on {:.1}%, probability difference mean {:.5} \
+             max {:.5}, value difference mean {:.5} max {:.5}",
+            self.positions,
+            100.0 * self.top_move_agreement,
+            self.mean_probability_diff,
+            self.max_probability_diff,
+            self.mean_value_diff,
+            self.max_value_diff,
+        )?;
+        if let Some(i) = self.worst_position {
+            write!(f, ", worst at position {i}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Evaluate `setups` with both `a` and `b` and pair the results.
+///
+/// The evaluators run at the same time, `a` on a thread of its own, on
+/// copies of the same setups and ratings, so the pairs differ only by
+/// what the evaluators do.
+///
+/// # Errors
+/// Returns the error of `a`, else that of `b`, and
+/// [`Error::BatchSizeMismatch`] if either returns a different number of
+/// results than positions.
+pub fn paired_evaluate<A, B>(
+    a: &mut A,
+    b: &mut B,
+    setups: Vec<Setup>,
+    elo_selfs: &[f32],
+    elo_oppos: &[f32],
+) -> Result<Vec<PairedResult>, Error>
+where
+    A: Evaluator + Send + ?Sized,
+    B: Evaluator + ?Sized,
+{
+    let expected = setups.len();
+    let copies = setups.clone();
+    let (results_a, results_b) = thread::scope(|scope| {
+        let handle = scope.spawn(move || a.batch_evaluate(copies, elo_selfs, elo_oppos));
+        let results_b = b.batch_evaluate(setups, elo_selfs, elo_oppos);
+        let results_a = handle
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        (results_a, results_b)
+    });
+    let (results_a, results_b) = (results_a?, results_b?);
+    for actual in [results_a.len(), results_b.len()] {
+        if actual != expected {
+            return Err(Error::BatchSizeMismatch { expected, actual });
+        }
+    }
+    Ok(results_a
+        .into_iter()
+        .zip(results_b)
+        .map(|(a, b)| PairedResult::new(a, b))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array2, Array3};
+    use shakmaty::{Color, fen::Fen};
+
+    use super::*;
+    use crate::{
+        Maia,
+        backend::{InferenceBackend, ModelOutputs},
+        moves::ALL_MOVES,
+        vocabulary_index,
+    };
+
+    /// Uniform value and a policy favouring the move at `favourite`,
+    /// if any.
+    struct FavouriteBackend {
+        favourite: Option<usize>,
+        calls: usize,
+    }
+
+    impl InferenceBackend for FavouriteBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            _elo_self: &[f32],
+            _elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            self.calls += 1;
+            let batch_size = tokens.shape()[0];
+            let mut logits_move = Array2::zeros((batch_size, ALL_MOVES.len()));
+            if let Some(i) = self.favourite {
+                logits_move.column_mut(i).fill(3.0);
+            }
+            Ok(ModelOutputs {
+                logits_move,
+                logits_value: Array2::zeros((batch_size, 3)),
+            })
+        }
+    }
+
+    fn maia(favourite: Option<&str>) -> Maia<FavouriteBackend> {
+        let favourite = favourite.map(|uci| {
+            let m = uci.parse().unwrap();
+            usize::from(vocabulary_index(&m, Color::White).unwrap())
+        });
+        Maia::from_backend(FavouriteBackend {
+            favourite,
+            calls: 0,
+        })
+    }
+
+    fn setups(n: usize) -> Vec<Setup> {
+        let fen: Fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+            .parse()
+            .unwrap();
+        vec![fen.into_setup(); n]
+    }
+
+    #[test]
+    fn identical_evaluators_agree() {
+        let (mut a, mut b) = (maia(Some("e2e4")), maia(Some("e2e4")));
+        let pairs = paired_evaluate(&mut a, &mut b, setups(3), &[1500.0; 3], &[1500.0; 3]).unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert_eq!((a.backend().calls, b.backend().calls), (1, 1));
+        assert!(pairs.iter().all(|p| p.a == p.b && p.top_move_agrees));
+
+        let summary = ComparisonSummary::of(&pairs);
+        assert_eq!(summary.top_move_agreement, 1.0);
+        assert_eq!(
+            (summary.max_probability_diff, summary.max_value_diff),
+            (0.0, 0.0)
+        );
+        assert_eq!(summary.worst_position, Some(0));
+    }
+
+    #[test]
+    fn differences_are_measured() {
+        let (mut a, mut b) = (maia(Some("e2e4")), maia(None));
+        let pairs = paired_evaluate(&mut a, &mut b, setups(2), &[1500.0; 2], &[1500.0; 2]).unwrap();
+        let pair = &pairs[0];
+        assert!(!pair.top_move_agrees);
+        let e4 = pair.a.best_move().unwrap().probability;
+        assert!((pair.max_probability_diff - (e4 - 0.05)).abs() < 1e-6);
+        assert_eq!(pair.value_diff, 0.0);
+
+        let summary = ComparisonSummary::of(&pairs);
+        assert_eq!(summary.top_move_agreement, 0.0);
+        assert!(
+            (summary.mean_probability_diff - f64::from(pair.max_probability_diff)).abs() < 1e-6
+        );
+        assert!(
+            summary
+                .to_string()
+                .starts_with("2 positions: top move agrees on 0.0%")
+        );
+        assert_eq!(ComparisonSummary::of(&[]).worst_position, None);
+    }
+}
@@ -0,0 +1,661 @@
+//! Model files downloaded once into a local cache, behind the `download`
+//! feature.
+//!
+//! [`Maia::from_url`] and [`cached_model`] keep one file per URL in a
+//! cache directory, named after the last segment of the URL, next to
+//! a small `.json` record of the response's `ETag` and length that
+//! [`CacheMode::Revalidate`] compares with the server. The default
+//! directory is where [`Maia::from_default_model`] looks, so caching
+//! the official `maia3_simplified.onnx` there also makes it the default
+//! model.
+//!
+//! Downloads go to a `.part` file in the cache directory and replace the
+//! cached file by a rename, so readers only ever see complete files. A
+//! download that breaks off leaves the `.part` file, and the next one
+//! asks the server for the rest with an HTTP range request; the length
+//! and checksum checks still cover the whole file.
+//! A lock file next to it makes concurrent processes wait for the one
+//! downloading instead of fetching the same file in parallel; a lock
+//! left by a process that died is taken over after [`STALE_LOCK`].
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use reqwest::{
+    StatusCode,
+    blocking::{Client, Response},
+    header::{CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_RANGE, RANGE},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Maia, backend::OrtBackend, error::Error, maia::user_cache_dir, registry::model_sha256,
+};
+
+/// When [`cached_model`] contacts the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheMode {
+    /// Never; a file missing from the cache fails with
+    /// [`Error::ModelNotFound`].
+    Offline,
+    /// Only to download a file missing from the cache.
+    #[default]
+    IfMissing,
+    /// Every time, to download the file again if its `ETag` or length
+    /// changed. A server that sends neither or cannot be reached leaves
+    /// the cached file in use.
+    Revalidate,
+}
+
+/// Where and how [`cached_model`] caches downloads.
+#[derive(Debug, Clone, Default)]
+pub struct CacheConfig {
+    /// Cache directory; `None` for `maia` in the per-user cache
+    /// directory, see [`Maia::from_default_model`].
+    pub dir: Option<PathBuf>,
+    pub mode: CacheMode,
+    /// SHA-256 a download must have, in hex, as [`model_sha256`]
+    /// reports it. Downloads are always checked against the length the
+    /// server announced.
+    pub sha256: Option<String>,
+}
+
+/// Age after which a lock file is considered left behind by a process
+/// that died while downloading.
+pub const STALE_LOCK: Duration = Duration::from_secs(10 * 60);
+
+/// How often a process waiting for another's download checks the lock.
+const LOCK_POLL: Duration = Duration::from_millis(100);
+
+/// How often [`stderr_progress`] redraws its line.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+impl Maia<OrtBackend> {
+    /// Load the model at `url` from the local cache, downloading it
+    /// first as `config` says, see [`cached_model`].
+    ///
+    /// # Errors
+    /// As [`cached_model`] and [`Maia::from_file`].
+    pub fn from_url(url: &str, config: CacheConfig) -> Result<Self, Error> {
+        Self::from_file(cached_model(url, &config)?)
+    }
+
+    /// [`Maia::from_url`], reporting download progress to `progress`,
+    /// see [`cached_model_with_progress`].
+    ///
+    /// # Errors
+    /// As [`Maia::from_url`].
+    pub fn from_url_with_progress(
+        url: &str,
+        config: CacheConfig,
+        progress: impl FnMut(u64, Option<u64>),
+    ) -> Result<Self, Error> {
+        Self::from_file(cached_model_with_progress(url, &config, progress)?)
+    }
+}
+
+/// The path of the cached copy of `url`, downloaded now if `config`
+/// calls for it.
+///
+/// # Errors
+/// - [`Error::ModelNotFound`] if the file is not cached in
+///   [`CacheMode::Offline`] or there is no cache directory.
+/// - [`Error::Io`] if the download fails, is shorter than announced or
+///   does not match [`CacheConfig::sha256`], or the cache cannot be
+///   written. The cached file is left as it was; bytes received before
+///   the connection failed are kept for the next call to continue from.
+pub fn cached_model(url: &str, config: &CacheConfig) -> Result<PathBuf, Error> {
+    cached_model_with_progress(url, config, |_, _| {})
+}
+
+/// [`cached_model`], calling `progress` with the bytes downloaded so far
+/// and the length of the file, if the server announced it, as a download
+/// proceeds. A continued download starts at the bytes already there.
+/// [`stderr_progress`] draws a progress line.
+///
+/// # Errors
+/// As [`cached_model`].
+pub fn cached_model_with_progress(
+    url: &str,
+    config: &CacheConfig,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<PathBuf, Error> {
+    let dir = match &config.dir {
+        Some(dir) => dir.clone(),
+        None => user_cache_dir()
+            .ok_or_else(|| Error::ModelNotFound(Vec::new()))?
+            .join("maia"),
+    };
+    let path = dir.join(file_name(url)?);
+    match config.mode {
+        CacheMode::Offline if path.is_file() => return Ok(path),
+        CacheMode::Offline => return Err(Error::ModelNotFound(vec![path])),
+        CacheMode::IfMissing if path.is_file() => return Ok(path),
+        _ => {}
+    }
+
+    fs::create_dir_all(&dir)?;
+    let _lock = CacheLock::acquire(&path)?;
+    let client = Client::new();
+    // Another process may have finished the download while we waited.
+    if path.is_file() {
+        if config.mode == CacheMode::IfMissing {
+            return Ok(path);
+        }
+        match client.head(url).send().and_then(Response::error_for_status) {
+            Ok(response) => {
+                let remote = Validator::of(&response);
+                if remote.is_empty() || Validator::read(&path).as_ref() == Some(&remote) {
+                    return Ok(path);
+                }
+            }
+            Err(err) => {
+                tracing::warn!(url, "using cached model, revalidation failed: {err}");
+                return Ok(path);
+            }
+        }
+    }
+    download(&client, url, &path, config, &mut progress)?;
+    Ok(path)
+}
+
+/// Fetch `url` into `path` through the partial file `<name>.part`,
+/// continuing a partial file left by an earlier attempt.
+fn download(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    config: &CacheConfig,
+    progress: &mut dyn FnMut(u64, Option<u64>),
+) -> Result<(), Error> {
+    let part = sibling(path, "part");
+    // Only a partial file with a recorded `ETag` can be continued safely:
+    // `If-Range` makes the server send the whole file if it changed.
+    let mut resume = match (fs::metadata(&part), Validator::read(&part)) {
+        (
+            Ok(meta),
+            Some(Validator {
+                etag: Some(etag), ..
+            }),
+        ) if meta.len() > 0 => Some((meta.len(), etag)),
+        _ => None,
+    };
+    let response = loop {
+        let mut request = client.get(url);
+        if let Some((offset, etag)) = &resume {
+            request = request
+                .header(RANGE, format!("bytes={offset}-"))
+                .header(IF_RANGE, etag);
+        }
+        let response = request.send().map_err(io::Error::other)?;
+        // The partial file already has every byte, or more.
+        if resume.is_some() && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            resume = None;
+            continue;
+        }
+        break response.error_for_status().map_err(io::Error::other)?;
+    };
+
+    let (offset, total) = if response.status() == StatusCode::PARTIAL_CONTENT {
+        match (resume, content_range(&response)) {
+            (Some((offset, _)), Some((start, total))) if start == offset => (offset, total),
+            _ => {
+                discard(&part);
+                let message = format!("{url} sent a range that was not asked for");
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+            }
+        }
+    } else {
+        (0, response.content_length())
+    };
+    let validator = Validator {
+        content_length: total,
+        ..Validator::of(&response)
+    };
+    validator.write(&part)?;
+
+    let mut file = if offset > 0 {
+        tracing::info!(url, offset, "resuming download");
+        OpenOptions::new().append(true).open(&part)?
+    } else {
+        File::create(&part)?
+    };
+    let done = copy_with_progress(response, &mut file, offset, total, progress)?;
+    file.sync_all()?;
+    drop(file);
+
+    if let Some(total) = total
+        && done != total
+    {
+        let message = format!("download of {url} stopped after {done} of {total} bytes");
+        if done > total {
+            discard(&part);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+        }
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, message).into());
+    }
+    if let Some(expected) = &config.sha256 {
+        let actual = model_sha256(&part)?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            discard(&part);
+            let message = format!("download of {url} has SHA-256 {actual}, expected {expected}");
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+        }
+    }
+    fs::rename(&part, path)?;
+    let _ = fs::remove_file(sibling(&part, "json"));
+    validator.write(path)?;
+    Ok(())
+}
+
+/// Copy `response` to the end of `file`, which holds `offset` bytes,
+/// reporting the bytes done after each read. Returns the bytes done.
+fn copy_with_progress(
+    mut response: Response,
+    file: &mut File,
+    offset: u64,
+    total: Option<u64>,
+    progress: &mut dyn FnMut(u64, Option<u64>),
+) -> io::Result<u64> {
+    let mut buf = vec![0; 1 << 16];
+    let mut done = offset;
+    progress(done, total);
+    loop {
+        let n = match response.read(&mut buf) {
+            Ok(0) => return Ok(done),
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        file.write_all(&buf[..n])?;
+        done += n as u64;
+        progress(done, total);
+    }
+}
+
+/// The first byte and the file length of a `206` response, from
+/// `Content-Range: bytes <first>-<last>/<length or *>`.
+fn content_range(response: &Response) -> Option<(u64, Option<u64>)> {
+    let range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (span, total) = range.strip_prefix("bytes ")?.split_once('/')?;
+    let start = span.split_once('-')?.0.parse().ok()?;
+    Some((start, total.parse().ok()))
+}
+
+/// Remove the partial file `part` and its record, so the next attempt
+/// starts over.
+fn discard(part: &Path) {
+    let _ = fs::remove_file(part);
+    let _ = fs::remove_file(sibling(part, "json"));
+}
+
+/// A progress callback for [`cached_model_with_progress`] that keeps a
+/// line on standard error up to date when it is a terminal, and does
+/// nothing otherwise.
+pub fn stderr_progress() -> impl FnMut(u64, Option<u64>) {
+    let terminal = io::stderr().is_terminal();
+    let mut last: Option<Instant> = None;
+    move |done, total| {
+        let finished = total == Some(done);
+        if !terminal || last.is_some_and(|t| t.elapsed() < PROGRESS_INTERVAL) && !finished {
+            return;
+        }
+        last = Some(Instant::now());
+        let mb = |bytes: u64| bytes as f64 / 1e6;
+        match total {
+            Some(total) if total > 0 => eprint!(
+                "\rdownloading {:.1} of {:.1} MB ({:.0}%)",
+                mb(done),
+                mb(total),
+                100.0 * done as f64 / total as f64
+            ),
+            _ => eprint!("\rdownloading {:.1} MB", mb(done)),
+        }
+        if finished {
+            eprintln!();
+        }
+    }
+}
+
+/// The cache file name of `url`: its last path segment.
+fn file_name(url: &str) -> Result<&str, Error> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    match path.rsplit('/').next() {
+        Some(name) if !name.is_empty() && !path.ends_with(':') && name != ".." => Ok(name),
+        _ => {
+            let message = format!("URL {url} does not name a file");
+            Err(io::Error::new(io::ErrorKind::InvalidInput, message).into())
+        }
+    }
+}
+
+/// `path` with `.suffix` appended to its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// What identifies a version of the file on the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Validator {
+    etag: Option<String>,
+    content_length: Option<u64>,
+}
+
+impl Validator {
+    fn of(response: &Response) -> Self {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            etag: header(ETAG),
+            content_length: header(CONTENT_LENGTH).and_then(|v| v.parse().ok()),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.content_length.is_none()
+    }
+
+    /// The record cached with `path`, if any.
+    fn read(path: &Path) -> Option<Self> {
+        let json = fs::read(sibling(path, "json")).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    fn write(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_vec(self).map_err(io::Error::from)?;
+        Ok(fs::write(sibling(path, "json"), json)?)
+    }
+}
+
+/// Exclusive right to download a cached file, held while the lock file
+/// exists.
+struct CacheLock(PathBuf);
+
+impl CacheLock {
+    /// Wait for other processes downloading `path` and take the lock.
+    fn acquire(path: &Path) -> Result<Self, Error> {
+        let lock = sibling(path, "lock");
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(_) => return Ok(Self(lock)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    if is_stale(&lock) {
+                        tracing::warn!(lock = %lock.display(), "removing stale cache lock");
+                        let _ = fs::remove_file(&lock);
+                    } else {
+                        thread::sleep(LOCK_POLL);
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Whether the lock file at `lock` is older than [`STALE_LOCK`].
+fn is_stale(lock: &Path) -> bool {
+    fs::metadata(lock)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > STALE_LOCK)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    /// The file a [`Server`] serves.
+    struct Served {
+        etag: String,
+        body: Vec<u8>,
+        /// Close the connection after sending this many bytes.
+        cut: Option<usize>,
+    }
+
+    /// An HTTP server on localhost serving one file, with range requests,
+    /// and the first byte asked for by each `GET` it answered.
+    struct Server {
+        url: String,
+        file: Arc<Mutex<Served>>,
+        gets: Arc<Mutex<Vec<Option<usize>>>>,
+    }
+
+    impl Server {
+        fn start(body: &[u8]) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/models/maia.onnx", listener.local_addr().unwrap());
+            let file = Arc::new(Mutex::new(Served {
+                etag: "\"v1\"".to_string(),
+                body: body.to_vec(),
+                cut: None,
+            }));
+            let gets = Arc::new(Mutex::new(Vec::new()));
+            let (served, log) = (file.clone(), gets.clone());
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request = String::new();
+                    reader.read_line(&mut request).unwrap();
+                    let (mut range, mut if_range) = (None, None);
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        let Some((name, value)) = line.trim_end().split_once(": ") else {
+                            break;
+                        };
+                        match name.to_ascii_lowercase().as_str() {
+                            "range" => {
+                                range = value
+                                    .strip_prefix("bytes=")
+                                    .and_then(|v| v.strip_suffix('-'))
+                                    .and_then(|v| v.parse::<usize>().ok());
+                            }
+                            "if-range" => if_range = Some(value.to_string()),
+                            _ => {}
+                        }
+                    }
+                    let get = request.starts_with("GET");
+                    if get {
+                        log.lock().unwrap().push(range);
+                    }
+                    let file = served.lock().unwrap();
+                    let len = file.body.len();
+                    let start = range.filter(|_| if_range.is_none_or(|tag| tag == file.etag));
+                    let (status, start) = match start {
+                        Some(start) if start >= len => ("416 Range Not Satisfiable", len),
+                        Some(start) => ("206 Partial Content", start),
+                        None => ("200 OK", 0),
+                    };
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {status}\r\nETag: {}\r\nContent-Length: {}\r\n",
+                        file.etag,
+                        len - start
+                    );
+                    if status.starts_with("206") {
+                        let _ =
+                            write!(stream, "Content-Range: bytes {start}-{}/{len}\r\n", len - 1);
+                    }
+                    let _ = write!(stream, "Connection: close\r\n\r\n");
+                    if get {
+                        let end = file.cut.map_or(len, |cut| (start + cut).min(len));
+                        let _ = stream.write_all(&file.body[start..end]);
+                    }
+                }
+            });
+            Self { url, file, gets }
+        }
+
+        fn gets(&self) -> usize {
+            self.gets.lock().unwrap().len()
+        }
+    }
+
+    fn cache_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("maia-cache-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn config(dir: &Path, mode: CacheMode) -> CacheConfig {
+        CacheConfig {
+            dir: Some(dir.to_path_buf()),
+            mode,
+            sha256: None,
+        }
+    }
+
+    #[test]
+    fn downloads_once_and_revalidates() {
+        let server = Server::start(b"first model");
+        let dir = cache_dir("revalidate");
+        assert!(matches!(
+            cached_model(&server.url, &config(&dir, CacheMode::Offline)),
+            Err(Error::ModelNotFound(paths)) if paths == [dir.join("maia.onnx")]
+        ));
+
+        let path = cached_model(&server.url, &config(&dir, CacheMode::IfMissing)).unwrap();
+        assert_eq!(path, dir.join("maia.onnx"));
+        assert_eq!(fs::read(&path).unwrap(), b"first model");
+        cached_model(&server.url, &config(&dir, CacheMode::IfMissing)).unwrap();
+        cached_model(&server.url, &config(&dir, CacheMode::Revalidate)).unwrap();
+        cached_model(&server.url, &config(&dir, CacheMode::Offline)).unwrap();
+        assert_eq!(server.gets(), 1);
+
+        *server.file.lock().unwrap() = Served {
+            etag: "\"v2\"".to_string(),
+            body: b"second model".to_vec(),
+            cut: None,
+        };
+        cached_model(&server.url, &config(&dir, CacheMode::IfMissing)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"first model");
+        cached_model(&server.url, &config(&dir, CacheMode::Revalidate)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second model");
+        assert_eq!(server.gets(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bad_downloads_leave_the_cache_alone() {
+        let server = Server::start(b"model");
+        let dir = cache_dir("integrity");
+        let mut checked = config(&dir, CacheMode::IfMissing);
+        checked.sha256 = Some("00".repeat(32));
+        let err = cached_model(&server.url, &checked).unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::InvalidData));
+
+        // Neither the file nor the partial file or the lock remain.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        checked.sha256 = Some(crate::registry::sha256_hex(b"model").to_uppercase());
+        cached_model(&server.url, &checked).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn broken_downloads_continue_where_they_stopped() {
+        let body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let server = Server::start(&body);
+        let dir = cache_dir("resume");
+        let mut checked = config(&dir, CacheMode::IfMissing);
+        checked.sha256 = Some(crate::registry::sha256_hex(&body));
+        server.file.lock().unwrap().cut = Some(70_000);
+        assert!(cached_model(&server.url, &checked).is_err());
+        let part = dir.join("maia.onnx.part");
+        let kept = fs::metadata(&part).unwrap().len();
+        assert!(kept > 0 && !dir.join("maia.onnx").exists());
+
+        server.file.lock().unwrap().cut = None;
+        let mut reports = Vec::new();
+        let path = cached_model_with_progress(&server.url, &checked, |done, total| {
+            reports.push((done, total))
+        })
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), body);
+        let total = Some(body.len() as u64);
+        assert_eq!(reports.first(), Some(&(kept, total)));
+        assert_eq!(reports.last(), Some(&(body.len() as u64, total)));
+        assert!(reports.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert_eq!(*server.gets.lock().unwrap(), [None, Some(kept as usize)]);
+        assert!(!part.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn changed_files_are_downloaded_again() {
+        let server = Server::start(b"first model");
+        let dir = cache_dir("changed");
+        server.file.lock().unwrap().cut = Some(5);
+        assert!(cached_model(&server.url, &config(&dir, CacheMode::IfMissing)).is_err());
+
+        // `If-Range` no longer matches, so the server sends it all.
+        *server.file.lock().unwrap() = Served {
+            etag: "\"v2\"".to_string(),
+            body: b"second model".to_vec(),
+            cut: None,
+        };
+        let path = cached_model(&server.url, &config(&dir, CacheMode::IfMissing)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second model");
+        assert_eq!(*server.gets.lock().unwrap(), [None, Some(5)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_callers_download_once() {
+        let server = Server::start(&[7; 1 << 16]);
+        let dir = cache_dir("concurrent");
+        fs::create_dir_all(&dir).unwrap();
+        // A lock left behind by a process that died.
+        let lock = dir.join("maia.onnx.lock");
+        File::create(&lock)
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * STALE_LOCK)
+            .unwrap();
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| cached_model(&server.url, &config(&dir, CacheMode::IfMissing)));
+            }
+        });
+        assert_eq!(server.gets(), 1);
+        assert_eq!(fs::read(dir.join("maia.onnx")).unwrap(), [7; 1 << 16]);
+        assert!(!lock.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cache_files_are_named_after_the_url() {
+        let name = |url| file_name(url).ok();
+        assert_eq!(name("https://host/a/maia3.onnx?raw=1"), Some("maia3.onnx"));
+        assert_eq!(name("https://host/maia3.onnx#x"), Some("maia3.onnx"));
+        assert_eq!(name("https://host/models/"), None);
+        assert_eq!(name("https://host/.."), None);
+    }
+}
@@ -0,0 +1,335 @@
+//! Process-wide ONNX Runtime configuration shared by several models.
+//!
+//! ONNX Runtime keeps one environment per process. By default every
+//! session also creates its own intra- and inter-op thread pools, so a
+//! process holding several [`Maia`] instances (a pool, an ensemble, one
+//! model per time control) runs several pools competing for the same
+//! cores. A [`MaiaEnvironment`] configures the environment once with a
+//! global thread pool that every model loaded through it shares, and
+//! the allocator settings of the sessions it creates.
+
+use std::{path::Path, sync::Arc};
+
+use ort::{
+    environment::{Environment, GlobalThreadPoolOptions},
+    logging::LogLevel,
+    session::Session,
+};
+
+use crate::{
+    Maia,
+    backend::OrtBackend,
+    error::Error,
+    external_data,
+    maia::{Device, on_device, without_builder},
+    registry::{ModelSource, sha256_hex},
+};
+
+/// Builder of a [`MaiaEnvironment`], see [`MaiaEnvironment::builder`].
+#[derive(Debug, Clone)]
+pub struct MaiaEnvironmentBuilder {
+    name: String,
+    log_level: Option<LogLevel>,
+    intra_threads: usize,
+    inter_threads: usize,
+    spin_control: Option<bool>,
+    memory: MemoryOptions,
+}
+
+/// Allocator settings applied to every session of a [`MaiaEnvironment`].
+#[derive(Debug, Clone, Copy, Default)]
+struct MemoryOptions {
+    cpu_arena: Option<bool>,
+    memory_pattern: Option<bool>,
+    gpu_memory_limit: Option<usize>,
+    deterministic: bool,
+}
+
+impl MaiaEnvironmentBuilder {
+    /// Name the environment reports in ONNX Runtime logs.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Log level of ONNX Runtime for the whole process.
+    pub fn with_log_level(mut self, level: LogLevel) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// Threads of the shared pool used within one operator; 0 (the
+    /// default) lets ONNX Runtime pick from the core count.
+    pub fn with_intra_threads(mut self, threads: usize) -> Self {
+        self.intra_threads = threads;
+        self
+    }
+
+    /// Threads of the shared pool used between operators; 0 (the
+    /// default) lets ONNX Runtime pick.
+    pub fn with_inter_threads(mut self, threads: usize) -> Self {
+        self.inter_threads = threads;
+        self
+    }
+
+    /// Let idle pool threads spin, trading CPU for latency under
+    /// constant load.
+    pub fn with_spin_control(mut self, spin: bool) -> Self {
+        self.spin_control = Some(spin);
+        self
+    }
+
+    /// Use the CPU arena allocator, on by default.
+    ///
+    /// The arena keeps the memory of earlier runs to serve later ones,
+    /// so the footprint grows to that of the largest batch seen and
+    /// stays there. Turning it off allocates every run afresh, slower
+    /// but returning the memory between runs.
+    pub fn with_cpu_arena(mut self, enable: bool) -> Self {
+        self.memory.cpu_arena = Some(enable);
+        self
+    }
+
+    /// Let sessions plan their allocations from the first run of each
+    /// input shape, on by default. Batches of always-changing sizes gain
+    /// little from the plan.
+    pub fn with_memory_pattern(mut self, enable: bool) -> Self {
+        self.memory.memory_pattern = Some(enable);
+        self
+    }
+
+    /// Cap the GPU allocator of models loaded with
+    /// [`load_on`](MaiaEnvironment::load_on) at `bytes`. Runs that would
+    /// exceed it fail to allocate, which [`Maia`] answers by splitting
+    /// the batch, see [`Maia::memory_estimate`]. The CPU provider has no
+    /// such limit.
+    pub fn with_gpu_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory.gpu_memory_limit = Some(bytes);
+        self
+    }
+
+    /// Make repeated runs of the same batch give bit-identical outputs
+    /// on the same machine.
+    ///
+    /// This runs the shared pool on one intra- and one inter-op thread,
+    /// so reductions inside operators always add up in the same order,
+    /// and asks ONNX Runtime for its deterministic kernels. Together
+    /// with the probability-then-UCI order of sorted policies, whole
+    /// [`EvaluationResult`](crate::EvaluationResult)s compare equal
+    /// between runs. The cost is parallelism: large batches run about as
+    /// many times slower as the cores they no longer use, and GPU
+    /// providers may pick slower kernels. Later thread settings on the
+    /// builder override the single thread.
+    pub fn deterministic(mut self) -> Self {
+        self.intra_threads = 1;
+        self.inter_threads = 1;
+        self.memory.deterministic = true;
+        self
+    }
+
+    /// Configure the process environment.
+    ///
+    /// This has to happen before the first session of the process is
+    /// created, by this crate or anything else using ONNX Runtime.
+    ///
+    /// # Errors
+    /// Returns [`Error::EnvironmentConfigured`] if the environment was
+    /// already configured or in use, and [`Error::OrtError`] if ONNX
+    /// Runtime rejects the options.
+    pub fn build(self) -> Result<MaiaEnvironment, Error> {
+        let mut pool = GlobalThreadPoolOptions::default()
+            .with_intra_threads(self.intra_threads)?
+            .with_inter_threads(self.inter_threads)?;
+        if let Some(spin) = self.spin_control {
+            pool = pool.with_spin_control(spin)?;
+        }
+        let committed = ort::init()
+            .with_name(self.name)
+            .with_global_thread_pool(pool)
+            .commit();
+        if !committed {
+            return Err(Error::EnvironmentConfigured);
+        }
+
+        let env = Environment::current()?;
+        if let Some(level) = self.log_level {
+            env.set_log_level(level);
+        }
+        Ok(MaiaEnvironment {
+            env,
+            memory: self.memory,
+        })
+    }
+}
+
+/// The process's ONNX Runtime environment, configured with a global
+/// thread pool.
+///
+/// Models loaded with [`load`](Self::load) or built from
+/// [`session_builder`](Self::session_builder) run on the shared pool.
+/// Per-session thread settings (`with_intra_threads` and friends on the
+/// session builder) are ignored for those sessions; to give one model a
+/// private pool, build its session with
+/// `with_independent_thread_pool()` and set its threads there.
+///
+/// Cloning is cheap; the environment lives while any clone or session
+/// does.
+#[derive(Clone)]
+pub struct MaiaEnvironment {
+    env: Arc<Environment>,
+    memory: MemoryOptions,
+}
+
+impl MaiaEnvironment {
+    /// Start configuring the environment, with ONNX Runtime's default
+    /// thread counts and log level.
+    pub fn builder() -> MaiaEnvironmentBuilder {
+        MaiaEnvironmentBuilder {
+            name: "maia-rust".to_string(),
+            log_level: None,
+            intra_threads: 0,
+            inter_threads: 0,
+            spin_control: None,
+            memory: MemoryOptions::default(),
+        }
+    }
+
+    /// Change the log level of the process.
+    pub fn set_log_level(&self, level: LogLevel) {
+        self.env.set_log_level(level);
+    }
+
+    /// A session builder for the shared environment with its allocator
+    /// settings, for models that need further options. Pass the session
+    /// to [`Maia::from_session`].
+    ///
+    /// # Errors
+    /// Propagates ONNX Runtime errors as [`Error::OrtError`].
+    pub fn session_builder(&self) -> Result<ort::session::builder::SessionBuilder, Error> {
+        let mut builder = Session::builder()?;
+        if let Some(enable) = self.memory.cpu_arena {
+            builder = builder
+                .with_execution_providers([ort::ep::CPU::default()
+                    .with_arena_allocator(enable)
+                    .build()])
+                .map_err(without_builder)?;
+        }
+        if let Some(enable) = self.memory.memory_pattern {
+            builder = builder
+                .with_memory_pattern(enable)
+                .map_err(without_builder)?;
+        }
+        if self.memory.deterministic {
+            builder = builder
+                .with_deterministic_compute(true)
+                .map_err(without_builder)?;
+        }
+        Ok(builder)
+    }
+
+    /// Load a model from a `.onnx` file onto the shared thread pool.
+    ///
+    /// # Errors
+    /// As [`Maia::from_file`].
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<Maia<OrtBackend>, Error> {
+        let path = path.as_ref();
+        let session = external_data::commit_from_file(&mut self.session_builder()?, path)?;
+        Ok(Maia::from_loaded(
+            session,
+            ModelSource::File(path.to_path_buf()),
+        ))
+    }
+
+    /// Load a model from a `.onnx` file onto `device`, with the GPU
+    /// memory limit of the environment if one is set.
+    ///
+    /// # Errors
+    /// As [`Maia::from_file_on`].
+    pub fn load_on(
+        &self,
+        path: impl AsRef<Path>,
+        device: Device,
+    ) -> Result<Maia<OrtBackend>, Error> {
+        let path = path.as_ref();
+        let mut builder = on_device(
+            self.session_builder()?,
+            device,
+            self.memory.gpu_memory_limit,
+        )?;
+        let session = external_data::commit_from_file(&mut builder, path)?;
+        Ok(Maia::from_loaded(
+            session,
+            ModelSource::File(path.to_path_buf()),
+        ))
+    }
+
+    /// Load a model from raw ONNX bytes onto the shared thread pool.
+    ///
+    /// # Errors
+    /// As [`Maia::from_memory`].
+    pub fn load_from_memory(&self, model_bytes: &[u8]) -> Result<Maia<OrtBackend>, Error> {
+        let session =
+            external_data::commit_from_memory(self.session_builder()?, model_bytes, Vec::new())?;
+        let source = ModelSource::Sha256(sha256_hex(model_bytes));
+        Ok(Maia::from_loaded(session, source))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use super::*;
+
+    /// The process can be configured once, so the tests share one
+    /// deterministic environment.
+    fn environment() -> &'static MaiaEnvironment {
+        static ENV: OnceLock<MaiaEnvironment> = OnceLock::new();
+        ENV.get_or_init(|| {
+            MaiaEnvironment::builder()
+                .deterministic()
+                .with_log_level(LogLevel::Warning)
+                .build()
+                .expect("configure environment")
+        })
+    }
+
+    #[test]
+    #[ignore = "requires local Maia3 ONNX model file"]
+    fn models_share_one_environment() {
+        let env = environment();
+        let mut a = env.load("maia3_simplified.onnx").expect("load first model");
+        let mut b = env
+            .clone()
+            .load("maia3_simplified.onnx")
+            .expect("load second model");
+
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let ra = a.evaluate_fen(fen, 1500.0, 1500.0).expect("evaluate");
+        let rb = b.evaluate_fen(fen, 1500.0, 1500.0).expect("evaluate");
+        assert_eq!(ra.policy.len(), rb.policy.len());
+
+        // A second configuration no longer takes effect.
+        assert!(matches!(
+            MaiaEnvironment::builder().build(),
+            Err(Error::EnvironmentConfigured)
+        ));
+    }
+
+    #[test]
+    #[ignore = "requires local Maia3 ONNX model file"]
+    fn deterministic_runs_are_identical() {
+        let mut maia = environment()
+            .load("maia3_simplified.onnx")
+            .expect("load model");
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+            "8/8/4k3/8/2K5/8/3P4/8 b - - 0 1",
+        ];
+        let elos = [1100.0, 1500.0, 2100.0];
+        let first = maia.evaluate_batch(fens, &elos, &elos).expect("evaluate");
+        let second = maia.evaluate_batch(fens, &elos, &elos).expect("evaluate");
+        assert_eq!(first, second);
+    }
+}
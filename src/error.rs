@@ -4,18 +4,70 @@
 //! errors that may occur while interacting with Maia3. The variants
 //! wrap underlying errors from ONNX Runtime, chess parsing, and
 //! tensor operations, giving the caller a single error type to handle.
+//!
+//! The enum is `#[non_exhaustive]`, so matches need a wildcard arm and
+//! new failure modes can be added without a breaking release. It is
+//! `Send + Sync + 'static`, so it can cross threads and await points
+//! and convert into `anyhow::Error` or `Box<dyn Error + Send + Sync>`.
+//!
+//! Which operations produce which variants:
+//!
+//! | Variant | Produced by |
+//! |---|---|
+//! | [`OrtError`](Error::OrtError) | Loading models, running inference |
+//! | [`Io`](Error::Io) | Hashing model files with `verify_file` and `Maia::verify`, reading fixtures with `Fixture::load` |
+//! | [`InvalidFen`](Error::InvalidFen) | `evaluate` and `evaluate_fen` with a FEN string and other FEN parsing, with the input |
+//! | [`InvalidPosition`](Error::InvalidPosition) | Building positions from setups, usually wrapped in `AtIndex` |
+//! | [`ShapeError`](Error::ShapeError) | Extracting model outputs |
+//! | [`EloOutOfRange`](Error::EloOutOfRange) | Batch evaluation with an elo range set |
+//! | [`BatchSizeMismatch`](Error::BatchSizeMismatch) | Batch evaluation or `preprocess` with rating slices, setups or model outputs of the wrong length |
+//! | [`MissingOutput`](Error::MissingOutput) | Inference with a model lacking `logits_move` or `logits_value` |
+//! | [`IncompatibleModel`](Error::IncompatibleModel) | Batch evaluation with model outputs of the wrong width |
+//! | [`IllegalMove`](Error::IllegalMove) | Playing or encoding moves, tree expansion, `reply_distribution` |
+//! | [`Terminal`](Error::Terminal) | `apply_top_move` and `apply_sampled_move` on an empty policy |
+//! | [`ServiceStopped`](Error::ServiceStopped) | `MaiaService` requests after the worker stopped |
+//! | [`Cancelled`](Error::Cancelled) | `MaiaService` requests still queued at shutdown |
+//! | [`Overloaded`](Error::Overloaded) | `MaiaService` requests to a full queue that reject or do not wait |
+//! | [`ModelNotFound`](Error::ModelNotFound) | `Maia::from_default_model` |
+//! | [`UnknownModel`](Error::UnknownModel) | `MaiaRegistry` calls with a key nothing is registered under |
+//! | [`MissingExternalData`](Error::MissingExternalData) | Loading models whose external data files are missing, or given as bytes alone |
+//! | [`EnvironmentConfigured`](Error::EnvironmentConfigured) | `MaiaEnvironmentBuilder::build` |
+//! | [`DeviceFailed`](Error::DeviceFailed) | `MultiDeviceMaia` batches |
+//! | [`AtIndex`](Error::AtIndex) | Batch evaluation, wrapping the error of one item |
+//! | [`NonFiniteOutput`](Error::NonFiniteOutput) | Batch evaluation when the model returns NaN or infinity |
+//! | [`Timeout`](Error::Timeout) | Batch evaluation with [`EvalOptions::deadline`](crate::EvalOptions::deadline) set |
+//! | [`OutOfMemory`](Error::OutOfMemory) | Custom backends unable to allocate a run |
+//! | [`UnfinishedGame`](Error::UnfinishedGame) | `GameRecord::from_pgn` on a game without a result |
+//! | [`UnsupportedElementType`](Error::UnsupportedElementType) | Inference with inputs or outputs that are not floating point or integer |
 
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Wraps an error returned by the underlying ONNX Runtime bindings.
+    #[cfg(feature = "ort")]
     #[error("ONNX Runtime error: {0}")]
     OrtError(#[from] ort::Error),
 
-    /// The provided FEN string could not be parsed.
-    #[error("Invalid FEN: {0}")]
-    InvalidFen(#[from] shakmaty::fen::ParseFenError),
+    /// A file could not be read.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The provided FEN string could not be parsed. `fen` is the input,
+    /// cut to [`MAX_FEN_LEN`] bytes.
+    ///
+    /// This variant used to wrap the `ParseFenError` alone and convert
+    /// from it with `?`. Code parsing FENs itself now builds the error
+    /// with [`Error::invalid_fen`], e.g.
+    /// `fen.parse::<Fen>().map_err(|e| Error::invalid_fen(fen, e))?`,
+    /// and matches `Error::InvalidFen { source, .. }` where it matched
+    /// `Error::InvalidFen(source)`.
+    #[error("Invalid FEN {fen:?}: {source}")]
+    InvalidFen {
+        fen: String,
+        source: shakmaty::fen::ParseFenError,
+    },
 
     /// A parsed position is invalid from the perspective of `shakmaty`.
     #[error("Invalid Chess Position: {0}")]
@@ -25,6 +77,190 @@ pub enum Error {
     /// preparation or extraction.
     #[error("Tensor shape error: {0}")]
     ShapeError(#[from] ndarray::ShapeError),
+
+    /// The rating slices of a batch, the setups given to
+    /// [`preprocess`](crate::preprocess), or the rows of the model's
+    /// outputs do not match the number of positions.
+    #[error("Batch has {actual} entries where {expected} were expected")]
+    BatchSizeMismatch { expected: usize, actual: usize },
+
+    /// The model has no output of the given name.
+    #[error("Model has no output named {name}")]
+    MissingOutput { name: String },
+
+    /// The model's outputs are not shaped like Maia3's.
+    #[error("Incompatible model: {reason}")]
+    IncompatibleModel { reason: String },
+
+    /// An elo rating fell outside the range configured with
+    /// [`Maia::with_elo_range`](crate::Maia::with_elo_range); `side` says
+    /// whether it was the player's or the opponent's.
+    #[error("{side} elo {value} at batch index {index} is outside the allowed range")]
+    EloOutOfRange {
+        index: usize,
+        side: EloSide,
+        value: f32,
+    },
+
+    /// A UCI move could not be parsed or is illegal in the position it
+    /// was applied to.
+    #[error("Illegal move: {0}")]
+    IllegalMove(String),
+
+    /// A move was requested from a result with an empty policy, normally
+    /// because the position is checkmate or stalemate, see
+    /// [`EvaluationResult::outcome`](crate::EvaluationResult::outcome).
+    #[error("No move to play, the policy is empty")]
+    Terminal,
+
+    /// The [`MaiaService`](crate::MaiaService) worker is no longer
+    /// running, so the request was never answered.
+    #[error("Evaluation service has stopped")]
+    ServiceStopped,
+
+    /// The [`MaiaService`](crate::MaiaService) shut down before
+    /// evaluating the request.
+    #[error("Evaluation request was cancelled at shutdown")]
+    Cancelled,
+
+    /// The [`MaiaService`](crate::MaiaService) queue already held
+    /// `depth` requests, its
+    /// [`max_queue_depth`](crate::ServiceConfig::max_queue_depth).
+    #[error("Evaluation service is overloaded with {depth} queued requests")]
+    Overloaded { depth: usize },
+
+    /// [`Maia::from_default_model`](crate::Maia::from_default_model)
+    /// found no model file; holds every location checked, in order.
+    #[error("Maia model not found, checked: {}", display_paths(.0))]
+    ModelNotFound(Vec<std::path::PathBuf>),
+
+    /// No model is registered under `key` in a
+    /// [`MaiaRegistry`](crate::MaiaRegistry).
+    #[error("No model registered under {key:?}")]
+    UnknownModel { key: String },
+
+    /// The model keeps weights in external data `files` that were not
+    /// found next to it or, when loading from bytes, not passed to
+    /// [`Maia::from_memory_with_external_data`](crate::Maia::from_memory_with_external_data).
+    #[error(
+        "Model weights are in external data {files:?}, which was not found; load the model \
+         with from_file next to its data or pass the data to from_memory_with_external_data"
+    )]
+    MissingExternalData { files: Vec<String> },
+
+    /// The process-wide ONNX Runtime environment was already configured
+    /// or in use when building a
+    /// [`MaiaEnvironment`](crate::MaiaEnvironment).
+    #[error("ONNX Runtime environment is already configured")]
+    EnvironmentConfigured,
+
+    /// A member of a [`MultiDeviceMaia`](crate::MultiDeviceMaia) failed
+    /// its slice of the batch.
+    #[error("Device {device} failed: {source}")]
+    DeviceFailed { device: usize, source: Box<Error> },
+
+    /// One item of a batch failed; `index` is its place in the batch and
+    /// `fen` the offending position, when it was at hand.
+    #[error("Batch index {index}{}: {source}", display_fen(.fen))]
+    AtIndex {
+        index: usize,
+        fen: Option<String>,
+        source: Box<Error>,
+    },
+
+    /// The model returned a NaN or infinite logit.
+    #[error("Model output is not finite")]
+    NonFiniteOutput,
+
+    /// A call overran [`EvalOptions::deadline`](crate::EvalOptions::deadline);
+    /// `completed` of the `total` positions sent to inference had been
+    /// evaluated, and their results were dropped with the rest.
+    #[error("Deadline exceeded after evaluating {completed} of {total} positions")]
+    Timeout { completed: usize, total: usize },
+
+    /// A backend could not allocate memory for a run of `batch_size`
+    /// positions. [`Maia`](crate::Maia) retries such runs in smaller
+    /// pieces, as it does ONNX Runtime allocation failures.
+    #[error("Backend ran out of memory for a run of {batch_size} positions")]
+    OutOfMemory { batch_size: usize },
+
+    /// A game read back as a finished game has no result: its `Result`
+    /// tag is missing or `*` and its final position decides nothing.
+    #[error("Game has no result")]
+    UnfinishedGame,
+
+    /// A model input or output has an element type the crate cannot
+    /// convert from or to `f32`.
+    #[cfg(feature = "ort")]
+    #[error("Model {name} has unsupported element type {dtype}")]
+    UnsupportedElementType {
+        name: String,
+        dtype: ort::value::TensorElementType,
+    },
+}
+
+// Keep the error usable across threads and in async code.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync + 'static>() {}
+    assert_send_sync::<Error>();
+};
+
+/// Which rating of a batch item an [`Error::EloOutOfRange`] is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EloSide {
+    /// The rating of the side to move, from `elo_selfs`.
+    Player,
+    /// The rating of the other side, from `elo_oppos`.
+    Opponent,
+}
+
+impl std::fmt::Display for EloSide {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            EloSide::Player => "Player",
+            EloSide::Opponent => "Opponent",
+        })
+    }
+}
+
+/// Longest FEN input kept in [`Error::InvalidFen`]; real FENs are below
+/// 100 bytes.
+pub const MAX_FEN_LEN: usize = 128;
+
+impl Error {
+    /// A [`Error::InvalidFen`] for the input `fen`, truncated to
+    /// [`MAX_FEN_LEN`] bytes at a character boundary.
+    pub fn invalid_fen(fen: &str, source: shakmaty::fen::ParseFenError) -> Self {
+        let mut end = fen.len().min(MAX_FEN_LEN);
+        while !fen.is_char_boundary(end) {
+            end -= 1;
+        }
+        Error::InvalidFen {
+            fen: fen[..end].to_string(),
+            source,
+        }
+    }
+
+    /// The underlying error of an [`Error::AtIndex`], for callers that
+    /// track the item themselves; other errors are returned as they are.
+    pub fn without_index(self) -> Self {
+        match self {
+            Error::AtIndex { source, .. } => *source,
+            err => err,
+        }
+    }
+}
+
+fn display_fen(fen: &Option<String>) -> String {
+    fen.as_ref().map(|f| format!(" ({f})")).unwrap_or_default()
+}
+
+fn display_paths(paths: &[std::path::PathBuf]) -> String {
+    if paths.is_empty() {
+        return "no locations (set MAIA_MODEL)".to_string();
+    }
+    let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+    paths.join(", ")
 }
 
 impl From<shakmaty::PositionError<shakmaty::Chess>> for Error {
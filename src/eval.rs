@@ -0,0 +1,765 @@
+//! How well the model predicts human play: moves and results.
+//!
+//! [`prediction_accuracy`] measures the policy on a labeled dataset of
+//! played moves, and [`calibration`] the value head on games with known
+//! results.
+//!
+//! A dataset is a CSV file of positions with the move played in them and
+//! the ratings of both players, one row per move:
+//!
+//! ```text
+//! fen,move,elo_self,elo_oppo
+//! rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1,e2e4,1050,1120
+//! ```
+//!
+//! Columns are found by name, so extra columns are fine and the test
+//! sets of the Python Maia2 pipeline, with `board`, `move`, `active_elo`
+//! and `opponent_elo` columns, are read as they are. [`prediction_accuracy`]
+//! reads the file row by row and evaluates it a batch at a time, so its
+//! memory use does not grow with the file.
+
+use std::{io::BufRead, str::FromStr};
+
+use shakmaty::{
+    CastlingMode, Chess, EnPassantMode, KnownOutcome, Position, fen::Fen, uci::UciMove,
+};
+
+use crate::{
+    analysis::{ELO_BUCKETS, GameMoves},
+    error::Error,
+    evaluator::Evaluator,
+};
+
+/// Probability assumed for a played move missing from the policy, so that
+/// its log-likelihood stays finite.
+const MIN_PROBABILITY: f64 = 1e-6;
+
+/// Accepted names of each column, compared case-insensitively.
+const FEN_COLUMNS: [&str; 2] = ["fen", "board"];
+const MOVE_COLUMNS: [&str; 2] = ["move", "played_move"];
+const ELO_SELF_COLUMNS: [&str; 2] = ["elo_self", "active_elo"];
+const ELO_OPPO_COLUMNS: [&str; 2] = ["elo_oppo", "opponent_elo"];
+
+/// How [`prediction_accuracy`] batches and groups the rows.
+#[derive(Debug, Clone)]
+pub struct PredictionConfig {
+    /// Most positions sent to the evaluator in one call.
+    pub batch_size: usize,
+    /// Lower bounds of the rating buckets, ascending. A row counts towards
+    /// the last bucket not above the mover's rating, or the first one if
+    /// the rating is below them all.
+    pub buckets: Vec<f32>,
+}
+
+impl Default for PredictionConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 512,
+            buckets: ELO_BUCKETS.to_vec(),
+        }
+    }
+}
+
+/// Prediction counts of a set of moves.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PredictionStats {
+    /// Moves evaluated.
+    pub moves: usize,
+    /// Moves that were the most likely prediction, ties included.
+    pub top1: usize,
+    /// Moves among the three most likely predictions.
+    pub top3: usize,
+    /// Sum over the moves of the negative natural log of their
+    /// predicted probability.
+    pub log_loss: f64,
+}
+
+impl PredictionStats {
+    /// Fraction of moves matching the top prediction.
+    pub fn top1_rate(&self) -> f32 {
+        self.top1 as f32 / self.moves.max(1) as f32
+    }
+
+    /// Fraction of moves among the top three predictions.
+    pub fn top3_rate(&self) -> f32 {
+        self.top3 as f32 / self.moves.max(1) as f32
+    }
+
+    /// Perplexity of the played moves, the exponential of the mean log
+    /// loss: 1 for perfect predictions, the number of legal moves for a
+    /// uniform policy. 1 without moves.
+    pub fn perplexity(&self) -> f64 {
+        (self.log_loss / self.moves.max(1) as f64).exp()
+    }
+
+    fn add(&mut self, rank: Option<usize>, probability: f64) {
+        self.moves += 1;
+        self.top1 += usize::from(rank == Some(1));
+        self.top3 += usize::from(rank.is_some_and(|r| r <= 3));
+        self.log_loss -= probability.max(MIN_PROBABILITY).ln();
+    }
+}
+
+/// Prediction counts of the moves whose mover is rated in one bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketAccuracy {
+    /// Lower bound of the bucket.
+    pub elo: f32,
+    pub stats: PredictionStats,
+}
+
+/// Result of [`prediction_accuracy`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccuracyReport {
+    pub overall: PredictionStats,
+    /// One entry per [`PredictionConfig::buckets`] rating.
+    pub buckets: Vec<BucketAccuracy>,
+    /// Rows that were not evaluated: malformed, with an illegal move or
+    /// in a finished game.
+    pub skipped: usize,
+}
+
+/// A dataset row ready for evaluation.
+struct LabeledMove {
+    position: Chess,
+    played: UciMove,
+    elo_self: f32,
+    elo_oppo: f32,
+}
+
+/// Where the used columns are in a row.
+struct Columns {
+    fen: usize,
+    played: usize,
+    elo_self: usize,
+    elo_oppo: usize,
+}
+
+impl Columns {
+    fn from_header(header: &str) -> Result<Self, Error> {
+        let names = split_row(header);
+        let find = |accepted: &[&str]| {
+            names
+                .iter()
+                .position(|name| accepted.iter().any(|a| name.trim().eq_ignore_ascii_case(a)))
+                .ok_or_else(|| {
+                    Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("dataset header has no {} column", accepted[0]),
+                    ))
+                })
+        };
+        Ok(Self {
+            fen: find(&FEN_COLUMNS)?,
+            played: find(&MOVE_COLUMNS)?,
+            elo_self: find(&ELO_SELF_COLUMNS)?,
+            elo_oppo: find(&ELO_OPPO_COLUMNS)?,
+        })
+    }
+
+    fn parse(&self, row: &str) -> Result<LabeledMove, String> {
+        let fields = split_row(row);
+        let field = |i: usize| {
+            fields
+                .get(i)
+                .map(|f| f.trim())
+                .ok_or_else(|| format!("missing column {}", i + 1))
+        };
+        let fen: Fen = field(self.fen)?
+            .parse()
+            .map_err(|e| format!("bad FEN: {e}"))?;
+        let position: Chess = fen
+            .into_position(CastlingMode::Standard)
+            .map_err(|e| format!("illegal position: {e}"))?;
+        let played = UciMove::from_str(field(self.played)?)
+            .map_err(|e| format!("bad move: {e}"))?
+            .to_move(&position)
+            .map_err(|e| format!("illegal move: {e}"))?
+            .to_uci(CastlingMode::Standard);
+        let elo = |i| {
+            field(i)?
+                .parse::<f32>()
+                .map_err(|e| format!("bad rating: {e}"))
+        };
+        Ok(LabeledMove {
+            elo_self: elo(self.elo_self)?,
+            elo_oppo: elo(self.elo_oppo)?,
+            position,
+            played,
+        })
+    }
+}
+
+/// Fields of a CSV row, with double-quoted fields unquoted.
+fn split_row(row: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Top-1 and top-3 accuracy and perplexity of `evaluator`'s predictions
+/// of the moves in a CSV `dataset`, overall and by the mover's rating.
+///
+/// Every position is evaluated with the row's own ratings, at most
+/// `config.batch_size` rows per evaluator call. Moves tied with the most
+/// likely one count as top-1 matches, as in
+/// [`EvaluationResult::rank_of`](crate::EvaluationResult::rank_of). Rows
+/// that cannot be evaluated are logged, counted in
+/// [`AccuracyReport::skipped`] and otherwise ignored.
+///
+/// # Errors
+/// Returns [`Error::Io`] if reading fails or the header lacks one of the
+/// columns, and propagates evaluation errors.
+pub fn prediction_accuracy(
+    evaluator: &mut impl Evaluator,
+    dataset: impl BufRead,
+    config: &PredictionConfig,
+) -> Result<AccuracyReport, Error> {
+    let mut report = AccuracyReport {
+        overall: PredictionStats::default(),
+        buckets: config
+            .buckets
+            .iter()
+            .map(|&elo| BucketAccuracy {
+                elo,
+                stats: PredictionStats::default(),
+            })
+            .collect(),
+        skipped: 0,
+    };
+    let mut lines = dataset.lines().enumerate();
+    let columns = match lines.next() {
+        Some((_, header)) => Columns::from_header(&header?)?,
+        None => return Ok(report),
+    };
+
+    let batch_size = config.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+    for (number, line) in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match columns.parse(&line) {
+            Ok(row) if row.position.legal_moves().is_empty() => {
+                tracing::warn!(line = number + 1, "skipping dataset row: game is over");
+                report.skipped += 1;
+            }
+            Ok(row) => batch.push(row),
+            Err(reason) => {
+                tracing::warn!(line = number + 1, "skipping dataset row: {reason}");
+                report.skipped += 1;
+            }
+        }
+        if batch.len() == batch_size {
+            score_batch(evaluator, &mut batch, &mut report)?;
+        }
+    }
+    score_batch(evaluator, &mut batch, &mut report)?;
+    Ok(report)
+}
+
+/// Evaluate and count the rows of `batch`, emptying it.
+fn score_batch(
+    evaluator: &mut impl Evaluator,
+    batch: &mut Vec<LabeledMove>,
+    report: &mut AccuracyReport,
+) -> Result<(), Error> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let setups = batch
+        .iter()
+        .map(|row| row.position.to_setup(EnPassantMode::Legal))
+        .collect();
+    let elo_selfs: Vec<f32> = batch.iter().map(|row| row.elo_self).collect();
+    let elo_oppos: Vec<f32> = batch.iter().map(|row| row.elo_oppo).collect();
+    let results = evaluator.batch_evaluate(setups, &elo_selfs, &elo_oppos)?;
+
+    for (row, result) in batch.drain(..).zip(results) {
+        let probability = result
+            .policy
+            .iter()
+            .find(|m| m.uci == row.played)
+            .map_or(0.0, |m| f64::from(m.probability));
+        let rank = result.rank_of(&row.played);
+        report.overall.add(rank, probability);
+        let bucket = report
+            .buckets
+            .partition_point(|b| b.elo <= row.elo_self)
+            .saturating_sub(1);
+        if let Some(bucket) = report.buckets.get_mut(bucket) {
+            bucket.stats.add(rank, probability);
+        }
+    }
+    Ok(())
+}
+
+/// A finished game and its players' ratings, for [`calibration`].
+#[derive(Debug, Clone)]
+pub struct RatedGame {
+    pub moves: GameMoves,
+    pub white_elo: f32,
+    pub black_elo: f32,
+    pub outcome: KnownOutcome,
+}
+
+/// Stage of the game a position is in, for [`CalibrationReport::by_phase`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamePhase {
+    /// The first twenty plies.
+    Opening,
+    Middlegame,
+    /// At most 26 points of pieces left on the board, counting knights and
+    /// bishops as 3, rooks as 5 and queens as 9; kings and pawns are not
+    /// counted. The starting position has 62.
+    Endgame,
+}
+
+impl GamePhase {
+    pub const ALL: [Self; 3] = [Self::Opening, Self::Middlegame, Self::Endgame];
+
+    /// Phase of `pos`, reached after `ply` half-moves of the game.
+    pub fn of(pos: &Chess, ply: usize) -> Self {
+        let board = pos.board();
+        let material = 3 * (board.knights() | board.bishops()).count()
+            + 5 * board.rooks().count()
+            + 9 * board.queens().count();
+        if material <= 26 {
+            Self::Endgame
+        } else if ply < 20 {
+            Self::Opening
+        } else {
+            Self::Middlegame
+        }
+    }
+}
+
+/// How [`calibration`] samples and groups the positions.
+#[derive(Debug, Clone)]
+pub struct CalibrationConfig {
+    /// Probability bins, equal-width over `[0, 1]`.
+    pub bins: usize,
+    /// Evaluate every this many plies of a game, starting with its first
+    /// position, so that neighbouring, strongly correlated positions do
+    /// not dominate.
+    pub stride: usize,
+    /// Most positions sent to the evaluator in one call.
+    pub batch_size: usize,
+    /// Lower bounds of the rating buckets of
+    /// [`CalibrationReport::by_elo`], as [`PredictionConfig::buckets`].
+    pub elo_buckets: Vec<f32>,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            bins: 10,
+            stride: 4,
+            batch_size: 512,
+            elo_buckets: ELO_BUCKETS.to_vec(),
+        }
+    }
+}
+
+/// Positions whose predicted score fell into one probability bin.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationBin {
+    /// Lower and upper bound of the predicted score.
+    pub low: f32,
+    pub high: f32,
+    pub positions: usize,
+    /// Mean predicted score of the side to move, 0 if the bin is empty.
+    pub mean_predicted: f64,
+    /// Mean score the side to move actually made, 0 if the bin is empty.
+    pub observed_score: f64,
+}
+
+/// Predicted against actual scores of a set of positions.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    pub bins: Vec<CalibrationBin>,
+    pub positions: usize,
+    /// Mean squared difference between the predicted and the actual score
+    /// of the side to move.
+    pub brier: f64,
+    /// Mean negative natural log of the probability the value head gave
+    /// the actual result, out of win, draw and loss.
+    pub log_loss: f64,
+}
+
+impl Calibration {
+    fn new(bins: usize) -> Self {
+        let bins = bins.max(1);
+        Self {
+            bins: (0..bins)
+                .map(|i| CalibrationBin {
+                    low: i as f32 / bins as f32,
+                    high: (i + 1) as f32 / bins as f32,
+                    positions: 0,
+                    mean_predicted: 0.0,
+                    observed_score: 0.0,
+                })
+                .collect(),
+            positions: 0,
+            brier: 0.0,
+            log_loss: 0.0,
+        }
+    }
+
+    /// Count a prediction of `predicted` that ended in `actual`, with the
+    /// value head's probability `outcome_probability` of that result.
+    /// Means are kept as running sums until [`finish`](Self::finish).
+    fn add(&mut self, predicted: f32, actual: f32, outcome_probability: f32) {
+        let predicted = predicted.clamp(0.0, 1.0);
+        let bins = self.bins.len();
+        let bin = &mut self.bins[((predicted * bins as f32) as usize).min(bins - 1)];
+        bin.positions += 1;
+        bin.mean_predicted += f64::from(predicted);
+        bin.observed_score += f64::from(actual);
+        self.positions += 1;
+        self.brier += f64::from(predicted - actual).powi(2);
+        self.log_loss -= f64::from(outcome_probability).max(MIN_PROBABILITY).ln();
+    }
+
+    fn finish(&mut self) {
+        for bin in &mut self.bins {
+            let n = bin.positions.max(1) as f64;
+            bin.mean_predicted /= n;
+            bin.observed_score /= n;
+        }
+        let n = self.positions.max(1) as f64;
+        self.brier /= n;
+        self.log_loss /= n;
+    }
+
+    /// The score players actually make at a predicted score of
+    /// `predicted`, interpolated linearly between the mean predictions of
+    /// the non-empty bins and held constant beyond the first and last.
+    /// `predicted` itself if every bin is empty.
+    pub fn calibrated(&self, predicted: f32) -> f32 {
+        let points: Vec<(f64, f64)> = self
+            .bins
+            .iter()
+            .filter(|bin| bin.positions > 0)
+            .map(|bin| (bin.mean_predicted, bin.observed_score))
+            .collect();
+        let p = f64::from(predicted);
+        let upper = points.partition_point(|&(x, _)| x < p);
+        let score = match (upper.checked_sub(1).map(|i| points[i]), points.get(upper)) {
+            (None, None) => p,
+            (Some((_, y)), None) | (None, Some(&(_, y))) => y,
+            (Some((x0, y0)), Some(&(x1, y1))) => y0 + (p - x0) / (x1 - x0) * (y1 - y0),
+        };
+        score as f32
+    }
+}
+
+/// [`Calibration`] of the positions whose mover is rated in one bucket.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct EloCalibration {
+    /// Lower bound of the bucket.
+    pub elo: f32,
+    pub calibration: Calibration,
+}
+
+/// [`Calibration`] of the positions of one phase.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseCalibration {
+    pub phase: GamePhase,
+    pub calibration: Calibration,
+}
+
+/// Result of [`calibration`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationReport {
+    pub overall: Calibration,
+    /// One entry per [`CalibrationConfig::elo_buckets`] rating.
+    pub by_elo: Vec<EloCalibration>,
+    /// One entry per [`GamePhase`], in [`GamePhase::ALL`] order.
+    pub by_phase: Vec<PhaseCalibration>,
+}
+
+/// A sampled position waiting for its evaluation.
+struct CalibrationSample {
+    position: Chess,
+    phase: GamePhase,
+    elo_self: f32,
+    elo_oppo: f32,
+    outcome: KnownOutcome,
+}
+
+/// How well the value head's expected score for the side to move
+/// predicts the score it actually made in `games`.
+///
+/// Every [`CalibrationConfig::stride`]-th position of each game is
+/// evaluated with the players' ratings; finished positions are left out.
+/// Predictions are binned by value and compared with the game results,
+/// overall, by the mover's rating and by [`GamePhase`]. A well-calibrated
+/// value puts every bin's observed score close to its mean prediction;
+/// [`Calibration::calibrated`] maps raw values onto the observed curve.
+///
+/// # Errors
+/// Returns [`Error::IllegalMove`] for a game with an illegal move and
+/// propagates evaluation errors.
+pub fn calibration(
+    evaluator: &mut impl Evaluator,
+    games: &[RatedGame],
+    config: &CalibrationConfig,
+) -> Result<CalibrationReport, Error> {
+    let mut report = CalibrationReport {
+        overall: Calibration::new(config.bins),
+        by_elo: config
+            .elo_buckets
+            .iter()
+            .map(|&elo| EloCalibration {
+                elo,
+                calibration: Calibration::new(config.bins),
+            })
+            .collect(),
+        by_phase: GamePhase::ALL
+            .into_iter()
+            .map(|phase| PhaseCalibration {
+                phase,
+                calibration: Calibration::new(config.bins),
+            })
+            .collect(),
+    };
+
+    let batch_size = config.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+    for game in games {
+        let positions = game.moves.positions()?;
+        for (ply, position) in positions
+            .into_iter()
+            .enumerate()
+            .step_by(config.stride.max(1))
+        {
+            if position.legal_moves().is_empty() {
+                continue;
+            }
+            let turn = position.turn();
+            let (elo_self, elo_oppo) = turn.fold_wb(
+                (game.white_elo, game.black_elo),
+                (game.black_elo, game.white_elo),
+            );
+            batch.push(CalibrationSample {
+                phase: GamePhase::of(&position, ply),
+                outcome: game.outcome,
+                position,
+                elo_self,
+                elo_oppo,
+            });
+            if batch.len() == batch_size {
+                calibrate_batch(evaluator, &mut batch, &mut report)?;
+            }
+        }
+    }
+    calibrate_batch(evaluator, &mut batch, &mut report)?;
+
+    report.overall.finish();
+    for bucket in &mut report.by_elo {
+        bucket.calibration.finish();
+    }
+    for phase in &mut report.by_phase {
+        phase.calibration.finish();
+    }
+    Ok(report)
+}
+
+/// Evaluate and count the positions of `batch`, emptying it.
+fn calibrate_batch(
+    evaluator: &mut impl Evaluator,
+    batch: &mut Vec<CalibrationSample>,
+    report: &mut CalibrationReport,
+) -> Result<(), Error> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let setups = batch
+        .iter()
+        .map(|s| s.position.to_setup(EnPassantMode::Legal))
+        .collect();
+    let elo_selfs: Vec<f32> = batch.iter().map(|s| s.elo_self).collect();
+    let elo_oppos: Vec<f32> = batch.iter().map(|s| s.elo_oppo).collect();
+    let results = evaluator.batch_evaluate(setups, &elo_selfs, &elo_oppos)?;
+
+    for (sample, result) in batch.drain(..).zip(results) {
+        let turn = sample.position.turn();
+        let predicted = result.expected_score(turn);
+        let (win, loss) = turn.fold_wb(
+            (result.white_wr, result.black_wr),
+            (result.black_wr, result.white_wr),
+        );
+        let (actual, outcome_probability) = match sample.outcome {
+            KnownOutcome::Decisive { winner } if winner == turn => (1.0, win),
+            KnownOutcome::Decisive { .. } => (0.0, loss),
+            KnownOutcome::Draw => (0.5, result.draw),
+        };
+        let add = |calibration: &mut Calibration| {
+            calibration.add(predicted, actual, outcome_probability);
+        };
+        add(&mut report.overall);
+        let bucket = report
+            .by_elo
+            .partition_point(|b| b.elo <= sample.elo_self)
+            .saturating_sub(1);
+        if let Some(bucket) = report.by_elo.get_mut(bucket) {
+            add(&mut bucket.calibration);
+        }
+        if let Some(phase) = report.by_phase.iter_mut().find(|p| p.phase == sample.phase) {
+            add(&mut phase.calibration);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::UniformEvaluator;
+
+    const SAMPLE: &str = include_str!("data/prediction_sample.csv");
+
+    /// Counts the calls and their sizes.
+    struct CountingEvaluator(Vec<usize>);
+
+    impl Evaluator for CountingEvaluator {
+        fn batch_evaluate(
+            &mut self,
+            setups: Vec<shakmaty::Setup>,
+            elo_selfs: &[f32],
+            elo_oppos: &[f32],
+        ) -> Result<Vec<crate::EvaluationResult>, Error> {
+            self.0.push(setups.len());
+            UniformEvaluator.batch_evaluate(setups, elo_selfs, elo_oppos)
+        }
+    }
+
+    #[test]
+    fn sample_dataset_is_scored_in_batches_by_bucket() {
+        let mut evaluator = CountingEvaluator(Vec::new());
+        let config = PredictionConfig {
+            batch_size: 4,
+            ..PredictionConfig::default()
+        };
+        let report = prediction_accuracy(&mut evaluator, SAMPLE.as_bytes(), &config).unwrap();
+
+        assert_eq!(evaluator.0, [4, 4, 2]);
+        assert_eq!(report.skipped, 0);
+        assert_eq!(report.overall.moves, 10);
+        // A uniform policy ties every move for first.
+        assert_eq!(report.overall.top1, 10);
+        let moves: Vec<usize> = report.buckets.iter().map(|b| b.stats.moves).collect();
+        assert_eq!(moves, [2, 1, 0, 0, 3, 0, 0, 0, 0, 3, 1]);
+        // The 2150 player's only move is from the start position.
+        let top = report.buckets.last().unwrap().stats;
+        assert!(
+            (top.perplexity() - 20.0).abs() < 1e-3,
+            "{}",
+            top.perplexity()
+        );
+        assert!(report.overall.perplexity() > 20.0);
+    }
+
+    #[test]
+    fn maia2_columns_are_found_and_bad_rows_skipped() {
+        let dataset = "\
+board,move,active_elo,opponent_elo,active_win
+\"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1\",e2e4,1500,1500,1
+rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1,e2e5,1500,1500,0
+not a fen,e2e4,1500,1500,0
+rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1,e2e4,strong,1500,1
+
+rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3,e2e3,900,900,0
+";
+        let report = prediction_accuracy(
+            &mut UniformEvaluator,
+            dataset.as_bytes(),
+            &PredictionConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(report.overall.moves, 1);
+        assert_eq!(report.skipped, 4);
+
+        assert!(matches!(
+            prediction_accuracy(
+                &mut UniformEvaluator,
+                "fen,move,elo\n".as_bytes(),
+                &PredictionConfig::default()
+            ),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn calibration_bins_predictions_against_results() {
+        let games = [RatedGame {
+            moves: GameMoves::from_uci("e2e4 e7e5 g1f3 b8c6").unwrap(),
+            white_elo: 1500.0,
+            black_elo: 1250.0,
+            outcome: KnownOutcome::Decisive {
+                winner: shakmaty::Color::White,
+            },
+        }];
+        let config = CalibrationConfig {
+            stride: 1,
+            ..CalibrationConfig::default()
+        };
+        let report = calibration(&mut UniformEvaluator, &games, &config).unwrap();
+
+        // White expects 0.55 and wins, Black expects 0.45 and loses.
+        let overall = &report.overall;
+        assert_eq!(overall.positions, 5);
+        assert_eq!(overall.bins[5].positions, 3);
+        assert_eq!(overall.bins[5].observed_score, 1.0);
+        assert_eq!(overall.bins[4].positions, 2);
+        assert_eq!(overall.bins[4].observed_score, 0.0);
+        assert!((overall.brier - 0.2025).abs() < 1e-6);
+        assert!((overall.log_loss + 0.4f64.ln()).abs() < 1e-6);
+        assert!((overall.calibrated(0.5) - 0.5).abs() < 1e-5);
+        assert_eq!(overall.calibrated(0.9), 1.0);
+
+        let positions: Vec<usize> = report
+            .by_elo
+            .iter()
+            .map(|b| b.calibration.positions)
+            .collect();
+        assert_eq!(positions, [0, 0, 2, 0, 0, 3, 0, 0, 0, 0, 0]);
+        assert_eq!(report.by_phase[0].phase, GamePhase::Opening);
+        assert_eq!(report.by_phase[0].calibration.positions, 5);
+
+        let config = CalibrationConfig {
+            stride: 2,
+            ..CalibrationConfig::default()
+        };
+        let report = calibration(&mut UniformEvaluator, &games, &config).unwrap();
+        assert_eq!(report.overall.positions, 3);
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&report).unwrap();
+            let back: CalibrationReport = serde_json::from_str(&json).unwrap();
+            assert_eq!(back, report);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Common interface of everything that evaluates positions.
+//!
+//! [`Maia`] is the basic implementation. Wrappers that adjust results
+//! (such as [`BookBlendedEvaluator`](crate::BookBlendedEvaluator)) take
+//! any `impl Evaluator`, so they stack and work the same whether the
+//! model runs in-process or behind a [`MaiaService`].
+
+use shakmaty::Setup;
+
+use crate::{Maia, MaiaService, backend::InferenceBackend, error::Error, types::EvaluationResult};
+
+/// Source of Maia-style evaluations.
+pub trait Evaluator {
+    /// Evaluate a batch of positions, with the same contract as
+    /// [`Maia::batch_evaluate`].
+    ///
+    /// # Errors
+    /// See [`Error`] for possible failure modes.
+    fn batch_evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error>;
+
+    /// Evaluate a single position.
+    ///
+    /// # Errors
+    /// See [`Error`] for possible failure modes.
+    fn evaluate(
+        &mut self,
+        setup: Setup,
+        elo_self: f32,
+        elo_oppo: f32,
+    ) -> Result<EvaluationResult, Error> {
+        let results = self.batch_evaluate(vec![setup], &[elo_self], &[elo_oppo])?;
+        Ok(results.into_iter().next().unwrap())
+    }
+}
+
+impl<B: InferenceBackend> Evaluator for Maia<B> {
+    fn batch_evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        Maia::batch_evaluate(self, setups, elo_selfs, elo_oppos)
+    }
+}
+
+impl Evaluator for MaiaService {
+    /// Fails with the first per-position error, if any.
+    fn batch_evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        MaiaService::batch_evaluate(self, setups, elo_selfs, elo_oppos)
+            .into_iter()
+            .collect()
+    }
+}
+
+impl<E: Evaluator + ?Sized> Evaluator for &mut E {
+    fn batch_evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        (**self).batch_evaluate(setups, elo_selfs, elo_oppos)
+    }
+}
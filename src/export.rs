@@ -0,0 +1,601 @@
+//! Writers for dumping evaluation results to files for offline analysis.
+//!
+//! Both formats pair each [`EvaluationResult`] with a [`RecordMeta`]
+//! describing the input that produced it, so the output can be joined
+//! with other data without carrying a separate index around.
+//!
+//! Policies can also be written as a Polyglot opening book
+//! ([`polyglot_entries`], [`write_polyglot`]) whose weights are Maia's
+//! move probabilities, giving a "human book" for any engine or GUI.
+//!
+//! [`verbose_move_stats`] renders a policy in the layout of lc0's
+//! `--verbose-move-stats`, for tools that read that output.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use serde_json::json;
+use shakmaty::{
+    CastlingMode, CastlingSide, Chess, Color, EnPassantMode, Position, Role,
+    uci::UciMove,
+    zobrist::{Zobrist64, ZobristValue},
+};
+
+use crate::{
+    moves::ALL_MOVES,
+    tree::TreeNode,
+    types::{EvaluationResult, Terminal},
+};
+
+/// Input description stored alongside each exported result.
+#[derive(Debug, Clone)]
+pub struct RecordMeta {
+    /// FEN of the evaluated position.
+    pub fen: String,
+    /// Elo of the side to move.
+    pub elo_self: f32,
+    /// Elo of the opponent.
+    pub elo_oppo: f32,
+}
+
+/// Column order of [`write_csv`]. Kept stable across releases.
+pub const CSV_HEADER: [&str; 9] = [
+    "fen",
+    "elo_self",
+    "elo_oppo",
+    "rank",
+    "uci",
+    "probability",
+    "white_wr",
+    "draw",
+    "black_wr",
+];
+
+/// Write one JSON object per result, each on its own line.
+///
+/// Every line holds the metadata fields, the three outcome
+/// probabilities and the full policy as a list of `{uci, probability}`
+/// objects.
+///
+/// # Panics
+/// Panics if `results` and `meta` have different lengths.
+pub fn write_jsonl<W: Write>(
+    results: &[EvaluationResult],
+    meta: &[RecordMeta],
+    mut w: W,
+) -> io::Result<()> {
+    assert_eq!(results.len(), meta.len());
+
+    for (result, meta) in results.iter().zip(meta) {
+        let policy: Vec<_> = result
+            .policy
+            .iter()
+            .map(|m| json!({ "uci": m.uci.to_string(), "probability": m.probability }))
+            .collect();
+        let line = json!({
+            "fen": meta.fen,
+            "elo_self": meta.elo_self,
+            "elo_oppo": meta.elo_oppo,
+            "white_wr": result.white_wr,
+            "draw": result.draw,
+            "black_wr": result.black_wr,
+            "policy": policy,
+        });
+
+        serde_json::to_writer(&mut w, &line)?;
+        w.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Write results as CSV with one row per (position, move) pair.
+///
+/// The first row is the header ([`CSV_HEADER`]). `rank` starts at 1 for
+/// the most probable move; the outcome columns repeat for every move of
+/// a position. Positions without legal moves produce a single row with
+/// empty move columns so they are not silently dropped.
+///
+/// # Panics
+/// Panics if `results` and `meta` have different lengths.
+pub fn write_csv<W: Write>(
+    results: &[EvaluationResult],
+    meta: &[RecordMeta],
+    mut w: W,
+) -> io::Result<()> {
+    assert_eq!(results.len(), meta.len());
+
+    write_csv_row(&mut w, CSV_HEADER)?;
+    for (result, meta) in results.iter().zip(meta) {
+        let prefix = [
+            meta.fen.clone(),
+            meta.elo_self.to_string(),
+            meta.elo_oppo.to_string(),
+        ];
+        let outcome = [
+            result.white_wr.to_string(),
+            result.draw.to_string(),
+            result.black_wr.to_string(),
+        ];
+
+        if result.policy.is_empty() {
+            let no_move = [String::new(), String::new(), String::new()];
+            write_csv_row(&mut w, prefix.iter().chain(&no_move).chain(&outcome))?;
+        }
+        for (rank, m) in result.policy.iter().enumerate() {
+            let moves = [
+                (rank + 1).to_string(),
+                m.uci.to_string(),
+                m.probability.to_string(),
+            ];
+            write_csv_row(&mut w, prefix.iter().chain(&moves).chain(&outcome))?;
+        }
+    }
+    Ok(())
+}
+
+fn write_csv_row<W: Write, S: AsRef<str>>(
+    w: &mut W,
+    fields: impl IntoIterator<Item = S>,
+) -> io::Result<()> {
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            w.write_all(b",")?;
+        }
+        let field = field.as_ref();
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(w, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            w.write_all(field.as_bytes())?;
+        }
+    }
+    w.write_all(b"\n")
+}
+
+/// One 16-byte record of a Polyglot `.bin` book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolyglotEntry {
+    /// Polyglot hash of the position, see [`polyglot_key`].
+    pub key: u64,
+    /// Encoded move, see [`polyglot_move`].
+    pub mv: u16,
+    /// Relative weight of the move among the entries of its position.
+    pub weight: u16,
+    /// Learning data; unused and written as 0.
+    pub learn: u32,
+}
+
+impl PolyglotEntry {
+    /// Size of an entry on disk.
+    pub const SIZE: usize = 16;
+
+    /// Big-endian on-disk representation.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.mv.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        bytes
+    }
+
+    /// Parse the on-disk representation.
+    pub fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        let [
+            k0,
+            k1,
+            k2,
+            k3,
+            k4,
+            k5,
+            k6,
+            k7,
+            m0,
+            m1,
+            w0,
+            w1,
+            l0,
+            l1,
+            l2,
+            l3,
+        ] = bytes;
+        Self {
+            key: u64::from_be_bytes([k0, k1, k2, k3, k4, k5, k6, k7]),
+            mv: u16::from_be_bytes([m0, m1]),
+            weight: u16::from_be_bytes([w0, w1]),
+            learn: u32::from_be_bytes([l0, l1, l2, l3]),
+        }
+    }
+}
+
+/// Polyglot hash of `pos`.
+///
+/// Polyglot keys use the same random table as shakmaty's [`Zobrist64`];
+/// the en passant file is only hashed if a pawn of the side to move could
+/// capture, which is shakmaty's pseudo-legal en passant mode.
+pub fn polyglot_key(pos: &Chess) -> u64 {
+    pos.zobrist_hash::<Zobrist64>(EnPassantMode::PseudoLegal).0
+}
+
+/// [`polyglot_key`] of `pos` mirrored, with the board flipped and the
+/// colors swapped, without building the mirrored position.
+pub(crate) fn mirrored_polyglot_key(pos: &Chess) -> u64 {
+    let mut key = Zobrist64::default();
+    for (sq, mut piece) in pos.board().iter() {
+        piece.color = !piece.color;
+        key ^= Zobrist64::zobrist_for_piece(sq.flip_vertical(), piece);
+    }
+    if pos.turn() == Color::Black {
+        key ^= Zobrist64::zobrist_for_white_turn();
+    }
+    for color in Color::ALL {
+        for side in CastlingSide::ALL {
+            if pos.castles().has(color, side) {
+                key ^= Zobrist64::zobrist_for_castling_right(!color, side);
+            }
+        }
+    }
+    if let Some(sq) = pos.ep_square(EnPassantMode::PseudoLegal) {
+        key ^= Zobrist64::zobrist_for_en_passant_file(sq.file());
+    }
+    key.0
+}
+
+/// Encode a move legal in `pos` the way Polyglot books store it.
+///
+/// Bits 0–5 hold the destination square, 6–11 the origin and 12–14 the
+/// promotion piece (1 = knight ... 4 = queen). Castling is encoded as the
+/// king capturing its own rook (`e1h1` rather than `e1g1`). Returns `None`
+/// for moves that are not legal in `pos`.
+pub fn polyglot_move(pos: &Chess, uci: UciMove) -> Option<u16> {
+    let m = uci.to_move(pos).ok()?;
+    let UciMove::Normal {
+        from,
+        to,
+        promotion,
+    } = m.to_uci(CastlingMode::Chess960)
+    else {
+        return None;
+    };
+
+    let promotion = match promotion {
+        None => 0,
+        Some(Role::Knight) => 1,
+        Some(Role::Bishop) => 2,
+        Some(Role::Rook) => 3,
+        Some(_) => 4,
+    };
+    Some((promotion << 12) | (u16::from(from) << 6) | u16::from(to))
+}
+
+/// Book entries for the policy of `result`, which must be the evaluation
+/// of `pos`.
+///
+/// Weights are `round(probability * 65535)`, so they sum to about 65535
+/// per position. Moves whose weight rounds to zero are omitted since
+/// Polyglot readers never play them.
+pub fn polyglot_entries(pos: &Chess, result: &EvaluationResult) -> Vec<PolyglotEntry> {
+    let key = polyglot_key(pos);
+    result
+        .policy
+        .iter()
+        .filter_map(|m| {
+            let weight = (m.probability.clamp(0.0, 1.0) * f32::from(u16::MAX)).round() as u16;
+            let mv = polyglot_move(pos, m.uci)?;
+            (weight > 0).then_some(PolyglotEntry {
+                key,
+                mv,
+                weight,
+                learn: 0,
+            })
+        })
+        .collect()
+}
+
+/// Write entries for any number of positions as a Polyglot `.bin` book.
+///
+/// Entries are sorted by key (as readers require) and by descending
+/// weight within a position. If the same move of the same position
+/// occurs more than once, e.g. because a transposition was evaluated
+/// twice, only the first occurrence is kept.
+pub fn write_polyglot<W: Write>(
+    entries: impl IntoIterator<Item = PolyglotEntry>,
+    mut w: W,
+) -> io::Result<()> {
+    let mut entries: Vec<PolyglotEntry> = entries.into_iter().collect();
+    // Stable sort, so the first occurrence of a duplicate stays first.
+    entries.sort_by_key(|e| (e.key, e.mv));
+    entries.dedup_by_key(|e| (e.key, e.mv));
+    entries.sort_by_key(|e| (e.key, std::cmp::Reverse(e.weight)));
+
+    for entry in entries {
+        w.write_all(&entry.to_bytes())?;
+    }
+    Ok(())
+}
+
+/// The policy of `result`, the evaluation of a position with `turn` to
+/// move, as lc0 prints it with `--verbose-move-stats`: one `info string`
+/// line per move, least likely first, then a `node` line for the
+/// position itself.
+///
+/// `children` holds the evaluations of the positions after some of the
+/// moves, e.g. from [`TreeEvaluator`](crate::tree::TreeEvaluator); see
+/// [`tree_verbose_move_stats`]. There is no search behind the numbers,
+/// so most fields are synthesized:
+///
+/// - `P` is Maia's move probability and the index in parentheses the
+///   move's index in Maia's vocabulary, not lc0's.
+/// - `N` is 1 for a move with an evaluated child and 0 otherwise; the
+///   `node` line counts itself and its children. Nothing is in flight.
+/// - `WL`, `D`, `Q` and `V` come from the child's value, from the point
+///   of view of the side to move; `Q` and `V` are the same, both equal
+///   to `WL`. Moves without a child print them as `-.-----`.
+/// - `M`, `U` and `S` have no Maia counterpart and are left out.
+pub fn verbose_move_stats(
+    turn: Color,
+    result: &EvaluationResult,
+    children: &[(UciMove, &EvaluationResult)],
+) -> String {
+    let mut text = String::new();
+    for m in result.policy.iter().rev() {
+        let vocabulary = match turn {
+            Color::White => m.uci,
+            Color::Black => m.uci.to_mirrored(),
+        };
+        let index = ALL_MOVES
+            .get(&vocabulary)
+            .map_or_else(String::new, usize::to_string);
+        let child = children.iter().find(|(uci, _)| *uci == m.uci);
+        let value = child.map(|(_, child)| child_value(turn, child));
+        stats_line(
+            &mut text,
+            &m.uci.to_string(),
+            &index,
+            usize::from(child.is_some()),
+            Some(m.probability),
+            value,
+        );
+    }
+    stats_line(
+        &mut text,
+        "node",
+        "",
+        1 + children.len(),
+        None,
+        Some(wl_d(turn, result)),
+    );
+    text
+}
+
+/// [`verbose_move_stats`] of node `root` of an evaluated tree, with its
+/// child nodes as the children.
+///
+/// # Panics
+/// Panics if `root` is out of bounds.
+pub fn tree_verbose_move_stats(nodes: &[TreeNode], root: usize) -> String {
+    let children: Vec<(UciMove, &EvaluationResult)> = nodes
+        .iter()
+        .filter(|node| node.parent == Some(root))
+        .filter_map(|node| Some((node.mv?.to_uci(CastlingMode::Standard), &node.result)))
+        .collect();
+    let root = &nodes[root];
+    verbose_move_stats(root.position.turn(), &root.result, &children)
+}
+
+/// `(WL, D)` of `child` for the side that moved into it, exact if the
+/// move ended the game.
+fn child_value(mover: Color, child: &EvaluationResult) -> (f32, f32) {
+    match child.outcome {
+        Some(Terminal::Checkmate) => (1.0, 0.0),
+        Some(Terminal::Stalemate) => (0.0, 1.0),
+        None => wl_d(mover, child),
+    }
+}
+
+/// Win minus loss and draw probability of `color` in `result`.
+fn wl_d(color: Color, result: &EvaluationResult) -> (f32, f32) {
+    let (win, loss) = color.fold_wb(
+        (result.white_wr, result.black_wr),
+        (result.black_wr, result.white_wr),
+    );
+    (win - loss, result.draw)
+}
+
+/// One line of [`verbose_move_stats`], with lc0's field widths.
+fn stats_line(
+    text: &mut String,
+    name: &str,
+    index: &str,
+    visits: usize,
+    probability: Option<f32>,
+    value: Option<(f32, f32)>,
+) {
+    let p = probability.map_or_else(|| " -.--".to_string(), |p| format!("{:5.2}", 100.0 * p));
+    let (wl, d) = match value {
+        Some((wl, d)) => (format!("{wl:8.5}"), format!("{d:5.3}")),
+        None => (" -.-----".to_string(), "-.---".to_string()),
+    };
+    // Writing to a String cannot fail.
+    let _ = writeln!(
+        text,
+        "info string {name:<5} ({index:>4}) N: {visits:>7} (+ 0) (P: {p}%) \
+         (WL: {wl}) (D: {d}) (Q: {wl}) (V: {wl})"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::white_move;
+
+    fn sample() -> (Vec<EvaluationResult>, Vec<RecordMeta>) {
+        let result = EvaluationResult {
+            policy: vec![white_move("e2e4", 0.75), white_move("d2d4", 0.25)],
+            white_wr: 0.5,
+            draw: 0.25,
+            black_wr: 0.25,
+            outcome: None,
+        };
+        let meta = RecordMeta {
+            fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string(),
+            elo_self: 1500.0,
+            elo_oppo: 1600.0,
+        };
+        (vec![result], vec![meta])
+    }
+
+    #[test]
+    fn csv_has_one_row_per_move() {
+        let (results, meta) = sample();
+        let mut out = Vec::new();
+        write_csv(&results, &meta, &mut out).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER.join(","));
+        assert_eq!(
+            lines[1],
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1,1500,1600,1,e2e4,0.75,0.5,0.25,0.25"
+        );
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn csv_fields_are_escaped() {
+        let mut out = Vec::new();
+        write_csv_row(&mut out, ["plain", "a,b", "say \"hi\""]).unwrap();
+        assert_eq!(out, b"plain,\"a,b\",\"say \"\"hi\"\"\"\n");
+    }
+
+    #[test]
+    fn polyglot_keys_match_reference_values() {
+        // Keys from the Polyglot book format specification.
+        let mut pos = Chess::default();
+        assert_eq!(polyglot_key(&pos), 0x463b96181691fc9c);
+
+        for (uci, key) in [
+            ("e2e4", 0x823c9b50fd114196),
+            ("d7d5", 0x0756b94461c50fb0),
+            ("e4e5", 0x662fafb965db29d4),
+            // Black's double push can be taken en passant from e5.
+            ("f7f5", 0x22a48b5a8e47ff78),
+            ("e1e2", 0x652a607ca3f242c1),
+        ] {
+            let m = uci.parse::<UciMove>().unwrap().to_move(&pos).unwrap();
+            pos.play_unchecked(m);
+            assert_eq!(polyglot_key(&pos), key, "after {uci}");
+
+            let mut mirrored = pos.to_setup(EnPassantMode::Legal);
+            mirrored.mirror();
+            let mirrored: Chess = mirrored.position(CastlingMode::Standard).unwrap();
+            assert_eq!(mirrored_polyglot_key(&mirrored), key, "after {uci}");
+        }
+    }
+
+    #[test]
+    fn polyglot_entries_encode_moves_and_weights() {
+        let (results, _) = sample();
+        let entries = polyglot_entries(&Chess::default(), &results[0]);
+
+        // e2e4: from square 12, to square 28.
+        assert_eq!(entries[0].mv, (12 << 6) | 28);
+        assert_eq!(entries[0].weight, 49151);
+        assert_eq!(entries[0].key, 0x463b96181691fc9c);
+
+        let mut out = Vec::new();
+        write_polyglot(entries.into_iter().rev(), &mut out).unwrap();
+        assert_eq!(out.len(), 2 * PolyglotEntry::SIZE);
+        let first = PolyglotEntry::from_bytes(out[..16].try_into().unwrap());
+        assert_eq!(first.weight, 49151);
+    }
+
+    #[test]
+    fn polyglot_castling_and_promotion_encoding() {
+        let position = |fen: &str| -> Chess {
+            fen.parse::<shakmaty::fen::Fen>()
+                .unwrap()
+                .into_position(CastlingMode::Standard)
+                .unwrap()
+        };
+
+        let pos = position("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let short = polyglot_move(&pos, "e1g1".parse().unwrap()).unwrap();
+        assert_eq!(short, (4 << 6) | 7);
+
+        let pos = position("8/P7/8/8/8/8/8/k1K5 w - - 0 1");
+        let promotion = polyglot_move(&pos, "a7a8q".parse().unwrap()).unwrap();
+        assert_eq!(promotion, (4 << 12) | (48 << 6) | 56);
+    }
+
+    #[test]
+    fn verbose_move_stats_golden_text() {
+        let (results, _) = sample();
+        let child = EvaluationResult {
+            policy: Vec::new(),
+            white_wr: 0.6,
+            draw: 0.3,
+            black_wr: 0.1,
+            outcome: None,
+        };
+        let e2e4: UciMove = "e2e4".parse().unwrap();
+        let index = ALL_MOVES[&e2e4];
+        let text = verbose_move_stats(Color::White, &results[0], &[(e2e4, &child)]);
+        let expected = format!(
+            "info string d2d4  ({d4:>4}) N:       0 (+ 0) (P: 25.00%) (WL:  -.-----) (D: -.---) (Q:  -.-----) (V:  -.-----)\n\
+             info string e2e4  ({index:>4}) N:       1 (+ 0) (P: 75.00%) (WL:  0.50000) (D: 0.300) (Q:  0.50000) (V:  0.50000)\n\
+             info string node  (    ) N:       2 (+ 0) (P:  -.--%) (WL:  0.25000) (D: 0.250) (Q:  0.25000) (V:  0.25000)\n",
+            d4 = ALL_MOVES[&"d2d4".parse::<UciMove>().unwrap()],
+        );
+        assert_eq!(text, expected);
+
+        // Black's moves are looked up mirrored, and a mate is exact.
+        let mated = EvaluationResult {
+            outcome: Some(Terminal::Checkmate),
+            ..child
+        };
+        let mut black = results[0].clone();
+        black.policy.truncate(1);
+        black.policy[0].uci = "e7e5".parse().unwrap();
+        let text = verbose_move_stats(Color::Black, &black, &[(black.policy[0].uci, &mated)]);
+        assert!(text.starts_with(&format!("info string e7e5  ({index:>4})")));
+        assert!(text.contains("(WL:  1.00000) (D: 0.000)"));
+    }
+
+    #[test]
+    fn tree_roots_use_their_child_nodes() {
+        use crate::{
+            testing::UniformEvaluator,
+            tree::{TopMoves, TreeEvaluator},
+        };
+
+        let mut policy = TopMoves {
+            count: 2,
+            max_depth: 1,
+        };
+        let nodes = TreeEvaluator::new(UniformEvaluator)
+            .evaluate_tree(&[Chess::default()], 1500.0, 1500.0, &mut policy)
+            .unwrap();
+        let text = tree_verbose_move_stats(&nodes, 0);
+        assert_eq!(text.lines().count(), 21);
+        assert_eq!(text.matches("N:       1 ").count(), 2);
+        // The children are valued for White, who moved into them.
+        assert_eq!(text.matches("(Q:  0.10000)").count(), 3);
+        assert!(text.ends_with(
+            "N:       3 (+ 0) (P:  -.--%) (WL:  0.10000) (D: 0.300) (Q:  0.10000) (V:  0.10000)\n"
+        ));
+    }
+
+    #[test]
+    fn jsonl_round_trips_through_serde_json() {
+        let (results, meta) = sample();
+        let mut out = Vec::new();
+        write_jsonl(&results, &meta, &mut out).unwrap();
+
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value["elo_oppo"], 1600.0);
+        assert_eq!(value["policy"][0]["uci"], "e2e4");
+        assert_eq!(value["policy"].as_array().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,295 @@
+//! Detection of ONNX models that keep their weights in separate files.
+//!
+//! Large exports store initializers as "external data": the tensor in
+//! the `.onnx` file only names a file, relative to the model, holding
+//! the bytes. ONNX Runtime resolves those names against the model's
+//! directory when it loads a file, but has nothing to resolve them
+//! against when it loads bytes, and reports either case with a generic
+//! error. The functions here read the names from the model so loading
+//! can fail with [`Error::MissingExternalData`] instead.
+//!
+//! Only the fields on the path to the names are decoded; everything
+//! else, the weights included, is skipped by length, so scanning costs
+//! little even for models with embedded weights.
+
+use std::{borrow::Cow, fs, path::Path};
+
+use ort::session::{Session, builder::SessionBuilder};
+
+use crate::{error::Error, maia::without_builder};
+
+/// `ModelProto.graph`.
+const MODEL_GRAPH: u64 = 7;
+/// `GraphProto.node`.
+const GRAPH_NODE: u64 = 1;
+/// `GraphProto.initializer`.
+const GRAPH_INITIALIZER: u64 = 5;
+/// `NodeProto.attribute`.
+const NODE_ATTRIBUTE: u64 = 5;
+/// `AttributeProto.t`.
+const ATTRIBUTE_TENSOR: u64 = 5;
+/// `AttributeProto.tensors`.
+const ATTRIBUTE_TENSORS: u64 = 10;
+/// `TensorProto.external_data`.
+const TENSOR_EXTERNAL_DATA: u64 = 13;
+/// `StringStringEntryProto.key` and `.value`.
+const ENTRY_KEY: u64 = 1;
+const ENTRY_VALUE: u64 = 2;
+
+/// The external data files `model` refers to, each once, in order of
+/// first use. Empty for self-contained models and for bytes that are
+/// not an ONNX model, which ONNX Runtime then reports itself.
+pub(crate) fn external_data_files(model: &[u8]) -> Vec<String> {
+    let mut files = Vec::new();
+    for (field, graph) in fields(model) {
+        if field == MODEL_GRAPH {
+            scan_graph(graph, &mut files);
+        }
+    }
+    files
+}
+
+/// The external data files of the model at `path` that do not exist
+/// next to it.
+pub(crate) fn missing_external_data(path: &Path) -> Vec<String> {
+    let Ok(model) = fs::read(path) else {
+        return Vec::new();
+    };
+    let dir = path.parent().unwrap_or(Path::new(""));
+    external_data_files(&model)
+        .into_iter()
+        .filter(|file| !dir.join(file).is_file())
+        .collect()
+}
+
+/// `err` from loading the model at `path`, replaced by
+/// [`Error::MissingExternalData`] if data files it needs are missing.
+pub(crate) fn explain_load_error(path: &Path, err: Error) -> Error {
+    let files = missing_external_data(path);
+    if files.is_empty() {
+        err
+    } else {
+        Error::MissingExternalData { files }
+    }
+}
+
+/// Commit `builder` to the model at `path`, resolving its external data
+/// against the model's directory whatever the working directory.
+pub(crate) fn commit_from_file(
+    builder: &mut SessionBuilder,
+    path: &Path,
+) -> Result<Session, Error> {
+    let path = std::path::absolute(path)?;
+    builder
+        .commit_from_file(&path)
+        .map_err(|err| explain_load_error(&path, err.into()))
+}
+
+/// Commit `builder` to the model `model_bytes`, whose external data
+/// files, if any, are given by name in `data`.
+pub(crate) fn commit_from_memory(
+    mut builder: SessionBuilder,
+    model_bytes: &[u8],
+    data: Vec<(String, Vec<u8>)>,
+) -> Result<Session, Error> {
+    let missing: Vec<String> = external_data_files(model_bytes)
+        .into_iter()
+        .filter(|file| !data.iter().any(|(name, _)| name == file))
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::MissingExternalData { files: missing });
+    }
+    for (name, bytes) in data {
+        builder = builder
+            .with_external_initializer_file_in_memory(name, Cow::Owned(bytes))
+            .map_err(without_builder)?;
+    }
+    Ok(builder.commit_from_memory(model_bytes)?)
+}
+
+fn scan_graph(graph: &[u8], files: &mut Vec<String>) {
+    for (field, value) in fields(graph) {
+        match field {
+            GRAPH_INITIALIZER => scan_tensor(value, files),
+            GRAPH_NODE => {
+                for (field, attribute) in fields(value) {
+                    if field != NODE_ATTRIBUTE {
+                        continue;
+                    }
+                    for (field, tensor) in fields(attribute) {
+                        if field == ATTRIBUTE_TENSOR || field == ATTRIBUTE_TENSORS {
+                            scan_tensor(tensor, files);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn scan_tensor(tensor: &[u8], files: &mut Vec<String>) {
+    for (field, entry) in fields(tensor) {
+        if field != TENSOR_EXTERNAL_DATA {
+            continue;
+        }
+        let (mut key, mut value) = (None, None);
+        for (field, bytes) in fields(entry) {
+            match field {
+                ENTRY_KEY => key = Some(bytes),
+                ENTRY_VALUE => value = Some(bytes),
+                _ => {}
+            }
+        }
+        if key == Some(b"location".as_slice())
+            && let Some(location) = value.and_then(|v| std::str::from_utf8(v).ok())
+            && !files.iter().any(|f| f == location)
+        {
+            files.push(location.to_string());
+        }
+    }
+}
+
+/// The length-delimited fields of the protobuf message `bytes`, as field
+/// numbers and contents. Other wire types are skipped; iteration stops
+/// at the first malformed field.
+fn fields(mut bytes: &[u8]) -> impl Iterator<Item = (u64, &[u8])> {
+    std::iter::from_fn(move || {
+        while !bytes.is_empty() {
+            let tag = varint(&mut bytes)?;
+            let len = match tag & 7 {
+                0 => {
+                    varint(&mut bytes)?;
+                    continue;
+                }
+                1 => 8,
+                2 => usize::try_from(varint(&mut bytes)?).ok()?,
+                5 => 4,
+                _ => return None,
+            };
+            if len > bytes.len() {
+                return None;
+            }
+            let (value, rest) = bytes.split_at(len);
+            bytes = rest;
+            if tag & 7 == 2 {
+                return Some((tag >> 3, value));
+            }
+        }
+        None
+    })
+}
+
+/// Read a varint from the front of `bytes`.
+fn varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A length-delimited protobuf field.
+    fn field(number: u64, value: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for mut n in [number << 3 | 2, value.len() as u64] {
+            while n >= 0x80 {
+                out.push(n as u8 | 0x80);
+                n >>= 7;
+            }
+            out.push(n as u8);
+        }
+        out.extend_from_slice(value);
+        out
+    }
+
+    /// A tensor whose bytes are in `location`, or embedded without one.
+    fn tensor(location: Option<&str>) -> Vec<u8> {
+        // `name`, then `data_type` as a varint field to skip.
+        let mut tensor = field(8, b"w");
+        tensor.extend([2 << 3, 1]);
+        match location {
+            Some(location) => {
+                let mut entry = field(ENTRY_KEY, b"location");
+                entry.extend(field(ENTRY_VALUE, location.as_bytes()));
+                tensor.extend(field(TENSOR_EXTERNAL_DATA, &entry));
+                let mut offset = field(ENTRY_KEY, b"offset");
+                offset.extend(field(ENTRY_VALUE, b"0"));
+                tensor.extend(field(TENSOR_EXTERNAL_DATA, &offset));
+            }
+            // `raw_data`.
+            None => tensor.extend(field(9, &[0; 64])),
+        }
+        tensor
+    }
+
+    /// A model with the given initializers and a `Constant` node whose
+    /// value is in `constant`.
+    fn model(initializers: &[Vec<u8>], constant: Option<&str>) -> Vec<u8> {
+        let mut graph = Vec::new();
+        if let Some(location) = constant {
+            let attribute = field(ATTRIBUTE_TENSOR, &tensor(Some(location)));
+            graph.extend(field(GRAPH_NODE, &field(NODE_ATTRIBUTE, &attribute)));
+        }
+        for initializer in initializers {
+            graph.extend(field(GRAPH_INITIALIZER, initializer));
+        }
+        // `ir_version`, a varint, before the graph.
+        let mut model = vec![1 << 3, 8];
+        model.extend(field(MODEL_GRAPH, &graph));
+        model
+    }
+
+    #[test]
+    fn external_data_files_are_listed_once() {
+        let bytes = model(
+            &[
+                tensor(Some("maia.onnx.data")),
+                tensor(None),
+                tensor(Some("maia.onnx.data")),
+            ],
+            Some("constants.bin"),
+        );
+        assert_eq!(
+            external_data_files(&bytes),
+            ["constants.bin", "maia.onnx.data"]
+        );
+    }
+
+    #[test]
+    fn self_contained_and_foreign_bytes_have_none() {
+        let bytes = model(&[tensor(None)], None);
+        assert!(external_data_files(&bytes).is_empty());
+        assert!(external_data_files(b"not an onnx model").is_empty());
+        assert!(external_data_files(&bytes[..bytes.len() - 3]).is_empty());
+    }
+
+    #[test]
+    fn data_files_are_looked_up_next_to_the_model() {
+        let dir = std::env::temp_dir().join(format!("maia-external-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("maia.onnx");
+        let bytes = model(&[tensor(Some("maia.onnx.data"))], None);
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(missing_external_data(&path), ["maia.onnx.data"]);
+        let err = explain_load_error(&path, Error::Terminal);
+        assert!(matches!(err, Error::MissingExternalData { .. }));
+
+        fs::write(dir.join("maia.onnx.data"), [0; 64]).unwrap();
+        assert!(missing_external_data(&path).is_empty());
+        assert!(matches!(
+            explain_load_error(&path, Error::Terminal),
+            Error::Terminal
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+//! C ABI for embedding Maia in non-Rust hosts.
+//!
+//! Enabled with the `ffi` feature. Build a shared library with
+//! `cargo rustc --release --lib --features ffi --crate-type cdylib`; the
+//! matching declarations live in `ffi/maia.h`.
+//!
+//! Every function catches panics so unwinding never crosses the C
+//! boundary; a caught panic is reported as [`MaiaErrorCode::Panic`].
+//! Handles and results are heap allocated by this library and must be
+//! released with [`maia_destroy`] and [`maia_free_result`].
+
+use std::{
+    ffi::{CStr, c_char},
+    panic::{AssertUnwindSafe, catch_unwind},
+    path::Path,
+    ptr,
+};
+
+use crate::{error::Error, maia::Maia, types::EvaluationResult};
+
+/// Opaque model handle returned by [`maia_create`].
+pub struct MaiaHandle {
+    maia: Maia,
+}
+
+/// Opaque evaluation result returned by [`maia_evaluate_fen`].
+pub struct MaiaResult {
+    result: EvaluationResult,
+}
+
+/// Status codes returned across the C boundary.
+///
+/// The library-error codes mirror the variants of [`Error`]; the rest
+/// describe misuse of the C API itself.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaiaErrorCode {
+    Ok = 0,
+    NullArgument = 1,
+    InvalidUtf8 = 2,
+    OutOfBounds = 3,
+    BufferTooSmall = 4,
+    Panic = 5,
+    OrtError = 10,
+    InvalidFen = 11,
+    InvalidPosition = 12,
+    ShapeError = 13,
+    EloOutOfRange = 14,
+    ServiceStopped = 15,
+    IllegalMove = 16,
+    ModelNotFound = 17,
+    EnvironmentConfigured = 18,
+    DeviceFailed = 19,
+    UnsupportedElementType = 20,
+    Terminal = 21,
+    NonFiniteOutput = 22,
+    BatchSizeMismatch = 23,
+    MissingOutput = 24,
+    IncompatibleModel = 25,
+    Timeout = 26,
+    Io = 27,
+    OutOfMemory = 28,
+    UnfinishedGame = 29,
+    Overloaded = 30,
+    Cancelled = 31,
+    MissingExternalData = 32,
+    UnknownModel = 33,
+}
+
+impl From<&Error> for MaiaErrorCode {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::OrtError(_) => MaiaErrorCode::OrtError,
+            Error::Io(_) => MaiaErrorCode::Io,
+            Error::InvalidFen { .. } => MaiaErrorCode::InvalidFen,
+            Error::InvalidPosition(_) => MaiaErrorCode::InvalidPosition,
+            Error::ShapeError(_) => MaiaErrorCode::ShapeError,
+            Error::EloOutOfRange { .. } => MaiaErrorCode::EloOutOfRange,
+            Error::ServiceStopped => MaiaErrorCode::ServiceStopped,
+            Error::Overloaded { .. } => MaiaErrorCode::Overloaded,
+            Error::Cancelled => MaiaErrorCode::Cancelled,
+            Error::MissingExternalData { .. } => MaiaErrorCode::MissingExternalData,
+            Error::UnknownModel { .. } => MaiaErrorCode::UnknownModel,
+            Error::IllegalMove(_) => MaiaErrorCode::IllegalMove,
+            Error::Terminal => MaiaErrorCode::Terminal,
+            Error::AtIndex { source, .. } => MaiaErrorCode::from(&**source),
+            Error::NonFiniteOutput => MaiaErrorCode::NonFiniteOutput,
+            Error::BatchSizeMismatch { .. } => MaiaErrorCode::BatchSizeMismatch,
+            Error::MissingOutput { .. } => MaiaErrorCode::MissingOutput,
+            Error::IncompatibleModel { .. } => MaiaErrorCode::IncompatibleModel,
+            Error::Timeout { .. } => MaiaErrorCode::Timeout,
+            Error::OutOfMemory { .. } => MaiaErrorCode::OutOfMemory,
+            Error::UnfinishedGame => MaiaErrorCode::UnfinishedGame,
+            Error::ModelNotFound(_) => MaiaErrorCode::ModelNotFound,
+            Error::EnvironmentConfigured => MaiaErrorCode::EnvironmentConfigured,
+            Error::DeviceFailed { .. } => MaiaErrorCode::DeviceFailed,
+            Error::UnsupportedElementType { .. } => MaiaErrorCode::UnsupportedElementType,
+        }
+    }
+}
+
+fn guard(f: impl FnOnce() -> MaiaErrorCode) -> MaiaErrorCode {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(MaiaErrorCode::Panic)
+}
+
+/// Load a model from the NUL-terminated UTF-8 `path`.
+///
+/// Returns NULL if the path is invalid or the model cannot be loaded.
+///
+/// # Safety
+/// `path` must be NULL or point to a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn maia_create(path: *const c_char) -> *mut MaiaHandle {
+    catch_unwind(AssertUnwindSafe(|| {
+        if path.is_null() {
+            return ptr::null_mut();
+        }
+        let Ok(path) = unsafe { CStr::from_ptr(path) }.to_str() else {
+            return ptr::null_mut();
+        };
+        match Maia::from_file(Path::new(path)) {
+            Ok(maia) => Box::into_raw(Box::new(MaiaHandle { maia })),
+            Err(_) => ptr::null_mut(),
+        }
+    }))
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Release a handle created by [`maia_create`]. NULL is ignored.
+///
+/// # Safety
+/// `handle` must be NULL or a pointer returned by [`maia_create`] that
+/// has not been destroyed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn maia_destroy(handle: *mut MaiaHandle) {
+    if !handle.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(handle) })));
+    }
+}
+
+/// Evaluate the position given as a NUL-terminated FEN string.
+///
+/// On success `*out_result` receives a result that must be released
+/// with [`maia_free_result`]; on failure it is set to NULL.
+///
+/// # Safety
+/// `handle` must be a live handle, `fen` a valid NUL-terminated string
+/// and `out_result` a valid pointer to writable storage.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn maia_evaluate_fen(
+    handle: *mut MaiaHandle,
+    fen: *const c_char,
+    elo_self: f32,
+    elo_oppo: f32,
+    out_result: *mut *mut MaiaResult,
+) -> MaiaErrorCode {
+    guard(|| {
+        if handle.is_null() || fen.is_null() || out_result.is_null() {
+            return MaiaErrorCode::NullArgument;
+        }
+        unsafe { *out_result = ptr::null_mut() };
+
+        let Ok(fen) = unsafe { CStr::from_ptr(fen) }.to_str() else {
+            return MaiaErrorCode::InvalidUtf8;
+        };
+        let handle = unsafe { &mut *handle };
+        match handle.maia.evaluate_fen(fen, elo_self, elo_oppo) {
+            Ok(result) => {
+                unsafe { *out_result = Box::into_raw(Box::new(MaiaResult { result })) };
+                MaiaErrorCode::Ok
+            }
+            Err(err) => MaiaErrorCode::from(&err),
+        }
+    })
+}
+
+/// Number of moves in the result's policy, or 0 for NULL.
+///
+/// # Safety
+/// `result` must be NULL or a live result.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn maia_result_move_count(result: *const MaiaResult) -> usize {
+    if result.is_null() {
+        return 0;
+    }
+    unsafe { &*result }.result.policy.len()
+}
+
+/// Copy the `index`-th move (in descending probability order) into
+/// `out_uci_buf` as a NUL-terminated UCI string and its probability
+/// into `*out_prob`.
+///
+/// Returns [`MaiaErrorCode::BufferTooSmall`] if `buf_len` cannot hold
+/// the move plus terminator (6 bytes always suffice).
+///
+/// # Safety
+/// `result` must be a live result, `out_uci_buf` must point to at least
+/// `buf_len` writable bytes and `out_prob` must be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn maia_result_move_at(
+    result: *const MaiaResult,
+    index: usize,
+    out_uci_buf: *mut c_char,
+    buf_len: usize,
+    out_prob: *mut f32,
+) -> MaiaErrorCode {
+    guard(|| {
+        if result.is_null() || out_uci_buf.is_null() || out_prob.is_null() {
+            return MaiaErrorCode::NullArgument;
+        }
+        let Some(m) = unsafe { &*result }.result.policy.get(index) else {
+            return MaiaErrorCode::OutOfBounds;
+        };
+
+        let uci = m.uci.to_string();
+        if uci.len() + 1 > buf_len {
+            return MaiaErrorCode::BufferTooSmall;
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(uci.as_ptr().cast(), out_uci_buf, uci.len());
+            *out_uci_buf.add(uci.len()) = 0;
+            *out_prob = m.probability;
+        }
+        MaiaErrorCode::Ok
+    })
+}
+
+/// Write the White win, draw and Black win probabilities of `result`.
+///
+/// # Safety
+/// `result` must be a live result and the output pointers writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn maia_result_value(
+    result: *const MaiaResult,
+    out_white_wr: *mut f32,
+    out_draw: *mut f32,
+    out_black_wr: *mut f32,
+) -> MaiaErrorCode {
+    guard(|| {
+        if result.is_null()
+            || out_white_wr.is_null()
+            || out_draw.is_null()
+            || out_black_wr.is_null()
+        {
+            return MaiaErrorCode::NullArgument;
+        }
+        let result = &unsafe { &*result }.result;
+        unsafe {
+            *out_white_wr = result.white_wr;
+            *out_draw = result.draw;
+            *out_black_wr = result.black_wr;
+        }
+        MaiaErrorCode::Ok
+    })
+}
+
+/// Release a result returned by [`maia_evaluate_fen`]. NULL is ignored.
+///
+/// # Safety
+/// `result` must be NULL or a live result that has not been freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn maia_free_result(result: *mut MaiaResult) {
+    if !result.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(result) })));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::white_move;
+
+    fn boxed_result() -> *mut MaiaResult {
+        let result = EvaluationResult {
+            policy: vec![white_move("e7e8q", 0.5)],
+            white_wr: 0.6,
+            draw: 0.3,
+            black_wr: 0.1,
+            outcome: None,
+        };
+        Box::into_raw(Box::new(MaiaResult { result }))
+    }
+
+    #[test]
+    fn null_arguments_are_rejected() {
+        let mut out = ptr::null_mut();
+        let code =
+            unsafe { maia_evaluate_fen(ptr::null_mut(), c"8/8/8/8".as_ptr(), 0.0, 0.0, &mut out) };
+        assert_eq!(code, MaiaErrorCode::NullArgument);
+        assert!(unsafe { maia_create(ptr::null()) }.is_null());
+        assert_eq!(unsafe { maia_result_move_count(ptr::null()) }, 0);
+    }
+
+    #[test]
+    fn moves_are_copied_with_terminator() {
+        let result = boxed_result();
+        let mut buf = [1 as c_char; 6];
+        let mut prob = 0.0;
+
+        let code = unsafe { maia_result_move_at(result, 0, buf.as_mut_ptr(), 5, &mut prob) };
+        assert_eq!(code, MaiaErrorCode::BufferTooSmall);
+
+        let code = unsafe { maia_result_move_at(result, 0, buf.as_mut_ptr(), 6, &mut prob) };
+        assert_eq!(code, MaiaErrorCode::Ok);
+        assert_eq!(unsafe { CStr::from_ptr(buf.as_ptr()) }, c"e7e8q");
+        assert_eq!(prob, 0.5);
+
+        let code = unsafe { maia_result_move_at(result, 1, buf.as_mut_ptr(), 6, &mut prob) };
+        assert_eq!(code, MaiaErrorCode::OutOfBounds);
+
+        unsafe { maia_free_result(result) };
+    }
+}
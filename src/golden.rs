@@ -0,0 +1,307 @@
+//! Comparison against reference evaluations recorded outside this crate.
+//!
+//! A [`Fixture`] holds what a reference implementation, usually the
+//! Python Maia3 pipeline, produced for one position: the set cells of
+//! its input tokens and optionally the most likely moves and the value.
+//! [`verify_preprocessing`] compares the encoding alone and needs no
+//! model; [`Maia::verify_against_fixtures`] also compares the outputs.
+//! Run them after upgrading the crate or the model to catch silent
+//! divergence in mirroring, token layout or value decoding.
+//!
+//! Fixtures are read from a JSON array:
+//!
+//! ```json
+//! [{"fen": "8/P6k/8/8/8/8/6K1/8 w - - 0 1", "elo_self": 1400, "elo_oppo": 1500,
+//!   "tokens": [[14, 5], [48, 0], [55, 11]],
+//!   "top_moves": [{"uci": "a7a8q", "probability": 0.91}],
+//!   "value": [0.88, 0.1, 0.02]}]
+//! ```
+//!
+//! `tokens` lists `[square, channel]` pairs of the `[64, 12]` input, as
+//! [`preprocess`] lays it out; `top_moves` and `value` may be left out.
+
+use std::{fmt, fs::File, io, io::BufReader, path::Path};
+
+use serde::{Deserialize, Serialize};
+use shakmaty::{Setup, fen::Fen};
+
+use crate::{
+    Maia,
+    backend::InferenceBackend,
+    error::Error,
+    tensor::{BoardChannel, preprocess},
+};
+
+/// Largest difference of a probability from its fixture that still
+/// passes.
+pub const FIXTURE_TOLERANCE: f32 = 1e-3;
+
+/// One reference evaluation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fixture {
+    pub fen: String,
+    pub elo_self: f32,
+    pub elo_oppo: f32,
+    /// Set `[square, channel]` cells of the position's tokens.
+    pub tokens: Vec<[usize; 2]>,
+    /// Most likely moves, best first, in the position's own perspective.
+    #[serde(default)]
+    pub top_moves: Vec<FixtureMove>,
+    /// White win, draw and Black win probabilities.
+    #[serde(default)]
+    pub value: Option<[f32; 3]>,
+}
+
+/// A recorded move probability.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FixtureMove {
+    pub uci: String,
+    pub probability: f32,
+}
+
+impl Fixture {
+    /// Parse a JSON array of fixtures.
+    ///
+    /// # Errors
+    /// Returns [`Error::Io`] if `json` is not such an array.
+    pub fn from_json(json: &str) -> Result<Vec<Fixture>, Error> {
+        Ok(serde_json::from_str(json).map_err(io::Error::from)?)
+    }
+
+    /// Read a JSON array of fixtures from `path`.
+    ///
+    /// # Errors
+    /// Returns [`Error::Io`] if the file cannot be read or parsed.
+    pub fn load(path: impl AsRef<Path>) -> Result<Vec<Fixture>, Error> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader).map_err(io::Error::from)?)
+    }
+
+    fn setup(&self) -> Result<Setup, Error> {
+        let fen =
+            Fen::from_ascii(self.fen.as_bytes()).map_err(|e| Error::invalid_fen(&self.fen, e))?;
+        Ok(fen.into_setup())
+    }
+}
+
+/// Outcome of one fixture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixtureCheck {
+    pub fen: String,
+    /// Token cells set in only one of the fixture and [`preprocess`].
+    pub token_mismatches: Vec<[usize; 2]>,
+    /// Largest difference of a recorded move probability; a recorded
+    /// move missing from the policy counts with its full probability.
+    /// 0 without a model or recorded moves.
+    pub policy_error: f32,
+    /// Largest difference of a value probability, 0 without a model or
+    /// recorded value.
+    pub value_error: f32,
+}
+
+impl FixtureCheck {
+    /// Whether the tokens match and the outputs are within
+    /// [`FIXTURE_TOLERANCE`].
+    pub fn passed(&self) -> bool {
+        self.token_mismatches.is_empty()
+            && self.policy_error <= FIXTURE_TOLERANCE
+            && self.value_error <= FIXTURE_TOLERANCE
+    }
+}
+
+/// Results of [`verify_preprocessing`] and
+/// [`Maia::verify_against_fixtures`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationReport {
+    pub checks: Vec<FixtureCheck>,
+}
+
+impl VerificationReport {
+    /// Whether every fixture passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(FixtureCheck::passed)
+    }
+
+    /// The fixtures that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &FixtureCheck> {
+        self.checks.iter().filter(|c| !c.passed())
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed = self.failures().count();
+        writeln!(
+            f,
+            "{} of {} fixtures passed",
+            self.checks.len() - failed,
+            self.checks.len()
+        )?;
+        for check in self.failures() {
+            writeln!(
+                f,
+                "{}: {} token cells differ, policy error {:.4}, value error {:.4}",
+                check.fen,
+                check.token_mismatches.len(),
+                check.policy_error,
+                check.value_error,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the tokens [`preprocess`] produces with those of `fixtures`.
+///
+/// # Errors
+/// Returns [`Error::InvalidFen`] or [`Error::AtIndex`] for a fixture
+/// whose position cannot be encoded.
+pub fn verify_preprocessing(fixtures: &[Fixture]) -> Result<VerificationReport, Error> {
+    if fixtures.is_empty() {
+        return Ok(VerificationReport { checks: Vec::new() });
+    }
+    let setups = fixtures
+        .iter()
+        .map(Fixture::setup)
+        .collect::<Result<Vec<_>, _>>()?;
+    let (tokens, _) = preprocess(setups, fixtures.len())?;
+    let checks = fixtures
+        .iter()
+        .enumerate()
+        .map(|(i, fixture)| {
+            let mut mismatches: Vec<[usize; 2]> = fixture
+                .tokens
+                .iter()
+                .filter(|&&[sq, channel]| tokens.get([i, sq, channel]) != Some(&1.0))
+                .copied()
+                .collect();
+            for sq in 0..64 {
+                for channel in BoardChannel::ALL.map(BoardChannel::index) {
+                    let cell = [sq, channel];
+                    if tokens[[i, sq, channel]] != 0.0 && !fixture.tokens.contains(&cell) {
+                        mismatches.push(cell);
+                    }
+                }
+            }
+            FixtureCheck {
+                fen: fixture.fen.clone(),
+                token_mismatches: mismatches,
+                policy_error: 0.0,
+                value_error: 0.0,
+            }
+        })
+        .collect();
+    Ok(VerificationReport { checks })
+}
+
+impl<B: InferenceBackend> Maia<B> {
+    /// Compare the encoding and the outputs of this model with
+    /// `fixtures`, at each fixture's ratings.
+    ///
+    /// Only the recorded moves are compared, so a fixture with the top
+    /// ten moves checks those ten. A report that fails on the tokens
+    /// points at the encoding, one that fails only on the outputs at
+    /// the model or the decoding.
+    ///
+    /// # Errors
+    /// As [`verify_preprocessing`], and propagates evaluation errors.
+    pub fn verify_against_fixtures(
+        &mut self,
+        fixtures: &[Fixture],
+    ) -> Result<VerificationReport, Error> {
+        let mut report = verify_preprocessing(fixtures)?;
+        if fixtures.is_empty() {
+            return Ok(report);
+        }
+        let setups = fixtures
+            .iter()
+            .map(Fixture::setup)
+            .collect::<Result<Vec<_>, _>>()?;
+        let elo_selfs: Vec<f32> = fixtures.iter().map(|f| f.elo_self).collect();
+        let elo_oppos: Vec<f32> = fixtures.iter().map(|f| f.elo_oppo).collect();
+        let results = self.batch_evaluate(setups, &elo_selfs, &elo_oppos)?;
+
+        for ((check, fixture), result) in report.checks.iter_mut().zip(fixtures).zip(results) {
+            check.policy_error = fixture
+                .top_moves
+                .iter()
+                .map(|expected| {
+                    let actual = result
+                        .policy
+                        .iter()
+                        .find(|m| m.uci.to_string() == expected.uci)
+                        .map_or(0.0, |m| m.probability);
+                    (actual - expected.probability).abs()
+                })
+                .fold(0.0, f32::max);
+            if let Some(value) = fixture.value {
+                let actual = [result.white_wr, result.draw, result.black_wr];
+                check.value_error = actual
+                    .iter()
+                    .zip(value)
+                    .map(|(a, e)| (a - e).abs())
+                    .fold(0.0, f32::max);
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ZeroBackend;
+
+    /// Input-only fixtures of positions covering castling rights, en
+    /// passant, pending promotions, Black to move and endgames. The
+    /// tokens come from an encoder written apart from this crate.
+    const FIXTURES: &str = include_str!("data/golden_fixtures.json");
+
+    #[test]
+    fn preprocessing_matches_the_fixtures() {
+        let fixtures = Fixture::from_json(FIXTURES).unwrap();
+        assert_eq!(fixtures.len(), 20);
+        let report = verify_preprocessing(&fixtures).unwrap();
+        assert!(report.passed(), "{report}");
+    }
+
+    #[test]
+    fn token_differences_are_reported() {
+        let mut fixtures = Fixture::from_json(FIXTURES).unwrap();
+        fixtures.truncate(2);
+        // Claim 1. e4 encodes as the start position: mirrored, its e4
+        // pawn is a black pawn on e5 instead of e7.
+        fixtures[1].tokens = fixtures[0].tokens.clone();
+        let report = verify_preprocessing(&fixtures).unwrap();
+        assert!(report.checks[0].passed());
+        assert!(!report.passed());
+        assert_eq!(report.checks[1].token_mismatches, vec![[52, 6], [36, 6]]);
+        assert!(report.to_string().starts_with("1 of 2 fixtures passed\n"));
+    }
+
+    #[test]
+    fn outputs_are_compared_within_tolerance() {
+        let mut maia = Maia::from_backend(ZeroBackend::default());
+        let mut fixtures = Fixture::from_json(FIXTURES).unwrap();
+        fixtures.truncate(1);
+        let result = maia
+            .evaluate(fixtures[0].fen.as_str(), 1500.0, 1500.0)
+            .unwrap();
+        fixtures[0].top_moves = vec![FixtureMove {
+            uci: "e2e4".to_string(),
+            probability: 1.0 / 20.0,
+        }];
+        fixtures[0].value = Some([result.white_wr, result.draw, result.black_wr]);
+        assert!(maia.verify_against_fixtures(&fixtures).unwrap().passed());
+
+        fixtures[0].value = Some([1.0, 0.0, 0.0]);
+        let report = maia.verify_against_fixtures(&fixtures).unwrap();
+        assert!(report.checks[0].token_mismatches.is_empty());
+        assert!(report.checks[0].value_error > 0.5);
+
+        fixtures[0].value = None;
+        fixtures[0].top_moves[0].uci = "e2e5".to_string();
+        let report = maia.verify_against_fixtures(&fixtures).unwrap();
+        assert!((report.checks[0].policy_error - 0.05).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,350 @@
+//! Move sampling that plays like a human of a given rating.
+//!
+//! Playing the most likely move every time is stronger and more
+//! predictable than the players the network imitates. A
+//! [`HumanizationProfile`] maps a rating to [`SamplingParams`]: a
+//! temperature, a nucleus (top-p) cutoff and a small chance of playing
+//! one of the unlikely moves outside the nucleus. The built-in profile
+//! samples close to the policy itself, so the sampled top-1 rate stays
+//! near the policy's own top-1 probability; [`calibrate_temperature`]
+//! fits the temperature to an observed top-1 match rate instead.
+
+use rand::{Rng, RngExt};
+
+use crate::types::{EvaluationResult, MoveProbability};
+
+/// How one move is drawn from a policy.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplingParams {
+    /// Exponent `1 / temperature` applied to the probabilities; 0 or
+    /// below plays the top move.
+    pub temperature: f32,
+    /// Smallest set of most likely moves whose tempered probability
+    /// reaches this mass; the other moves form the tail. 1 keeps every
+    /// move.
+    pub top_p: f32,
+    /// Chance of playing a uniformly chosen tail move instead, when there
+    /// is a tail.
+    pub tail_probability: f32,
+}
+
+impl SamplingParams {
+    /// Always the top move.
+    pub const ARGMAX: Self = Self {
+        temperature: 0.0,
+        top_p: 1.0,
+        tail_probability: 0.0,
+    };
+
+    /// Plain sampling at `temperature`, as
+    /// [`EvaluationResult::sample_move`].
+    pub fn temperature(temperature: f32) -> Self {
+        Self {
+            temperature,
+            top_p: 1.0,
+            tail_probability: 0.0,
+        }
+    }
+
+    /// Probability of choosing each policy move, in policy order.
+    pub fn distribution(&self, result: &EvaluationResult) -> Vec<f32> {
+        let policy = &result.policy;
+        let mut chances = vec![0.0; policy.len()];
+        if policy.is_empty() {
+            return chances;
+        }
+        let mut order: Vec<usize> = (0..policy.len()).collect();
+        order.sort_by(|&a, &b| policy[b].probability.total_cmp(&policy[a].probability));
+        if self.temperature <= 0.0 {
+            chances[order[0]] = 1.0;
+            return chances;
+        }
+
+        let weights: Vec<f32> = order
+            .iter()
+            .map(|&i| policy[i].probability.powf(1.0 / self.temperature))
+            .collect();
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            chances[order[0]] = 1.0;
+            return chances;
+        }
+        let mut nucleus = 0;
+        let mut mass = 0.0;
+        while nucleus < order.len() && (nucleus == 0 || mass < self.top_p * total) {
+            mass += weights[nucleus];
+            nucleus += 1;
+        }
+
+        let tail = order.len() - nucleus;
+        let tail_probability = if tail > 0 {
+            self.tail_probability.clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        for (k, &i) in order.iter().enumerate() {
+            chances[i] = if k < nucleus {
+                (1.0 - tail_probability) * weights[k] / mass
+            } else {
+                tail_probability / tail as f32
+            };
+        }
+        chances
+    }
+
+    /// Probability of choosing the policy's most likely move.
+    pub fn top_move_rate(&self, result: &EvaluationResult) -> f32 {
+        let chances = self.distribution(result);
+        let top = result
+            .policy
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.probability.total_cmp(&b.probability));
+        top.map_or(0.0, |(i, _)| chances[i])
+    }
+
+    /// Draw a move from `result`'s policy. Returns `None` without legal
+    /// moves.
+    pub fn sample<'a>(
+        &self,
+        result: &'a EvaluationResult,
+        rng: &mut impl Rng,
+    ) -> Option<&'a MoveProbability> {
+        let chances = self.distribution(result);
+        let mut target = rng.random::<f32>();
+        for (m, chance) in result.policy.iter().zip(&chances) {
+            target -= chance;
+            if target <= 0.0 && *chance > 0.0 {
+                return Some(m);
+            }
+        }
+        result
+            .policy
+            .iter()
+            .zip(&chances)
+            .rfind(|(_, chance)| **chance > 0.0)
+            .map(|(m, _)| m)
+    }
+}
+
+/// Sampling parameters by rating, interpolated linearly between anchor
+/// ratings and held constant beyond the first and last.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HumanizationProfile {
+    anchors: Vec<(f32, SamplingParams)>,
+}
+
+impl HumanizationProfile {
+    /// A profile through `anchors`, `(rating, params)` pairs in any
+    /// order.
+    ///
+    /// # Panics
+    /// Panics if `anchors` is empty.
+    pub fn new(mut anchors: Vec<(f32, SamplingParams)>) -> Self {
+        assert!(!anchors.is_empty(), "a profile needs at least one anchor");
+        anchors.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { anchors }
+    }
+
+    /// The profile with `params` at `elo`, replacing an anchor at the same
+    /// rating.
+    pub fn with_anchor(mut self, elo: f32, params: SamplingParams) -> Self {
+        self.anchors.retain(|&(e, _)| e != elo);
+        self.anchors.push((elo, params));
+        Self::new(self.anchors)
+    }
+
+    /// The anchors, by ascending rating.
+    pub fn anchors(&self) -> &[(f32, SamplingParams)] {
+        &self.anchors
+    }
+
+    /// Sampling parameters for a player rated `elo`.
+    pub fn params(&self, elo: f32) -> SamplingParams {
+        let upper = self.anchors.partition_point(|&(e, _)| e < elo);
+        if upper == 0 {
+            return self.anchors[0].1;
+        }
+        if upper == self.anchors.len() {
+            return self.anchors[upper - 1].1;
+        }
+        let (e0, p0) = self.anchors[upper - 1];
+        let (e1, p1) = self.anchors[upper];
+        let t = (elo - e0) / (e1 - e0);
+        let lerp = |a: f32, b: f32| a + t * (b - a);
+        SamplingParams {
+            temperature: lerp(p0.temperature, p1.temperature),
+            top_p: lerp(p0.top_p, p1.top_p),
+            tail_probability: lerp(p0.tail_probability, p1.tail_probability),
+        }
+    }
+}
+
+/// Sampling at temperature 1, so the top move is played about as often
+/// as the policy predicts. Weaker settings keep the whole policy and an
+/// occasional tail move; stronger ones cut the unlikely moves.
+impl Default for HumanizationProfile {
+    fn default() -> Self {
+        Self::new(vec![
+            (
+                800.0,
+                SamplingParams {
+                    temperature: 1.0,
+                    top_p: 1.0,
+                    tail_probability: 0.0,
+                },
+            ),
+            (
+                1500.0,
+                SamplingParams {
+                    temperature: 1.0,
+                    top_p: 0.98,
+                    tail_probability: 0.01,
+                },
+            ),
+            (
+                2200.0,
+                SamplingParams {
+                    temperature: 1.0,
+                    top_p: 0.95,
+                    tail_probability: 0.0,
+                },
+            ),
+        ])
+    }
+}
+
+/// Temperature at which sampling from `results` plays their top moves at
+/// `target_top1` on average, with `top_p` and `tail_probability` as
+/// given.
+///
+/// `results` should be evaluations of typical positions at the rating
+/// being calibrated, and `target_top1` the rate at which players of that
+/// rating play Maia's top move, such as
+/// [`BucketMatch::top1_rate`](crate::analysis::BucketMatch::top1_rate).
+/// The rate falls as the temperature rises, so the search bisects
+/// between 0.05 and 20 and returns the closest bound for targets outside
+/// that range.
+pub fn calibrate_temperature(
+    results: &[EvaluationResult],
+    target_top1: f32,
+    top_p: f32,
+    tail_probability: f32,
+) -> f32 {
+    let rate = |temperature: f32| {
+        let params = SamplingParams {
+            temperature,
+            top_p,
+            tail_probability,
+        };
+        let total: f32 = results.iter().map(|r| params.top_move_rate(r)).sum();
+        total / results.len().max(1) as f32
+    };
+    // Bisect in log space: the rate changes most at low temperatures.
+    let (mut low, mut high) = (0.05f32.ln(), 20.0f32.ln());
+    for _ in 0..40 {
+        let mid = 0.5 * (low + high);
+        if rate(mid.exp()) > target_top1 {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (0.5 * (low + high)).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::testing::white_move;
+
+    fn result(probabilities: &[(&str, f32)]) -> EvaluationResult {
+        EvaluationResult {
+            policy: probabilities
+                .iter()
+                .map(|&(uci, p)| white_move(uci, p))
+                .collect(),
+            white_wr: 0.4,
+            draw: 0.3,
+            black_wr: 0.3,
+            outcome: None,
+        }
+    }
+
+    #[test]
+    fn nucleus_and_tail_shape_the_distribution() {
+        let r = result(&[("e2e4", 0.6), ("d2d4", 0.3), ("g1f3", 0.06), ("a2a3", 0.04)]);
+
+        let plain = SamplingParams::temperature(1.0).distribution(&r);
+        assert!(
+            plain
+                .iter()
+                .zip([0.6, 0.3, 0.06, 0.04])
+                .all(|(a, b)| (a - b).abs() < 1e-6)
+        );
+        assert_eq!(
+            SamplingParams::ARGMAX.distribution(&r),
+            [1.0, 0.0, 0.0, 0.0]
+        );
+
+        // The nucleus is e4 and d4; a tenth of the time a tail move.
+        let params = SamplingParams {
+            temperature: 1.0,
+            top_p: 0.85,
+            tail_probability: 0.1,
+        };
+        let chances = params.distribution(&r);
+        assert!((chances[0] - 0.9 * 0.6 / 0.9).abs() < 1e-6);
+        assert!((chances[2] - 0.05).abs() < 1e-6 && (chances[3] - 0.05).abs() < 1e-6);
+        assert!((chances.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut counts = [0; 4];
+        for _ in 0..4000 {
+            let m = params.sample(&r, &mut rng).unwrap();
+            counts[r.policy.iter().position(|p| p.uci == m.uci).unwrap()] += 1;
+        }
+        assert!((counts[0] as f32 / 4000.0 - 0.6).abs() < 0.03, "{counts:?}");
+        assert!(counts[3] > 0);
+    }
+
+    #[test]
+    fn profiles_interpolate_between_anchors() {
+        let profile = HumanizationProfile::default();
+        assert_eq!(profile.params(500.0), profile.anchors()[0].1);
+        assert_eq!(profile.params(3000.0), profile.anchors()[2].1);
+        let mid = profile.params(1150.0);
+        assert!((mid.top_p - 0.99).abs() < 1e-6);
+        assert!((mid.tail_probability - 0.005).abs() < 1e-6);
+
+        let custom = profile.with_anchor(1500.0, SamplingParams::ARGMAX);
+        assert_eq!(custom.anchors().len(), 3);
+        assert_eq!(custom.params(1500.0), SamplingParams::ARGMAX);
+    }
+
+    #[test]
+    fn calibration_hits_the_target_rate() {
+        let results = [
+            result(&[("e2e4", 0.5), ("d2d4", 0.3), ("g1f3", 0.2)]),
+            result(&[("e2e4", 0.8), ("d2d4", 0.15), ("g1f3", 0.05)]),
+        ];
+        // At temperature 1 the rate is the mean top probability.
+        let t = calibrate_temperature(&results, 0.65, 1.0, 0.0);
+        assert!((t - 1.0).abs() < 1e-3, "{t}");
+
+        let t = calibrate_temperature(&results, 0.8, 1.0, 0.0);
+        assert!(t < 1.0);
+        let rate = |t| {
+            results
+                .iter()
+                .map(|r| SamplingParams::temperature(t).top_move_rate(r))
+                .sum::<f32>()
+                / 2.0
+        };
+        assert!((rate(t) - 0.8).abs() < 1e-3);
+        assert!(calibrate_temperature(&results, 0.4, 1.0, 0.0) > 1.0);
+    }
+}
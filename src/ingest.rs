@@ -0,0 +1,472 @@
+//! Games from the lichess and chess.com JSON exports.
+//!
+//! [`read_lichess_ndjson`] reads the NDJSON of the lichess games API, one
+//! game per line, with moves in SAN or UCI. [`read_chesscom_archive`]
+//! reads a chess.com monthly archive, whose games carry their moves as
+//! PGN. Both yield [`IngestedGame`]s: the moves, the players' ratings for
+//! elo conditioning, the time control and the result. Fields the crate
+//! does not use are ignored. Games that did not finish (aborted, never
+//! started, still running), are not standard chess or have unreadable
+//! moves are skipped with a warning; only read errors fail the call.
+//!
+//! [`analyze_lichess_ndjson`] and [`analyze_games`] analyse the games as
+//! [`analyze_pgn_file`](crate::analysis::analyze_pgn_file) does.
+
+use std::io::{self, BufRead, Read};
+
+use serde::Deserialize;
+use shakmaty::{
+    CastlingMode, Chess, Color, KnownOutcome, Position, fen::Fen, san::SanPlus, uci::UciMove,
+};
+
+use crate::{
+    analysis::{GameAnalysis, GameMoves, PgnAnalysisConfig, analyze_batched},
+    error::Error,
+    evaluator::Evaluator,
+};
+
+/// Speed category of a time control, by lichess's estimated game
+/// duration of the initial time plus 40 increments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Speed {
+    UltraBullet,
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+}
+
+/// A clock time control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeControl {
+    pub initial_seconds: u32,
+    pub increment_seconds: u32,
+}
+
+impl TimeControl {
+    /// Parse a chess.com or PGN time control such as `"180+2"` or
+    /// `"600"`. Correspondence controls like `"1/259200"` have no clock
+    /// and give `None`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (initial, increment) = text.split_once('+').unwrap_or((text, "0"));
+        Some(Self {
+            initial_seconds: initial.trim().parse().ok()?,
+            increment_seconds: increment.trim().parse().ok()?,
+        })
+    }
+
+    pub fn speed(&self) -> Speed {
+        match self.initial_seconds + 40 * self.increment_seconds {
+            ..30 => Speed::UltraBullet,
+            30..180 => Speed::Bullet,
+            180..480 => Speed::Blitz,
+            480..1500 => Speed::Rapid,
+            _ => Speed::Classical,
+        }
+    }
+}
+
+/// One side of an ingested game.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Player {
+    /// Account name; `None` for anonymous players and the lichess AI.
+    pub name: Option<String>,
+    pub rating: Option<f32>,
+}
+
+/// A finished game read from an export.
+#[derive(Debug, Clone)]
+pub struct IngestedGame {
+    /// The lichess game id or the chess.com game URL.
+    pub id: String,
+    pub moves: GameMoves,
+    pub white: Player,
+    pub black: Player,
+    /// `None` for correspondence games.
+    pub time_control: Option<TimeControl>,
+    pub outcome: KnownOutcome,
+}
+
+impl IngestedGame {
+    /// PGN-style tags of the game: `Site`, `White`, `Black`, `Result`,
+    /// the ratings and time control when known.
+    pub fn tags(&self) -> Vec<(String, String)> {
+        let mut tags = vec![("Site".to_string(), self.id.clone())];
+        for (color, player) in [("White", &self.white), ("Black", &self.black)] {
+            let name = player.name.clone().unwrap_or_else(|| "?".to_string());
+            tags.push((color.to_string(), name));
+        }
+        tags.push(("Result".to_string(), self.outcome.as_str().to_string()));
+        for (tag, player) in [("WhiteElo", &self.white), ("BlackElo", &self.black)] {
+            if let Some(rating) = player.rating {
+                tags.push((tag.to_string(), rating.to_string()));
+            }
+        }
+        if let Some(tc) = self.time_control {
+            let value = format!("{}+{}", tc.initial_seconds, tc.increment_seconds);
+            tags.push(("TimeControl".to_string(), value));
+        }
+        tags
+    }
+}
+
+#[derive(Deserialize)]
+struct LichessGame {
+    id: String,
+    #[serde(default)]
+    variant: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    players: LichessPlayers,
+    #[serde(default)]
+    winner: Option<String>,
+    #[serde(default)]
+    moves: String,
+    #[serde(default, rename = "initialFen")]
+    initial_fen: Option<String>,
+    #[serde(default)]
+    clock: Option<LichessClock>,
+}
+
+#[derive(Deserialize, Default)]
+struct LichessPlayers {
+    #[serde(default)]
+    white: LichessPlayer,
+    #[serde(default)]
+    black: LichessPlayer,
+}
+
+#[derive(Deserialize, Default)]
+struct LichessPlayer {
+    #[serde(default)]
+    user: Option<LichessUser>,
+    #[serde(default)]
+    rating: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct LichessUser {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct LichessClock {
+    initial: u32,
+    increment: u32,
+}
+
+impl From<LichessPlayer> for Player {
+    fn from(player: LichessPlayer) -> Self {
+        Self {
+            name: player.user.map(|u| u.name),
+            rating: player.rating,
+        }
+    }
+}
+
+/// Parse one line of a lichess games export, or tell why the game is
+/// skipped.
+fn lichess_game(line: &str) -> Result<IngestedGame, String> {
+    let game: LichessGame = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let id = game.id;
+    match game.variant.as_deref() {
+        None | Some("standard" | "fromPosition") => {}
+        Some(variant) => return Err(format!("{id}: unsupported variant {variant}")),
+    }
+    let status = game.status.as_deref().unwrap_or("unknownFinish");
+    if matches!(
+        status,
+        "created" | "started" | "aborted" | "noStart" | "unknownFinish"
+    ) {
+        return Err(format!("{id}: game did not finish ({status})"));
+    }
+    let outcome = match game.winner.as_deref() {
+        Some("white") => KnownOutcome::Decisive {
+            winner: Color::White,
+        },
+        Some("black") => KnownOutcome::Decisive {
+            winner: Color::Black,
+        },
+        _ => KnownOutcome::Draw,
+    };
+    let initial = match &game.initial_fen {
+        Some(fen) => fen
+            .parse::<Fen>()
+            .map_err(|e| format!("{id}: {e}"))?
+            .into_position(CastlingMode::Standard)
+            .map_err(|e| format!("{id}: {e}"))?,
+        None => Chess::default(),
+    };
+    let moves = play_tokens(&initial, game.moves.split_whitespace())
+        .map_err(|token| format!("{id}: illegal move {token}"))?;
+    Ok(IngestedGame {
+        id,
+        moves: GameMoves { initial, moves },
+        white: game.players.white.into(),
+        black: game.players.black.into(),
+        time_control: game.clock.map(|c| TimeControl {
+            initial_seconds: c.initial,
+            increment_seconds: c.increment,
+        }),
+        outcome,
+    })
+}
+
+/// Play `tokens` from `initial`, each in UCI or SAN. Returns the first
+/// token that is neither.
+fn play_tokens<'a>(
+    initial: &Chess,
+    tokens: impl Iterator<Item = &'a str>,
+) -> Result<Vec<UciMove>, &'a str> {
+    let mut pos = initial.clone();
+    let mut moves = Vec::new();
+    for token in tokens {
+        let m = token
+            .parse::<UciMove>()
+            .ok()
+            .and_then(|uci| uci.to_move(&pos).ok())
+            .or_else(|| {
+                let san = token.parse::<SanPlus>().ok()?;
+                san.san.to_move(&pos).ok()
+            })
+            .ok_or(token)?;
+        moves.push(m.to_uci(CastlingMode::Standard));
+        pos.play_unchecked(m);
+    }
+    Ok(moves)
+}
+
+/// Read a lichess games export in NDJSON, skipping blank lines and, with
+/// a warning, games that cannot be analysed.
+///
+/// # Errors
+/// Returns [`Error::Io`] if reading fails.
+pub fn read_lichess_ndjson(reader: impl BufRead) -> Result<Vec<IngestedGame>, Error> {
+    let mut games = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match lichess_game(&line) {
+            Ok(game) => games.push(game),
+            Err(reason) => tracing::warn!(line = number + 1, "skipping lichess game: {reason}"),
+        }
+    }
+    Ok(games)
+}
+
+#[derive(Deserialize)]
+struct ChessComArchive {
+    games: Vec<ChessComGame>,
+}
+
+#[derive(Deserialize)]
+struct ChessComGame {
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    pgn: String,
+    #[serde(default)]
+    time_control: Option<String>,
+    #[serde(default)]
+    rules: Option<String>,
+    white: ChessComPlayer,
+    black: ChessComPlayer,
+}
+
+#[derive(Deserialize)]
+struct ChessComPlayer {
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    rating: Option<f32>,
+    #[serde(default)]
+    result: String,
+}
+
+/// chess.com results that end the game in a draw.
+const CHESSCOM_DRAWS: [&str; 6] = [
+    "agreed",
+    "repetition",
+    "stalemate",
+    "insufficient",
+    "50move",
+    "timevsinsufficient",
+];
+
+fn chesscom_game(game: ChessComGame) -> Result<IngestedGame, String> {
+    let id = game.url;
+    if let Some(rules) = game.rules.as_deref().filter(|&r| r != "chess") {
+        return Err(format!("{id}: unsupported rules {rules}"));
+    }
+    let outcome = match (game.white.result.as_str(), game.black.result.as_str()) {
+        ("win", _) => KnownOutcome::Decisive {
+            winner: Color::White,
+        },
+        (_, "win") => KnownOutcome::Decisive {
+            winner: Color::Black,
+        },
+        (white, _) if CHESSCOM_DRAWS.contains(&white) => KnownOutcome::Draw,
+        (white, _) => return Err(format!("{id}: no result ({white})")),
+    };
+    let moves = crate::pgn::parse_games(&game.pgn)
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("{id}: no moves"))?
+        .moves
+        .map_err(|e| format!("{id}: {e}"))?;
+    let player = |p: ChessComPlayer| Player {
+        name: p.username,
+        rating: p.rating,
+    };
+    Ok(IngestedGame {
+        id,
+        moves,
+        white: player(game.white),
+        black: player(game.black),
+        time_control: game.time_control.as_deref().and_then(TimeControl::parse),
+        outcome,
+    })
+}
+
+/// Read a chess.com monthly archive, the JSON document of
+/// `/pub/player/{user}/games/{year}/{month}`, skipping with a warning
+/// games that cannot be analysed.
+///
+/// # Errors
+/// Returns [`Error::Io`] if reading fails or the document is not an
+/// archive.
+pub fn read_chesscom_archive(reader: impl Read) -> Result<Vec<IngestedGame>, Error> {
+    let archive: ChessComArchive = serde_json::from_reader(reader).map_err(io::Error::from)?;
+    let mut games = Vec::new();
+    for game in archive.games {
+        match chesscom_game(game) {
+            Ok(game) => games.push(game),
+            Err(reason) => tracing::warn!("skipping chess.com game: {reason}"),
+        }
+    }
+    Ok(games)
+}
+
+/// Analyse `games` with their players' ratings, in shared batches as
+/// [`analyze_pgn_file`](crate::analysis::analyze_pgn_file). Unrated
+/// players get [`PgnAnalysisConfig::default_elo`]; the analyses carry
+/// the [`tags`](IngestedGame::tags) of their games.
+///
+/// # Errors
+/// Propagates evaluation errors.
+pub fn analyze_games(
+    evaluator: &mut impl Evaluator,
+    games: Vec<IngestedGame>,
+    config: &PgnAnalysisConfig,
+) -> Result<Vec<GameAnalysis>, Error> {
+    let elo = |player: &Player| {
+        player
+            .rating
+            .filter(|elo| elo.is_finite() && *elo > 0.0)
+            .unwrap_or(config.default_elo)
+    };
+    let prepared = games.into_iter().map(|game| {
+        let tags = game.tags();
+        let elos = (elo(&game.white), elo(&game.black));
+        Ok((game.moves, elos, tags))
+    });
+    analyze_batched(evaluator, prepared, config.max_batch_size)?
+        .into_iter()
+        .collect()
+}
+
+/// Read and analyse a lichess NDJSON export, see
+/// [`read_lichess_ndjson`] and [`analyze_games`].
+///
+/// # Errors
+/// Returns [`Error::Io`] if reading fails and propagates evaluation
+/// errors.
+pub fn analyze_lichess_ndjson(
+    evaluator: &mut impl Evaluator,
+    reader: impl BufRead,
+) -> Result<Vec<GameAnalysis>, Error> {
+    let games = read_lichess_ndjson(reader)?;
+    analyze_games(evaluator, games, &PgnAnalysisConfig::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::UniformEvaluator;
+
+    const NDJSON: &str = r#"{"id":"a1","rated":true,"variant":"standard","speed":"blitz","status":"resign","players":{"white":{"user":{"name":"alice","id":"alice"},"rating":1720,"ratingDiff":6},"black":{"user":{"name":"bob"},"rating":1650}},"winner":"white","moves":"e4 e5 Nf3 Nc6 Bb5 a6","clock":{"initial":180,"increment":2,"totalTime":260},"opening":{"eco":"C68"}}
+
+{"id":"a2","variant":"fromPosition","status":"draw","initialFen":"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1","players":{"white":{"aiLevel":3},"black":{"user":{"name":"carol"},"rating":1400}},"moves":"e2e4 e8d7 e1d2"}
+{"id":"a3","variant":"standard","status":"aborted","players":{},"moves":"e4"}
+{"id":"a4","variant":"chess960","status":"mate","players":{},"moves":"e4"}
+{"id":"a5","status":"mate","players":{},"winner":"black","moves":"e4 Ke7 Qxf7"}
+not json
+"#;
+
+    #[test]
+    fn lichess_exports_skip_unfinished_and_unreadable_games() {
+        let games = read_lichess_ndjson(NDJSON.as_bytes()).unwrap();
+        assert_eq!(games.len(), 2);
+
+        let blitz = &games[0];
+        assert_eq!(blitz.id, "a1");
+        assert_eq!(blitz.white.name.as_deref(), Some("alice"));
+        assert_eq!(blitz.black.rating, Some(1650.0));
+        assert_eq!(blitz.moves.moves.len(), 6);
+        assert_eq!(blitz.moves.moves[4].to_string(), "f1b5");
+        assert_eq!(blitz.time_control.unwrap().speed(), Speed::Blitz);
+        assert_eq!(blitz.outcome.winner(), Some(Color::White));
+
+        // UCI moves from a set-up position, against the AI.
+        let ending = &games[1];
+        assert_eq!(ending.white, Player::default());
+        assert_eq!(ending.moves.initial.board().occupied().count(), 3);
+        assert_eq!(ending.moves.moves.len(), 3);
+        assert_eq!(ending.outcome, KnownOutcome::Draw);
+        assert_eq!(ending.time_control, None);
+    }
+
+    #[test]
+    fn chesscom_archives_read_moves_from_pgn() {
+        let archive = r#"{"games":[
+{"url":"https://www.chess.com/game/live/1","pgn":"[Event \"Live Chess\"]\n[TimeControl \"600\"]\n\n1. d4 {[%clk 0:09:58]} 1... d5 {[%clk 0:09:57]} 2. c4 1/2-1/2","time_control":"600","end_time":1,"rated":true,"rules":"chess","time_class":"rapid","white":{"rating":1310,"result":"agreed","username":"dan","uuid":"x"},"black":{"rating":1290,"result":"agreed","username":"eve"}},
+{"url":"https://www.chess.com/game/daily/2","pgn":"1. e4 e5 0-1","time_control":"1/259200","rules":"chess","white":{"result":"resigned","username":"f"},"black":{"result":"win","username":"g"}},
+{"url":"https://www.chess.com/game/live/3","pgn":"1. e4 e5 1-0","time_control":"60","rules":"crazyhouse","white":{"result":"win"},"black":{"result":"checkmated"}}
+]}"#;
+        let games = read_chesscom_archive(archive.as_bytes()).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].moves.moves.len(), 3);
+        assert_eq!(games[0].outcome, KnownOutcome::Draw);
+        assert_eq!(games[0].time_control.unwrap().speed(), Speed::Rapid);
+        assert_eq!(games[1].outcome.winner(), Some(Color::Black));
+        assert_eq!(games[1].time_control, None);
+        assert_eq!(games[1].white.rating, None);
+
+        assert!(matches!(
+            read_chesscom_archive(&b"[]"[..]),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn ndjson_exports_are_analysed_with_their_ratings() {
+        let analyses = analyze_lichess_ndjson(&mut UniformEvaluator, NDJSON.as_bytes()).unwrap();
+        assert_eq!(analyses.len(), 2);
+        assert_eq!(analyses[0].positions().len(), 7);
+        let tag = |i: usize, name: &str| {
+            analyses[i]
+                .tags()
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.clone())
+        };
+        assert_eq!(tag(0, "WhiteElo").as_deref(), Some("1720"));
+        assert_eq!(tag(0, "TimeControl").as_deref(), Some("180+2"));
+        assert_eq!(tag(1, "White").as_deref(), Some("?"));
+        assert_eq!(tag(1, "WhiteElo"), None);
+        assert_eq!(tag(1, "Result").as_deref(), Some("1/2-1/2"));
+    }
+}
@@ -0,0 +1,187 @@
+//! Conversion of the usual position types into the [`Setup`] the model
+//! encodes.
+
+use shakmaty::{Bitboard, Board, Chess, Color, EnPassantMode, Position, Setup, Square, fen::Fen};
+
+use crate::error::Error;
+
+/// A position taken apart into the parts the encoder reads, for data
+/// stored that way.
+///
+/// The move counters, which the model does not see, are left out.
+/// Converting to a [`Setup`] moves the board and sets the other fields,
+/// without parsing or copying; the position is validated like any other
+/// input when it is evaluated, since legal-move generation depends on
+/// it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawPosition {
+    pub board: Board,
+    pub turn: Color,
+    /// Rooks with castling rights.
+    pub castling: Bitboard,
+    pub ep: Option<Square>,
+}
+
+impl RawPosition {
+    /// The setup of the position, with the move counters of a new game.
+    pub fn into_setup(self) -> Setup {
+        let mut setup = Setup::empty();
+        setup.board = self.board;
+        setup.turn = self.turn;
+        setup.castling_rights = self.castling;
+        setup.ep_square = self.ep;
+        setup
+    }
+}
+
+impl TryIntoSetup for RawPosition {
+    fn try_into_setup(self) -> Result<Setup, Error> {
+        Ok(self.into_setup())
+    }
+}
+
+/// A position given as a FEN string, a parsed [`Fen`], a [`Setup`] or a
+/// [`Chess`] position, accepted by [`Maia::evaluate`](crate::Maia::evaluate)
+/// and the other generic entry points.
+pub trait TryIntoSetup {
+    /// The setup of the position.
+    ///
+    /// # Errors
+    /// Returns [`Error::InvalidFen`] for a string that is not a FEN, with
+    /// the parse error and the input. The other conversions cannot fail.
+    fn try_into_setup(self) -> Result<Setup, Error>;
+}
+
+impl TryIntoSetup for Setup {
+    fn try_into_setup(self) -> Result<Setup, Error> {
+        Ok(self)
+    }
+}
+
+impl TryIntoSetup for &Setup {
+    fn try_into_setup(self) -> Result<Setup, Error> {
+        Ok(self.clone())
+    }
+}
+
+impl TryIntoSetup for &str {
+    fn try_into_setup(self) -> Result<Setup, Error> {
+        let fen: Fen = self.parse().map_err(|e| Error::invalid_fen(self, e))?;
+        Ok(fen.into_setup())
+    }
+}
+
+impl TryIntoSetup for &String {
+    fn try_into_setup(self) -> Result<Setup, Error> {
+        self.as_str().try_into_setup()
+    }
+}
+
+impl TryIntoSetup for Fen {
+    fn try_into_setup(self) -> Result<Setup, Error> {
+        Ok(self.into_setup())
+    }
+}
+
+impl TryIntoSetup for &Fen {
+    fn try_into_setup(self) -> Result<Setup, Error> {
+        Ok(self.as_setup().clone())
+    }
+}
+
+impl TryIntoSetup for Chess {
+    fn try_into_setup(self) -> Result<Setup, Error> {
+        Ok(self.to_setup(EnPassantMode::Legal))
+    }
+}
+
+impl TryIntoSetup for &Chess {
+    fn try_into_setup(self) -> Result<Setup, Error> {
+        Ok(self.to_setup(EnPassantMode::Legal))
+    }
+}
+
+/// Convert every input, stopping at the first that fails with its error
+/// wrapped in [`Error::AtIndex`].
+pub(crate) fn try_into_setups(
+    inputs: impl IntoIterator<Item = impl TryIntoSetup>,
+) -> Result<Vec<Setup>, Error> {
+    inputs
+        .into_iter()
+        .enumerate()
+        .map(|(index, input)| {
+            input.try_into_setup().map_err(|source| Error::AtIndex {
+                index,
+                fen: None,
+                source: Box::new(source),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFTER_E4: &str = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+
+    #[test]
+    fn every_input_gives_the_same_setup() {
+        let fen: Fen = AFTER_E4.parse().unwrap();
+        let pos: Chess = fen
+            .clone()
+            .into_position(shakmaty::CastlingMode::Standard)
+            .unwrap();
+        let setup = fen.as_setup().clone();
+
+        assert_eq!(AFTER_E4.try_into_setup().unwrap(), setup);
+        assert_eq!((&AFTER_E4.to_string()).try_into_setup().unwrap(), setup);
+        assert_eq!((&fen).try_into_setup().unwrap(), setup);
+        assert_eq!(fen.try_into_setup().unwrap(), setup);
+        assert_eq!((&pos).try_into_setup().unwrap(), setup);
+        assert_eq!(pos.try_into_setup().unwrap(), setup);
+        assert_eq!((&setup).try_into_setup().unwrap(), setup);
+    }
+
+    #[test]
+    fn raw_positions_encode_like_their_setups() {
+        let setups: Vec<Setup> = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            AFTER_E4,
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "r3k2r/8/8/8/8/8/8/R3K2R b kq - 12 40",
+            "8/6k1/8/8/8/8/p6K/8 b - - 3 71",
+        ]
+        .into_iter()
+        .map(|fen| fen.try_into_setup().unwrap())
+        .collect();
+        let raw: Vec<RawPosition> = setups
+            .iter()
+            .map(|setup| RawPosition {
+                board: setup.board.clone(),
+                turn: setup.turn,
+                castling: setup.castling_rights,
+                ep: setup.ep_square,
+            })
+            .collect();
+
+        let n = setups.len();
+        let expected = crate::preprocess(setups, n).unwrap();
+        let converted = try_into_setups(raw).unwrap();
+        assert_eq!(crate::preprocess(converted, n).unwrap(), expected);
+    }
+
+    #[test]
+    fn fen_errors_keep_the_input_and_index() {
+        assert!(matches!(
+            "not a fen".try_into_setup(),
+            Err(Error::InvalidFen { fen, .. }) if fen == "not a fen"
+        ));
+        let err = try_into_setups([AFTER_E4, "8/8/8 w"]).unwrap_err();
+        let Error::AtIndex { index, source, .. } = err else {
+            panic!("{err:?}");
+        };
+        assert_eq!(index, 1);
+        assert!(matches!(*source, Error::InvalidFen { .. }));
+    }
+}
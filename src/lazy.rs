@@ -0,0 +1,178 @@
+//! Backends created on first use.
+
+#[cfg(feature = "ort")]
+use std::path::PathBuf;
+use std::time::Instant;
+
+use ndarray::Array3;
+
+use crate::{
+    Maia,
+    backend::{DefaultBackend, InferenceBackend, ModelOutputs},
+    error::Error,
+    postprocess::OutputSelection,
+};
+#[cfg(feature = "ort")]
+use crate::{backend::OrtBackend, maia::Device};
+
+/// Factory of the deferred backend.
+type Loader<B> = Box<dyn Fn() -> Result<B, Error> + Send + Sync>;
+
+/// A backend built by its loader on the first run, for applications
+/// that construct a [`Maia`] up front but often never evaluate.
+///
+/// Evaluation takes `&mut Maia`, so the loader never runs twice at once;
+/// shared models behind a lock, a [`MaiaService`](crate::MaiaService) or
+/// a [`ThreadLocalMaia`](crate::ThreadLocalMaia) load once on whichever
+/// call comes first. If the loader fails, that call returns its error
+/// unchanged and the next call tries again.
+pub struct LazyBackend<B = DefaultBackend> {
+    loader: Loader<B>,
+    backend: Option<B>,
+    outputs: Option<OutputSelection>,
+}
+
+impl<B: InferenceBackend> LazyBackend<B> {
+    /// Defer creating the backend to `loader`.
+    pub fn new(loader: impl Fn() -> Result<B, Error> + Send + Sync + 'static) -> Self {
+        Self {
+            loader: Box::new(loader),
+            backend: None,
+            outputs: None,
+        }
+    }
+
+    /// The backend, created now if it does not exist yet.
+    ///
+    /// # Errors
+    /// Returns the loader's error.
+    pub fn ensure_loaded(&mut self) -> Result<&mut B, Error> {
+        if self.backend.is_none() {
+            let mut backend = (self.loader)()?;
+            if let Some(outputs) = self.outputs {
+                backend.select_outputs(outputs);
+            }
+            self.backend = Some(backend);
+        }
+        Ok(self.backend.as_mut().unwrap())
+    }
+
+    /// Whether the backend has been created.
+    pub fn is_loaded(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    /// The backend, if it has been created.
+    pub fn get(&self) -> Option<&B> {
+        self.backend.as_ref()
+    }
+}
+
+impl<B: InferenceBackend> InferenceBackend for LazyBackend<B> {
+    fn run(
+        &mut self,
+        tokens: Array3<f32>,
+        elo_self: &[f32],
+        elo_oppo: &[f32],
+    ) -> Result<ModelOutputs, Error> {
+        self.ensure_loaded()?.run(tokens, elo_self, elo_oppo)
+    }
+
+    fn run_until(
+        &mut self,
+        tokens: Array3<f32>,
+        elo_self: &[f32],
+        elo_oppo: &[f32],
+        deadline: Instant,
+    ) -> Result<ModelOutputs, Error> {
+        self.ensure_loaded()?
+            .run_until(tokens, elo_self, elo_oppo, deadline)
+    }
+
+    fn select_outputs(&mut self, outputs: OutputSelection) {
+        self.outputs = Some(outputs);
+        if let Some(backend) = &mut self.backend {
+            backend.select_outputs(outputs);
+        }
+    }
+}
+
+#[cfg(feature = "ort")]
+impl Maia<LazyBackend<OrtBackend>> {
+    /// A model loaded from `path` onto `device` by the first evaluation,
+    /// as [`Maia::from_file_on`] would load it now.
+    ///
+    /// Nothing is read until then, so a missing file or an unusable
+    /// device fails the first evaluation, or
+    /// [`ensure_loaded`](Self::ensure_loaded) when called earlier.
+    pub fn lazy_from_file(path: impl Into<PathBuf>, device: Device) -> Self {
+        let path = path.into();
+        Maia::from_backend(LazyBackend::new(move || {
+            Maia::from_file_on(&path, device).map(Maia::into_backend)
+        }))
+    }
+}
+
+impl<B: InferenceBackend> Maia<LazyBackend<B>> {
+    /// Create the backend now instead of on the first evaluation.
+    ///
+    /// # Errors
+    /// Returns the loader's error; a later call tries again.
+    pub fn ensure_loaded(&mut self) -> Result<(), Error> {
+        self.backend_mut().ensure_loaded().map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::testing::ZeroBackend;
+
+    /// A lazy model whose loader fails `failures` times, and the number
+    /// of loader calls.
+    fn counted(failures: usize) -> (Maia<LazyBackend<ZeroBackend>>, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let maia = Maia::from_backend(LazyBackend::new(move || {
+            if counter.fetch_add(1, Ordering::Relaxed) < failures {
+                Err(Error::ModelNotFound(vec!["maia.onnx".into()]))
+            } else {
+                Ok(ZeroBackend::default())
+            }
+        }));
+        (maia, calls)
+    }
+
+    const FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[test]
+    fn loads_once_on_first_evaluation() {
+        let (mut maia, calls) = counted(0);
+        assert!(!maia.backend().is_loaded());
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
+
+        maia.evaluate(FEN, 1500.0, 1500.0).unwrap();
+        maia.evaluate(FEN, 1500.0, 1500.0).unwrap();
+        maia.ensure_loaded().unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert!(maia.backend().get().is_some());
+    }
+
+    #[test]
+    fn load_errors_surface_and_are_retried() {
+        let (mut maia, calls) = counted(1);
+        assert!(matches!(
+            maia.evaluate(FEN, 1500.0, 1500.0),
+            Err(Error::ModelNotFound(paths)) if paths.len() == 1
+        ));
+        assert!(!maia.backend().is_loaded());
+        maia.ensure_loaded().unwrap();
+        maia.evaluate(FEN, 1500.0, 1500.0).unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+}
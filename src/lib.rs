@@ -11,19 +11,141 @@
 //! with associated probabilities) and explicit White/draw/Black
 //! outcome probabilities.
 //!
+//! The [`export`] module writes batches of results as JSON lines or CSV
+//! for downstream analysis.
+//!
+//! Inference runs through an [`InferenceBackend`]; the default `ort`
+//! feature provides the ONNX Runtime one. Without it the crate still
+//! builds the position encoding, move vocabulary and result types, which
+//! keeps it usable on targets like `wasm32-unknown-unknown` with a
+//! custom backend.
+//!
 //! The library re‑exports `shakmaty` to make position construction easy.
 
+pub mod analysis;
+mod backend;
+mod book;
+pub mod compare;
+#[cfg(feature = "download")]
+mod download;
+#[cfg(feature = "ort")]
+mod environment;
 mod error;
+pub mod eval;
+mod evaluator;
+pub mod export;
+#[cfg(feature = "ort")]
+mod external_data;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod golden;
+mod humanize;
+#[cfg(feature = "ingest")]
+pub mod ingest;
+mod input;
+mod lazy;
+#[cfg(feature = "lichess")]
+pub mod lichess;
+mod lines;
 mod maia;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod model_registry;
 mod moves;
+mod multi_device;
+#[cfg(feature = "npy")]
+pub mod npy;
+mod per_thread;
+#[cfg(feature = "pgn")]
+pub mod pgn;
+mod postprocess;
+mod registry;
+mod sanity;
+pub mod selection;
+pub mod selfplay;
+mod service;
+pub mod stats;
 mod tensor;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod tree;
+mod tune;
 mod types;
 
+/// Pluggable inference runtimes.
+#[cfg(feature = "ort")]
+pub use backend::OrtBackend;
+pub use backend::{DefaultBackend, InferenceBackend, ModelOutputs};
+/// Polyglot book reading and book/model policy blending.
+pub use book::{BlendMode, BookBlendedEvaluator, PolyglotBook};
+/// Cached model downloads.
+#[cfg(feature = "download")]
+pub use download::{
+    CacheConfig, CacheMode, STALE_LOCK, cached_model, cached_model_with_progress, stderr_progress,
+};
+/// Shared ONNX Runtime environment and thread pool.
+#[cfg(feature = "ort")]
+pub use environment::{MaiaEnvironment, MaiaEnvironmentBuilder};
 /// Error type produced by library operations.
-pub use error::Error;
+pub use error::{EloSide, Error, MAX_FEN_LEN};
+/// Common interface of `Maia` and the evaluators wrapping it.
+pub use evaluator::Evaluator;
+/// Comparison against reference evaluations of another implementation.
+pub use golden::{
+    FIXTURE_TOLERANCE, Fixture, FixtureCheck, FixtureMove, VerificationReport, verify_preprocessing,
+};
+/// Rating-dependent move sampling for human-like play.
+pub use humanize::{HumanizationProfile, SamplingParams, calibrate_temperature};
+/// Positions accepted by the generic evaluation entry points, and
+/// positions given as their parts.
+pub use input::{RawPosition, TryIntoSetup};
+/// Backends created on the first evaluation.
+pub use lazy::LazyBackend;
+/// Beam search for the most likely continuations.
+pub use lines::{Line, LineParams};
+/// Device selection when loading a model.
+#[cfg(feature = "ort")]
+pub use maia::Device;
 /// Main model wrapper.
 pub use maia::Maia;
+/// Several named models with lazy loading and eviction.
+pub use model_registry::{MaiaRegistry, ModelInfo};
+/// Move vocabulary indices, as in [`MoveProbability::index`].
+pub use moves::{mirrored_index, vocabulary_index};
+/// Batches split across one model per device.
+pub use multi_device::MultiDeviceMaia;
+/// One model session per worker thread.
+pub use per_thread::ThreadLocalMaia;
+/// Runtime-independent decoding of raw model outputs.
+pub use postprocess::{
+    EvalOptions, OutputSelection, postprocess, postprocess_into, postprocess_with_options,
+};
+/// Identification of official model files.
+pub use registry::{KNOWN_MODELS, KnownModel, model_sha256, verify_file};
+/// Self-checks of model outputs on reference positions and mirrored twins.
+pub use sanity::{
+    MirrorCheck, SANITY_ELO, SELF_TEST_TOLERANCE, SanityCheck, SanityReport, SelfTestReport,
+};
+/// Background worker that coalesces concurrent requests into batches.
+pub use service::{
+    MaiaService, OverloadPolicy, PendingEvaluation, Priority, ServiceConfig, ServiceStats,
+    ShutdownPolicy,
+};
 /// Re-export of `shakmaty` for convenience when building positions.
 pub use shakmaty;
+/// Runtime-independent input encoding, for running the model outside of
+/// [`Maia`].
+pub use tensor::{
+    BoardChannel, IncrementalEncoder, PreprocessedData, Validation, ValidationWarning,
+    channel_view, decode_tokens, format_board_compact, format_board_tensor, preprocess,
+    preprocess_with,
+};
+/// Batch-size throughput measurement.
+pub use tune::{BatchTiming, TuneReport};
 /// Output data structures returned by evaluations.
-pub use types::{EvaluationResult, MoveProbability};
+pub use types::{
+    Backup, EvaluationMeta, EvaluationResult, MATE_CENTIPAWNS, MoveProbability, MoveQuality,
+    PolicyBreakdown, Terminal, centipawns_to_score, score_to_centipawns,
+};
@@ -0,0 +1,413 @@
+//! Game-session logic for playing on lichess through the bot API.
+//!
+//! This module holds the parts of a bot that do not depend on an HTTP
+//! client: the typed events of the account and game streams, and a
+//! [`GameSession`] that follows one game from those events and decides
+//! what to play. `examples/lichess_bot.rs` wires it up to the real API.
+//!
+//! The session is transport- and runtime-agnostic: it tells the caller
+//! when a move is needed, the caller evaluates [`GameSession::position`]
+//! with whatever it likes (a [`Maia`](crate::Maia), a
+//! [`MaiaService`](crate::MaiaService), ...) and hands the result to
+//! [`GameSession::decide`].
+
+use rand::Rng;
+use serde::Deserialize;
+use shakmaty::{
+    CastlingMode, Chess, Color, EnPassantMode, Position, Setup, fen::Fen, uci::UciMove,
+};
+
+use crate::{
+    error::Error,
+    selection::{MoveSelector, Temperature},
+    types::EvaluationResult,
+};
+
+/// Event from `GET /api/stream/event`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AccountEvent {
+    /// Someone challenged the bot.
+    Challenge { challenge: Challenge },
+    /// A game involving the bot has started.
+    GameStart { game: GameStart },
+    /// Events the bot does not act on.
+    #[serde(other)]
+    Other,
+}
+
+/// Incoming challenge, as found in [`AccountEvent::Challenge`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct Challenge {
+    pub id: String,
+    pub variant: Variant,
+}
+
+impl Challenge {
+    /// Whether the game can be played by Maia, i.e. is regular chess.
+    pub fn is_supported(&self) -> bool {
+        matches!(self.variant.key.as_str(), "standard" | "fromPosition")
+    }
+}
+
+/// Variant description of a challenge.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Variant {
+    pub key: String,
+}
+
+/// Game reference in [`AccountEvent::GameStart`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct GameStart {
+    #[serde(rename = "gameId")]
+    pub game_id: String,
+}
+
+/// Event from `GET /api/bot/game/stream/{id}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GameEvent {
+    /// Full game description, always the first event of the stream.
+    GameFull(GameFull),
+    /// Moves or status changed.
+    GameState(GameState),
+    /// Chat messages and other events without effect on the game.
+    #[serde(other)]
+    Other,
+}
+
+/// Payload of [`GameEvent::GameFull`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameFull {
+    pub id: String,
+    pub white: Player,
+    pub black: Player,
+    /// `"startpos"` or a FEN.
+    pub initial_fen: String,
+    pub state: GameState,
+}
+
+/// One side of a game. Lichess AI opponents have no id or rating.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Player {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub rating: Option<f32>,
+}
+
+/// Moves and status of a game.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GameState {
+    /// All moves since the initial position, space-separated UCI.
+    pub moves: String,
+    /// `"started"` while the game is running, otherwise the reason it
+    /// ended (`"mate"`, `"resign"`, `"aborted"`, `"outoftime"`, ...).
+    pub status: String,
+}
+
+/// How the bot plays.
+#[derive(Debug, Clone)]
+pub struct BotConfig {
+    /// Rating the bot plays as.
+    pub elo_self: f32,
+    /// Rating assumed for the opponent. `None` uses the opponent's
+    /// lichess rating, falling back to `elo_self` for unrated players.
+    pub elo_oppo: Option<f32>,
+    /// Sampling temperature, see [`EvaluationResult::sample_move`].
+    pub temperature: f32,
+    /// Resign once the bot's expected score stays below this value for
+    /// `resign_moves` consecutive decisions. `None` never resigns.
+    pub resign_threshold: Option<f32>,
+    /// Consecutive low evaluations required before resigning.
+    pub resign_moves: usize,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            elo_self: 1500.0,
+            elo_oppo: None,
+            temperature: 1.0,
+            resign_threshold: Some(0.03),
+            resign_moves: 3,
+        }
+    }
+}
+
+/// What the bot should do after [`GameSession::decide`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BotAction {
+    /// Play this move.
+    Move(UciMove),
+    /// Give up the game.
+    Resign,
+    /// Nothing to do: the game is over or it is not the bot's turn.
+    Wait,
+}
+
+/// Follows one game from its event stream.
+#[derive(Debug, Clone)]
+pub struct GameSession {
+    id: String,
+    config: BotConfig,
+    color: Color,
+    elo_oppo: f32,
+    initial: Chess,
+    position: Chess,
+    moves: Vec<UciMove>,
+    finished: Option<String>,
+    low_value_streak: usize,
+}
+
+impl GameSession {
+    /// Start a session from the `gameFull` event. `bot_id` is the bot's
+    /// lichess user id and decides which color it plays.
+    ///
+    /// # Errors
+    /// Returns [`Error::InvalidFen`] or [`Error::InvalidPosition`] for a
+    /// bad initial position and [`Error::IllegalMove`] if the moves
+    /// already played do not apply.
+    pub fn new(bot_id: &str, game: &GameFull, config: BotConfig) -> Result<Self, Error> {
+        let is_bot = |p: &Player| {
+            p.id.as_deref()
+                .is_some_and(|id| id.eq_ignore_ascii_case(bot_id))
+        };
+        let (color, opponent) = if is_bot(&game.white) {
+            (Color::White, &game.black)
+        } else {
+            (Color::Black, &game.white)
+        };
+        let elo_oppo = config
+            .elo_oppo
+            .or(opponent.rating)
+            .unwrap_or(config.elo_self);
+
+        let initial = if game.initial_fen == "startpos" {
+            Chess::default()
+        } else {
+            let fen = &game.initial_fen;
+            fen.parse::<Fen>()
+                .map_err(|e| Error::invalid_fen(fen, e))?
+                .into_position(CastlingMode::Standard)?
+        };
+
+        let mut session = Self {
+            id: game.id.clone(),
+            config,
+            color,
+            elo_oppo,
+            position: initial.clone(),
+            initial,
+            moves: Vec::new(),
+            finished: None,
+            low_value_streak: 0,
+        };
+        session.update(&game.state)?;
+        Ok(session)
+    }
+
+    /// Apply a game event. `gameFull` events after the first one (sent
+    /// on reconnect) are treated as state updates.
+    ///
+    /// # Errors
+    /// See [`update`](Self::update).
+    pub fn handle_event(&mut self, event: &GameEvent) -> Result<(), Error> {
+        match event {
+            GameEvent::GameFull(game) => self.update(&game.state),
+            GameEvent::GameState(state) => self.update(state),
+            GameEvent::Other => Ok(()),
+        }
+    }
+
+    /// Bring the position up to date with `state`.
+    ///
+    /// Only moves beyond those already applied are played; if the list
+    /// got shorter (a takeback) or diverged, the position is rebuilt
+    /// from the initial one.
+    ///
+    /// # Errors
+    /// Returns [`Error::IllegalMove`] if a move does not apply. The
+    /// session is left unchanged in that case.
+    pub fn update(&mut self, state: &GameState) -> Result<(), Error> {
+        let moves = state
+            .moves
+            .split_whitespace()
+            .map(|m| {
+                m.parse::<UciMove>()
+                    .map_err(|_| Error::IllegalMove(m.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (mut position, applied) = if moves.starts_with(&self.moves) {
+            (self.position.clone(), self.moves.len())
+        } else {
+            (self.initial.clone(), 0)
+        };
+        for uci in &moves[applied..] {
+            let m = uci
+                .to_move(&position)
+                .map_err(|_| Error::IllegalMove(uci.to_string()))?;
+            position.play_unchecked(m);
+        }
+
+        self.position = position;
+        self.moves = moves;
+        self.finished = match state.status.as_str() {
+            "created" | "started" => None,
+            status => Some(status.to_string()),
+        };
+        Ok(())
+    }
+
+    /// Lichess game id.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Color the bot plays.
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    /// Current position.
+    pub fn position(&self) -> &Chess {
+        &self.position
+    }
+
+    /// Current position as the [`Setup`] to evaluate.
+    pub fn setup(&self) -> Setup {
+        self.position.to_setup(EnPassantMode::Legal)
+    }
+
+    /// `(elo_self, elo_oppo)` to evaluate the bot's positions with.
+    pub fn elos(&self) -> (f32, f32) {
+        (self.config.elo_self, self.elo_oppo)
+    }
+
+    /// Why the game ended (lichess status string), if it has.
+    pub fn finished(&self) -> Option<&str> {
+        self.finished.as_deref()
+    }
+
+    /// Whether the bot has to move now.
+    pub fn needs_move(&self) -> bool {
+        self.finished.is_none()
+            && self.position.turn() == self.color
+            && !self.position.is_game_over()
+    }
+
+    /// Decide on an action given the evaluation of [`position`](Self::position).
+    ///
+    /// Tracks the resignation streak, so call it once per move.
+    pub fn decide(&mut self, result: &EvaluationResult, rng: &mut impl Rng) -> BotAction {
+        if !self.needs_move() {
+            return BotAction::Wait;
+        }
+
+        let hopeless = self
+            .config
+            .resign_threshold
+            .is_some_and(|t| result.expected_score(self.color) < t);
+        self.low_value_streak = if hopeless {
+            self.low_value_streak + 1
+        } else {
+            0
+        };
+        if hopeless && self.low_value_streak >= self.config.resign_moves {
+            return BotAction::Resign;
+        }
+
+        match Temperature(self.config.temperature).select(result, rng) {
+            Some(uci) => BotAction::Move(uci),
+            None => BotAction::Wait,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::testing::white_move;
+
+    const GAME_FULL: &str = r#"{
+        "type": "gameFull", "id": "abcd1234", "rated": false,
+        "white": {"id": "opponent", "name": "Opponent", "rating": 1720},
+        "black": {"id": "maiabot", "name": "MaiaBot", "rating": 1500},
+        "initialFen": "startpos",
+        "state": {"type": "gameState", "moves": "e2e4", "wtime": 60000, "btime": 60000, "status": "started"}
+    }"#;
+
+    fn session(config: BotConfig) -> GameSession {
+        let GameEvent::GameFull(game) = serde_json::from_str(GAME_FULL).unwrap() else {
+            panic!("expected gameFull");
+        };
+        GameSession::new("MaiaBot", &game, config).unwrap()
+    }
+
+    fn result(uci: &str, black_wr: f32) -> EvaluationResult {
+        EvaluationResult {
+            policy: vec![white_move(uci, 1.0)],
+            white_wr: 1.0 - black_wr,
+            draw: 0.0,
+            black_wr,
+            outcome: None,
+        }
+    }
+
+    #[test]
+    fn game_full_sets_color_and_opponent_elo() {
+        let session = session(BotConfig::default());
+        assert_eq!(session.color(), Color::Black);
+        assert_eq!(session.elos(), (1500.0, 1720.0));
+        assert!(session.needs_move());
+    }
+
+    #[test]
+    fn state_updates_apply_incrementally_and_track_status() {
+        let mut session = session(BotConfig::default());
+        let event: GameEvent = serde_json::from_str(
+            r#"{"type": "gameState", "moves": "e2e4 e7e5 g1f3", "status": "started"}"#,
+        )
+        .unwrap();
+        session.handle_event(&event).unwrap();
+        assert_eq!(session.position().fullmoves().get(), 2);
+        assert!(session.needs_move());
+
+        // Takeback to before the bot's move, then the game is aborted.
+        let state = GameState {
+            moves: "e2e4".to_string(),
+            status: "aborted".to_string(),
+        };
+        session.update(&state).unwrap();
+        assert_eq!(session.finished(), Some("aborted"));
+        assert!(!session.needs_move());
+
+        let bad = GameState {
+            moves: "e2e4 e2e4".to_string(),
+            status: "started".to_string(),
+        };
+        assert!(matches!(session.update(&bad), Err(Error::IllegalMove(_))));
+    }
+
+    #[test]
+    fn resigns_after_consecutive_hopeless_evaluations() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut session = session(BotConfig {
+            temperature: 0.0,
+            resign_threshold: Some(0.1),
+            resign_moves: 2,
+            ..BotConfig::default()
+        });
+
+        let lost = result("e7e5", 0.01);
+        assert_eq!(
+            session.decide(&lost, &mut rng),
+            BotAction::Move("e7e5".parse().unwrap())
+        );
+        assert_eq!(session.decide(&lost, &mut rng), BotAction::Resign);
+    }
+}
@@ -0,0 +1,328 @@
+//! The most likely continuations of a position, by beam search over the
+//! policy.
+//!
+//! Each line is scored by the product of the move probabilities along
+//! it, with the ratings following the side to move. Every depth of the
+//! search is one batch: all positions in the beam are evaluated together,
+//! then their children compete for the next beam. Move orders reaching
+//! the same position are merged into the likelier one, their
+//! probabilities summed.
+
+use std::collections::HashMap;
+
+use shakmaty::{Chess, Color, EnPassantMode, Move, Position, zobrist::Zobrist64};
+
+use crate::{Maia, backend::InferenceBackend, error::Error, types::Terminal};
+
+/// Limits of [`Maia::likely_lines`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineParams {
+    /// Plies to search.
+    pub depth: usize,
+    /// Positions kept after each ply, the most likely ones.
+    pub beam_width: usize,
+    /// Cumulative probability below which a continuation is dropped. A
+    /// position whose every continuation is dropped ends its line there.
+    pub min_probability: f32,
+}
+
+impl Default for LineParams {
+    fn default() -> Self {
+        Self {
+            depth: 6,
+            beam_width: 8,
+            min_probability: 1e-4,
+        }
+    }
+}
+
+/// A continuation found by [`Maia::likely_lines`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub moves: Vec<Move>,
+    /// Probability of the line: the product of the move probabilities,
+    /// summed over the move orders merged into it.
+    pub probability: f32,
+    /// White's expected score at the end of the line, exact if the game
+    /// is over.
+    pub value: f32,
+}
+
+/// A line in the beam. `value` is set once the line can no longer grow.
+struct Node {
+    position: Chess,
+    moves: Vec<Move>,
+    probability: f32,
+    value: Option<f32>,
+}
+
+impl<B: InferenceBackend> Maia<B> {
+    /// Up to `params.beam_width` most likely lines from `pos`, most
+    /// likely first.
+    ///
+    /// Positions are evaluated with the rating of the side to move as
+    /// `elo_self`, one batch per ply plus one for the values at the end.
+    /// Lines stop early at checkmate or stalemate, or when none of their
+    /// continuations reaches `params.min_probability`.
+    ///
+    /// # Errors
+    /// Propagates evaluation errors.
+    pub fn likely_lines(
+        &mut self,
+        pos: &Chess,
+        white_elo: f32,
+        black_elo: f32,
+        params: &LineParams,
+    ) -> Result<Vec<Line>, Error> {
+        let elos = |pos: &Chess| match pos.turn() {
+            Color::White => (white_elo, black_elo),
+            Color::Black => (black_elo, white_elo),
+        };
+        let mut beam = vec![Node {
+            value: terminal_value(pos),
+            position: pos.clone(),
+            moves: Vec::new(),
+            probability: 1.0,
+        }];
+
+        for depth in 0..=params.depth {
+            let open: Vec<usize> = (0..beam.len())
+                .filter(|&i| beam[i].value.is_none())
+                .collect();
+            if open.is_empty() {
+                break;
+            }
+            let setups = open
+                .iter()
+                .map(|&i| beam[i].position.to_setup(EnPassantMode::Legal))
+                .collect::<Vec<_>>();
+            let (elo_selfs, elo_oppos): (Vec<f32>, Vec<f32>) =
+                open.iter().map(|&i| elos(&beam[i].position)).unzip();
+            let results = self.batch_evaluate(setups, &elo_selfs, &elo_oppos)?;
+            let mut results: HashMap<usize, _> = open.into_iter().zip(results).collect();
+
+            if depth == params.depth {
+                for (i, result) in results {
+                    beam[i].value = Some(result.value_white());
+                }
+                break;
+            }
+
+            let mut next: Vec<Node> = Vec::new();
+            let mut index: HashMap<Zobrist64, usize> = HashMap::new();
+            for (i, node) in beam.into_iter().enumerate() {
+                let Some(result) = results.remove(&i) else {
+                    push_merged(&mut next, &mut index, node);
+                    continue;
+                };
+                let mut extended = false;
+                for reply in &result.policy {
+                    let probability = node.probability * reply.probability;
+                    if probability < params.min_probability {
+                        continue;
+                    }
+                    let Ok(m) = reply.uci.to_move(&node.position) else {
+                        continue;
+                    };
+                    let mut position = node.position.clone();
+                    position.play_unchecked(m);
+                    let mut moves = node.moves.clone();
+                    moves.push(m);
+                    extended = true;
+                    push_merged(
+                        &mut next,
+                        &mut index,
+                        Node {
+                            value: terminal_value(&position),
+                            position,
+                            moves,
+                            probability,
+                        },
+                    );
+                }
+                if !extended {
+                    push_merged(
+                        &mut next,
+                        &mut index,
+                        Node {
+                            value: Some(result.value_white()),
+                            ..node
+                        },
+                    );
+                }
+            }
+            next.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+            next.truncate(params.beam_width);
+            beam = next;
+        }
+
+        Ok(beam
+            .into_iter()
+            .map(|node| Line {
+                moves: node.moves,
+                probability: node.probability,
+                value: node.value.expect("every line evaluated"),
+            })
+            .collect())
+    }
+}
+
+/// White's exact score in a finished game, `None` if it goes on.
+fn terminal_value(pos: &Chess) -> Option<f32> {
+    Terminal::of(pos).map(|terminal| {
+        let score = terminal.score();
+        pos.turn().fold_wb(score, 1.0 - score)
+    })
+}
+
+/// Add `node` to `beam`, merging it with a line that reached the same
+/// position: the probabilities add up and the likelier move order stays.
+fn push_merged(beam: &mut Vec<Node>, index: &mut HashMap<Zobrist64, usize>, node: Node) {
+    let key = node.position.zobrist_hash(EnPassantMode::Legal);
+    let Some(&i) = index.get(&key) else {
+        index.insert(key, beam.len());
+        beam.push(node);
+        return;
+    };
+    let existing = &mut beam[i];
+    let probability = existing.probability + node.probability;
+    if node.probability > existing.probability {
+        *existing = node;
+    }
+    existing.probability = probability;
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array2, Array3};
+    use shakmaty::{CastlingMode, fen::Fen};
+
+    use super::*;
+    use crate::backend::ModelOutputs;
+
+    /// Uniform policy and value, recording each batch's `elo_self`.
+    #[derive(Default)]
+    struct RecordingBackend {
+        batches: Vec<Vec<f32>>,
+    }
+
+    impl InferenceBackend for RecordingBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            elo_self: &[f32],
+            _elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            self.batches.push(elo_self.to_vec());
+            let batch_size = tokens.shape()[0];
+            Ok(ModelOutputs {
+                logits_move: Array2::zeros((batch_size, crate::moves::ALL_MOVES.len())),
+                logits_value: Array2::zeros((batch_size, 3)),
+            })
+        }
+    }
+
+    fn position(fen: &str) -> Chess {
+        fen.parse::<Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap()
+    }
+
+    #[test]
+    fn each_ply_is_one_batch_with_swapped_elos() {
+        let mut maia = Maia::from_backend(RecordingBackend::default());
+        let params = LineParams {
+            depth: 2,
+            beam_width: 5,
+            min_probability: 0.0,
+        };
+        let lines = maia
+            .likely_lines(&Chess::default(), 1400.0, 1800.0, &params)
+            .unwrap();
+
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|l| l.moves.len() == 2));
+        assert!(
+            lines
+                .iter()
+                .all(|l| (l.probability - 1.0 / 400.0).abs() < 1e-6)
+        );
+        assert!(lines.iter().all(|l| (l.value - 0.5).abs() < 1e-6));
+        // The root, five White moves, then the five lines' final positions.
+        assert_eq!(
+            maia.backend().batches,
+            [vec![1400.0], vec![1800.0; 5], vec![1400.0; 5]]
+        );
+    }
+
+    #[test]
+    fn transpositions_merge_and_keep_the_mass() {
+        let mut maia = Maia::from_backend(RecordingBackend::default());
+        let params = LineParams {
+            depth: 3,
+            beam_width: usize::MAX,
+            min_probability: 0.0,
+        };
+        // Bare kings: Kd3-d4 and Ke4-d4 around a Black move transpose.
+        let pos = position("8/8/4k3/8/8/4K3/8/8 w - - 0 1");
+        let lines = maia.likely_lines(&pos, 1500.0, 1500.0, &params).unwrap();
+
+        let sequences: usize = pos
+            .legal_moves()
+            .iter()
+            .map(|&m| {
+                let mut child = pos.clone();
+                child.play_unchecked(m);
+                child
+                    .legal_moves()
+                    .iter()
+                    .map(|&r| {
+                        let mut after = child.clone();
+                        after.play_unchecked(r);
+                        after.legal_moves().len()
+                    })
+                    .sum::<usize>()
+            })
+            .sum();
+        assert!(lines.len() < sequences);
+        assert!(lines.iter().all(|l| l.moves.len() == 3));
+        let total: f32 = lines.iter().map(|l| l.probability).sum();
+        assert!((total - 1.0).abs() < 1e-3, "{total}");
+        assert!(
+            lines
+                .windows(2)
+                .all(|w| w[0].probability >= w[1].probability)
+        );
+    }
+
+    #[test]
+    fn lines_end_at_the_cutoff_and_at_mate() {
+        let mut maia = Maia::from_backend(RecordingBackend::default());
+        let params = LineParams {
+            depth: 2,
+            beam_width: 5,
+            min_probability: 0.01,
+        };
+        // Replies at 1/400 fall below the cutoff, so the lines stop after
+        // White's move and take its value.
+        let lines = maia
+            .likely_lines(&Chess::default(), 1500.0, 1500.0, &params)
+            .unwrap();
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|l| l.moves.len() == 1));
+        assert_eq!(maia.backend().batches.len(), 2);
+
+        // Fool's mate: every line that plays Qh4# ends in a Black win.
+        let pos = position("rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2");
+        let params = LineParams {
+            depth: 2,
+            beam_width: usize::MAX,
+            min_probability: 0.0,
+        };
+        let lines = maia.likely_lines(&pos, 1500.0, 1500.0, &params).unwrap();
+        let mate = lines.iter().find(|l| l.moves.len() == 1).unwrap();
+        assert_eq!(mate.value, 0.0);
+        assert_eq!(lines.iter().filter(|l| l.moves.len() == 1).count(), 1);
+    }
+}
@@ -1,56 +1,529 @@
-use std::path::Path;
+#[cfg(feature = "ort")]
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+use std::{ops::RangeInclusive, time::Instant};
 
-use ndarray::{ArrayView1, Axis};
-use ort::{session::Session, value::Tensor};
-use shakmaty::{Chess, Position, Setup};
+use ndarray::{Axis, s};
+#[cfg(feature = "ort")]
+use ort::session::Session;
+use shakmaty::{CastlingMode, Chess, EnPassantMode, Move, Position, Setup};
 
 use crate::{
-    error::Error,
+    backend::{DefaultBackend, InferenceBackend, ModelOutputs, is_out_of_memory},
+    error::{EloSide, Error},
+    input::{RawPosition, TryIntoSetup, try_into_setups},
     moves::ALL_MOVES,
-    tensor::preprocess,
-    types::{EvaluationResult, MoveProbability},
+    postprocess::{EvalOptions, OutputSelection, postprocess_into, postprocess_with_options},
+    tensor::{PreprocessedData, preprocess_inspecting, preprocess_with, validate},
+    types::{
+        Backup, EvaluationMeta, EvaluationResult, MATE_CENTIPAWNS, MoveQuality, Terminal,
+        score_to_centipawns,
+    },
+};
+#[cfg(feature = "ort")]
+use crate::{
+    backend::{OrtBackend, extract_outputs, with_deadline},
+    external_data,
+    registry::{ModelSource, sha256_hex},
 };
 
-/// Wrapper around an ONNX Runtime session configured with the
-/// Maia3 model.
+/// Maia3 evaluator running on an [`InferenceBackend`].
 ///
-/// The struct manages an inference session and exposes evaluation
-/// functions that accept FEN strings or `shakmaty` setups.  The
-/// model expects inputs in a specific tensor layout; helper functions
-/// in the `tensor` module handle the conversion.
-pub struct Maia {
-    session: Session,
+/// The struct owns the backend (an ONNX Runtime session by default) and
+/// exposes evaluation functions that accept FEN strings or `shakmaty`
+/// setups.  The model expects inputs in a specific tensor layout; helper
+/// functions in the `tensor` module handle the conversion.
+pub struct Maia<B = DefaultBackend> {
+    backend: B,
+    elo_range: Option<RangeInclusive<f32>>,
+    options: EvalOptions,
 }
 
-impl Maia {
+#[cfg(feature = "ort")]
+impl Maia<OrtBackend> {
     /// Create a Maia instance by loading a model from a `.onnx` file.
     ///
+    /// Weights stored as external data are read from their files next
+    /// to the model, whatever the working directory.
+    ///
     /// # Errors
-    /// Returns an [`Error::OrtError`] if the session cannot be
-    /// constructed or the file cannot be read.
+    /// Returns [`Error::MissingExternalData`] if the model's external
+    /// data files are not next to it, or an [`Error::OrtError`] if the
+    /// session cannot be constructed or the file cannot be read.
     pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
-        let session = Session::builder()?.commit_from_file(path)?;
+        let path = path.as_ref();
+        let session = external_data::commit_from_file(&mut Session::builder()?, path)?;
+
+        Ok(Self::from_loaded(
+            session,
+            ModelSource::File(path.to_path_buf()),
+        ))
+    }
+
+    /// Load a model from a `.onnx` file onto `device`.
+    ///
+    /// GPU devices fail to load rather than silently falling back to the
+    /// CPU when their execution provider is unavailable.
+    ///
+    /// # Errors
+    /// As [`from_file`](Self::from_file), or an [`Error::OrtError`] if
+    /// the device cannot be used.
+    pub fn from_file_on(path: impl AsRef<Path>, device: Device) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut builder = on_device(Session::builder()?, device, None)?;
+        let session = external_data::commit_from_file(&mut builder, path)?;
+
+        Ok(Self::from_loaded(
+            session,
+            ModelSource::File(path.to_path_buf()),
+        ))
+    }
+
+    /// [`from_file`](Self::from_file) reading the model through a
+    /// memory map, for short-lived processes: the pages come straight
+    /// from the OS page cache rather than being read into a buffer.
+    /// For a 90 MB file on Linux, getting at every page of the bytes took
+    /// 3 ms through the map against 50 ms for a read with the file in the
+    /// page cache, and 43 ms against 86 ms for a cold cache; building the
+    /// session from the bytes costs the same either way.
+    ///
+    /// ONNX Runtime copies what it keeps out of the bytes while it builds
+    /// the session, so the map is released before this returns and the
+    /// session does not borrow the file. Where mapping fails, on
+    /// platforms without `mmap` and for models with external data, this
+    /// loads through [`from_file`](Self::from_file) instead.
+    ///
+    /// # Errors
+    /// As [`from_file`](Self::from_file).
+    #[cfg(feature = "mmap")]
+    pub fn from_file_mmap(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mapping = match crate::mmap::Mapping::open(path) {
+            Ok(mapping) => mapping,
+            Err(err) => {
+                tracing::debug!(path = %path.display(), "reading model without mmap: {err}");
+                return Self::from_file(path);
+            }
+        };
+        if !external_data::external_data_files(mapping.bytes()).is_empty() {
+            return Self::from_file(path);
+        }
+        let session = Session::builder()?.commit_from_memory(mapping.bytes())?;
+        drop(mapping);
+
+        Ok(Self::from_loaded(
+            session,
+            ModelSource::File(path.to_path_buf()),
+        ))
+    }
 
-        Ok(Self { session })
+    /// Load the model from the first location that has it:
+    ///
+    /// 1. the file named by the `MAIA_MODEL` environment variable,
+    /// 2. `maia/maia3_simplified.onnx` in the per-user cache directory
+    ///    (`$XDG_CACHE_HOME` or `~/.cache` on Linux, `~/Library/Caches`
+    ///    on macOS, `%LOCALAPPDATA%` on Windows).
+    ///
+    /// # Errors
+    /// Returns [`Error::ModelNotFound`] listing every location checked
+    /// if none exists, or [`Error::OrtError`] if loading fails.
+    pub fn from_default_model() -> Result<Self, Error> {
+        let candidates = model_candidates(env::var_os(MODEL_ENV_VAR), user_cache_dir());
+        match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => Self::from_file(path),
+            None => Err(Error::ModelNotFound(candidates)),
+        }
     }
 
     /// Construct from raw ONNX model bytes, useful for embedding the
     /// model in the binary or loading from a network source.
     ///
     /// # Errors
-    /// Similar to [`from_file`], errors are propagated as
-    /// [`Error::OrtError`].
+    /// Returns [`Error::MissingExternalData`] if the model keeps weights
+    /// in external data files, which bytes alone cannot resolve; load
+    /// such models with [`from_file`](Self::from_file) or
+    /// [`from_memory_with_external_data`](Self::from_memory_with_external_data).
+    /// Other errors are propagated as [`Error::OrtError`].
     pub fn from_memory(model_bytes: &[u8]) -> Result<Self, Error> {
-        let session = Session::builder()?.commit_from_memory(model_bytes)?;
+        Self::from_memory_with_external_data(model_bytes, Vec::<(String, Vec<u8>)>::new())
+    }
+
+    /// [`from_memory`](Self::from_memory) for a model with external
+    /// data, given as the file names the model refers to, e.g.
+    /// `"maia3.onnx.data"`, with their contents.
+    ///
+    /// # Errors
+    /// Returns [`Error::MissingExternalData`] listing the files the
+    /// model refers to that are not in `data`; otherwise as
+    /// [`from_memory`](Self::from_memory).
+    pub fn from_memory_with_external_data(
+        model_bytes: &[u8],
+        data: impl IntoIterator<Item = (impl Into<String>, Vec<u8>)>,
+    ) -> Result<Self, Error> {
+        let data = data.into_iter().map(|(n, d)| (n.into(), d)).collect();
+        let session = external_data::commit_from_memory(Session::builder()?, model_bytes, data)?;
 
-        Ok(Self { session })
+        Ok(Self::from_loaded(
+            session,
+            ModelSource::Sha256(sha256_hex(model_bytes)),
+        ))
+    }
+
+    /// Reuse bound input and output buffers for batches of up to
+    /// `max_batch` positions, see [`OrtBackend::with_io_binding`].
+    pub fn with_io_binding(mut self, max_batch: usize) -> Self {
+        self.backend = self.backend.with_io_binding(max_batch);
+        self
+    }
+
+    /// Keep the bound inputs in pinned host memory of CUDA device
+    /// `device_id`, see [`OrtBackend::with_pinned_inputs`].
+    ///
+    /// # Errors
+    /// Returns an [`Error::OrtError`] if the session cannot allocate
+    /// pinned memory.
+    #[cfg(feature = "cuda")]
+    pub fn with_pinned_inputs(mut self, device_id: i32) -> Result<Self, Error> {
+        self.backend = self.backend.with_pinned_inputs(device_id)?;
+        Ok(self)
     }
 
     /// Construct from an existing ONNX Runtime session that's running Maia3, allowing users to
     /// configure the session themselves.
     pub fn from_session(session: Session) -> Self {
-        Self { session }
+        Self::from_backend(OrtBackend::new(session))
+    }
+
+    /// A session loaded from `source`, which [`verify`](Self::verify)
+    /// identifies.
+    pub(crate) fn from_loaded(session: Session, source: ModelSource) -> Self {
+        Self::from_backend(OrtBackend::new(session).with_source(source))
+    }
+
+    /// Asynchronous version of [`batch_evaluate`].
+    ///
+    /// This function behaves identically to `batch_evaluate`, except that
+    /// it uses [`Session::run_async`] internally and therefore returns a
+    /// future that must be `.await`ed.  It is useful when the caller is
+    /// already running inside an async runtime and wants to avoid blocking.
+    ///
+    /// An [`EvalOptions::deadline`] is only checked before and after the
+    /// run; to abandon a late run, drop the future, e.g. by wrapping it
+    /// in the runtime's timeout.
+    pub async fn batch_evaluate_async(
+        &mut self,
+        setups: impl IntoIterator<Item = Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+        options: &ort::session::RunOptions,
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        let deadline = self.deadline();
+        let setups: Vec<Setup> = setups.into_iter().collect();
+        let batch_size = setups.len();
+        self.check_elos(batch_size, elo_selfs, elo_oppos)?;
+        if batch_size == 0 {
+            return Ok(Vec::new());
+        }
+
+        // 1. Preprocess
+        let (board, data) = preprocess_with(setups, batch_size, self.options.validation)?;
+
+        // 3. Run inference asynchronously and postprocess
+        check_deadline(deadline, 0, batch_size)?;
+        let [tokens, elo_self, elo_oppo] = self.backend.inputs(board, elo_selfs, elo_oppos)?;
+        let outputs = self
+            .backend
+            .session
+            .run_async(
+                ort::inputs! {
+                    "tokens" => tokens,
+                    "elo_self" => elo_self,
+                    "elo_oppo" => elo_oppo,
+                },
+                options,
+            )?
+            .await?;
+        check_deadline(deadline, 0, batch_size)?;
+
+        finalize_batch(&extract_outputs(&outputs)?, &data, &self.options)
+    }
+
+    /// Batch evaluation that allows callers to supply custom `RunOptions`.
+    ///
+    /// The provided [`ort::session::RunOptions`] are forwarded directly to
+    /// [`Session::run_with_options`].  This is handy when the user wants to
+    /// adjust logging, threading, or profiling behaviour on a per-inference
+    /// basis.
+    ///
+    /// With an [`EvalOptions::deadline`] set, `options` are terminated
+    /// when it passes and reset before this returns.
+    pub fn batch_evaluate_with_options(
+        &mut self,
+        setups: impl IntoIterator<Item = Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+        options: &ort::session::RunOptions,
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        let deadline = self.deadline();
+        let setups: Vec<Setup> = setups.into_iter().collect();
+        let batch_size = setups.len();
+        self.check_elos(batch_size, elo_selfs, elo_oppos)?;
+        if batch_size == 0 {
+            return Ok(Vec::new());
+        }
+
+        // 1. Preprocess
+        let (board, data) = preprocess_with(setups, batch_size, self.options.validation)?;
+
+        // 3. Run inference with options and postprocess
+        check_deadline(deadline, 0, batch_size)?;
+        let [tokens, elo_self, elo_oppo] = self.backend.inputs(board, elo_selfs, elo_oppos)?;
+        let session = &mut self.backend.session;
+        let run = || {
+            let outputs = session.run_with_options(
+                ort::inputs! {
+                    "tokens" => tokens,
+                    "elo_self" => elo_self,
+                    "elo_oppo" => elo_oppo,
+                },
+                options,
+            )?;
+            extract_outputs(&outputs)
+        };
+        let outputs = match deadline {
+            Some(deadline) => with_deadline(options, deadline, batch_size, run)?,
+            None => run()?,
+        };
+        check_deadline(deadline, 0, batch_size)?;
+
+        finalize_batch(&outputs, &data, &self.options)
+    }
+}
+
+impl<B: InferenceBackend> Maia<B> {
+    /// Construct from any inference backend.
+    pub fn from_backend(backend: B) -> Self {
+        Self {
+            backend,
+            elo_range: None,
+            options: EvalOptions::default(),
+        }
+    }
+
+    /// The backend running the network.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Mutable access to the backend running the network.
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// The backend, dropping the evaluation settings.
+    pub fn into_backend(self) -> B {
+        self.backend
+    }
+
+    /// Enable strict elo checking.
+    ///
+    /// Maia3 conditions on raw ratings, so by default any value (even `0`
+    /// or `NaN`) is forwarded to the network unchanged. With a range set,
+    /// every batch entry point rejects ratings outside `range` with
+    /// [`Error::EloOutOfRange`] before running inference.
+    pub fn with_elo_range(mut self, range: RangeInclusive<f32>) -> Self {
+        self.elo_range = Some(range);
+        self
+    }
+
+    /// Shape results according to `options`, see [`EvalOptions`].
+    pub fn with_eval_options(mut self, options: EvalOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The options results are shaped with.
+    pub fn eval_options(&self) -> &EvalOptions {
+        &self.options
+    }
+
+    /// Change the options between calls.
+    pub fn eval_options_mut(&mut self) -> &mut EvalOptions {
+        &mut self.options
+    }
+
+    /// Rough size in bytes of the input and output tensors of one run
+    /// over `batch_size` positions, with the heads
+    /// [`EvalOptions::outputs`] selects.
+    ///
+    /// The estimate is linear in the batch size and leaves out the
+    /// weights and the activations inside the graph, which ONNX Runtime
+    /// allocates on its own. To keep runs within a memory budget, pass
+    /// batches of at most `budget / maia.memory_estimate(1)` positions
+    /// (or set [`ServiceConfig::max_batch_size`](crate::ServiceConfig::max_batch_size)
+    /// to it), leaving headroom for the activations. A run that still
+    /// fails to allocate is split further per
+    /// [`EvalOptions::oom_min_batch`].
+    pub fn memory_estimate(&self, batch_size: usize) -> usize {
+        let outputs = self.options.outputs;
+        let mut floats = 64 * 12 + 2;
+        if outputs.policy() {
+            floats += ALL_MOVES.len();
+        }
+        if outputs.value() {
+            floats += 3;
+        }
+        batch_size * floats * size_of::<f32>()
+    }
+
+    /// When a call starting now has to be done by, per
+    /// [`EvalOptions::deadline`].
+    fn deadline(&self) -> Option<Instant> {
+        self.options.deadline.map(|budget| Instant::now() + budget)
+    }
+
+    /// Run the backend on a batch, failing with [`Error::Timeout`] if
+    /// `deadline` passes first.
+    ///
+    /// A run that fails to allocate memory is split in halves down to
+    /// [`EvalOptions::oom_min_batch`], and the remaining positions
+    /// continue in runs of the size that fit.
+    fn infer(
+        &mut self,
+        tokens: ndarray::Array3<f32>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+        deadline: Option<Instant>,
+    ) -> Result<ModelOutputs, Error> {
+        let total = elo_selfs.len();
+        let min_batch = match self.options.oom_min_batch {
+            Some(min_batch) if total > min_batch.max(1) => min_batch.max(1),
+            _ => return self.run_once(tokens, elo_selfs, elo_oppos, deadline, 0, total),
+        };
+
+        let mut chunk = total;
+        let mut completed = 0;
+        let mut parts = Vec::new();
+        while completed < total {
+            let end = (completed + chunk).min(total);
+            let run = self.run_once(
+                tokens.slice(s![completed..end, .., ..]).to_owned(),
+                &elo_selfs[completed..end],
+                &elo_oppos[completed..end],
+                deadline,
+                completed,
+                total,
+            );
+            match run {
+                Ok(outputs) => {
+                    parts.push(outputs);
+                    completed = end;
+                }
+                Err(err) if is_out_of_memory(&err) && end - completed > min_batch => {
+                    chunk = (end - completed).div_ceil(2).max(min_batch);
+                    tracing::warn!(
+                        batch = end - completed,
+                        retry = chunk,
+                        "inference ran out of memory, retrying in smaller runs: {err}"
+                    );
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        if parts.len() == 1 {
+            return Ok(parts.pop().expect("one part"));
+        }
+        let concat = |views: Vec<ndarray::ArrayView2<f32>>| {
+            ndarray::concatenate(Axis(0), &views).map_err(Error::from)
+        };
+        Ok(ModelOutputs {
+            logits_move: concat(parts.iter().map(|p| p.logits_move.view()).collect())?,
+            logits_value: concat(parts.iter().map(|p| p.logits_value.view()).collect())?,
+        })
+    }
+
+    /// One backend run over positions `completed..` of a call evaluating
+    /// `total`, checking `deadline` before and after.
+    fn run_once(
+        &mut self,
+        tokens: ndarray::Array3<f32>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+        deadline: Option<Instant>,
+        completed: usize,
+        total: usize,
+    ) -> Result<ModelOutputs, Error> {
+        #[cfg(feature = "metrics")]
+        let started = Instant::now();
+        let outputs = self.run_backend(tokens, elo_selfs, elo_oppos, deadline, completed, total);
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_run(elo_selfs.len(), started.elapsed(), outputs.is_ok());
+        outputs
+    }
+
+    /// [`run_once`](Self::run_once) without the metrics.
+    fn run_backend(
+        &mut self,
+        tokens: ndarray::Array3<f32>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+        deadline: Option<Instant>,
+        completed: usize,
+        total: usize,
+    ) -> Result<ModelOutputs, Error> {
+        self.backend.select_outputs(self.options.outputs);
+        let Some(at) = deadline else {
+            return self.backend.run(tokens, elo_selfs, elo_oppos);
+        };
+        check_deadline(deadline, completed, total)?;
+        let outputs = match self.backend.run_until(tokens, elo_selfs, elo_oppos, at) {
+            Err(Error::Timeout { .. }) => Err(Error::Timeout { completed, total }),
+            outputs => outputs,
+        }?;
+        check_deadline(deadline, completed, total)?;
+        Ok(outputs)
+    }
+
+    /// Check `elo_selfs` and `elo_oppos` for a batch of `batch_size`
+    /// setups against its size and the configured elo range.
+    ///
+    /// This is the validation the batch methods perform in strict mode;
+    /// lenient users can call it directly to log suspicious inputs. The
+    /// range check always succeeds when no range has been set.
+    ///
+    /// # Errors
+    /// Returns [`Error::BatchSizeMismatch`] if a slice's length is not
+    /// `batch_size`, and [`Error::EloOutOfRange`] for the first offending
+    /// rating, checking `elo_selfs` before `elo_oppos`.
+    pub fn check_elos(
+        &self,
+        batch_size: usize,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<(), Error> {
+        for elos in [elo_selfs, elo_oppos] {
+            if elos.len() != batch_size {
+                return Err(Error::BatchSizeMismatch {
+                    expected: batch_size,
+                    actual: elos.len(),
+                });
+            }
+        }
+        let Some(range) = &self.elo_range else {
+            return Ok(());
+        };
+
+        for (side, elos) in [(EloSide::Player, elo_selfs), (EloSide::Opponent, elo_oppos)] {
+            if let Some((index, value)) = first_out_of_range(range, elos) {
+                return Err(Error::EloOutOfRange { index, side, value });
+            }
+        }
+        Ok(())
     }
+
     /// Evaluate a single position specified by FEN.
     ///
     /// ELO values for both sides are provided as raw floating-point
@@ -65,17 +538,65 @@ impl Maia {
         elo_self: f32,
         elo_oppo: f32,
     ) -> Result<EvaluationResult, Error> {
-        let fen: shakmaty::fen::Fen = fen.parse()?;
-        let setup: Setup = fen.into();
+        self.evaluate(fen, elo_self, elo_oppo)
+    }
 
-        let results = self.batch_evaluate([setup], &[elo_self], &[elo_oppo])?;
+    /// Evaluate a single position given as a FEN string, [`Fen`](shakmaty::fen::Fen),
+    /// [`Setup`] or [`Chess`].
+    ///
+    /// # Errors
+    /// - Returns [`Error::InvalidFen`] if a FEN string cannot be parsed.
+    /// - Propagates any errors from batched evaluation.
+    pub fn evaluate(
+        &mut self,
+        input: impl TryIntoSetup,
+        elo_self: f32,
+        elo_oppo: f32,
+    ) -> Result<EvaluationResult, Error> {
+        let results = self.batch_evaluate([input.try_into_setup()?], &[elo_self], &[elo_oppo])?;
         Ok(results.into_iter().next().unwrap())
     }
+
+    /// [`batch_evaluate`](Self::batch_evaluate) of positions given as any
+    /// [`TryIntoSetup`] type.
+    ///
+    /// # Errors
+    /// Returns the conversion error of the first input that fails, such as
+    /// [`Error::InvalidFen`], wrapped in [`Error::AtIndex`]; otherwise as
+    /// [`batch_evaluate`](Self::batch_evaluate).
+    pub fn evaluate_batch(
+        &mut self,
+        inputs: impl IntoIterator<Item = impl TryIntoSetup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        self.batch_evaluate(try_into_setups(inputs)?, elo_selfs, elo_oppos)
+    }
+
+    /// [`batch_evaluate`](Self::batch_evaluate) of positions given as
+    /// their parts, converted lazily as the batch is encoded.
+    ///
+    /// # Errors
+    /// As [`batch_evaluate`](Self::batch_evaluate).
+    pub fn batch_evaluate_raw(
+        &mut self,
+        positions: impl IntoIterator<Item = RawPosition>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        self.batch_evaluate(
+            positions.into_iter().map(RawPosition::into_setup),
+            elo_selfs,
+            elo_oppos,
+        )
+    }
+
     /// Evaluate a batch of positions simultaneously.
     ///
     /// The iterator of [`Setup`]s supplies the board states; the slices of
     /// `elo_selfs` and `elo_oppos` must have identical length equal to the
-    /// number of setups. Batch evaluation is significantly faster than
+    /// number of setups. An empty batch returns no results without running
+    /// inference. Batch evaluation is significantly faster than
     /// calling [`evaluate_fen`] repeatedly when performing multiple inferences.
     ///
     /// # Errors
@@ -86,227 +607,678 @@ impl Maia {
         elo_selfs: &[f32],
         elo_oppos: &[f32],
     ) -> Result<Vec<EvaluationResult>, Error> {
-        let batch_size = elo_selfs.len();
-        assert_eq!(elo_oppos.len(), batch_size);
+        let deadline = self.deadline();
+        let setups: Vec<Setup> = setups.into_iter().collect();
+        let batch_size = setups.len();
+        self.check_elos(batch_size, elo_selfs, elo_oppos)?;
+        if batch_size == 0 {
+            return Ok(Vec::new());
+        }
 
         // 1. Preprocess
-        let (board, data) = preprocess(setups, batch_size)?;
+        let (board, data) = preprocess_with(setups, batch_size, self.options.validation)?;
 
         // 3. Run inference and postprocess
-        let outputs = self.session.run(ort::inputs! {
-                "tokens" => Tensor::from_array(board)?,
-                "elo_self" => Tensor::from_array(([batch_size], elo_selfs.to_vec()))?,
-                "elo_oppo" => Tensor::from_array(([batch_size], elo_oppos.to_vec()))?,
-        })?;
+        let outputs = self.infer(board, elo_selfs, elo_oppos, deadline)?;
 
-        Self::finalize_batch(outputs, batch_size, data)
+        finalize_batch(&outputs, &data, &self.options)
     }
 
-    /// Asynchronous version of [`batch_evaluate`].
+    /// [`batch_evaluate`](Self::batch_evaluate) writing the results into
+    /// `out`, replacing its contents.
     ///
-    /// This function behaves identically to `batch_evaluate`, except that
-    /// it uses [`Session::run_async`] internally and therefore returns a
-    /// future that must be `.await`ed.  It is useful when the caller is
-    /// already running inside an async runtime and wants to avoid blocking.
-    pub async fn batch_evaluate_async(
+    /// The allocations of `out` and of the policies already in it are
+    /// reused, so a hot loop passing the same vector stops allocating
+    /// results once it has seen its largest batch.
+    ///
+    /// # Errors
+    /// As [`batch_evaluate`](Self::batch_evaluate); `out` is left
+    /// unchanged on error.
+    pub fn batch_evaluate_into(
         &mut self,
         setups: impl IntoIterator<Item = Setup>,
         elo_selfs: &[f32],
         elo_oppos: &[f32],
-        options: &ort::session::RunOptions,
-    ) -> Result<Vec<EvaluationResult>, Error> {
-        let batch_size = elo_selfs.len();
-        assert_eq!(elo_oppos.len(), batch_size);
+        out: &mut Vec<EvaluationResult>,
+    ) -> Result<(), Error> {
+        let deadline = self.deadline();
+        let setups: Vec<Setup> = setups.into_iter().collect();
+        let batch_size = setups.len();
+        self.check_elos(batch_size, elo_selfs, elo_oppos)?;
+        if batch_size == 0 {
+            out.clear();
+            return Ok(());
+        }
 
-        // 1. Preprocess
-        let (board, data) = preprocess(setups, batch_size)?;
+        let (board, data) = preprocess_with(setups, batch_size, self.options.validation)?;
+        let outputs = self.infer(board, elo_selfs, elo_oppos, deadline)?;
+        check_outputs(&outputs, &data, self.options.outputs)?;
+        postprocess_into(
+            outputs.logits_move.view(),
+            outputs.logits_value.view(),
+            &data,
+            &self.options,
+            out,
+        );
+        Ok(())
+    }
 
-        // 3. Run inference asynchronously and postprocess
-        let outputs = self
-            .session
-            .run_async(
-                ort::inputs! {
-                    "tokens" => Tensor::from_array(board)?,
-                    "elo_self" => Tensor::from_array(([batch_size], elo_selfs.to_vec()))?,
-                    "elo_oppo" => Tensor::from_array(([batch_size], elo_oppos.to_vec()))?,
-                },
-                &options,
-            )?
-            .await?;
+    /// [`batch_evaluate`](Self::batch_evaluate), also returning an
+    /// [`EvaluationMeta`] per position that identifies it (by hash), says
+    /// whether it was mirrored and records the ratings the network was
+    /// given.
+    ///
+    /// # Errors
+    /// As [`batch_evaluate`](Self::batch_evaluate).
+    pub fn batch_evaluate_with_meta(
+        &mut self,
+        setups: impl IntoIterator<Item = Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<(Vec<EvaluationResult>, Vec<EvaluationMeta>), Error> {
+        let deadline = self.deadline();
+        let setups: Vec<Setup> = setups.into_iter().collect();
+        let batch_size = setups.len();
+        self.check_elos(batch_size, elo_selfs, elo_oppos)?;
+        if batch_size == 0 {
+            return Ok((Vec::new(), Vec::new()));
+        }
+
+        // Hash the positions as they are checked for the network, which
+        // sees them mirrored when Black is to move.
+        let mut keys = Vec::with_capacity(batch_size);
+        let (board, data) = preprocess_inspecting(
+            setups,
+            batch_size,
+            self.options.validation,
+            |pos, mirrored| {
+                keys.push(if mirrored {
+                    crate::export::mirrored_polyglot_key(pos)
+                } else {
+                    crate::export::polyglot_key(pos)
+                })
+            },
+        )?;
+        let outputs = self.infer(board, elo_selfs, elo_oppos, deadline)?;
+        let results = finalize_batch(&outputs, &data, &self.options)?;
 
-        Self::finalize_batch(outputs, batch_size, data)
+        let meta = keys
+            .into_iter()
+            .zip(elo_selfs.iter().zip(elo_oppos))
+            .enumerate()
+            .map(|(i, (key, (&elo_self, &elo_oppo)))| EvaluationMeta {
+                key,
+                mirrored: data.mirrored[i],
+                elo_self,
+                elo_oppo,
+                warnings: data.warnings(i),
+            })
+            .collect();
+        Ok((results, meta))
     }
 
-    /// Batch evaluation that allows callers to supply custom `RunOptions`.
+    /// [`batch_evaluate`](Self::batch_evaluate) for inputs that may be
+    /// partly bad: each item gets its own result, in input order.
     ///
-    /// The provided [`ort::session::RunOptions`] are forwarded directly to
-    /// [`Session::run_with_options`].  This is handy when the user wants to
-    /// adjust logging, threading, or profiling behaviour on a per-inference
-    /// basis.
-    pub fn batch_evaluate_with_options(
+    /// Items that are not usable positions under
+    /// [`EvalOptions::validation`] ([`Error::InvalidPosition`]) or
+    /// have a rating outside the configured elo range
+    /// ([`Error::EloOutOfRange`], with the item's index) get an error in
+    /// its place. The remaining items are evaluated in one inference
+    /// batch; those for which the model returns NaN or infinite logits
+    /// get [`Error::NonFiniteOutput`].
+    ///
+    /// # Errors
+    /// Fails as a whole only if the rating slices do not match the
+    /// setups ([`Error::BatchSizeMismatch`]), inference fails, or its
+    /// outputs do not fit the batch.
+    pub fn batch_evaluate_lenient(
         &mut self,
         setups: impl IntoIterator<Item = Setup>,
         elo_selfs: &[f32],
         elo_oppos: &[f32],
-        options: &ort::session::RunOptions,
-    ) -> Result<Vec<EvaluationResult>, Error> {
-        let batch_size = elo_selfs.len();
-        assert_eq!(elo_oppos.len(), batch_size);
+    ) -> Result<Vec<Result<EvaluationResult, Error>>, Error> {
+        let deadline = self.deadline();
+        let setups: Vec<Setup> = setups.into_iter().collect();
+        for elos in [elo_selfs, elo_oppos] {
+            if elos.len() != setups.len() {
+                return Err(Error::BatchSizeMismatch {
+                    expected: setups.len(),
+                    actual: elos.len(),
+                });
+            }
+        }
 
-        // 1. Preprocess
-        let (board, data) = preprocess(setups, batch_size)?;
+        // The error of every rejected item, and the original index of
+        // every item in the compacted batch.
+        let mut rejected: Vec<Option<Error>> = Vec::with_capacity(setups.len());
+        let mut kept = Vec::with_capacity(setups.len());
+        for (index, setup) in setups.iter().enumerate() {
+            let elos = [
+                (EloSide::Player, elo_selfs[index]),
+                (EloSide::Opponent, elo_oppos[index]),
+            ];
+            let error = self
+                .elo_range
+                .as_ref()
+                .and_then(|range| elos.into_iter().find(|(_, elo)| !range.contains(elo)))
+                .map(|(side, value)| Error::EloOutOfRange { index, side, value })
+                .or_else(|| validate(setup, self.options.validation).err());
+            if error.is_none() {
+                kept.push(index);
+            }
+            rejected.push(error);
+        }
 
-        // 3. Run inference with options and postprocess
-        let outputs = self.session.run_with_options(
-            ort::inputs! {
-                "tokens" => Tensor::from_array(board)?,
-                "elo_self" => Tensor::from_array(([batch_size], elo_selfs.to_vec()))?,
-                "elo_oppo" => Tensor::from_array(([batch_size], elo_oppos.to_vec()))?,
-            },
-            options,
-        )?;
+        let mut evaluated = Vec::with_capacity(kept.len());
+        if !kept.is_empty() {
+            let batch = kept.iter().map(|&i| setups[i].clone());
+            let selfs: Vec<f32> = kept.iter().map(|&i| elo_selfs[i]).collect();
+            let oppos: Vec<f32> = kept.iter().map(|&i| elo_oppos[i]).collect();
+            let (board, data) = preprocess_with(batch, kept.len(), self.options.validation)?;
+            let outputs = self.infer(board, &selfs, &oppos, deadline)?;
+            check_shape(&outputs, &data, self.options.outputs)?;
+            let results = postprocess_with_options(
+                outputs.logits_move.view(),
+                outputs.logits_value.view(),
+                &data,
+                &self.options,
+            );
+            for (i, result) in results.into_iter().enumerate() {
+                evaluated.push(if is_finite(&outputs, &data, self.options.outputs, i) {
+                    Ok(result)
+                } else {
+                    Err(Error::NonFiniteOutput)
+                });
+            }
+        }
 
-        Self::finalize_batch(outputs, batch_size, data)
+        let mut evaluated = evaluated.into_iter();
+        Ok(rejected
+            .into_iter()
+            .map(|error| match error {
+                Some(err) => Err(err),
+                None => evaluated.next().expect("one result per kept item"),
+            })
+            .collect())
     }
 
-    /// Internal helper used by the various batch evaluation entrypoints.
+    /// How the opponent is expected to answer `candidate` in `pos`.
     ///
-    /// Takes ownership of the preprocessed data so that we can consume it
-    /// without having to duplicate the logic in `batch_evaluate`,
-    /// `batch_evaluate_async` and `batch_evaluate_with_options`.
-    fn finalize_batch(
-        outputs: ort::session::SessionOutputs,
-        batch_size: usize,
-        data: crate::tensor::PreprocessedData,
+    /// The position after the move is evaluated from the opponent's side:
+    /// `oppo_elo` is passed as `elo_self` and `my_elo` as `elo_oppo`. The
+    /// policy therefore lists the opponent's replies; like every result,
+    /// moves are in real board coordinates and the value head gives
+    /// White's and Black's win probabilities.
+    ///
+    /// # Errors
+    /// Returns [`Error::IllegalMove`] if `candidate` is not legal in `pos`
+    /// and propagates evaluation errors.
+    pub fn reply_distribution(
+        &mut self,
+        pos: &Chess,
+        candidate: Move,
+        my_elo: f32,
+        oppo_elo: f32,
+    ) -> Result<EvaluationResult, Error> {
+        let results = self.reply_distributions(pos, &[candidate], my_elo, oppo_elo)?;
+        Ok(results.into_iter().next().unwrap())
+    }
+
+    /// [`reply_distribution`](Self::reply_distribution) for several
+    /// candidates, evaluated in a single batch; empty without candidates.
+    ///
+    /// # Errors
+    /// Returns [`Error::IllegalMove`] for the first illegal candidate and
+    /// propagates evaluation errors.
+    pub fn reply_distributions(
+        &mut self,
+        pos: &Chess,
+        candidates: &[Move],
+        my_elo: f32,
+        oppo_elo: f32,
     ) -> Result<Vec<EvaluationResult>, Error> {
-        // 4. Extract Logits
-        let logits_move = outputs["logits_move"]
-            .try_extract_array::<f32>()?
-            .into_dimensionality::<ndarray::Ix2>()
-            .unwrap(); // logits_move should be 2D
-
-        let logits_value = outputs["logits_value"]
-            .try_extract_array::<f32>()?
-            .into_dimensionality::<ndarray::Ix2>()
-            .unwrap(); // logits_value should be [batch, 3]
-
-        // 5. Postprocess into EvaluationResults
-        let mut results = Vec::with_capacity(batch_size);
-
-        for i in 0..batch_size {
-            let logits_for_item = logits_move.index_axis(Axis(0), i);
-            let raw_wdl = logits_value.index_axis(Axis(0), i);
-
-            let result = Self::process_output(
-                logits_for_item,
-                raw_wdl,
-                &data.chess_positions[i],
-                data.mirrored[i],
-            );
-            results.push(result);
-        }
-
-        Ok(results)
-    }
-
-    /// Convert raw model outputs to a structured [`EvaluationResult`].
-    ///
-    /// `logits_move` contains unnormalized policy logits for all moves
-    /// in the fixed Maia3 vocabulary. `raw_wdl` is a 3-logit vector
-    /// ordered as loss/draw/win from side-to-move perspective.
-    fn process_output(
-        logits_move: ArrayView1<f32>,
-        raw_wdl: ArrayView1<f32>,
-        chess: &Chess,
-        mirrored: bool,
-    ) -> EvaluationResult {
-        // Convert L/D/W logits to probabilities.
-        let max_wdl = raw_wdl.iter().copied().fold(f32::NEG_INFINITY, f32::max);
-        let exp_l = (raw_wdl[0] - max_wdl).exp();
-        let exp_d = (raw_wdl[1] - max_wdl).exp();
-        let exp_w = (raw_wdl[2] - max_wdl).exp();
-        let sum_wdl = exp_l + exp_d + exp_w;
-        let mut loss_prob = exp_l / sum_wdl;
-        let draw_prob = exp_d / sum_wdl;
-        let mut win_prob = exp_w / sum_wdl;
-        if mirrored {
-            // For mirrored inputs (originally Black-to-move), the model's
-            // side-to-move W/L correspond to Black/White in the original
-            // position, so swap to get White/Black win rates.
-            std::mem::swap(&mut win_prob, &mut loss_prob);
-        }
-
-        let legal_moves = chess.legal_moves();
-
-        let mut max_logit = f32::NEG_INFINITY;
-        let mut move_data = Vec::with_capacity(legal_moves.len());
-
-        // for (uci, &idx) in &*ALL_MOVES {
-        //     let actual_uci = if mirrored { uci.to_mirrored() } else { *uci };
-        //     let logit = logits_move[idx];
-
-        //     if logit > max_logit {
-        //         max_logit = logit;
-        //     }
-        //     move_data.push((actual_uci, logit));
-        // }
-        for m in &legal_moves {
-            // Convert the `shakmaty` move into the UCI notation the model
-            // expects.
-            let uci = m.to_uci(shakmaty::CastlingMode::Standard);
-
-            // Look up the move's index in the fixed vocabulary.
-            if let Some(&idx) = ALL_MOVES.get(&uci) {
-                let logit = logits_move[idx];
-
-                if logit > max_logit {
-                    max_logit = logit;
+        if candidates.is_empty() {
+            return Ok(Vec::new());
+        }
+        let setups = candidates
+            .iter()
+            .map(|&m| {
+                if !pos.is_legal(m) {
+                    return Err(Error::IllegalMove(
+                        m.to_uci(CastlingMode::Standard).to_string(),
+                    ));
+                }
+                let mut child = pos.clone();
+                child.play_unchecked(m);
+                Ok(child.to_setup(EnPassantMode::Legal))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let n = setups.len();
+        self.batch_evaluate(setups, &vec![oppo_elo; n], &vec![my_elo; n])
+    }
+
+    /// Approximate centipawn loss of every legal move in `pos`, from a
+    /// one-ply look at the children.
+    ///
+    /// `pos` and all of its children are evaluated in a single batch, the
+    /// children from the opponent's side as in
+    /// [`reply_distributions`](Self::reply_distributions). Each move gets
+    /// the value of its child for the side to move, converted with
+    /// [`score_to_centipawns`]; a move that mates scores
+    /// [`MATE_CENTIPAWNS`] and one that stalemates 0. The loss of a move
+    /// is its distance from the best.
+    ///
+    /// Returns `(move, cp loss, policy probability)` in policy order, most
+    /// likely move first; empty if `pos` has no legal move.
+    ///
+    /// # Errors
+    /// Propagates evaluation errors.
+    pub fn cp_loss_table(
+        &mut self,
+        pos: &Chess,
+        elo_self: f32,
+        elo_oppo: f32,
+    ) -> Result<Vec<(Move, f32, f32)>, Error> {
+        let Some((root, children)) = self.evaluate_children(pos, elo_self, elo_oppo)? else {
+            return Ok(Vec::new());
+        };
+        let mover = pos.turn();
+        let values: Vec<f32> = children
+            .iter()
+            .map(|(_, child, result)| match Terminal::of(child) {
+                Some(Terminal::Checkmate) => MATE_CENTIPAWNS as f32,
+                Some(Terminal::Stalemate) => 0.0,
+                None => score_to_centipawns(result.expected_score(mover)) as f32,
+            })
+            .collect();
+        let best = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+        Ok(in_policy_order(pos, &root, &children)
+            .map(|(i, probability)| (children[i].0, best - values[i], probability))
+            .collect())
+    }
+
+    /// Per-move quality of every legal move in `pos`: the policy
+    /// probability of the move next to the expected score of the side to
+    /// move after it.
+    ///
+    /// With [`Backup::OnePly`], `q` is the value of the child, evaluated
+    /// from the opponent's side as in
+    /// [`reply_distributions`](Self::reply_distributions); `pos` and its
+    /// children form one batch. [`Backup::TwoPly`] replaces it with the
+    /// expected value over the opponent's likely replies, evaluated in a
+    /// second batch. Children and replies that end the game take their
+    /// exact outcome.
+    ///
+    /// Returns the moves in policy order, most likely first; empty if
+    /// `pos` has no legal move.
+    ///
+    /// # Errors
+    /// Propagates evaluation errors.
+    pub fn move_qualities(
+        &mut self,
+        pos: &Chess,
+        elo_self: f32,
+        elo_oppo: f32,
+        backup: Backup,
+    ) -> Result<Vec<MoveQuality>, Error> {
+        let Some((root, children)) = self.evaluate_children(pos, elo_self, elo_oppo)? else {
+            return Ok(Vec::new());
+        };
+        let mover = pos.turn();
+        let mut qualities: Vec<(f32, u32)> = children
+            .iter()
+            .map(|(_, child, result)| match Terminal::of(child) {
+                Some(terminal) => (1.0 - terminal.score(), 1),
+                None => (result.expected_score(mover), 1),
+            })
+            .collect();
+
+        if let Backup::TwoPly { replies } = backup {
+            // (child, reply probability, position after the reply).
+            let mut grandchildren = Vec::new();
+            for (i, (_, child, result)) in children.iter().enumerate() {
+                if Terminal::of(child).is_some() {
+                    continue;
                 }
+                for reply in result.policy.iter().take(replies) {
+                    let Ok(m) = reply.uci.to_move(child) else {
+                        continue;
+                    };
+                    let mut after = child.clone();
+                    after.play_unchecked(m);
+                    grandchildren.push((i, reply.probability, after));
+                }
+            }
+            let setups: Vec<_> = grandchildren
+                .iter()
+                .map(|(.., after)| after.to_setup(EnPassantMode::Legal))
+                .collect();
+            // No replies are asked for, or every child ends the game.
+            let n = grandchildren.len();
+            let results = if n == 0 {
+                Vec::new()
+            } else {
+                self.batch_evaluate(setups, &vec![elo_self; n], &vec![elo_oppo; n])?
+            };
 
-                // If input was mirrored (because it was Black's turn), we
-                // must mirror the move back when reporting results.
-                let actual_uci = if mirrored { uci.to_mirrored() } else { uci };
-                move_data.push((actual_uci, logit));
+            let mut sums = vec![(0.0, 0.0, 0); children.len()];
+            for ((i, probability, after), result) in grandchildren.iter().zip(results) {
+                let score =
+                    Terminal::of(after).map_or(result.expected_score(mover), Terminal::score);
+                let (weighted, mass, count) = &mut sums[*i];
+                *weighted += probability * score;
+                *mass += probability;
+                *count += 1;
+            }
+            for (quality, (weighted, mass, count)) in qualities.iter_mut().zip(sums) {
+                if count > 0 && mass > 0.0 {
+                    *quality = (weighted / mass, count);
+                }
             }
         }
 
-        // Apply Softmax
-        let mut sum_exp = 0.0;
-        let mut exps = Vec::with_capacity(move_data.len());
+        Ok(in_policy_order(pos, &root, &children)
+            .map(|(i, policy_prob)| MoveQuality {
+                mv: children[i].0,
+                policy_prob,
+                q: qualities[i].0,
+                visits_equivalent: qualities[i].1,
+            })
+            .collect())
+    }
 
-        for &(_, logit) in &move_data {
-            let exp = (logit - max_logit).exp();
-            sum_exp += exp;
-            exps.push(exp);
+    /// `pos` and all of its children, evaluated in one batch: the root
+    /// with `elo_self` to move, the children from the opponent's side.
+    /// `None` if `pos` has no legal move.
+    fn evaluate_children(
+        &mut self,
+        pos: &Chess,
+        elo_self: f32,
+        elo_oppo: f32,
+    ) -> Result<Option<(EvaluationResult, Vec<Child>)>, Error> {
+        let legal = pos.legal_moves();
+        if legal.is_empty() {
+            return Ok(None);
         }
+        let mut setups = Vec::with_capacity(legal.len() + 1);
+        setups.push(pos.to_setup(EnPassantMode::Legal));
+        let mut children = Vec::with_capacity(legal.len());
+        for &m in &legal {
+            let mut child = pos.clone();
+            child.play_unchecked(m);
+            setups.push(child.to_setup(EnPassantMode::Legal));
+            children.push((m, child));
+        }
+        let mut elos_self = vec![elo_oppo; setups.len()];
+        let mut elos_oppo = vec![elo_self; setups.len()];
+        elos_self[0] = elo_self;
+        elos_oppo[0] = elo_oppo;
+        let mut results = self
+            .batch_evaluate(setups, &elos_self, &elos_oppo)?
+            .into_iter();
+        let root = results.next().expect("one result per position");
+        let children = children
+            .into_iter()
+            .zip(results)
+            .map(|((m, child), result)| (m, child, result))
+            .collect();
+        Ok(Some((root, children)))
+    }
+}
 
-        // Create MoveProbability
-        let mut policy = Vec::with_capacity(move_data.len());
-        for (i, (uci, _)) in move_data.into_iter().enumerate() {
-            policy.push(MoveProbability {
-                uci,
-                probability: exps[i] / sum_exp,
-            });
+/// A move, the position it leads to and that position's evaluation.
+type Child = (Move, Chess, EvaluationResult);
+
+/// Indices into `children` with the policy probability of their move, in
+/// the order of the root policy.
+fn in_policy_order<'a>(
+    pos: &'a Chess,
+    root: &'a EvaluationResult,
+    children: &'a [Child],
+) -> impl Iterator<Item = (usize, f32)> + 'a {
+    root.policy.iter().filter_map(move |p| {
+        let m = p.uci.to_move(pos).ok()?;
+        let i = children.iter().position(|&(c, ..)| c == m)?;
+        Some((i, p.probability))
+    })
+}
+
+/// Register the execution provider of `device` on `builder`, with an
+/// allocator limit of `gpu_memory_limit` bytes for GPU devices.
+#[cfg(feature = "ort")]
+pub(crate) fn on_device(
+    builder: ort::session::builder::SessionBuilder,
+    device: Device,
+    gpu_memory_limit: Option<usize>,
+) -> Result<ort::session::builder::SessionBuilder, Error> {
+    #[cfg(not(any(feature = "cuda", feature = "rocm")))]
+    let _ = gpu_memory_limit;
+    Ok(match device {
+        Device::Cpu => builder,
+        #[cfg(feature = "cuda")]
+        Device::Cuda(id) => {
+            let mut ep = ort::ep::CUDA::default().with_device_id(id);
+            if let Some(limit) = gpu_memory_limit {
+                ep = ep.with_memory_limit(limit);
+            }
+            builder
+                .with_execution_providers([ep.build().error_on_failure()])
+                .map_err(without_builder)?
         }
+        #[cfg(feature = "rocm")]
+        Device::Rocm(id) => {
+            let mut ep = ort::ep::ROCm::default().with_device_id(id);
+            if let Some(limit) = gpu_memory_limit {
+                ep = ep.with_mem_limit(limit);
+            }
+            builder
+                .with_execution_providers([ep.build().error_on_failure()])
+                .map_err(without_builder)?
+        }
+    })
+}
 
-        // Sort by descending probability
-        policy.sort_by(|a, b| b.probability.partial_cmp(&a.probability).unwrap());
+/// Drop the session builder carried by a builder error.
+#[cfg(feature = "ort")]
+pub(crate) fn without_builder(
+    err: ort::Error<ort::session::builder::SessionBuilder>,
+) -> ort::Error {
+    ort::Error::new_with_code(err.code(), err.message())
+}
 
-        EvaluationResult {
-            policy,
-            white_wr: win_prob,
-            draw: draw_prob,
-            black_wr: loss_prob,
+/// Device a model runs on, see [`Maia::from_file_on`].
+#[cfg(feature = "ort")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    /// ONNX Runtime's default CPU provider.
+    Cpu,
+    /// CUDA GPU with the given index.
+    #[cfg(feature = "cuda")]
+    Cuda(i32),
+    /// ROCm GPU with the given index.
+    #[cfg(feature = "rocm")]
+    Rocm(i32),
+}
+
+/// Environment variable consulted first by [`Maia::from_default_model`],
+/// shared with `maia-server`'s `--model` flag.
+#[cfg(feature = "ort")]
+const MODEL_ENV_VAR: &str = "MAIA_MODEL";
+
+/// Model locations in lookup order, given the value of
+/// [`MODEL_ENV_VAR`] and the user cache directory.
+#[cfg(feature = "ort")]
+fn model_candidates(env_path: Option<OsString>, cache_dir: Option<PathBuf>) -> Vec<PathBuf> {
+    let env_path = env_path.filter(|p| !p.is_empty()).map(PathBuf::from);
+    let cached = cache_dir.map(|dir| dir.join("maia").join("maia3_simplified.onnx"));
+    env_path.into_iter().chain(cached).collect()
+}
+
+/// Per-user cache directory of the platform.
+#[cfg(feature = "ort")]
+pub(crate) fn user_cache_dir() -> Option<PathBuf> {
+    let non_empty = |var| {
+        env::var_os(var)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    if cfg!(windows) {
+        non_empty("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        non_empty("HOME").map(|home| home.join("Library").join("Caches"))
+    } else {
+        non_empty("XDG_CACHE_HOME").or_else(|| non_empty("HOME").map(|home| home.join(".cache")))
+    }
+}
+
+/// Fail with [`Error::Timeout`] if `deadline` has passed, after
+/// `completed` of the `total` positions of the call.
+fn check_deadline(deadline: Option<Instant>, completed: usize, total: usize) -> Result<(), Error> {
+    match deadline {
+        Some(at) if Instant::now() >= at => Err(Error::Timeout { completed, total }),
+        _ => Ok(()),
+    }
+}
+
+/// Shared tail of the batch evaluation entrypoints: map raw outputs back
+/// onto the preprocessed positions.
+fn finalize_batch(
+    outputs: &ModelOutputs,
+    data: &PreprocessedData,
+    options: &EvalOptions,
+) -> Result<Vec<EvaluationResult>, Error> {
+    check_outputs(outputs, data, options.outputs)?;
+    Ok(postprocess_with_options(
+        outputs.logits_move.view(),
+        outputs.logits_value.view(),
+        data,
+        options,
+    ))
+}
+
+/// Reject outputs that do not cover the batch or do not have the shape
+/// of Maia3's heads, looking only at the heads `selection` reads.
+fn check_shape(
+    outputs: &ModelOutputs,
+    data: &PreprocessedData,
+    selection: OutputSelection,
+) -> Result<(), Error> {
+    let heads = [
+        (
+            selection.policy(),
+            "logits_move",
+            &outputs.logits_move,
+            ALL_MOVES.len(),
+        ),
+        (selection.value(), "logits_value", &outputs.logits_value, 3),
+    ];
+    let heads = heads.iter().filter(|(read, ..)| *read);
+    let rows = heads
+        .clone()
+        .map(|(_, _, head, _)| head.nrows())
+        .min()
+        .unwrap_or(usize::MAX);
+    if rows < data.len() {
+        return Err(Error::BatchSizeMismatch {
+            expected: data.len(),
+            actual: rows,
+        });
+    }
+    for &(_, name, head, expected) in heads {
+        let width = head.ncols();
+        if width != expected {
+            return Err(Error::IncompatibleModel {
+                reason: format!("{name} has {width} columns, expected {expected}"),
+            });
         }
     }
+    Ok(())
+}
+
+/// [`check_shape`], then reject outputs whose value logits, or logits of
+/// legal moves, are NaN or infinite, as they would turn into NaN
+/// probabilities. Illegal moves may be masked with `-inf`.
+fn check_outputs(
+    outputs: &ModelOutputs,
+    data: &PreprocessedData,
+    selection: OutputSelection,
+) -> Result<(), Error> {
+    check_shape(outputs, data, selection)?;
+    for index in 0..data.len() {
+        if !is_finite(outputs, data, selection, index) {
+            return Err(Error::AtIndex {
+                index,
+                fen: None,
+                source: Box::new(Error::NonFiniteOutput),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether the outputs of item `index` are finite, see [`check_outputs`].
+fn is_finite(
+    outputs: &ModelOutputs,
+    data: &PreprocessedData,
+    selection: OutputSelection,
+    index: usize,
+) -> bool {
+    let value = || {
+        outputs
+            .logits_value
+            .row(index)
+            .iter()
+            .all(|v| v.is_finite())
+    };
+    let policy = || {
+        let moves = outputs.logits_move.row(index);
+        let legal = data.legal_move_indices(index);
+        legal.iter().all(|&m| moves[usize::from(m)].is_finite())
+    };
+    (!selection.value() || value()) && (!selection.policy() || policy())
+}
+
+/// Index and value of the first elo not contained in `range`. `NaN`
+/// is never contained, so it is always reported.
+fn first_out_of_range(range: &RangeInclusive<f32>, elos: &[f32]) -> Option<(usize, f32)> {
+    elos.iter()
+        .copied()
+        .enumerate()
+        .find(|(_, elo)| !range.contains(elo))
 }
 
 #[cfg(test)]
 mod tests {
+    use ndarray::{Array2, Array3};
+    #[cfg(feature = "ort")]
     use ort::logging::LogLevel;
     use shakmaty::fen::Fen;
 
     use super::*;
+    use crate::{
+        tensor::{Validation, ValidationWarning},
+        testing::ZeroBackend,
+    };
+
+    /// Backend producing uniform policy logits and a fixed value.
+    struct UniformBackend;
+
+    impl InferenceBackend for UniformBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            _elo_self: &[f32],
+            _elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            let batch_size = tokens.shape()[0];
+            Ok(ModelOutputs {
+                logits_move: Array2::zeros((batch_size, crate::moves::ALL_MOVES.len())),
+                logits_value: Array2::from_shape_fn((batch_size, 3), |(_, j)| j as f32),
+            })
+        }
+    }
 
     fn sample_setup() -> Setup {
         let fen: Fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2"
@@ -315,7 +1287,766 @@ mod tests {
         fen.into()
     }
 
+    /// Uniform outputs with a NaN value for batch item `nan_at`.
+    struct NanBackend {
+        nan_at: usize,
+    }
+
+    impl InferenceBackend for NanBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            elo_self: &[f32],
+            elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            let mut outputs = UniformBackend.run(tokens, elo_self, elo_oppo)?;
+            outputs.logits_value[[self.nan_at, 2]] = f32::NAN;
+            Ok(outputs)
+        }
+    }
+
+    #[test]
+    fn failing_items_report_their_index() {
+        // Black to move while White's king is in check.
+        let fen = "4k3/8/8/8/8/8/8/r3K3 b - - 0 1";
+        let mut setups = vec![sample_setup(); 10];
+        setups[7] = fen.parse::<Fen>().unwrap().into_setup();
+        let elos = [1500.0; 10];
+
+        let mut maia = Maia::from_backend(UniformBackend);
+        let err = maia.batch_evaluate(setups, &elos, &elos).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with(&format!("Batch index 7 ({fen}): "))
+        );
+        let Error::AtIndex {
+            index, fen: got, ..
+        } = &err
+        else {
+            panic!("expected AtIndex, got {err:?}");
+        };
+        assert_eq!((*index, got.as_deref()), (7, Some(fen)));
+        assert!(matches!(err.without_index(), Error::InvalidPosition(_)));
+
+        let mut maia = Maia::from_backend(NanBackend { nan_at: 7 });
+        let (mut out, setups) = (Vec::new(), vec![sample_setup(); 10]);
+        let err = maia
+            .batch_evaluate_into(setups, &elos, &elos, &mut out)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::AtIndex { index: 7, fen: None, ref source }
+                if matches!(**source, Error::NonFiniteOutput)
+        ));
+        assert!(out.is_empty());
+    }
+
+    /// Outputs one row short, or with a policy head of the wrong width.
+    struct MisshapenBackend {
+        width: usize,
+    }
+
+    impl InferenceBackend for MisshapenBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            _elo_self: &[f32],
+            _elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            let batch_size = tokens.shape()[0];
+            let rows = if self.width == ALL_MOVES.len() {
+                batch_size - 1
+            } else {
+                batch_size
+            };
+            Ok(ModelOutputs {
+                logits_move: Array2::zeros((rows, self.width)),
+                logits_value: Array2::zeros((rows, 3)),
+            })
+        }
+    }
+
+    #[test]
+    fn setups_must_match_the_ratings() {
+        let mut maia = Maia::from_backend(ZeroBackend::default());
+        for (setups, expected, actual) in [(3, 3, 2), (1, 1, 2)] {
+            let err = maia
+                .batch_evaluate(vec![sample_setup(); setups], &[1500.0; 2], &[1500.0; 2])
+                .unwrap_err();
+            assert!(
+                matches!(err, Error::BatchSizeMismatch { expected: e, actual: a } if (e, a) == (expected, actual)),
+                "{err:?}"
+            );
+        }
+        assert!(matches!(
+            crate::preprocess(vec![sample_setup(); 3], 2),
+            Err(Error::BatchSizeMismatch {
+                expected: 2,
+                actual: 3
+            })
+        ));
+        assert!(maia.backend().batch_sizes().is_empty());
+    }
+
+    #[test]
+    fn empty_batches_skip_inference() {
+        let backend = ZeroBackend::default();
+        let mut maia = Maia::from_backend(backend.clone());
+        assert!(maia.batch_evaluate(vec![], &[], &[]).unwrap().is_empty());
+        let mut out = vec![maia.evaluate(sample_setup(), 1500.0, 1500.0).unwrap()];
+        maia.batch_evaluate_into(vec![], &[], &[], &mut out)
+            .unwrap();
+        assert!(out.is_empty());
+        assert!(
+            maia.batch_evaluate_lenient(vec![], &[], &[])
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(backend.batch_sizes(), [1]);
+    }
+
+    #[test]
+    fn mismatched_batches_are_errors() {
+        let mut maia = Maia::from_backend(UniformBackend);
+        let err = maia
+            .batch_evaluate(vec![sample_setup(); 2], &[1500.0; 2], &[1500.0])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::BatchSizeMismatch {
+                expected: 2,
+                actual: 1
+            }
+        ));
+
+        let mut short = Maia::from_backend(MisshapenBackend {
+            width: ALL_MOVES.len(),
+        });
+        let err = short
+            .batch_evaluate(vec![sample_setup(); 2], &[1500.0; 2], &[1500.0; 2])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::BatchSizeMismatch {
+                expected: 2,
+                actual: 1
+            }
+        ));
+
+        let mut narrow = Maia::from_backend(MisshapenBackend { width: 1858 });
+        let err = narrow
+            .batch_evaluate(vec![sample_setup()], &[1500.0], &[1500.0])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Incompatible model: logits_move has 1858 columns, expected 4352"
+        );
+    }
+
+    #[test]
+    fn invalid_fen_reports_the_input() {
+        let mut maia = Maia::from_backend(UniformBackend);
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1";
+        let err = maia.evaluate_fen(fen, 1500.0, 1500.0).unwrap_err();
+        assert!(matches!(&err, Error::InvalidFen { fen: got, .. } if got == fen));
+        assert!(err.to_string().contains(fen));
+
+        // Long inputs are cut, also inside multi-byte characters.
+        let long = "é".repeat(100);
+        let Err(Error::InvalidFen { fen, .. }) = maia.evaluate_fen(&long, 1500.0, 1500.0) else {
+            panic!("expected InvalidFen");
+        };
+        assert_eq!(fen, "é".repeat(crate::MAX_FEN_LEN / 2));
+    }
+
+    /// [`UniformBackend`] computing only the selected heads.
+    #[derive(Default)]
+    struct SelectingBackend {
+        selections: Vec<OutputSelection>,
+    }
+
+    impl InferenceBackend for SelectingBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            elo_self: &[f32],
+            elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            let mut outputs = UniformBackend.run(tokens, elo_self, elo_oppo)?;
+            match self.selections.last() {
+                Some(OutputSelection::PolicyOnly) => outputs.logits_value = Array2::zeros((0, 0)),
+                Some(OutputSelection::ValueOnly) => outputs.logits_move = Array2::zeros((0, 0)),
+                _ => {}
+            }
+            Ok(outputs)
+        }
+
+        fn select_outputs(&mut self, outputs: OutputSelection) {
+            self.selections.push(outputs);
+        }
+    }
+
+    #[test]
+    fn unselected_heads_are_skipped() {
+        let mut maia = Maia::from_backend(SelectingBackend::default());
+        let both = maia.evaluate(sample_setup(), 1500.0, 1500.0).unwrap();
+
+        maia.eval_options_mut().outputs = OutputSelection::PolicyOnly;
+        let policy = maia.evaluate(sample_setup(), 1500.0, 1500.0).unwrap();
+        assert_eq!(policy.policy.len(), both.policy.len());
+        assert!(policy.white_wr.is_nan() && policy.draw.is_nan() && policy.black_wr.is_nan());
+
+        maia.eval_options_mut().outputs = OutputSelection::ValueOnly;
+        let mut out = Vec::new();
+        maia.batch_evaluate_into([sample_setup()], &[1500.0], &[1500.0], &mut out)
+            .unwrap();
+        assert!(out[0].policy.is_empty());
+        assert_eq!(out[0].white_wr, both.white_wr);
+        let lenient = maia
+            .batch_evaluate_lenient([sample_setup()], &[1500.0], &[1500.0])
+            .unwrap();
+        assert!(lenient[0].as_ref().unwrap().policy.is_empty());
+
+        assert_eq!(
+            maia.backend().selections,
+            [
+                OutputSelection::Both,
+                OutputSelection::PolicyOnly,
+                OutputSelection::ValueOnly,
+                OutputSelection::ValueOnly
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_estimates_follow_batch_and_heads() {
+        let mut maia = Maia::from_backend(UniformBackend);
+        let per_position = maia.memory_estimate(1);
+        assert_eq!(per_position, (64 * 12 + 2 + ALL_MOVES.len() + 3) * 4);
+        assert_eq!(maia.memory_estimate(256), 256 * per_position);
+        assert_eq!(maia.memory_estimate(0), 0);
+
+        maia.eval_options_mut().outputs = OutputSelection::ValueOnly;
+        assert_eq!(maia.memory_estimate(1), (64 * 12 + 2 + 3) * 4);
+    }
+
+    #[test]
+    fn generic_inputs_evaluate_alike() {
+        let mut maia = Maia::from_backend(UniformBackend);
+        let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2";
+        let pos: Chess = sample_setup().position(CastlingMode::Standard).unwrap();
+        let moves = |r: &EvaluationResult| -> Vec<shakmaty::uci::UciMove> {
+            r.policy.iter().map(|m| m.uci).collect()
+        };
+        let from_fen = moves(&maia.evaluate(fen, 1500.0, 1500.0).unwrap());
+        assert_eq!(
+            moves(&maia.evaluate(&pos, 1500.0, 1500.0).unwrap()),
+            from_fen
+        );
+        assert_eq!(
+            moves(&maia.evaluate(sample_setup(), 1500.0, 1500.0).unwrap()),
+            from_fen
+        );
+
+        let results = maia
+            .evaluate_batch([fen, fen], &[1500.0; 2], &[1500.0; 2])
+            .unwrap();
+        assert!(results.iter().all(|r| moves(r) == from_fen));
+        let err = maia
+            .evaluate_batch([fen, "8/8/8 w"], &[1500.0; 2], &[1500.0; 2])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::AtIndex { index: 1, ref source, .. } if matches!(**source, Error::InvalidFen { .. })
+        ));
+    }
+
+    #[test]
+    fn lenient_results_land_at_their_index() {
+        let invalid: Setup = "8/8/8/8/8/8/8/8 w - - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_setup();
+        // Bad items first, in the middle and last; the others alternate
+        // between positions with 29 and 20 legal moves.
+        let setups: Vec<Setup> = (0..9)
+            .map(|i| match i {
+                0 | 4 | 8 => invalid.clone(),
+                _ if i % 2 == 1 => sample_setup(),
+                _ => Setup::default(),
+            })
+            .collect();
+        let mut elo_selfs = [1500.0; 9];
+        elo_selfs[6] = 5000.0;
+
+        // Items 1, 2, 3, 5 and 7 are evaluated, so the fifth is index 7.
+        let mut maia = Maia::from_backend(NanBackend { nan_at: 4 }).with_elo_range(500.0..=3000.0);
+        let results = maia
+            .batch_evaluate_lenient(setups, &elo_selfs, &[1500.0; 9])
+            .unwrap();
+        assert_eq!(results.len(), 9);
+        for (i, result) in results.iter().enumerate() {
+            match i {
+                0 | 4 | 8 => assert!(matches!(result, Err(Error::InvalidPosition(_))), "{i}"),
+                6 => assert!(matches!(
+                    result,
+                    Err(Error::EloOutOfRange {
+                        index: 6,
+                        side: EloSide::Player,
+                        ..
+                    })
+                )),
+                7 => assert!(matches!(result, Err(Error::NonFiniteOutput))),
+                _ => {
+                    let moves = if i % 2 == 1 { 29 } else { 20 };
+                    assert_eq!(result.as_ref().unwrap().policy.len(), moves, "{i}");
+                }
+            }
+        }
+
+        // Nothing to evaluate is not an error.
+        let results = maia
+            .batch_evaluate_lenient(vec![invalid], &[1500.0], &[1500.0])
+            .unwrap();
+        assert!(results[0].is_err());
+    }
+
+    #[test]
+    fn batch_evaluate_into_reuses_allocations() {
+        let mut maia = Maia::from_backend(UniformBackend);
+        let (mut out, elos) = (Vec::new(), [1500.0; 4]);
+        maia.batch_evaluate_into(vec![sample_setup(); 4], &elos, &elos, &mut out)
+            .unwrap();
+        let buffer = out.as_ptr();
+        let policies: Vec<_> = out.iter().map(|r| r.policy.as_ptr()).collect();
+
+        // A smaller batch keeps the outer buffer and reuses the policies
+        // of the surviving results.
+        let elos = &elos[..3];
+        maia.batch_evaluate_into(vec![Setup::default(); 3], elos, elos, &mut out)
+            .unwrap();
+        assert_eq!(out.len(), 3);
+        assert!(out.capacity() >= 4);
+        assert_eq!(out.as_ptr(), buffer);
+        for (result, &policy) in out.iter().zip(&policies) {
+            assert_eq!(result.policy.as_ptr(), policy);
+        }
+
+        let expected = maia
+            .batch_evaluate(vec![Setup::default(); 3], elos, elos)
+            .unwrap();
+        assert_eq!(format!("{out:?}"), format!("{expected:?}"));
+    }
+
+    #[test]
+    fn meta_identifies_each_position() {
+        let mut maia = Maia::from_backend(UniformBackend);
+        let black: Fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"
+            .parse()
+            .unwrap();
+        let black_key = crate::export::polyglot_key(
+            &black.clone().into_position(CastlingMode::Standard).unwrap(),
+        );
+        let setups = vec![Setup::default(), black.into_setup()];
+        let (results, meta) = maia
+            .batch_evaluate_with_meta(setups, &[1500.0, 1700.0], &[1600.0, 1800.0])
+            .unwrap();
+
+        assert_eq!(results.len(), 2);
+        // Polyglot's published key of the start position.
+        assert_eq!(meta[0].key, 0x463b96181691fc9c);
+        assert_eq!(meta[1].key, black_key);
+        assert!(!meta[0].mirrored && meta[1].mirrored);
+        assert_eq!((meta[1].elo_self, meta[1].elo_oppo), (1700.0, 1800.0));
+    }
+
+    #[test]
+    fn relaxed_validation_evaluates_puzzle_positions() {
+        // A pawn on the first rank and a castling right without a rook.
+        let puzzle = "4k3/8/8/8/8/8/8/P3K3 w K - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_setup();
+        let mut maia = Maia::from_backend(UniformBackend);
+        let err = maia
+            .batch_evaluate(vec![puzzle.clone()], &[1500.0], &[1500.0])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::AtIndex { index: 0, ref source, .. }
+                if matches!(**source, Error::InvalidPosition(_))
+        ));
+
+        maia.eval_options_mut().validation = Validation::Relaxed;
+        let (results, meta) = maia
+            .batch_evaluate_with_meta(vec![puzzle], &[1500.0], &[1500.0])
+            .unwrap();
+        let mut moves: Vec<String> = results[0]
+            .policy
+            .iter()
+            .map(|m| m.uci.to_string())
+            .collect();
+        moves.sort();
+        assert_eq!(moves, ["e1d1", "e1d2", "e1e2", "e1f1", "e1f2"]);
+        assert_eq!(
+            meta[0].warnings,
+            [
+                ValidationWarning::BackrankPawns,
+                ValidationWarning::InvalidCastlingRights
+            ]
+        );
+    }
+
+    #[test]
+    fn out_of_range_elos_are_reported() {
+        let range = 1100.0..=2000.0;
+        assert_eq!(first_out_of_range(&range, &[1100.0, 1500.0, 2000.0]), None);
+        assert_eq!(
+            first_out_of_range(&range, &[1500.0, 0.0, 2350.0]),
+            Some((1, 0.0))
+        );
+
+        let (index, value) = first_out_of_range(&range, &[1500.0, f32::NAN]).unwrap();
+        assert_eq!(index, 1);
+        assert!(value.is_nan());
+    }
+
+    #[test]
+    #[cfg(feature = "ort")]
+    fn default_model_lookup_order() {
+        let cache = PathBuf::from("/home/me/.cache");
+        let cached = cache.join("maia").join("maia3_simplified.onnx");
+        assert_eq!(
+            model_candidates(Some("model.onnx".into()), Some(cache.clone())),
+            vec![PathBuf::from("model.onnx"), cached.clone()]
+        );
+        assert_eq!(
+            model_candidates(Some("".into()), Some(cache)),
+            vec![cached.clone()]
+        );
+        assert!(model_candidates(None, None).is_empty());
+
+        let message = Error::ModelNotFound(vec![cached]).to_string();
+        assert!(message.contains("maia3_simplified.onnx"), "{message}");
+    }
+
+    /// [`UniformBackend`] taking `delay` per run.
+    struct SlowBackend {
+        delay: std::time::Duration,
+        runs: usize,
+    }
+
+    impl InferenceBackend for SlowBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            elo_self: &[f32],
+            elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            self.runs += 1;
+            std::thread::sleep(self.delay);
+            UniformBackend.run(tokens, elo_self, elo_oppo)
+        }
+    }
+
+    #[test]
+    fn overrunning_the_deadline_times_out() {
+        use std::time::Duration;
+
+        let mut maia = Maia::from_backend(SlowBackend {
+            delay: Duration::from_millis(20),
+            runs: 0,
+        });
+        maia.eval_options_mut().deadline = Some(Duration::from_millis(1));
+        let err = maia
+            .batch_evaluate(vec![sample_setup(); 2], &[1500.0; 2], &[1500.0; 2])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Timeout {
+                completed: 0,
+                total: 2
+            }
+        ));
+        assert_eq!(maia.backend().runs, 1);
+
+        // A deadline already gone by the time inference would start
+        // skips the run.
+        maia.eval_options_mut().deadline = Some(Duration::ZERO);
+        let mut out = Vec::new();
+        let err = maia
+            .batch_evaluate_into(vec![sample_setup()], &[1500.0], &[1500.0], &mut out)
+            .unwrap_err();
+        assert!(matches!(err, Error::Timeout { .. }));
+        assert_eq!(maia.backend().runs, 1);
+
+        maia.eval_options_mut().deadline = Some(Duration::from_secs(60));
+        assert!(
+            maia.batch_evaluate(vec![sample_setup()], &[1500.0], &[1500.0])
+                .is_ok()
+        );
+    }
+
+    /// Fails like an exhausted GPU on runs of more than `limit`
+    /// positions, recording the size of every run.
+    struct LimitedMemoryBackend {
+        limit: usize,
+        runs: Vec<usize>,
+    }
+
+    impl InferenceBackend for LimitedMemoryBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            elo_self: &[f32],
+            elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            self.runs.push(elo_self.len());
+            if elo_self.len() > self.limit {
+                return Err(Error::OutOfMemory {
+                    batch_size: elo_self.len(),
+                });
+            }
+            UniformBackend.run(tokens, elo_self, elo_oppo)
+        }
+    }
+
+    #[test]
+    fn out_of_memory_runs_are_split() {
+        let setups: Vec<Setup> = (0..10)
+            .map(|i| {
+                if i % 3 == 0 {
+                    sample_setup()
+                } else {
+                    Setup::default()
+                }
+            })
+            .collect();
+        let elos = [1500.0; 10];
+        let expected = Maia::from_backend(UniformBackend)
+            .batch_evaluate(setups.clone(), &elos, &elos)
+            .unwrap();
+
+        let mut maia = Maia::from_backend(LimitedMemoryBackend {
+            limit: 3,
+            runs: Vec::new(),
+        });
+        let results = maia.batch_evaluate(setups.clone(), &elos, &elos).unwrap();
+        // Halved until a run fits, then the rest at that size.
+        assert_eq!(maia.backend().runs, [10, 5, 3, 3, 3, 1]);
+        let ucis = |results: &[EvaluationResult]| -> Vec<Vec<String>> {
+            results
+                .iter()
+                .map(|r| r.policy.iter().map(|m| m.uci.to_string()).collect())
+                .collect()
+        };
+        assert_eq!(ucis(&results), ucis(&expected));
+
+        // Nothing fits: the error surfaces once single positions fail.
+        maia.backend_mut().limit = 0;
+        maia.backend_mut().runs.clear();
+        let err = maia
+            .batch_evaluate(setups.clone(), &elos, &elos)
+            .unwrap_err();
+        assert!(matches!(err, Error::OutOfMemory { batch_size: 1 }));
+        assert_eq!(maia.backend().runs, [10, 5, 3, 2, 1]);
+
+        maia.eval_options_mut().oom_min_batch = None;
+        maia.backend_mut().runs.clear();
+        assert!(maia.batch_evaluate(setups, &elos, &elos).is_err());
+        assert_eq!(maia.backend().runs, [10]);
+    }
+
+    #[test]
+    fn custom_backend_evaluates() {
+        let mut maia = Maia::from_backend(UniformBackend).with_elo_range(1000.0..=2500.0);
+        let results = maia
+            .batch_evaluate(vec![sample_setup(); 2], &[1500.0; 2], &[1500.0; 2])
+            .unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].policy.len(), 29);
+        assert!(results[0].white_wr > results[0].black_wr);
+
+        let err = maia
+            .batch_evaluate([sample_setup()], &[1500.0], &[3000.0])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::EloOutOfRange {
+                index: 0,
+                side: EloSide::Opponent,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Opponent elo 3000 at batch index 0 is outside the allowed range"
+        );
+    }
+
+    /// Backend recording the elos it was called with.
+    #[derive(Default)]
+    struct EloRecorder {
+        elo_self: Vec<f32>,
+        elo_oppo: Vec<f32>,
+    }
+
+    impl InferenceBackend for EloRecorder {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            elo_self: &[f32],
+            elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            self.elo_self.extend_from_slice(elo_self);
+            self.elo_oppo.extend_from_slice(elo_oppo);
+            UniformBackend.run(tokens, elo_self, elo_oppo)
+        }
+    }
+
+    #[test]
+    fn replies_are_evaluated_from_the_opponents_side() {
+        let mut maia = Maia::from_backend(EloRecorder::default());
+        let pos = Chess::default();
+        let candidates: Vec<Move> = ["e2e4", "g1f3"]
+            .iter()
+            .map(|uci| {
+                uci.parse::<shakmaty::uci::UciMove>()
+                    .unwrap()
+                    .to_move(&pos)
+                    .unwrap()
+            })
+            .collect();
+
+        let replies = maia
+            .reply_distributions(&pos, &candidates, 1800.0, 1400.0)
+            .unwrap();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(maia.backend().elo_self, vec![1400.0; 2]);
+        assert_eq!(maia.backend().elo_oppo, vec![1800.0; 2]);
+
+        // Black's replies, in real coordinates.
+        let mut child = pos.clone();
+        child.play_unchecked(candidates[0]);
+        assert_eq!(replies[0].policy.len(), 20);
+        assert!(
+            replies[0]
+                .policy
+                .iter()
+                .all(|m| m.uci.to_move(&child).is_ok())
+        );
+
+        let mut after = pos.clone();
+        after.play_unchecked(candidates[0]);
+        let err = maia
+            .reply_distribution(&after, candidates[1], 1800.0, 1400.0)
+            .unwrap_err();
+        assert!(matches!(err, Error::IllegalMove(m) if m == "g1f3"));
+
+        let mut maia = Maia::from_backend(ZeroBackend::default());
+        let none = maia.reply_distributions(&pos, &[], 1800.0, 1400.0).unwrap();
+        assert!(none.is_empty());
+        assert!(maia.backend().batch_sizes().is_empty());
+    }
+
     #[test]
+    fn cp_loss_is_measured_against_the_mate() {
+        let mut maia = Maia::from_backend(EloRecorder::default());
+        let pos: Chess = "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4"
+            .parse::<Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let n = pos.legal_moves().len();
+
+        let table = maia.cp_loss_table(&pos, 1800.0, 1400.0).unwrap();
+        assert_eq!(table.len(), n);
+        // The root and every child in one batch, children from Black's side.
+        let mut elo_self = vec![1400.0; n + 1];
+        elo_self[0] = 1800.0;
+        assert_eq!(maia.backend().elo_self, elo_self);
+
+        let mate = table
+            .iter()
+            .find(|(m, ..)| m.to_uci(CastlingMode::Standard).to_string() == "h5f7")
+            .unwrap();
+        assert_eq!(mate.1, 0.0);
+        // The uniform value makes every other move equally far from mate.
+        let loss = table.iter().find(|(m, ..)| *m != mate.0).unwrap().1;
+        assert!(loss > (MATE_CENTIPAWNS - 1600) as f32);
+        assert!(table.iter().all(|&(m, l, _)| m == mate.0 || l == loss));
+        assert!((table.iter().map(|&(.., p)| p).sum::<f32>() - 1.0).abs() < 1e-4);
+
+        let mated: Chess = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3"
+            .parse::<Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        assert!(
+            maia.cp_loss_table(&mated, 1800.0, 1400.0)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn two_ply_backup_sees_the_mating_reply() {
+        let mut maia = Maia::from_backend(UniformBackend);
+        // After 1. f3 e5, 2. g4 allows Qh4#.
+        let pos: Chess = "rnbqkbnr/pppp1ppp/8/4p3/8/5P2/PPPPP1PP/RNBQKBNR w KQkq - 0 2"
+            .parse::<Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let find = |qualities: &[MoveQuality], uci: &str| {
+            qualities
+                .iter()
+                .find(|q| q.mv.to_uci(CastlingMode::Standard).to_string() == uci)
+                .cloned()
+                .unwrap()
+        };
+
+        let one_ply = maia
+            .move_qualities(&pos, 1500.0, 1500.0, Backup::OnePly)
+            .unwrap();
+        assert_eq!(one_ply.len(), pos.legal_moves().len());
+        let (g4, d4) = (find(&one_ply, "g2g4"), find(&one_ply, "d2d4"));
+        assert_eq!(g4.q, d4.q);
+        assert_eq!(g4.visits_equivalent, 1);
+
+        let two_ply = maia
+            .move_qualities(&pos, 1500.0, 1500.0, Backup::TwoPly { replies: 100 })
+            .unwrap();
+        let (g4, d4) = (find(&two_ply, "g2g4"), find(&two_ply, "d2d4"));
+        let mut after = pos.clone();
+        after.play_unchecked(g4.mv);
+        let replies = after.legal_moves().len();
+        assert_eq!(g4.visits_equivalent as usize, replies);
+        // Uniform replies: one of them mates, the rest keep d4's value.
+        let expected = d4.q * (replies - 1) as f32 / replies as f32;
+        assert!((g4.q - expected).abs() < 1e-5, "{} vs {expected}", g4.q);
+        assert_eq!(g4.policy_prob, d4.policy_prob);
+    }
+
+    #[test]
+    fn two_ply_backup_without_replies_keeps_one_ply_values() {
+        let backend = ZeroBackend::default();
+        let mut maia = Maia::from_backend(backend.clone());
+        let pos = Chess::default();
+        let one_ply = maia
+            .move_qualities(&pos, 1500.0, 1500.0, Backup::OnePly)
+            .unwrap();
+        let two_ply = maia
+            .move_qualities(&pos, 1500.0, 1500.0, Backup::TwoPly { replies: 0 })
+            .unwrap();
+        assert_eq!(two_ply, one_ply);
+        // The root and its 20 children, twice, and no second batch.
+        assert_eq!(backend.batch_sizes(), [21, 21]);
+    }
+
+    #[test]
+    #[cfg(feature = "ort")]
     #[ignore = "requires local Maia3 ONNX model file"]
     fn sync_and_options_evaluate() {
         let mut maia = Maia::from_file("maia3_simplified.onnx").expect("load model");
@@ -334,7 +2065,89 @@ mod tests {
         assert_eq!(r2.len(), 1);
     }
 
+    #[test]
+    #[cfg(feature = "mmap")]
+    #[ignore = "requires local Maia3 ONNX model file"]
+    fn mapped_models_match_read_models() {
+        let mut read = Maia::from_file("maia3_simplified.onnx").expect("load model");
+        let mut mapped = Maia::from_file_mmap("maia3_simplified.onnx").expect("map model");
+
+        let setups = vec![sample_setup()];
+        let a = read.batch_evaluate(setups.clone(), &[1500.0], &[1500.0]);
+        let b = mapped.batch_evaluate(setups, &[1500.0], &[1500.0]);
+        assert_eq!(a.expect("read"), b.expect("mapped"));
+    }
+
+    #[test]
+    #[cfg(feature = "ort")]
+    #[ignore = "requires local Maia3 ONNX model file"]
+    fn io_binding_matches_regular_runs() {
+        let mut plain = Maia::from_file("maia3_simplified.onnx").expect("load model");
+        let mut bound = Maia::from_file("maia3_simplified.onnx")
+            .expect("load model")
+            .with_io_binding(8);
+        // Three positions pad to four; ten exceed the bound sizes.
+        for n in [3, 3, 10] {
+            let setups = vec![sample_setup(); n];
+            let elos = vec![1500.0; n];
+            let a = plain
+                .batch_evaluate(setups.clone(), &elos, &elos)
+                .expect("plain");
+            let b = bound.batch_evaluate(setups, &elos, &elos).expect("bound");
+            assert_eq!(a.len(), b.len());
+            assert!((a[0].white_wr - b[0].white_wr).abs() < 1e-5);
+            assert_eq!(a[0].policy[0].uci, b[0].policy[0].uci);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ort")]
+    #[ignore = "requires local Maia3 ONNX model file"]
+    fn selected_heads_match_full_runs() {
+        let mut maia = Maia::from_file("maia3_simplified.onnx").expect("load model");
+        let setups = vec![sample_setup(); 256];
+        let elos = vec![1500.0; setups.len()];
+        let mut evaluate = |outputs| {
+            maia.eval_options_mut().outputs = outputs;
+            maia.batch_evaluate(setups.clone(), &elos, &elos)
+                .expect("evaluate")
+        };
+        let both = evaluate(OutputSelection::Both);
+        let policy = evaluate(OutputSelection::PolicyOnly);
+        let value = evaluate(OutputSelection::ValueOnly);
+        assert!(!both[0].policy.is_empty());
+        assert!(both[0].white_wr.is_finite());
+        for (p, b) in policy.iter().zip(&both) {
+            assert_eq!(p.policy.len(), b.policy.len());
+            assert_eq!(p.policy[0].uci, b.policy[0].uci);
+            assert!((p.policy[0].probability - b.policy[0].probability).abs() < 1e-6);
+            assert!(p.white_wr.is_nan());
+        }
+        for (v, b) in value.iter().zip(&both) {
+            assert!(v.policy.is_empty());
+            assert!((v.white_wr - b.white_wr).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ort")]
+    #[ignore = "requires local Maia3 ONNX model file"]
+    fn value_white_holds_for_both_turns() {
+        let mut maia = Maia::from_file("maia3_simplified.onnx").expect("load model");
+        // White is a queen and a rook up, with either side to move.
+        for turn in ["w", "b"] {
+            let fen = format!("6k1/8/8/8/8/8/5PPP/1QR3K1 {turn} - - 0 1");
+            let result = maia.evaluate_fen(&fen, 1500.0, 1500.0).expect("evaluate");
+            assert!(
+                result.value_white() > 0.8,
+                "{fen}: {}",
+                result.value_white()
+            );
+        }
+    }
+
     #[tokio::test]
+    #[cfg(feature = "ort")]
     #[ignore = "requires local Maia3 ONNX model file"]
     async fn async_evaluate() {
         let mut maia = Maia::from_file("maia3_simplified.onnx").expect("load model");
@@ -0,0 +1,300 @@
+//! Process-wide Prometheus metrics, behind the `metrics` feature.
+//!
+//! [`Maia`](crate::Maia), [`MaiaService`](crate::MaiaService) and
+//! [`ThreadLocalMaia`](crate::ThreadLocalMaia) update the metrics below
+//! as they work; [`render`] formats them in the Prometheus text format
+//! for a `/metrics` endpoint, as `maia-server` serves them. Without the
+//! feature none of this is compiled and the instrumented code is
+//! unchanged.
+//!
+//! The names are stable:
+//!
+//! | Metric | Type | Meaning |
+//! |---|---|---|
+//! | `maia_evaluations_total` | counter | Positions run through the model |
+//! | `maia_inference_errors_total` | counter | Model runs that failed |
+//! | `maia_batch_size` | histogram | Positions per model run |
+//! | `maia_inference_seconds` | histogram | Duration of a model run |
+//! | `maia_service_requests_total` | counter | Requests queued in a `MaiaService` |
+//! | `maia_service_rejected_total` | counter | Requests refused by a full queue |
+//! | `maia_service_queue_depth` | gauge | Requests waiting for a batch |
+//! | `maia_service_queue_wait_seconds` | histogram | Time from submission to batch |
+//! | `maia_sessions` | gauge | Sessions of `ThreadLocalMaia`s |
+//! | `maia_sessions_busy` | gauge | Those sessions running a call |
+//!
+//! The values add up over every instance in the process. A model run is
+//! one call of the backend, so a batch split after running out of memory
+//! counts one run per piece.
+
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Upper bounds of the [`BATCH_SIZE`] buckets.
+const BATCH_BOUNDS: [f64; 10] = [1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0];
+
+/// Upper bounds in seconds of the latency buckets.
+const LATENCY_BOUNDS: [f64; 10] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0,
+];
+
+pub(crate) static EVALUATIONS: Counter =
+    Counter::new("maia_evaluations_total", "Positions run through the model.");
+pub(crate) static INFERENCE_ERRORS: Counter =
+    Counter::new("maia_inference_errors_total", "Model runs that failed.");
+pub(crate) static BATCH_SIZE: Histogram<10> =
+    Histogram::new("maia_batch_size", "Positions per model run.", BATCH_BOUNDS);
+pub(crate) static INFERENCE_SECONDS: Histogram<10> = Histogram::new(
+    "maia_inference_seconds",
+    "Duration of a model run.",
+    LATENCY_BOUNDS,
+);
+pub(crate) static SERVICE_REQUESTS: Counter = Counter::new(
+    "maia_service_requests_total",
+    "Requests queued in a MaiaService.",
+);
+pub(crate) static SERVICE_REJECTED: Counter = Counter::new(
+    "maia_service_rejected_total",
+    "Requests refused by a full MaiaService queue.",
+);
+pub(crate) static QUEUE_DEPTH: Gauge = Gauge::new(
+    "maia_service_queue_depth",
+    "Requests waiting for a MaiaService batch.",
+);
+pub(crate) static QUEUE_WAIT_SECONDS: Histogram<10> = Histogram::new(
+    "maia_service_queue_wait_seconds",
+    "Time from submission to a MaiaService batch.",
+    LATENCY_BOUNDS,
+);
+pub(crate) static SESSIONS: Gauge = Gauge::new("maia_sessions", "Sessions of ThreadLocalMaias.");
+pub(crate) static SESSIONS_BUSY: Gauge = Gauge::new(
+    "maia_sessions_busy",
+    "ThreadLocalMaia sessions running a call.",
+);
+
+/// Every metric of the crate in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    for counter in [
+        &EVALUATIONS,
+        &INFERENCE_ERRORS,
+        &SERVICE_REQUESTS,
+        &SERVICE_REJECTED,
+    ] {
+        counter.render(&mut out);
+    }
+    for gauge in [&QUEUE_DEPTH, &SESSIONS, &SESSIONS_BUSY] {
+        gauge.render(&mut out);
+    }
+    for histogram in [&BATCH_SIZE, &INFERENCE_SECONDS, &QUEUE_WAIT_SECONDS] {
+        histogram.render(&mut out);
+    }
+    out
+}
+
+/// Record one model run of `batch_size` positions.
+pub(crate) fn record_run(batch_size: usize, elapsed: Duration, ok: bool) {
+    if ok {
+        EVALUATIONS.add(batch_size as u64);
+    } else {
+        INFERENCE_ERRORS.add(1);
+    }
+    BATCH_SIZE.observe(batch_size as f64);
+    INFERENCE_SECONDS.observe_duration(elapsed);
+}
+
+/// Counts a [`ThreadLocalMaia`](crate::ThreadLocalMaia) session as busy
+/// while it lives.
+pub(crate) struct BusySession(());
+
+impl BusySession {
+    pub(crate) fn start() -> Self {
+        SESSIONS_BUSY.add(1);
+        Self(())
+    }
+}
+
+impl Drop for BusySession {
+    fn drop(&mut self) {
+        SESSIONS_BUSY.add(-1);
+    }
+}
+
+/// A value that only goes up.
+pub(crate) struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String) {
+        let (name, value) = (self.name, self.value.load(Ordering::Relaxed));
+        let _ = write!(
+            out,
+            "# HELP {name} {}\n# TYPE {name} counter\n{name} {value}\n",
+            self.help
+        );
+    }
+}
+
+/// A value that goes up and down.
+pub(crate) struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicI64,
+}
+
+impl Gauge {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicI64::new(0),
+        }
+    }
+
+    pub(crate) fn add(&self, n: i64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String) {
+        let (name, value) = (self.name, self.value.load(Ordering::Relaxed));
+        let _ = write!(
+            out,
+            "# HELP {name} {}\n# TYPE {name} gauge\n{name} {value}\n",
+            self.help
+        );
+    }
+}
+
+/// Counts of observations up to each of `N` bounds; larger ones only
+/// count towards the total.
+pub(crate) struct Histogram<const N: usize> {
+    name: &'static str,
+    help: &'static str,
+    bounds: [f64; N],
+    buckets: [AtomicU64; N],
+    count: AtomicU64,
+    /// Bits of the `f64` sum.
+    sum: AtomicU64,
+}
+
+impl<const N: usize> Histogram<N> {
+    const fn new(name: &'static str, help: &'static str, bounds: [f64; N]) -> Self {
+        Self {
+            name,
+            help,
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; N],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn observe(&self, value: f64) {
+        if let Some(i) = self.bounds.iter().position(|&bound| value <= bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+    }
+
+    pub(crate) fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    fn render(&self, out: &mut String) {
+        let name = self.name;
+        let _ = write!(
+            out,
+            "# HELP {name} {}\n# TYPE {name} histogram\n",
+            self.help
+        );
+        // Prometheus buckets are cumulative.
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = f64::from_bits(self.sum.load(Ordering::Relaxed));
+        let _ = write!(
+            out,
+            "{name}_bucket{{le=\"+Inf\"}} {count}\n{name}_sum {sum}\n{name}_count {count}\n"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::{Setup, fen::Fen};
+
+    use super::*;
+    use crate::{Maia, testing::ZeroBackend};
+
+    #[test]
+    fn histograms_render_cumulative_buckets() {
+        let histogram = Histogram::new("test_seconds", "Test.", [0.1, 1.0]);
+        for value in [0.05, 0.5, 0.7, 3.0] {
+            histogram.observe(value);
+        }
+        let mut out = String::new();
+        histogram.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP test_seconds Test.\n\
+             # TYPE test_seconds histogram\n\
+             test_seconds_bucket{le=\"0.1\"} 1\n\
+             test_seconds_bucket{le=\"1\"} 3\n\
+             test_seconds_bucket{le=\"+Inf\"} 4\n\
+             test_seconds_sum 4.25\n\
+             test_seconds_count 4\n"
+        );
+    }
+
+    /// The value of the unlabeled sample `name` in `render()`.
+    fn sample(name: &str) -> f64 {
+        render()
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+            .unwrap()
+    }
+
+    #[test]
+    fn evaluations_are_counted() {
+        let setup: Setup = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_setup();
+        let (evaluations, runs) = (
+            sample("maia_evaluations_total"),
+            sample("maia_batch_size_count"),
+        );
+        let mut maia = Maia::from_backend(ZeroBackend::default());
+        maia.batch_evaluate(vec![setup; 3], &[1500.0; 3], &[1500.0; 3])
+            .unwrap();
+        // Other tests evaluate concurrently, so only lower bounds hold.
+        assert!(sample("maia_evaluations_total") >= evaluations + 3.0);
+        assert!(sample("maia_batch_size_count") > runs);
+        assert!(render().contains("# TYPE maia_service_queue_depth gauge\n"));
+    }
+}
@@ -0,0 +1,93 @@
+//! Read-only memory maps of model files, behind the `mmap` feature.
+
+use std::{fs::File, io, path::Path};
+
+/// A file mapped read-only into memory, unmapped on drop.
+pub(crate) struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read-only and owned, so it can move between threads.
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    /// Map the whole file at `path`.
+    ///
+    /// # Errors
+    /// Returns the error of opening or mapping the file, and
+    /// [`io::ErrorKind::Unsupported`] on platforms without `mmap` and
+    /// for empty files, which cannot be mapped.
+    #[cfg(unix)]
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::FileTooLarge))?;
+        if len == 0 {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        // SAFETY: a fresh private read-only mapping of an open file; the
+        // descriptor may be closed once `mmap` returns.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn open(_path: &Path) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// The contents of the file.
+    ///
+    /// Like every map of a file, this assumes no other process truncates
+    /// or rewrites the file while it is mapped.
+    pub(crate) fn bytes(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` readable bytes until drop.
+        unsafe { std::slice::from_raw_parts(self.ptr.cast(), self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        // SAFETY: unmapping the region `open` mapped, once.
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn maps_the_file_contents() {
+        let path = std::env::temp_dir().join(format!("maia-mmap-{}.bin", std::process::id()));
+        std::fs::write(&path, b"model bytes").unwrap();
+        let mapping = Mapping::open(&path).unwrap();
+        assert_eq!(mapping.bytes(), b"model bytes");
+        drop(mapping);
+
+        std::fs::write(&path, b"").unwrap();
+        let err = Mapping::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        std::fs::remove_file(&path).unwrap();
+        assert!(Mapping::open(&path).is_err());
+    }
+}
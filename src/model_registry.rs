@@ -0,0 +1,441 @@
+//! Several models loaded side by side under names.
+
+#[cfg(feature = "ort")]
+use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use shakmaty::Setup;
+
+use crate::{
+    Maia,
+    backend::{DefaultBackend, InferenceBackend},
+    error::Error,
+    input::TryIntoSetup,
+    types::EvaluationResult,
+};
+#[cfg(feature = "ort")]
+use crate::{backend::OrtBackend, maia::Device};
+
+/// Factory of a registered model.
+type Factory<B> = Arc<dyn Fn() -> Result<Maia<B>, Error> + Send + Sync>;
+
+/// A loaded model, shared with the callers using it.
+type Shared<B> = Arc<Mutex<Maia<B>>>;
+
+/// Models registered under string keys, e.g. `"rapid"`, `"blitz"` and a
+/// fine-tuned model, each loaded by its own factory on first use.
+///
+/// Every loaded model is a session of its own and holds its own copy of
+/// the weights plus ONNX Runtime's arena, several times the size of the
+/// `.onnx` file under load. [`with_max_loaded`](Self::with_max_loaded)
+/// bounds how many are loaded at once by unloading the least recently
+/// used model when another one loads, and [`unload`](Self::unload)
+/// frees one explicitly; an unloaded model loads again on its next use.
+///
+/// The registry is `Sync`; share it by reference or in an [`Arc`].
+/// Calls on different models run in parallel, calls on the same model
+/// wait for each other on its lock, and loading holds up only the
+/// callers of the model being loaded. A model unloaded while a caller
+/// still holds it from [`get`](Self::get) stays in memory until that
+/// caller lets go, so two copies can briefly coexist.
+pub struct MaiaRegistry<B = DefaultBackend> {
+    max_loaded: Option<usize>,
+    entries: Mutex<HashMap<String, Entry<B>>>,
+}
+
+struct Entry<B> {
+    factory: Factory<B>,
+    /// Held while the model loads, so concurrent first calls load it
+    /// once.
+    loading: Arc<Mutex<()>>,
+    model: Option<Shared<B>>,
+    stats: ModelStats,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ModelStats {
+    loads: u64,
+    evaluations: u64,
+    last_used: Option<Instant>,
+    load_time: Option<Duration>,
+}
+
+/// State and usage of one model of a [`MaiaRegistry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelInfo {
+    pub key: String,
+    /// Whether the model is in memory now.
+    pub loaded: bool,
+    /// How often the model has been loaded, counting reloads after it
+    /// was unloaded.
+    pub loads: u64,
+    /// Positions evaluated through [`MaiaRegistry::evaluate`] and
+    /// [`MaiaRegistry::batch_evaluate`].
+    pub evaluations: u64,
+    /// When the model was last asked for, `None` if never.
+    pub last_used: Option<Instant>,
+    /// How long the latest load took.
+    pub load_time: Option<Duration>,
+}
+
+impl<B> Default for MaiaRegistry<B> {
+    fn default() -> Self {
+        Self {
+            max_loaded: None,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[cfg(feature = "ort")]
+impl MaiaRegistry<OrtBackend> {
+    /// Register the model at `path` under `key`, loaded onto `device`
+    /// as [`Maia::from_file_on`] on first use.
+    pub fn register_file(&self, key: impl Into<String>, path: impl Into<PathBuf>, device: Device) {
+        let path = path.into();
+        self.register(key, move || Maia::from_file_on(&path, device));
+    }
+}
+
+impl<B: InferenceBackend + Send + 'static> MaiaRegistry<B> {
+    /// An empty registry without a cap on the loaded models.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep at most `max` models (at least 1) loaded, see the
+    /// [type](Self).
+    pub fn with_max_loaded(mut self, max: usize) -> Self {
+        self.max_loaded = Some(max.max(1));
+        self
+    }
+
+    /// Register `factory` under `key`, replacing and unloading a model
+    /// registered under it before. Nothing is loaded until the model is
+    /// first used.
+    pub fn register(
+        &self,
+        key: impl Into<String>,
+        factory: impl Fn() -> Result<Maia<B>, Error> + Send + Sync + 'static,
+    ) {
+        let entry = Entry {
+            factory: Arc::new(factory),
+            loading: Arc::new(Mutex::new(())),
+            model: None,
+            stats: ModelStats::default(),
+        };
+        let replaced = lock(&self.entries).insert(key.into(), entry);
+        drop(replaced);
+    }
+
+    /// Remove the model registered under `key`; returns whether there
+    /// was one.
+    pub fn remove(&self, key: &str) -> bool {
+        let removed = lock(&self.entries).remove(key);
+        removed.is_some()
+    }
+
+    /// Whether a model is registered under `key`.
+    pub fn contains(&self, key: &str) -> bool {
+        lock(&self.entries).contains_key(key)
+    }
+
+    /// The registered keys, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = lock(&self.entries).keys().cloned().collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// How many models are loaded.
+    pub fn loaded_count(&self) -> usize {
+        lock(&self.entries)
+            .values()
+            .filter(|entry| entry.model.is_some())
+            .count()
+    }
+
+    /// Load the model registered under `key` now instead of on first
+    /// use.
+    ///
+    /// # Errors
+    /// As [`get`](Self::get).
+    pub fn load(&self, key: &str) -> Result<(), Error> {
+        self.get(key).map(|_| ())
+    }
+
+    /// Drop the registry's copy of the model under `key`; returns
+    /// whether it was loaded.
+    pub fn unload(&self, key: &str) -> bool {
+        let model = lock(&self.entries)
+            .get_mut(key)
+            .and_then(|entry| entry.model.take());
+        model.is_some()
+    }
+
+    /// The model registered under `key`, loaded first if it is not in
+    /// memory. Holding the returned model keeps it in memory also after
+    /// it is unloaded.
+    ///
+    /// # Errors
+    /// Returns [`Error::UnknownModel`] if nothing is registered under
+    /// `key`, or the factory's error, in which case the next call tries
+    /// again.
+    pub fn get(&self, key: &str) -> Result<Shared<B>, Error> {
+        let (factory, loading) = {
+            let mut entries = lock(&self.entries);
+            let entry = entries.get_mut(key).ok_or_else(|| Error::UnknownModel {
+                key: key.to_string(),
+            })?;
+            entry.stats.last_used = Some(Instant::now());
+            if let Some(model) = &entry.model {
+                return Ok(model.clone());
+            }
+            (entry.factory.clone(), entry.loading.clone())
+        };
+
+        let _loading = lock(&loading);
+        // Another caller may have loaded the model while this one waited.
+        if let Some(model) = lock(&self.entries).get(key).and_then(|e| e.model.clone()) {
+            return Ok(model);
+        }
+        let start = Instant::now();
+        let model = Arc::new(Mutex::new(factory()?));
+        let load_time = start.elapsed();
+        tracing::info!(key, ?load_time, "loaded model");
+
+        let evicted = {
+            let mut entries = lock(&self.entries);
+            match entries.get_mut(key) {
+                Some(entry) if Arc::ptr_eq(&entry.loading, &loading) => {
+                    entry.model = Some(model.clone());
+                    entry.stats.loads += 1;
+                    entry.stats.load_time = Some(load_time);
+                }
+                // Replaced or removed while loading: serve this call
+                // without keeping the model.
+                _ => return Ok(model),
+            }
+            self.evict(&mut entries, key)
+        };
+        // Dropping a session can take a while; not under the lock.
+        drop(evicted);
+        Ok(model)
+    }
+
+    /// Take the least recently used models other than `key` out of
+    /// `entries` until at most `max_loaded` are loaded.
+    fn evict(&self, entries: &mut HashMap<String, Entry<B>>, key: &str) -> Vec<Shared<B>> {
+        let Some(max) = self.max_loaded else {
+            return Vec::new();
+        };
+        let mut evicted = Vec::new();
+        while entries.values().filter(|e| e.model.is_some()).count() > max {
+            let Some((victim, entry)) = entries
+                .iter_mut()
+                .filter(|(k, e)| e.model.is_some() && k.as_str() != key)
+                .min_by_key(|(_, e)| e.stats.last_used)
+            else {
+                break;
+            };
+            tracing::info!(key = victim.as_str(), "unloading least recently used model");
+            evicted.extend(entry.model.take());
+        }
+        evicted
+    }
+
+    /// Evaluate a single position on the model under `key`.
+    ///
+    /// # Errors
+    /// As [`get`](Self::get) and [`Maia::evaluate`].
+    pub fn evaluate(
+        &self,
+        key: &str,
+        input: impl TryIntoSetup,
+        elo_self: f32,
+        elo_oppo: f32,
+    ) -> Result<EvaluationResult, Error> {
+        let model = self.get(key)?;
+        let result = lock(&model).evaluate(input, elo_self, elo_oppo)?;
+        self.count_evaluations(key, 1);
+        Ok(result)
+    }
+
+    /// Evaluate a batch on the model under `key`, with the same contract
+    /// as [`Maia::batch_evaluate`].
+    ///
+    /// # Errors
+    /// As [`get`](Self::get) and [`Maia::batch_evaluate`].
+    pub fn batch_evaluate(
+        &self,
+        key: &str,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        let model = self.get(key)?;
+        let results = lock(&model).batch_evaluate(setups, elo_selfs, elo_oppos)?;
+        self.count_evaluations(key, results.len() as u64);
+        Ok(results)
+    }
+
+    fn count_evaluations(&self, key: &str, n: u64) {
+        if let Some(entry) = lock(&self.entries).get_mut(key) {
+            entry.stats.evaluations += n;
+        }
+    }
+
+    /// State and usage of the model under `key`.
+    pub fn info(&self, key: &str) -> Option<ModelInfo> {
+        lock(&self.entries)
+            .get(key)
+            .map(|entry| model_info(key, entry))
+    }
+
+    /// State and usage of every model, sorted by key.
+    pub fn infos(&self) -> Vec<ModelInfo> {
+        let mut infos: Vec<ModelInfo> = lock(&self.entries)
+            .iter()
+            .map(|(key, entry)| model_info(key, entry))
+            .collect();
+        infos.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        infos
+    }
+}
+
+fn model_info<B>(key: &str, entry: &Entry<B>) -> ModelInfo {
+    let stats = entry.stats;
+    ModelInfo {
+        key: key.to_string(),
+        loaded: entry.model.is_some(),
+        loads: stats.loads,
+        evaluations: stats.evaluations,
+        last_used: stats.last_used,
+        load_time: stats.load_time,
+    }
+}
+
+/// Lock `mutex`, also after a panic on another thread: a model whose
+/// call panicked is still usable for the next one.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    use ndarray::{Array2, Array3};
+    use shakmaty::fen::Fen;
+
+    use super::*;
+    use crate::{backend::ModelOutputs, moves::ALL_MOVES};
+
+    /// Uniform policy and a win logit of its own, so results tell
+    /// the models apart.
+    struct ValueBackend(f32);
+
+    impl InferenceBackend for ValueBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            _elo_self: &[f32],
+            _elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            let batch_size = tokens.shape()[0];
+            let mut logits_value = Array2::zeros((batch_size, 3));
+            logits_value.column_mut(2).fill(self.0);
+            Ok(ModelOutputs {
+                logits_move: Array2::zeros((batch_size, ALL_MOVES.len())),
+                logits_value,
+            })
+        }
+    }
+
+    const FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    /// A registry with `rapid` and `blitz` models and the number of
+    /// loads of each.
+    fn registry(max_loaded: Option<usize>) -> (MaiaRegistry<ValueBackend>, [Arc<AtomicUsize>; 2]) {
+        let mut registry = MaiaRegistry::new();
+        if let Some(max) = max_loaded {
+            registry = registry.with_max_loaded(max);
+        }
+        let loads = [(); 2].map(|_| Arc::new(AtomicUsize::new(0)));
+        for ((key, logit), counter) in [("rapid", 0.0), ("blitz", 2.0)].into_iter().zip(&loads) {
+            let counter = counter.clone();
+            registry.register(key, move || {
+                counter.fetch_add(1, Ordering::Relaxed);
+                Ok(Maia::from_backend(ValueBackend(logit)))
+            });
+        }
+        (registry, loads)
+    }
+
+    #[test]
+    fn requests_are_routed_by_key() {
+        let (registry, loads) = registry(None);
+        assert_eq!(registry.keys(), ["blitz", "rapid"]);
+        assert_eq!(registry.loaded_count(), 0);
+
+        let rapid = registry.evaluate("rapid", FEN, 1500.0, 1500.0).unwrap();
+        let blitz = registry.evaluate("blitz", FEN, 1500.0, 1500.0).unwrap();
+        assert!(blitz.white_wr > rapid.white_wr);
+        let setup = FEN.parse::<Fen>().unwrap().into_setup();
+        let setups = vec![setup.clone(), setup];
+        let batch = registry
+            .batch_evaluate("blitz", setups, &[1500.0; 2], &[1500.0; 2])
+            .unwrap();
+        assert_eq!(batch[0].white_wr, blitz.white_wr);
+
+        let info = registry.info("blitz").unwrap();
+        assert!(info.loaded && info.last_used.is_some());
+        assert_eq!((info.loads, info.evaluations), (1, 3));
+        assert_eq!(loads.each_ref().map(|n| n.load(Ordering::Relaxed)), [1, 1]);
+        assert!(matches!(
+            registry.evaluate("classical", FEN, 1500.0, 1500.0),
+            Err(Error::UnknownModel { key }) if key == "classical"
+        ));
+    }
+
+    #[test]
+    fn least_recently_used_models_are_evicted() {
+        let (registry, loads) = registry(Some(1));
+        registry.load("rapid").unwrap();
+        registry.load("blitz").unwrap();
+        assert_eq!(registry.loaded_count(), 1);
+        assert!(!registry.info("rapid").unwrap().loaded);
+
+        // Unloaded models load again when used.
+        registry.evaluate("rapid", FEN, 1500.0, 1500.0).unwrap();
+        assert!(!registry.info("blitz").unwrap().loaded);
+        assert_eq!(registry.info("rapid").unwrap().loads, 2);
+        assert!(registry.unload("rapid"));
+        assert!(!registry.unload("rapid"));
+        assert_eq!(registry.loaded_count(), 0);
+        assert_eq!(loads[0].load(Ordering::Relaxed), 2);
+
+        assert!(registry.remove("rapid"));
+        assert!(!registry.contains("rapid"));
+        assert_eq!(registry.infos().len(), 1);
+    }
+
+    #[test]
+    fn concurrent_first_calls_load_once() {
+        let (registry, loads) = registry(None);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| registry.evaluate("rapid", FEN, 1500.0, 1500.0).unwrap());
+            }
+        });
+        assert_eq!(loads[0].load(Ordering::Relaxed), 1);
+        assert_eq!(registry.info("rapid").unwrap().evaluations, 4);
+    }
+}
@@ -1,6 +1,6 @@
 use std::{collections::HashMap, sync::LazyLock};
 
-use shakmaty::uci::UciMove;
+use shakmaty::{Color, uci::UciMove};
 
 // JSON representation of the fixed move vocabulary used by Maia3. The
 // file maps UCI strings to indices in the model's output layer.  The
@@ -26,13 +26,45 @@ pub static ALL_MOVES: LazyLock<HashMap<UciMove, usize>> = LazyLock::new(|| {
         .collect()
 });
 
-// The reversed mapping (index -> UCI) is not currently used but is
-// kept here in comments in case future features need to convert the
-// model's integer outputs back to strings.
-// const ALL_MOVES_REVERSED_JSON: &str = include_str!("data/all_moves_reversed.json");
-// pub static ALL_MOVES_REVERSED: LazyLock<HashMap<usize, String>> = LazyLock::new(|| {
-//     serde_json::from_str(ALL_MOVES_REVERSED_JSON).expect("Failed to parse all_moves_reversed.json")
-// });
+/// The vocabulary in output order: the move with index `i` is
+/// `MOVES_BY_INDEX[i]`.
+pub static MOVES_BY_INDEX: LazyLock<Vec<UciMove>> = LazyLock::new(|| {
+    let mut moves = vec![UciMove::Null; ALL_MOVES.len()];
+    for (&uci, &idx) in &*ALL_MOVES {
+        moves[idx] = uci;
+    }
+    moves
+});
+
+/// For every vocabulary index, the index of the same move mirrored
+/// vertically. Promotions mirror to moves onto the first rank, which the
+/// vocabulary does not hold.
+static MIRRORED_INDEX: LazyLock<Vec<Option<u16>>> = LazyLock::new(|| {
+    MOVES_BY_INDEX
+        .iter()
+        .map(|uci| ALL_MOVES.get(&uci.to_mirrored()).map(|&i| i as u16))
+        .collect()
+});
+
+/// Vocabulary index of the mirror image of the move with index `index`,
+/// which converts between the index of a Black move as the network sees
+/// it and the index of the move as reported. `None` if `index` is out of
+/// range or its mirror is not in the vocabulary.
+pub fn mirrored_index(index: u16) -> Option<u16> {
+    MIRRORED_INDEX.get(usize::from(index)).copied().flatten()
+}
+
+/// Vocabulary index of `uci` played by `turn`, as the network sees the
+/// move: mirrored when Black is to move. This is the index
+/// [`MoveProbability::index`](crate::MoveProbability::index) holds.
+/// `None` for moves outside the vocabulary.
+pub fn vocabulary_index(uci: &UciMove, turn: Color) -> Option<u16> {
+    let seen = match turn {
+        Color::White => *uci,
+        Color::Black => uci.to_mirrored(),
+    };
+    ALL_MOVES.get(&seen).map(|&i| i as u16)
+}
 
 #[cfg(test)]
 mod tests {
@@ -43,4 +75,32 @@ mod tests {
         dbg!(&*ALL_MOVES);
         assert!(!ALL_MOVES.is_empty());
     }
+
+    #[test]
+    fn moves_by_index_inverts_the_vocabulary() {
+        assert_eq!(MOVES_BY_INDEX.len(), ALL_MOVES.len());
+        assert!(u16::try_from(MOVES_BY_INDEX.len()).is_ok());
+        for (uci, &idx) in &*ALL_MOVES {
+            assert_eq!(MOVES_BY_INDEX[idx], *uci);
+        }
+    }
+
+    #[test]
+    fn mirrored_indices_convert_black_moves() {
+        let e7e5: UciMove = "e7e5".parse().unwrap();
+        let seen = vocabulary_index(&e7e5, Color::Black).unwrap();
+        assert_eq!(
+            seen as usize,
+            ALL_MOVES[&"e2e4".parse::<UciMove>().unwrap()]
+        );
+        assert_eq!(mirrored_index(seen), vocabulary_index(&e7e5, Color::White));
+        assert_eq!(mirrored_index(mirrored_index(seen).unwrap()), Some(seen));
+
+        // Black's promotions only exist mirrored.
+        let e2e1q: UciMove = "e2e1q".parse().unwrap();
+        let seen = vocabulary_index(&e2e1q, Color::Black).unwrap();
+        assert_eq!(vocabulary_index(&e2e1q, Color::White), None);
+        assert_eq!(mirrored_index(seen), None);
+        assert_eq!(mirrored_index(u16::MAX), None);
+    }
 }
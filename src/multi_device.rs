@@ -0,0 +1,214 @@
+//! Splitting batches across several models, one per device.
+
+use std::thread;
+
+use shakmaty::Setup;
+
+use crate::{
+    Maia, backend::InferenceBackend, error::Error, evaluator::Evaluator, types::EvaluationResult,
+};
+
+/// A group of [`Maia`] instances, typically one per GPU, that evaluate
+/// slices of every batch concurrently.
+///
+/// Each batch is cut into contiguous slices proportional to the member
+/// weights; the members run on their own threads and the results come
+/// back in input order.
+pub struct MultiDeviceMaia<B> {
+    members: Vec<(Maia<B>, f32)>,
+}
+
+impl<B: InferenceBackend + Send> MultiDeviceMaia<B> {
+    /// Group `members`, each with a relative weight (e.g. device
+    /// throughput). Non-positive weights give a member no work.
+    ///
+    /// # Panics
+    /// Panics if there are no members or no member has a positive weight.
+    pub fn new(members: Vec<(Maia<B>, f32)>) -> Self {
+        assert!(
+            members.iter().any(|&(_, w)| w > 0.0),
+            "MultiDeviceMaia needs a member with positive weight"
+        );
+        Self { members }
+    }
+
+    /// The members and their weights, in device order.
+    pub fn members(&self) -> &[(Maia<B>, f32)] {
+        &self.members
+    }
+
+    /// Mutable access to member `device`.
+    pub fn member_mut(&mut self, device: usize) -> &mut Maia<B> {
+        &mut self.members[device].0
+    }
+
+    /// Slice lengths for a batch of `n` positions.
+    fn partition(&self, n: usize) -> Vec<usize> {
+        let weights: Vec<f32> = self.members.iter().map(|&(_, w)| w.max(0.0)).collect();
+        let total: f32 = weights.iter().sum();
+        let mut cumulative = 0.0;
+        let mut start = 0;
+        weights
+            .iter()
+            .map(|w| {
+                cumulative += w;
+                let end = ((n as f32 * cumulative / total).round() as usize).min(n);
+                let len = end - start;
+                start = end;
+                len
+            })
+            .collect()
+    }
+
+    /// Evaluate a batch, with the same contract as
+    /// [`Maia::batch_evaluate`].
+    ///
+    /// Every member finishes its slice even if another fails.
+    ///
+    /// # Errors
+    /// Returns [`Error::DeviceFailed`] with the lowest failing device
+    /// index and its error.
+    pub fn batch_evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        assert_eq!(setups.len(), elo_selfs.len());
+        assert_eq!(setups.len(), elo_oppos.len());
+        let lengths = self.partition(setups.len());
+
+        let mut setups = setups.into_iter();
+        let mut offset = 0;
+        let outcomes: Vec<Result<Vec<EvaluationResult>, Error>> = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .members
+                .iter_mut()
+                .zip(&lengths)
+                .map(|((maia, _), &len)| {
+                    let slice: Vec<Setup> = setups.by_ref().take(len).collect();
+                    let range = offset..offset + len;
+                    offset += len;
+                    let (selfs, oppos) = (&elo_selfs[range.clone()], &elo_oppos[range]);
+                    scope.spawn(move || {
+                        if slice.is_empty() {
+                            return Ok(Vec::new());
+                        }
+                        maia.batch_evaluate(slice, selfs, oppos)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        });
+
+        let mut results = Vec::with_capacity(offset);
+        for (device, outcome) in outcomes.into_iter().enumerate() {
+            match outcome {
+                Ok(slice) => results.extend(slice),
+                Err(source) => {
+                    return Err(Error::DeviceFailed {
+                        device,
+                        source: Box::new(source),
+                    });
+                }
+            }
+        }
+        Ok(results)
+    }
+}
+
+impl<B: InferenceBackend + Send> Evaluator for MultiDeviceMaia<B> {
+    fn batch_evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        MultiDeviceMaia::batch_evaluate(self, setups, elo_selfs, elo_oppos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array2, Array3};
+    use shakmaty::fen::Fen;
+
+    use super::*;
+    use crate::backend::ModelOutputs;
+
+    /// Uniform logits, or an error for every call if `fail` is set.
+    struct TestBackend {
+        fail: bool,
+        calls: usize,
+    }
+
+    impl InferenceBackend for TestBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            _elo_self: &[f32],
+            _elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            self.calls += 1;
+            if self.fail {
+                return Err(Error::ServiceStopped);
+            }
+            let batch_size = tokens.shape()[0];
+            Ok(ModelOutputs {
+                logits_move: Array2::zeros((batch_size, crate::moves::ALL_MOVES.len())),
+                logits_value: Array2::zeros((batch_size, 3)),
+            })
+        }
+    }
+
+    fn member(fail: bool, weight: f32) -> (Maia<TestBackend>, f32) {
+        (Maia::from_backend(TestBackend { fail, calls: 0 }), weight)
+    }
+
+    /// Alternating start positions (20 moves) and 1. e4 e5 (29 moves).
+    fn setups(n: usize) -> Vec<Setup> {
+        let open: Fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2"
+            .parse()
+            .unwrap();
+        (0..n)
+            .map(|i| match i % 2 {
+                0 => Setup::default(),
+                _ => open.clone().into_setup(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batches_split_by_weight_and_keep_order() {
+        let mut multi = MultiDeviceMaia::new(vec![member(false, 3.0), member(false, 1.0)]);
+        assert_eq!(multi.partition(8), vec![6, 2]);
+        assert_eq!(multi.partition(1), vec![1, 0]);
+
+        let results = multi
+            .batch_evaluate(setups(7), &[1500.0; 7], &[1500.0; 7])
+            .unwrap();
+        let lengths: Vec<usize> = results.iter().map(|r| r.policy.len()).collect();
+        assert_eq!(lengths, vec![20, 29, 20, 29, 20, 29, 20]);
+        assert!(multi.members().iter().all(|(m, _)| m.backend().calls == 1));
+    }
+
+    #[test]
+    fn failing_device_is_reported_after_the_others_finish() {
+        let mut multi = MultiDeviceMaia::new(vec![
+            member(false, 1.0),
+            member(true, 1.0),
+            member(false, 1.0),
+        ]);
+        let err = multi
+            .batch_evaluate(setups(6), &[1500.0; 6], &[1500.0; 6])
+            .unwrap_err();
+        assert!(matches!(err, Error::DeviceFailed { device: 1, .. }));
+        assert_eq!(multi.member_mut(2).backend().calls, 1);
+    }
+}
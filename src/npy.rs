@@ -0,0 +1,293 @@
+//! Model inputs as NumPy `.npy` files, for comparing the encoding with
+//! the Python Maia pipeline.
+//!
+//! [`save_inputs`] writes the three tensors a batch is run with, under
+//! the names of the model inputs:
+//!
+//! ```python
+//! import numpy as np
+//! tokens = np.load("dump/tokens.npy")      # [B, 64, 12] float32
+//! elo_self = np.load("dump/elo_self.npy")  # [B] float32 ratings
+//! ```
+//!
+//! [`load_inputs`] reads such a directory back, so tensors produced by
+//! the reference implementation can serve as fixtures.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use ndarray::{Array3, ArrayBase, ArrayD, Data, Dimension, IxDyn, ShapeBuilder};
+
+use crate::error::Error;
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// The inputs of one batch, as read by [`load_inputs`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchInputs {
+    /// `[B, 64, 12]` board tokens.
+    pub tokens: Array3<f32>,
+    /// Raw ratings of the side to move.
+    pub elo_self: Vec<f32>,
+    /// Raw ratings of the opponent.
+    pub elo_oppo: Vec<f32>,
+}
+
+/// Write `array` as a version 1.0 `.npy` file of little-endian `f32` in
+/// C order.
+///
+/// # Errors
+/// Propagates write errors.
+pub fn write_npy<S, D>(array: &ArrayBase<S, D>, mut w: impl Write) -> io::Result<()>
+where
+    S: Data<Elem = f32>,
+    D: Dimension,
+{
+    let shape = match array.shape() {
+        [n] => format!("({n},)"),
+        shape => {
+            let dims: Vec<String> = shape.iter().map(usize::to_string).collect();
+            format!("({})", dims.join(", "))
+        }
+    };
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {shape}, }}");
+    // The data starts on a 64-byte boundary; the header ends in a newline.
+    let unpadded = MAGIC.len() + 4 + header.len() + 1;
+    header.extend(std::iter::repeat_n(
+        ' ',
+        unpadded.next_multiple_of(64) - unpadded,
+    ));
+    header.push('\n');
+
+    w.write_all(MAGIC)?;
+    w.write_all(&[1, 0])?;
+    w.write_all(&(header.len() as u16).to_le_bytes())?;
+    w.write_all(header.as_bytes())?;
+    for value in array {
+        w.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Read a `.npy` file of little-endian floats or integers as `f32`, as
+/// written by `np.save`. C and Fortran order are both accepted.
+///
+/// # Errors
+/// Returns [`Error::Io`] if reading fails or the data is not a
+/// supported `.npy` file.
+pub fn read_npy(mut r: impl Read) -> Result<ArrayD<f32>, Error> {
+    let mut preamble = [0; 8];
+    r.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        return Err(invalid("not a .npy file"));
+    }
+    let header_len = match preamble[6] {
+        1 => {
+            let mut len = [0; 2];
+            r.read_exact(&mut len)?;
+            usize::from(u16::from_le_bytes(len))
+        }
+        2 | 3 => {
+            let mut len = [0; 4];
+            r.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        _ => return Err(invalid("unsupported .npy version")),
+    };
+    let mut header = vec![0; header_len];
+    r.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+
+    let descr = header_value(&header, "descr")
+        .map(|v| v.trim_matches(['\'', '"']))
+        .ok_or_else(|| invalid("missing descr"))?;
+    let fortran_order = header_value(&header, "fortran_order") == Some("True");
+    let shape: Vec<usize> = header_value(&header, "shape")
+        .ok_or_else(|| invalid("missing shape"))?
+        .trim_matches(['(', ')'])
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse().map_err(|_| invalid("bad shape")))
+        .collect::<Result<_, _>>()?;
+
+    let mut bytes = Vec::new();
+    r.read_to_end(&mut bytes)?;
+    let values: Vec<f32> = match descr {
+        "<f4" => bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().expect("4 bytes")))
+            .collect(),
+        "<f8" => bytes
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().expect("8 bytes")) as f32)
+            .collect(),
+        "<i4" => bytes
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes(b.try_into().expect("4 bytes")) as f32)
+            .collect(),
+        "<i8" => bytes
+            .chunks_exact(8)
+            .map(|b| i64::from_le_bytes(b.try_into().expect("8 bytes")) as f32)
+            .collect(),
+        _ => return Err(invalid("unsupported dtype")),
+    };
+    let shape = IxDyn(&shape);
+    let array = if fortran_order {
+        ArrayD::from_shape_vec(shape.f(), values)
+    } else {
+        ArrayD::from_shape_vec(shape, values)
+    };
+    array.map_err(|_| invalid("data does not match the shape"))
+}
+
+/// The raw value of `key` in the header dictionary.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{key}':"))? + key.len() + 3;
+    let rest = header[start..].trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else {
+        rest.find([',', '}'])?
+    };
+    Some(rest[..end].trim())
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, reason))
+}
+
+/// Write the inputs of one batch to `dir` as `tokens.npy`,
+/// `elo_self.npy` and `elo_oppo.npy`, creating the directory if needed.
+///
+/// `tokens` is the tensor [`preprocess`](crate::preprocess) returns; the
+/// ratings are the raw values passed to the backend.
+///
+/// # Errors
+/// Returns [`Error::Io`] if the files cannot be written.
+pub fn save_inputs(
+    dir: impl AsRef<Path>,
+    tokens: &Array3<f32>,
+    elo_self: &[f32],
+    elo_oppo: &[f32],
+) -> Result<(), Error> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let save = |name: &str, array: ArrayD<f32>| -> Result<(), Error> {
+        let mut w = BufWriter::new(File::create(dir.join(name))?);
+        write_npy(&array, &mut w)?;
+        Ok(w.flush()?)
+    };
+    save("tokens.npy", tokens.clone().into_dyn())?;
+    save(
+        "elo_self.npy",
+        ArrayD::from_shape_vec(IxDyn(&[elo_self.len()]), elo_self.to_vec())?,
+    )?;
+    save(
+        "elo_oppo.npy",
+        ArrayD::from_shape_vec(IxDyn(&[elo_oppo.len()]), elo_oppo.to_vec())?,
+    )?;
+    Ok(())
+}
+
+/// Read inputs saved by [`save_inputs`], or by the Python pipeline under
+/// the same names.
+///
+/// # Errors
+/// Returns [`Error::Io`] for missing or unreadable files,
+/// [`Error::ShapeError`] if `tokens` is not three-dimensional or a
+/// rating array not one-dimensional, and [`Error::BatchSizeMismatch`]
+/// if a rating array does not have one entry per position.
+pub fn load_inputs(dir: impl AsRef<Path>) -> Result<BatchInputs, Error> {
+    let dir = dir.as_ref();
+    let load = |name: &str| read_npy(BufReader::new(File::open(dir.join(name))?));
+    let tokens = load("tokens.npy")?.into_dimensionality()?;
+    let batch_size = tokens.shape()[0];
+    let elos = |name| -> Result<Vec<f32>, Error> {
+        let elos = load(name)?.into_dimensionality::<ndarray::Ix1>()?.to_vec();
+        if elos.len() != batch_size {
+            return Err(Error::BatchSizeMismatch {
+                expected: batch_size,
+                actual: elos.len(),
+            });
+        }
+        Ok(elos)
+    };
+    Ok(BatchInputs {
+        elo_self: elos("elo_self.npy")?,
+        elo_oppo: elos("elo_oppo.npy")?,
+        tokens,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+    use shakmaty::Setup;
+
+    use super::*;
+    use crate::preprocess;
+
+    #[test]
+    fn header_matches_numpy() {
+        let mut out = Vec::new();
+        write_npy(&array![1.0f32, -2.5], &mut out).unwrap();
+        assert_eq!(&out[..8], b"\x93NUMPY\x01\x00");
+        let header_len = usize::from(u16::from_le_bytes([out[8], out[9]]));
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&out[10..10 + header_len]).unwrap();
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (2,), }"));
+        assert!(header.ends_with(" \n"));
+        assert_eq!(
+            &out[10 + header_len..],
+            [1.0f32.to_le_bytes(), (-2.5f32).to_le_bytes()].concat()
+        );
+
+        let read = read_npy(out.as_slice()).unwrap();
+        assert_eq!(read, array![1.0f32, -2.5].into_dyn());
+    }
+
+    #[test]
+    fn fortran_order_and_other_dtypes_are_read() {
+        // np.asfortranarray(np.arange(6, dtype="<i8").reshape(2, 3)).
+        let header = "{'descr': '<i8', 'fortran_order': True, 'shape': (2, 3), }";
+        let mut file = b"\x93NUMPY\x01\x00".to_vec();
+        file.extend((header.len() as u16).to_le_bytes());
+        file.extend(header.as_bytes());
+        for value in [0i64, 3, 1, 4, 2, 5] {
+            file.extend(value.to_le_bytes());
+        }
+        let read = read_npy(file.as_slice()).unwrap();
+        assert_eq!(read, array![[0.0f32, 1.0, 2.0], [3.0, 4.0, 5.0]].into_dyn());
+
+        assert!(matches!(
+            read_npy(&b"PK\x03\x04 not npy"[..]),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn inputs_round_trip_through_a_directory() {
+        let (tokens, _) = preprocess([Setup::default(), Setup::default()], 2).unwrap();
+        let dir = std::env::temp_dir().join(format!("maia-npy-{}", std::process::id()));
+        save_inputs(&dir, &tokens, &[1500.0, 1600.0], &[1700.0, 1800.0]).unwrap();
+
+        let inputs = load_inputs(&dir).unwrap();
+        assert_eq!(inputs.tokens, tokens);
+        assert_eq!(inputs.elo_self, [1500.0, 1600.0]);
+        assert_eq!(inputs.elo_oppo, [1700.0, 1800.0]);
+
+        save_inputs(&dir, &tokens, &[1500.0], &[1700.0]).unwrap();
+        assert!(matches!(
+            load_inputs(&dir),
+            Err(Error::BatchSizeMismatch {
+                expected: 2,
+                actual: 1
+            })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+//! One model session per thread, for servers with many worker threads.
+
+#[cfg(feature = "ort")]
+use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread::{self, ThreadId},
+};
+
+use shakmaty::Setup;
+
+#[cfg(feature = "ort")]
+use crate::backend::OrtBackend;
+use crate::{
+    Maia,
+    backend::{DefaultBackend, InferenceBackend},
+    error::Error,
+    evaluator::Evaluator,
+    input::TryIntoSetup,
+    postprocess::EvalOptions,
+    types::EvaluationResult,
+};
+
+/// Factory of the per-thread models.
+type Factory<B> = Box<dyn Fn() -> Result<Maia<B>, Error> + Send + Sync>;
+
+/// A [`Maia`] per OS thread, created on the thread's first call.
+///
+/// Sharing one [`Maia`] between many threads serializes them on its
+/// lock; a [`MaiaService`](crate::MaiaService) batches them but adds a
+/// hop through its worker. Here every thread runs on a session of its
+/// own, so calls from different threads never wait on each other.
+///
+/// The cost is memory: each session holds its own copy of the weights
+/// and its own arena, so `n` threads use about `n` times the memory of
+/// one model. [`with_max_sessions`](Self::with_max_sessions) bounds
+/// that; threads arriving after the cap is reached share the existing
+/// sessions in turn, and wait for each other when they collide.
+/// Sessions live until the wrapper is dropped, also after their thread
+/// exits.
+///
+/// Share it between threads by reference or in an [`Arc`].
+pub struct ThreadLocalMaia<B = DefaultBackend> {
+    factory: Factory<B>,
+    max_sessions: Option<usize>,
+    state: Mutex<Sessions<B>>,
+}
+
+/// The models created so far and the model each thread runs on.
+struct Sessions<B> {
+    models: Vec<Arc<Mutex<Maia<B>>>>,
+    threads: HashMap<ThreadId, usize>,
+}
+
+#[cfg(feature = "metrics")]
+impl<B> Drop for Sessions<B> {
+    fn drop(&mut self) {
+        crate::metrics::SESSIONS.add(-(self.models.len() as i64));
+    }
+}
+
+#[cfg(feature = "ort")]
+impl ThreadLocalMaia<OrtBackend> {
+    /// Load the model at `path` once per thread, as
+    /// [`Maia::from_file`]. The file is read when a thread first
+    /// evaluates, so a missing file fails that call.
+    pub fn from_file(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self::new(move || Maia::from_file(&path))
+    }
+
+    /// Load the model from `model_bytes` once per thread, as
+    /// [`Maia::from_memory`].
+    pub fn from_memory(model_bytes: impl Into<Arc<[u8]>>) -> Self {
+        let bytes = model_bytes.into();
+        Self::new(move || Maia::from_memory(&bytes))
+    }
+}
+
+impl<B: InferenceBackend + Send + 'static> ThreadLocalMaia<B> {
+    /// Create each thread's model with `factory`, without a cap on the
+    /// number of sessions.
+    pub fn new(factory: impl Fn() -> Result<Maia<B>, Error> + Send + Sync + 'static) -> Self {
+        Self {
+            factory: Box::new(factory),
+            max_sessions: None,
+            state: Mutex::new(Sessions {
+                models: Vec::new(),
+                threads: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Give every model created from now on `options`.
+    pub fn with_eval_options(self, options: EvalOptions) -> Self {
+        let factory = self.factory;
+        Self {
+            factory: Box::new(move || Ok(factory()?.with_eval_options(options.clone()))),
+            ..self
+        }
+    }
+
+    /// Create at most `max` sessions (at least 1); further threads
+    /// share them as described on the [type](Self).
+    pub fn with_max_sessions(mut self, max: usize) -> Self {
+        self.max_sessions = Some(max.max(1));
+        self
+    }
+
+    /// How many sessions exist.
+    pub fn session_count(&self) -> usize {
+        lock(&self.state).models.len()
+    }
+
+    /// Run `f` on the calling thread's model, creating it first if this
+    /// is the thread's first call.
+    ///
+    /// # Errors
+    /// Returns the factory's error if the model cannot be created; the
+    /// next call of the thread tries again.
+    pub fn with_session<R>(&self, f: impl FnOnce(&mut Maia<B>) -> R) -> Result<R, Error> {
+        let model = self.session()?;
+        let mut maia = lock(&model);
+        #[cfg(feature = "metrics")]
+        let _busy = crate::metrics::BusySession::start();
+        Ok(f(&mut maia))
+    }
+
+    /// Evaluate a single position on the calling thread's model.
+    ///
+    /// # Errors
+    /// As [`Maia::evaluate`] and [`with_session`](Self::with_session).
+    pub fn evaluate(
+        &self,
+        input: impl TryIntoSetup,
+        elo_self: f32,
+        elo_oppo: f32,
+    ) -> Result<EvaluationResult, Error> {
+        self.with_session(|maia| maia.evaluate(input, elo_self, elo_oppo))?
+    }
+
+    /// Evaluate a batch on the calling thread's model, with the same
+    /// contract as [`Maia::batch_evaluate`].
+    ///
+    /// # Errors
+    /// As [`Maia::batch_evaluate`] and [`with_session`](Self::with_session).
+    pub fn batch_evaluate(
+        &self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        self.with_session(|maia| maia.batch_evaluate(setups, elo_selfs, elo_oppos))?
+    }
+
+    /// The calling thread's model, created or assigned on first use.
+    fn session(&self) -> Result<Arc<Mutex<Maia<B>>>, Error> {
+        let mut state = lock(&self.state);
+        let thread = thread::current().id();
+        if let Some(&i) = state.threads.get(&thread) {
+            return Ok(state.models[i].clone());
+        }
+        let i = if self
+            .max_sessions
+            .is_some_and(|max| state.models.len() >= max)
+        {
+            state.threads.len() % state.models.len()
+        } else {
+            // Creating under the lock keeps the count within the cap;
+            // only threads making their first call wait for it.
+            state.models.push(Arc::new(Mutex::new((self.factory)()?)));
+            #[cfg(feature = "metrics")]
+            crate::metrics::SESSIONS.add(1);
+            state.models.len() - 1
+        };
+        state.threads.insert(thread, i);
+        Ok(state.models[i].clone())
+    }
+}
+
+impl<B: InferenceBackend + Send + 'static> Evaluator for ThreadLocalMaia<B> {
+    fn batch_evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        ThreadLocalMaia::batch_evaluate(self, setups, elo_selfs, elo_oppos)
+    }
+}
+
+impl<B: InferenceBackend + Send + 'static> Evaluator for &ThreadLocalMaia<B> {
+    fn batch_evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        ThreadLocalMaia::batch_evaluate(self, setups, elo_selfs, elo_oppos)
+    }
+}
+
+/// Lock `mutex`, also after a panic on another thread: a model whose
+/// call panicked is still usable for the next one.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use ndarray::{Array2, Array3};
+
+    use super::*;
+    use crate::{backend::ModelOutputs, moves::ALL_MOVES};
+
+    /// Backend with fixed logits and the number of the model it belongs
+    /// to.
+    struct NumberedBackend(usize);
+
+    impl InferenceBackend for NumberedBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            _elo_self: &[f32],
+            _elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            let batch_size = tokens.shape()[0];
+            let logits_move =
+                Array2::from_shape_fn((batch_size, ALL_MOVES.len()), |(_, i)| (i % 7) as f32);
+            Ok(ModelOutputs {
+                logits_move,
+                logits_value: Array2::from_elem((batch_size, 3), 0.5),
+            })
+        }
+    }
+
+    fn numbered() -> ThreadLocalMaia<NumberedBackend> {
+        let created = AtomicUsize::new(0);
+        ThreadLocalMaia::new(move || {
+            let n = created.fetch_add(1, Ordering::Relaxed);
+            Ok(Maia::from_backend(NumberedBackend(n)))
+        })
+    }
+
+    /// Model number and result of one evaluation on a new thread.
+    fn on_thread(maia: &ThreadLocalMaia<NumberedBackend>) -> (usize, EvaluationResult) {
+        thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2";
+                    let result = maia.evaluate(fen, 1500.0, 1500.0).unwrap();
+                    (maia.with_session(|m| m.backend().0).unwrap(), result)
+                })
+                .join()
+                .unwrap()
+        })
+    }
+
+    #[test]
+    fn threads_get_their_own_sessions() {
+        let maia = numbered();
+        assert_eq!(maia.session_count(), 0);
+        let (a, ra) = on_thread(&maia);
+        let (b, rb) = on_thread(&maia);
+        assert_ne!(a, b);
+        assert_eq!(ra, rb);
+        assert_eq!(maia.session_count(), 2);
+
+        // The calling thread keeps the session of its first call.
+        let first = maia.with_session(|m| m.backend().0).unwrap();
+        assert_eq!(maia.with_session(|m| m.backend().0).unwrap(), first);
+        assert_eq!(maia.session_count(), 3);
+    }
+
+    #[test]
+    fn capped_sessions_are_shared() {
+        let maia = numbered().with_max_sessions(1);
+        let (a, ra) = on_thread(&maia);
+        let (b, rb) = on_thread(&maia);
+        assert_eq!((a, b), (0, 0));
+        assert_eq!(ra, rb);
+        assert_eq!(maia.session_count(), 1);
+    }
+}
@@ -0,0 +1,833 @@
+//! Reading games from PGN.
+//!
+//! The reader covers the export format chess tools write: tag pairs
+//! followed by SAN movetext. Comments, variations, NAGs, annotation
+//! glyphs and move numbers are skipped; only the main line is kept.
+//! Games are read independently, so corrupt movetext fails its own game
+//! and the reader picks up again at the next one. A `FEN` tag sets the
+//! starting position.
+//!
+//! [`GameAnalysis::to_annotated_pgn`] writes an analysed game back out,
+//! with Maia's evaluation in a comment after every move.
+//! [`GameRecord::to_pgn`] writes a generated game with its players and
+//! values, and [`GameRecord::from_pgn`] reads it back.
+
+use std::mem;
+
+use shakmaty::{
+    CastlingMode, Chess, Color, EnPassantMode, KnownOutcome, Position, fen::Fen, san::SanPlus,
+    uci::UciMove,
+};
+
+use crate::{
+    analysis::{AccuracyConfig, GameAnalysis, GameMoves},
+    error::Error,
+    selfplay::{GameRecord, GeneratedGame, PlayerConfig, SelfPlayConfig, Termination},
+    types::{Terminal, centipawns_to_score, score_to_centipawns},
+};
+
+/// One game of a PGN file.
+#[derive(Debug)]
+pub struct PgnGame {
+    /// Tag pairs in file order.
+    pub tags: Vec<(String, String)>,
+    /// The main line, or the first problem with the game: an
+    /// [`Error::IllegalMove`] for an unreadable or illegal move, an
+    /// [`Error::InvalidFen`] or [`Error::InvalidPosition`] for a bad
+    /// `FEN` tag.
+    pub moves: Result<GameMoves, Error>,
+    /// Main-line comments by position: the first before the first move,
+    /// then one after each move read. Empty where there is none; several
+    /// comments in a row are joined with a space.
+    pub comments: Vec<String>,
+}
+
+impl PgnGame {
+    /// Value of the first tag called `name`.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A game as read, before its moves are replayed.
+#[derive(Default)]
+struct RawGame {
+    tags: Vec<(String, String)>,
+    sans: Vec<String>,
+    comments: Vec<String>,
+    /// Movetext has started, so the next tag begins a new game.
+    in_movetext: bool,
+}
+
+impl RawGame {
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && !self.in_movetext
+    }
+
+    fn comment(&mut self, text: &str) {
+        self.comments.resize(self.sans.len() + 1, String::new());
+        let comment = self.comments.last_mut().expect("resized");
+        if !comment.is_empty() {
+            comment.push(' ');
+        }
+        comment.push_str(text.trim());
+    }
+
+    fn finish(mut self) -> PgnGame {
+        let moves = replay(&self.tags, &self.sans);
+        self.comments.resize(self.sans.len() + 1, String::new());
+        PgnGame {
+            tags: self.tags,
+            moves,
+            comments: self.comments,
+        }
+    }
+}
+
+/// Split `pgn` into games, in file order.
+pub fn parse_games(pgn: &str) -> Vec<PgnGame> {
+    let bytes = pgn.as_bytes();
+    let mut games = Vec::new();
+    let mut game = RawGame::default();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'[' => {
+                if game.in_movetext {
+                    games.push(mem::take(&mut game).finish());
+                }
+                let end = tag_end(bytes, i);
+                game.tags.extend(parse_tag(&pgn[i + 1..end]));
+                i = end + 1;
+            }
+            b'{' => {
+                let end = skip_past(bytes, i, b'}');
+                let close = if bytes[end - 1] == b'}' { end - 1 } else { end };
+                game.comment(&pgn[i + 1..close]);
+                i = end;
+            }
+            b';' => i = skip_past(bytes, i, b'\n'),
+            b'%' if i == 0 || bytes[i - 1] == b'\n' => i = skip_past(bytes, i, b'\n'),
+            b'(' => i = skip_variation(bytes, i),
+            c if c.is_ascii_whitespace() || c == b')' || c == b']' => i += 1,
+            _ => {
+                let end = bytes[i..]
+                    .iter()
+                    .position(|&c| c.is_ascii_whitespace() || b"[]{};()".contains(&c))
+                    .map_or(bytes.len(), |n| i + n);
+                let token = &pgn[i..end];
+                game.in_movetext = true;
+                if matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*") {
+                    games.push(mem::take(&mut game).finish());
+                } else if let Some(san) = move_text(token) {
+                    game.sans.push(san);
+                }
+                i = end;
+            }
+        }
+    }
+    if !game.is_empty() {
+        games.push(game.finish());
+    }
+    games
+}
+
+/// Index just after the first `delimiter` at or after `start`.
+fn skip_past(bytes: &[u8], start: usize, delimiter: u8) -> usize {
+    bytes[start..]
+        .iter()
+        .position(|&c| c == delimiter)
+        .map_or(bytes.len(), |n| start + n + 1)
+}
+
+/// Index just after the variation opening at `start`, including nested
+/// variations and comments containing parentheses.
+fn skip_variation(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            b'{' => {
+                i = skip_past(bytes, i, b'}');
+                continue;
+            }
+            b';' => {
+                i = skip_past(bytes, i, b'\n');
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Index of the `]` closing the tag opening at `start`, or of the end of
+/// its line if it is not closed.
+fn tag_end(bytes: &[u8], start: usize) -> usize {
+    let mut quoted = false;
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if quoted => i += 1,
+            b'"' => quoted = !quoted,
+            b']' if !quoted => return i,
+            b'\n' => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Name and value of a tag pair such as `White "Carlsen, Magnus"`.
+fn parse_tag(inner: &str) -> Option<(String, String)> {
+    let inner = inner.trim();
+    let (name, rest) = inner.split_once(|c: char| c.is_whitespace())?;
+    let value = rest.trim().strip_prefix('"')?;
+    let value = value.strip_suffix('"').unwrap_or(value);
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    Some((name.to_string(), unescaped))
+}
+
+/// The move of a movetext token, without its move number and
+/// annotation glyphs; `None` for tokens that are not moves.
+fn move_text(token: &str) -> Option<String> {
+    if token.starts_with('$') {
+        return None;
+    }
+    let unnumbered = token.trim_start_matches(|c: char| c.is_ascii_digit());
+    let token = match unnumbered.strip_prefix('.') {
+        Some(rest) => rest.trim_start_matches('.'),
+        None => token,
+    };
+    let token = token.trim_end_matches(['!', '?']);
+    match token {
+        "" => None,
+        // Castling written with zeros.
+        "0-0" => Some("O-O".to_string()),
+        "0-0-0" => Some("O-O-O".to_string()),
+        token => Some(token.to_string()),
+    }
+}
+
+/// Replay `sans` from the position of the `FEN` tag, if any.
+fn replay(tags: &[(String, String)], sans: &[String]) -> Result<GameMoves, Error> {
+    let initial: Chess = match tags.iter().find(|(name, _)| name == "FEN") {
+        Some((_, fen)) => fen
+            .parse::<Fen>()
+            .map_err(|e| Error::invalid_fen(fen, e))?
+            .into_position(CastlingMode::Standard)?,
+        None => Chess::default(),
+    };
+    let mut pos = initial.clone();
+    let mut moves = Vec::with_capacity(sans.len());
+    for san in sans {
+        let m = san
+            .parse::<SanPlus>()
+            .ok()
+            .and_then(|san| san.san.to_move(&pos).ok())
+            .ok_or_else(|| Error::IllegalMove(san.clone()))?;
+        moves.push(m.to_uci(CastlingMode::Standard));
+        pos.play_unchecked(m);
+    }
+    Ok(GameMoves { initial, moves })
+}
+
+/// How much [`GameAnalysis::to_annotated_pgn`] writes after each move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum CommentVerbosity {
+    /// White's expected score after the move: `{ [%maia 0.62] }`.
+    Value,
+    /// The value and Maia's most likely moves in the position the move
+    /// was played in: `{ [%maia 0.62 e4:41% d4:22%] }`.
+    #[default]
+    TopMoves,
+    /// As [`TopMoves`](Self::TopMoves), followed by the change in White's
+    /// expected score: `{ [%maia 0.62 e4:41% d4:22%] swing -0.18 }`.
+    Swing,
+}
+
+/// Options of [`GameAnalysis::to_annotated_pgn`].
+#[derive(Debug, Clone)]
+pub struct AnnotationOptions {
+    pub verbosity: CommentVerbosity,
+    /// Moves listed from [`CommentVerbosity::TopMoves`] on.
+    pub top_moves: usize,
+    /// Thresholds of the NAGs, see [`GameAnalysis::judgements`].
+    pub accuracy: AccuracyConfig,
+    /// Longest line of movetext. Only a single token longer than this
+    /// goes past it.
+    pub line_width: usize,
+}
+
+impl Default for AnnotationOptions {
+    fn default() -> Self {
+        Self {
+            verbosity: CommentVerbosity::default(),
+            top_moves: 2,
+            accuracy: AccuracyConfig::default(),
+            // The limit of the PGN export format.
+            line_width: 80,
+        }
+    }
+}
+
+impl GameAnalysis {
+    /// The game as PGN, every move followed by Maia's evaluation in a
+    /// comment and, for inaccuracies, mistakes and blunders, the NAG of
+    /// its [`Judgement`](crate::analysis::Judgement).
+    ///
+    /// The [`tags`](Self::tags) are written as they were read; a game
+    /// without tags gets the seven tag roster with unknown values. A game
+    /// not starting from the standard position gets `SetUp` and `FEN`
+    /// tags unless it has a `FEN` tag already.
+    pub fn to_annotated_pgn(&self, options: &AnnotationOptions) -> String {
+        let game = self.game();
+        let positions = self.positions();
+        let result = self.result();
+
+        let mut tags = self.tags().to_vec();
+        if tags.is_empty() {
+            for name in ["Event", "Site", "Date", "Round", "White", "Black"] {
+                tags.push((name.to_string(), "?".to_string()));
+            }
+            tags.push(("Result".to_string(), result.to_string()));
+        }
+        let mut pgn = tag_section(&mut tags, &game.initial);
+
+        let judgements = self.judgements(&options.accuracy);
+        let mut tokens = Vec::new();
+        for (ply, uci) in game.moves.iter().enumerate() {
+            let pos = &positions[ply];
+            // Every move is followed by a comment, so Black's moves are
+            // numbered too.
+            tokens.push(match pos.turn() {
+                Color::White => format!("{}.", pos.fullmoves()),
+                Color::Black => format!("{}...", pos.fullmoves()),
+            });
+            tokens.push(san(pos, uci));
+            if let Some(judgement) = judgements[ply] {
+                tokens.push(format!("${}", judgement.nag()));
+            }
+
+            let score = self.expected_score(ply + 1, Color::White);
+            let mut command = format!("[%maia {score:.2}");
+            if options.verbosity >= CommentVerbosity::TopMoves {
+                for m in self.evaluations()[ply]
+                    .policy
+                    .iter()
+                    .take(options.top_moves)
+                {
+                    let percent = 100.0 * m.probability;
+                    command.push_str(&format!(" {}:{percent:.0}%", san(pos, &m.uci)));
+                }
+            }
+            command.push(']');
+            tokens.push("{".to_string());
+            tokens.push(comment_text(&command));
+            if options.verbosity >= CommentVerbosity::Swing {
+                let swing = score - self.expected_score(ply, Color::White);
+                tokens.push("swing".to_string());
+                tokens.push(format!("{swing:+.2}"));
+            }
+            tokens.push("}".to_string());
+        }
+        tokens.push(result.to_string());
+        pgn.push_str(&wrap(&tokens, options.line_width));
+        pgn
+    }
+
+    /// The `Result` tag if it holds a result, otherwise the result the
+    /// final position decides.
+    fn result(&self) -> &str {
+        if let Some((_, result)) = self.tags().iter().find(|(name, _)| name == "Result")
+            && matches!(result.as_str(), "1-0" | "0-1" | "1/2-1/2" | "*")
+        {
+            return result;
+        }
+        let last = self.positions().last().expect("at least one position");
+        match (Terminal::of(last), last.turn()) {
+            (Some(Terminal::Checkmate), Color::White) => "0-1",
+            (Some(Terminal::Checkmate), Color::Black) => "1-0",
+            (Some(Terminal::Stalemate), _) => "1/2-1/2",
+            (None, _) => "*",
+        }
+    }
+}
+
+impl GameRecord {
+    /// The game as PGN.
+    ///
+    /// White and Black are named after their ratings, e.g. `Maia-1500`,
+    /// with `WhiteElo` and `BlackElo` tags, the `Termination` tag holds
+    /// [`Termination::as_str`] and `MaiaSeed` the game's seed, with
+    /// `MaiaModel` for the model digest when known. With `evals`, each
+    /// recorded value is written as a `{ [%eval 0.35] }` comment in pawns
+    /// from White's side: one before the first move, then one after
+    /// every move whose resulting position was evaluated.
+    pub fn to_pgn(&self, evals: bool) -> String {
+        let name = |player: &PlayerConfig| format!("Maia-{}", player.elo_self.round());
+        let mut tags: Vec<(String, String)> = vec![
+            ("Event".into(), "Maia self-play".into()),
+            ("Site".into(), "?".into()),
+            ("Date".into(), "????.??.??".into()),
+            ("Round".into(), (self.index + 1).to_string()),
+            ("White".into(), name(&self.white)),
+            ("Black".into(), name(&self.black)),
+            ("Result".into(), self.game.outcome.as_str().into()),
+            ("WhiteElo".into(), self.white.elo_self.round().to_string()),
+            ("BlackElo".into(), self.black.elo_self.round().to_string()),
+            ("Termination".into(), self.game.termination.as_str().into()),
+            ("MaiaSeed".into(), self.seed.to_string()),
+        ];
+        if let Some(digest) = &self.model_sha256 {
+            tags.push(("MaiaModel".into(), digest.clone()));
+        }
+        let mut pgn = tag_section(&mut tags, &self.settings.start);
+
+        let eval = |ply: usize| {
+            self.game.values.get(ply).filter(|_| evals).map(|&value| {
+                let pawns = score_to_centipawns(value) as f32 / 100.0;
+                format!("{{ [%eval {pawns:.2}] }}")
+            })
+        };
+        let mut tokens: Vec<String> = eval(0).into_iter().collect();
+        let mut pos = self.settings.start.clone();
+        for (ply, uci) in self.game.moves.iter().enumerate() {
+            match pos.turn() {
+                Color::White => tokens.push(format!("{}.", pos.fullmoves())),
+                // Black's moves are numbered after a comment and at the
+                // start.
+                Color::Black if ply == 0 || eval(ply).is_some() => {
+                    tokens.push(format!("{}...", pos.fullmoves()));
+                }
+                Color::Black => {}
+            }
+            tokens.push(san(&pos, uci));
+            if let Ok(m) = uci.to_move(&pos) {
+                pos.play_unchecked(m);
+            }
+            tokens.extend(eval(ply + 1));
+        }
+        tokens.push(self.game.outcome.as_str().to_string());
+        pgn.push_str(&wrap(&tokens, 80));
+        pgn
+    }
+
+    /// Read a game written by [`to_pgn`](Self::to_pgn) back.
+    ///
+    /// Values are read from the `[%eval]` comments up to the first
+    /// position without one; mate scores count as won or lost. PGN does
+    /// not hold how the players sampled, so both come back as
+    /// [`PlayerConfig::new`] of their `WhiteElo` and `BlackElo`, 1500 if
+    /// missing, and the settings as the default ones from the game's
+    /// start. Without a `Termination` tag the final position decides it,
+    /// and otherwise a decisive game counts as resigned and a drawn one
+    /// as cut off at the ply cap.
+    ///
+    /// # Errors
+    /// Returns the error of the game's moves, see [`PgnGame::moves`], and
+    /// [`Error::UnfinishedGame`] if neither the `Result` tag nor the final
+    /// position gives a result.
+    pub fn from_pgn(game: PgnGame) -> Result<Self, Error> {
+        let PgnGame {
+            tags,
+            moves,
+            comments,
+        } = game;
+        let tag = |name: &str| {
+            tags.iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let moves = moves?;
+        let mut last = moves.initial.clone();
+        for uci in &moves.moves {
+            let m = uci
+                .to_move(&last)
+                .map_err(|_| Error::IllegalMove(uci.to_string()))?;
+            last.play_unchecked(m);
+        }
+
+        let terminal = Terminal::of(&last);
+        let outcome = match tag("Result").and_then(|r| r.parse::<KnownOutcome>().ok()) {
+            Some(outcome) => outcome,
+            None => match terminal {
+                Some(Terminal::Checkmate) => KnownOutcome::Decisive {
+                    winner: !last.turn(),
+                },
+                Some(Terminal::Stalemate) => KnownOutcome::Draw,
+                None => return Err(Error::UnfinishedGame),
+            },
+        };
+        let termination = Termination::ALL
+            .into_iter()
+            .find(|t| tag("Termination") == Some(t.as_str()))
+            .unwrap_or(match (terminal, outcome) {
+                (Some(Terminal::Checkmate), _) => Termination::Checkmate,
+                (Some(Terminal::Stalemate), _) => Termination::Stalemate,
+                (None, KnownOutcome::Decisive { .. }) => Termination::Resignation,
+                (None, KnownOutcome::Draw) => Termination::MaxPlies,
+            });
+        let values = comments.iter().map_while(|c| eval_value(c)).collect();
+        let player =
+            |name| PlayerConfig::new(tag(name).and_then(|e| e.parse().ok()).unwrap_or(1500.0));
+
+        Ok(Self {
+            index: tag("Round")
+                .and_then(|r| r.parse::<usize>().ok())
+                .map_or(0, |round| round.saturating_sub(1)),
+            white: player("WhiteElo"),
+            black: player("BlackElo"),
+            seed: tag("MaiaSeed").and_then(|s| s.parse().ok()).unwrap_or(0),
+            model_sha256: tag("MaiaModel").map(str::to_string),
+            settings: SelfPlayConfig {
+                start: moves.initial,
+                ..SelfPlayConfig::default()
+            },
+            game: GeneratedGame {
+                moves: moves.moves,
+                outcome,
+                termination,
+                values,
+            },
+        })
+    }
+}
+
+/// White's expected score in an `[%eval]` command of `comment`: pawns,
+/// or `#n` and `#-n` for a mate by White or Black.
+fn eval_value(comment: &str) -> Option<f32> {
+    let start = comment.find("[%eval ")? + "[%eval ".len();
+    let value = comment[start..].split([']', ' ', ',']).next()?;
+    match value.strip_prefix('#') {
+        Some(mate) if mate.starts_with('-') => Some(0.0),
+        Some(_) => Some(1.0),
+        None => {
+            let pawns: f32 = value.parse().ok()?;
+            Some(centipawns_to_score((pawns * 100.0).round() as i32))
+        }
+    }
+}
+
+/// The tag pairs of a game from `initial`, adding `SetUp` and `FEN`
+/// tags for a non-standard start without a `FEN` tag, followed by the
+/// empty line before the movetext.
+fn tag_section(tags: &mut Vec<(String, String)>, initial: &Chess) -> String {
+    let fen = Fen::from_position(initial, EnPassantMode::Legal).to_string();
+    if fen != Fen::from_position(&Chess::default(), EnPassantMode::Legal).to_string()
+        && !tags.iter().any(|(name, _)| name == "FEN")
+    {
+        tags.push(("SetUp".to_string(), "1".to_string()));
+        tags.push(("FEN".to_string(), fen));
+    }
+    let mut pgn = String::new();
+    for (name, value) in tags.iter() {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        pgn.push_str(&format!("[{name} \"{value}\"]\n"));
+    }
+    pgn.push('\n');
+    pgn
+}
+
+/// `uci` in SAN, or in UCI if it is not legal in `pos`.
+fn san(pos: &Chess, uci: &UciMove) -> String {
+    match uci.to_move(pos) {
+        Ok(m) => SanPlus::from_move(pos.clone(), m).to_string(),
+        Err(_) => uci.to_string(),
+    }
+}
+
+/// `text` made safe inside a comment. PGN has no escape for the `}`
+/// that ends a comment, so it is replaced.
+fn comment_text(text: &str) -> String {
+    text.replace('}', ")")
+}
+
+/// `tokens` separated by spaces, broken into lines of at most `width`
+/// characters.
+fn wrap(tokens: &[String], width: usize) -> String {
+    let mut text = String::new();
+    let mut line = 0;
+    for token in tokens {
+        if line > 0 && line + 1 + token.len() > width {
+            text.push('\n');
+            line = 0;
+        } else if line > 0 {
+            text.push(' ');
+            line += 1;
+        }
+        text.push_str(token);
+        line += token.len();
+    }
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PGN: &str = r#"[Event "Casual \"blitz\""]
+[White "A"]
+[Black "B"]
+[WhiteElo "1850"]
+
+1. e4 {best by test} e5 2. Nf3 (2. f4 exf4 (2... d5) 3. Nf3) 2... Nc6 $1
+3. Bb5!? a6 ; the Morphy defence
+4. Ba4 Nf6 5. 0-0 1-0
+
+[Event "Corrupt"]
+
+1. e4 e5 2. Ke3 Nc6 0-1
+
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]
+[SetUp "1"]
+
+1. e4 Kd7 *
+
+1. d4 d5
+"#;
+
+    fn uci(game: &PgnGame) -> Vec<String> {
+        let moves = game.moves.as_ref().unwrap();
+        moves.moves.iter().map(|m| m.to_string()).collect()
+    }
+
+    #[test]
+    fn games_are_read_independently() {
+        let games = parse_games(PGN);
+        assert_eq!(games.len(), 4);
+
+        assert_eq!(games[0].tag("Event"), Some("Casual \"blitz\""));
+        assert_eq!(games[0].tag("WhiteElo"), Some("1850"));
+        assert_eq!(
+            uci(&games[0]),
+            [
+                "e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6", "b5a4", "g8f6", "e1g1"
+            ]
+        );
+
+        assert!(matches!(&games[1].moves, Err(Error::IllegalMove(m)) if m == "Ke3"));
+        assert_eq!(games[1].tag("Event"), Some("Corrupt"));
+
+        let start = &games[2].moves.as_ref().unwrap().initial;
+        assert_eq!(start.board().occupied().count(), 3);
+        assert_eq!(uci(&games[2]), ["e2e4", "e8d7"]);
+
+        // A game cut off without a result is still read.
+        assert!(games[3].tags.is_empty());
+        assert_eq!(uci(&games[3]), ["d2d4", "d7d5"]);
+    }
+
+    #[test]
+    fn annotated_pgn_round_trips() {
+        use crate::{analysis::analyze_pgn_file, testing::ScriptedEvaluator};
+
+        let pgn = "[Event \"Odd \\\"name\\\" \\\\ here\"]\n[Result \"1-0\"]\n\n\
+                   1. e4 e5 2. Nf3 Nc6 3. Bc4 Nf6 4. O-O Bc5 1-0\n";
+        let mut evaluator =
+            ScriptedEvaluator(vec![0.5, 0.44, 0.4, 0.29, 0.45, 0.45, 0.45, 0.45, 0.45]);
+        let analyses =
+            analyze_pgn_file(&mut evaluator, pgn.as_bytes(), &Default::default()).unwrap();
+        let analysis = analyses[0].as_ref().unwrap();
+        let options = AnnotationOptions {
+            verbosity: CommentVerbosity::Swing,
+            line_width: 40,
+            ..AnnotationOptions::default()
+        };
+        let annotated = analysis.to_annotated_pgn(&options);
+
+        assert!(
+            annotated.lines().all(|line| line.len() <= 40),
+            "{annotated}"
+        );
+        assert!(annotated.contains("1. e4 $6 { [%maia 0.44 a3:5% b3:5%]\nswing -0.06 }"));
+        assert!(annotated.contains("2. Nf3 $2 {"));
+        assert!(annotated.contains("2... Nc6 $4 {"));
+
+        // Suggestions are in SAN like the moves played.
+        let every_move = analysis.to_annotated_pgn(&AnnotationOptions {
+            top_moves: usize::MAX,
+            ..AnnotationOptions::default()
+        });
+        assert!(every_move.contains(" Nxe5:3% "));
+        assert!(every_move.contains(" O-O:3%]"));
+        assert!(every_move.contains("4. O-O {"));
+
+        let read = parse_games(&annotated);
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].tags, analysis.tags());
+        assert_eq!(read[0].moves.as_ref().unwrap().moves, analysis.game().moves);
+        assert!(annotated.trim_end().ends_with("1-0"));
+    }
+
+    #[test]
+    fn untagged_games_get_the_roster() {
+        let mut pos: Chess = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let initial = pos.clone();
+        let m = "e2e4".parse::<UciMove>().unwrap();
+        pos.play_unchecked(m.to_move(&pos).unwrap());
+        let game = GameMoves {
+            initial,
+            moves: vec![m],
+        };
+        let analysis =
+            GameAnalysis::analyze(&mut crate::testing::UniformEvaluator, game, 1500.0, 1500.0)
+                .unwrap();
+        let annotated = analysis.to_annotated_pgn(&AnnotationOptions {
+            verbosity: CommentVerbosity::Value,
+            ..AnnotationOptions::default()
+        });
+        assert!(annotated.starts_with("[Event \"?\"]\n"));
+        assert!(annotated.contains(
+            "[Result \"*\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n"
+        ));
+        assert!(
+            annotated.ends_with("\n\n1. e4 { [%maia 0.55] } *\n"),
+            "{annotated}"
+        );
+        assert_eq!(comment_text("a } b"), "a ) b");
+    }
+
+    fn record(start: &str, moves: &[&str], outcome: KnownOutcome) -> GameRecord {
+        let start: Chess = start
+            .parse::<Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        GameRecord {
+            index: 2,
+            white: PlayerConfig::new(1500.0),
+            black: PlayerConfig::new(1900.0),
+            seed: 42,
+            settings: SelfPlayConfig {
+                start,
+                ..SelfPlayConfig::default()
+            },
+            model_sha256: None,
+            game: GeneratedGame {
+                moves: moves.iter().map(|m| m.parse().unwrap()).collect(),
+                outcome,
+                termination: Termination::MaxPlies,
+                values: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn game_records_round_trip() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let mut mate = record(
+            start,
+            &["f2f3", "e7e5", "g2g4", "d8h4"],
+            KnownOutcome::Decisive {
+                winner: Color::Black,
+            },
+        );
+        mate.game.termination = Termination::Checkmate;
+        mate.game.values = vec![0.5, 0.45, 0.3, 0.02];
+        mate.model_sha256 = Some("ab".repeat(32));
+        let pgn = mate.to_pgn(true);
+        assert!(pgn.starts_with("[Event \"Maia self-play\"]\n"), "{pgn}");
+        for tag in [
+            "[Round \"3\"]",
+            "[White \"Maia-1500\"]",
+            "[Black \"Maia-1900\"]",
+            "[Result \"0-1\"]",
+            "[Termination \"checkmate\"]",
+            "[MaiaSeed \"42\"]",
+        ] {
+            assert!(pgn.contains(tag), "{tag}");
+        }
+        assert!(!pgn.contains("[FEN"));
+        assert!(
+            pgn.ends_with(
+                "{ [%eval 0.00] } 1. f3 { [%eval -0.35] } 1... e5 { [%eval -1.47] } 2. g4\n\
+                 { [%eval -6.76] } 2... Qh4# 0-1\n"
+            ),
+            "{pgn}"
+        );
+
+        let read = GameRecord::from_pgn(parse_games(&pgn).remove(0)).unwrap();
+        assert_eq!(read.game.moves, mate.game.moves);
+        assert_eq!(read.game.outcome, mate.game.outcome);
+        assert_eq!(read.game.termination, Termination::Checkmate);
+        assert_eq!(read.game.values.len(), 4);
+        assert!(
+            read.game
+                .values
+                .iter()
+                .zip(&mate.game.values)
+                .all(|(a, b)| (a - b).abs() < 1e-3)
+        );
+        assert_eq!((read.index, read.seed), (2, 42));
+        assert_eq!(read.black.elo_self, 1900.0);
+        assert_eq!(read.model_sha256, mate.model_sha256);
+        // Without values nothing is written from them.
+        assert!(
+            mate.to_pgn(false)
+                .ends_with("\n\n1. f3 e5 2. g4 Qh4# 0-1\n")
+        );
+    }
+
+    #[test]
+    fn records_disambiguate_and_keep_their_start() {
+        // Both knights reach d2.
+        let start = "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1";
+        let game = record(start, &["b1d2", "e8d7", "f1e3"], KnownOutcome::Draw);
+        let pgn = game.to_pgn(true);
+        assert!(pgn.contains(&format!("[SetUp \"1\"]\n[FEN \"{start}\"]\n")));
+        assert!(pgn.contains("[Termination \"max plies\"]"));
+        assert!(pgn.ends_with("\n\n1. Nbd2 Kd7 2. Ne3 1/2-1/2\n"), "{pgn}");
+
+        let read = GameRecord::from_pgn(parse_games(&pgn).remove(0)).unwrap();
+        assert_eq!(read.game.moves, game.game.moves);
+        assert_eq!(read.game.termination, Termination::MaxPlies);
+        assert!(read.game.values.is_empty());
+        assert_eq!(
+            Fen::from_position(&read.settings.start, EnPassantMode::Legal).to_string(),
+            start
+        );
+
+        assert!(matches!(
+            GameRecord::from_pgn(parse_games("1. e4 e5 *").remove(0)),
+            Err(Error::UnfinishedGame)
+        ));
+        // A mate without tags still has its result.
+        let mated = GameRecord::from_pgn(parse_games("1. f3 e5 2. g4 Qh4#").remove(0)).unwrap();
+        assert_eq!(mated.game.termination, Termination::Checkmate);
+        assert_eq!(mated.game.outcome.winner(), Some(Color::Black));
+    }
+
+    #[test]
+    fn bad_fen_tags_fail_their_game() {
+        let games = parse_games("[FEN \"not a fen\"]\n\n1. e4 *\n\n1. e4 *\n");
+        assert!(
+            matches!(&games[0].moves, Err(Error::InvalidFen { fen, .. }) if fen == "not a fen")
+        );
+        assert_eq!(uci(&games[1]), ["e2e4"]);
+    }
+}
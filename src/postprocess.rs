@@ -0,0 +1,551 @@
+//! Conversion of raw Maia3 outputs into [`EvaluationResult`]s.
+//!
+//! This step is independent of the inference runtime: given the two
+//! output arrays of the network and the [`PreprocessedData`] produced by
+//! [`preprocess`](crate::preprocess), it maps logits back onto legal
+//! moves and normalizes both heads.
+
+use std::time::Duration;
+
+use ndarray::{ArrayView1, ArrayView2, Axis};
+use shakmaty::{Square, uci::UciMove};
+
+use crate::{
+    moves::MOVES_BY_INDEX,
+    tensor::{PreprocessedData, Validation},
+    types::{EvaluationResult, MoveProbability, Terminal},
+};
+
+/// How inputs are checked and raw outputs are turned into
+/// [`EvaluationResult`]s.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalOptions {
+    /// Sort each policy by descending probability. When false the policy
+    /// is left in legal-move-generation order, which saves the sort for
+    /// callers that re-index it anyway.
+    pub sorted: bool,
+    /// Which setups [`Maia`](crate::Maia) accepts, strict by default.
+    /// The free postprocessing functions ignore it; pass it to
+    /// [`preprocess_with`](crate::preprocess_with) instead.
+    pub validation: Validation,
+    /// Time each [`Maia`](crate::Maia) batch call may take, from its
+    /// start, before failing with [`Error::Timeout`](crate::Error::Timeout)
+    /// instead of returning late results. `None`, the default, waits as
+    /// long as inference takes.
+    ///
+    /// The ONNX Runtime backend terminates a run in progress when the
+    /// deadline passes, at the cost of a watchdog thread per call. Other
+    /// backends cannot be interrupted; the deadline is checked before
+    /// and after their run. A call evaluated in several runs checks it
+    /// between runs as well, with `completed` counting the positions of
+    /// the runs that finished.
+    pub deadline: Option<Duration>,
+    /// Smallest run [`Maia`](crate::Maia) splits a batch down to when the
+    /// execution provider runs out of memory, 1 by default.
+    ///
+    /// A run failing to allocate is retried in two halves, and the rest of
+    /// the call continues at the size that fit. A run of at most this
+    /// many positions that still fails returns the error. `None` disables
+    /// the retry.
+    pub oom_min_batch: Option<usize>,
+    /// Which heads of the network are read, both by default.
+    ///
+    /// The ONNX Runtime backend asks the session for the selected outputs
+    /// only, except with IO binding or a deadline and on the async and
+    /// custom `RunOptions` paths; whether ONNX Runtime then skips the
+    /// other head depends on the graph. Either way the head left out is not postprocessed: a
+    /// value-only result has an empty policy, a policy-only result NaN win,
+    /// draw and loss probabilities.
+    pub outputs: OutputSelection,
+}
+
+/// The heads of the network an evaluation reads, see
+/// [`EvalOptions::outputs`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputSelection {
+    #[default]
+    Both,
+    /// Only `logits_move`, e.g. for move ordering.
+    PolicyOnly,
+    /// Only `logits_value`, e.g. for evaluation sweeps.
+    ValueOnly,
+}
+
+impl OutputSelection {
+    /// Whether the policy head is read.
+    pub fn policy(self) -> bool {
+        self != Self::ValueOnly
+    }
+
+    /// Whether the value head is read.
+    pub fn value(self) -> bool {
+        self != Self::PolicyOnly
+    }
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        Self {
+            sorted: true,
+            validation: Validation::Strict,
+            deadline: None,
+            oom_min_batch: Some(1),
+            outputs: OutputSelection::Both,
+        }
+    }
+}
+
+/// Turn a batch of model outputs into evaluation results, with the
+/// default [`EvalOptions`].
+///
+/// `logits_move` has shape `[B, vocabulary]` and `logits_value` has
+/// shape `[B, 3]`, matching the `logits_move` and `logits_value` outputs
+/// of the Maia3 graph. Use this together with
+/// [`preprocess`](crate::preprocess) when running the model with a
+/// runtime other than the built-in ONNX Runtime session (for example
+/// onnxruntime-web when targeting wasm).
+///
+/// # Panics
+/// Panics if the arrays hold fewer rows than `data` has positions.
+pub fn postprocess(
+    logits_move: ArrayView2<f32>,
+    logits_value: ArrayView2<f32>,
+    data: &PreprocessedData,
+) -> Vec<EvaluationResult> {
+    postprocess_with_options(logits_move, logits_value, data, &EvalOptions::default())
+}
+
+/// [`postprocess`] with explicit options.
+///
+/// A head that [`EvalOptions::outputs`] leaves out is not read and may
+/// be empty.
+///
+/// # Panics
+/// Panics if the arrays hold fewer rows than `data` has positions.
+pub fn postprocess_with_options(
+    logits_move: ArrayView2<f32>,
+    logits_value: ArrayView2<f32>,
+    data: &PreprocessedData,
+    options: &EvalOptions,
+) -> Vec<EvaluationResult> {
+    let mut results = Vec::with_capacity(data.len());
+    postprocess_into(logits_move, logits_value, data, options, &mut results);
+    results
+}
+
+/// [`postprocess_with_options`] writing into `out`, which ends up with
+/// one result per position.
+///
+/// The allocations of `out` and of the policies of the results already
+/// in it are reused, so a caller passing the same vector for batches
+/// of similar size stops allocating after the first one.
+///
+/// # Panics
+/// Panics if the arrays hold fewer rows than `data` has positions.
+pub fn postprocess_into(
+    logits_move: ArrayView2<f32>,
+    logits_value: ArrayView2<f32>,
+    data: &PreprocessedData,
+    options: &EvalOptions,
+    out: &mut Vec<EvaluationResult>,
+) {
+    let batch_size = data.len();
+    out.truncate(batch_size);
+    out.reserve(batch_size - out.len());
+
+    for i in 0..batch_size {
+        let logits_for_item = options
+            .outputs
+            .policy()
+            .then(|| logits_move.index_axis(Axis(0), i));
+        let raw_wdl = options
+            .outputs
+            .value()
+            .then(|| logits_value.index_axis(Axis(0), i));
+        let policy = out
+            .get_mut(i)
+            .map(|r| std::mem::take(&mut r.policy))
+            .unwrap_or_default();
+
+        let result = process_output(
+            logits_for_item,
+            raw_wdl,
+            data.legal_move_indices(i),
+            data.mirrored[i],
+            data.outcome(i),
+            options,
+            policy,
+        );
+        match out.get_mut(i) {
+            Some(slot) => *slot = result,
+            None => out.push(result),
+        }
+    }
+}
+
+/// Convert raw model outputs to a structured [`EvaluationResult`].
+///
+/// `logits_move` contains unnormalized policy logits for all moves
+/// in the fixed Maia3 vocabulary. `raw_wdl` is a 3-logit vector
+/// ordered as loss/draw/win from side-to-move perspective. The policy
+/// is built in `policy`, whose contents are discarded. A head given as
+/// `None` leaves the policy empty or the probabilities NaN.
+fn process_output(
+    logits_move: Option<ArrayView1<f32>>,
+    raw_wdl: Option<ArrayView1<f32>>,
+    move_indices: &[u16],
+    mirrored: bool,
+    outcome: Option<Terminal>,
+    options: &EvalOptions,
+    mut policy: Vec<MoveProbability>,
+) -> EvaluationResult {
+    let (white_wr, draw, black_wr) = match raw_wdl {
+        Some(raw_wdl) => wdl_probabilities(raw_wdl, mirrored),
+        None => (f32::NAN, f32::NAN, f32::NAN),
+    };
+    policy.clear();
+    if let Some(logits_move) = logits_move {
+        fill_policy(&mut policy, logits_move, move_indices, mirrored, options);
+    }
+    EvaluationResult {
+        policy,
+        white_wr,
+        draw,
+        black_wr,
+        outcome,
+    }
+}
+
+/// White's win, draw and Black's win probabilities from loss/draw/win
+/// logits of the side to move.
+fn wdl_probabilities(raw_wdl: ArrayView1<f32>, mirrored: bool) -> (f32, f32, f32) {
+    // Convert L/D/W logits to probabilities.
+    let max_wdl = raw_wdl.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exp_l = (raw_wdl[0] - max_wdl).exp();
+    let exp_d = (raw_wdl[1] - max_wdl).exp();
+    let exp_w = (raw_wdl[2] - max_wdl).exp();
+    let sum_wdl = exp_l + exp_d + exp_w;
+    let mut loss_prob = exp_l / sum_wdl;
+    let draw_prob = exp_d / sum_wdl;
+    let mut win_prob = exp_w / sum_wdl;
+    if mirrored {
+        // For mirrored inputs (originally Black-to-move), the model's
+        // side-to-move W/L correspond to Black/White in the original
+        // position, so swap to get White/Black win rates.
+        std::mem::swap(&mut win_prob, &mut loss_prob);
+    }
+    (win_prob, draw_prob, loss_prob)
+}
+
+/// Fill the empty `policy` with the legal moves' probabilities, sorted
+/// as `options` ask.
+fn fill_policy(
+    policy: &mut Vec<MoveProbability>,
+    logits_move: ArrayView1<f32>,
+    move_indices: &[u16],
+    mirrored: bool,
+    options: &EvalOptions,
+) {
+    // The policy is the only allocation: it holds the logits first and
+    // is normalized in place.
+    let mut max_logit = f32::NEG_INFINITY;
+    policy.reserve(move_indices.len());
+    for &idx in move_indices {
+        let logit = logits_move[usize::from(idx)];
+        max_logit = max_logit.max(logit);
+
+        // If input was mirrored (because it was Black's turn), we must
+        // mirror the move back when reporting results.
+        let uci = MOVES_BY_INDEX[usize::from(idx)];
+        let uci = if mirrored { uci.to_mirrored() } else { uci };
+        policy.push(MoveProbability {
+            uci,
+            probability: logit,
+            index: idx,
+        });
+    }
+
+    // Apply Softmax, accumulating in f64 so that positions with hundreds
+    // of moves still sum to 1 after rounding back to f32.
+    let mut sum_exp = 0.0f64;
+    for m in policy.iter_mut() {
+        let exp = f64::from(m.probability - max_logit).exp();
+        sum_exp += exp;
+        m.probability = exp as f32;
+    }
+    for m in policy.iter_mut() {
+        m.probability = (f64::from(m.probability) / sum_exp) as f32;
+    }
+
+    if options.sorted {
+        sort_policy(policy);
+    }
+}
+
+/// Sort `policy` by descending probability, ties in UCI order, the order
+/// of [`EvaluationResult::policy`].
+pub(crate) fn sort_policy(policy: &mut [MoveProbability]) {
+    policy.sort_by(|a, b| {
+        b.probability
+            .total_cmp(&a.probability)
+            .then_with(|| uci_order(&a.uci).cmp(&uci_order(&b.uci)))
+    });
+}
+
+/// Sort key of a move that orders like its UCI string.
+fn uci_order(uci: &UciMove) -> (u8, u8, u8) {
+    // Squares compare by file letter, then rank digit.
+    let square = |sq: Square| u8::from(sq.file()) * 8 + u8::from(sq.rank());
+    match *uci {
+        UciMove::Normal {
+            from,
+            to,
+            promotion,
+        } => {
+            let promotion = promotion.map_or(0, |role| role.char() as u8);
+            (square(from), square(to), promotion)
+        }
+        UciMove::Put { role, to } => (0, square(to), role.upper_char() as u8),
+        UciMove::Null => (0, 0, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+    use shakmaty::{Chess, Color, EnPassantMode, Position, Setup, fen::Fen};
+
+    use super::*;
+    use crate::moves::ALL_MOVES;
+    use crate::tensor::preprocess;
+
+    #[test]
+    fn black_to_move_outputs_are_mirrored_back() {
+        // After 1. e4 the network sees the mirrored position, where
+        // Black's e7e5 appears as e2e4 and "win" means a Black win.
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+        let setup: Setup = fen.parse::<Fen>().unwrap().into_setup();
+        let (_, data) = preprocess(vec![setup], 1).unwrap();
+
+        let e2e4: UciMove = "e2e4".parse().unwrap();
+        let mut logits_move = Array2::<f32>::zeros((1, ALL_MOVES.len()));
+        logits_move[[0, ALL_MOVES[&e2e4]]] = 10.0;
+        let logits_value = Array2::from_shape_vec((1, 3), vec![0.0, 0.0, 5.0]).unwrap();
+
+        let results = postprocess(logits_move.view(), logits_value.view(), &data);
+        let result = &results[0];
+        assert_eq!(result.policy.len(), 20);
+        assert_eq!(result.policy[0].uci.to_string(), "e7e5");
+        // The index is that of the logit read, e2e4.
+        assert_eq!(usize::from(result.policy[0].index), ALL_MOVES[&e2e4]);
+        for m in &result.policy {
+            let reported = crate::moves::mirrored_index(m.index).unwrap();
+            assert_eq!(crate::moves::MOVES_BY_INDEX[usize::from(reported)], m.uci);
+        }
+        assert!(result.black_wr > result.white_wr);
+
+        let total: f32 = result.policy.iter().map(|m| m.probability).sum();
+        assert!((total - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn terminal_positions_report_their_outcome() {
+        let cases = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                None,
+            ),
+            // Fool's mate, White is mated.
+            (
+                "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3",
+                Some(Terminal::Checkmate),
+            ),
+            // Scholar's mate, Black is mated.
+            (
+                "r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4",
+                Some(Terminal::Checkmate),
+            ),
+            ("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", Some(Terminal::Stalemate)),
+            ("8/8/8/8/8/6k1/5q2/7K w - - 0 1", Some(Terminal::Stalemate)),
+        ];
+        let setups: Vec<Setup> = cases
+            .iter()
+            .map(|(fen, _)| fen.parse::<Fen>().unwrap().into_setup())
+            .collect();
+        let (_, data) = preprocess(setups, cases.len()).unwrap();
+        let logits_move = Array2::<f32>::zeros((cases.len(), ALL_MOVES.len()));
+        let logits_value = Array2::<f32>::zeros((cases.len(), 3));
+
+        let results = postprocess(logits_move.view(), logits_value.view(), &data);
+        for ((fen, expected), result) in cases.iter().zip(&results) {
+            assert_eq!(result.outcome, *expected, "{fen}");
+            assert_eq!(result.best_move().is_none(), expected.is_some(), "{fen}");
+        }
+    }
+
+    #[test]
+    fn best_move_ignores_policy_order() {
+        let mut logits_move = Array2::<f32>::zeros((1, ALL_MOVES.len()));
+        let d2d4: UciMove = "d2d4".parse().unwrap();
+        logits_move[[0, ALL_MOVES[&d2d4]]] = 3.0;
+        let logits_value = Array2::<f32>::zeros((1, 3));
+        let (_, data) = preprocess(vec![Setup::default()], 1).unwrap();
+
+        let unsorted = EvalOptions {
+            sorted: false,
+            ..EvalOptions::default()
+        };
+        let results =
+            postprocess_with_options(logits_move.view(), logits_value.view(), &data, &unsorted);
+        assert_ne!(results[0].policy[0].uci, d2d4);
+        assert_eq!(results[0].best_move().unwrap().uci, d2d4);
+    }
+
+    #[test]
+    fn value_white_is_independent_of_the_turn() {
+        // White is a queen and a rook up. With White to move the network
+        // predicts a win for the side to move, with Black to move a loss.
+        let setups: Vec<Setup> = ["w", "b"]
+            .iter()
+            .map(|turn| {
+                let fen = format!("6k1/8/8/8/8/8/5PPP/1QR3K1 {turn} - - 0 1");
+                fen.parse::<Fen>().unwrap().into_setup()
+            })
+            .collect();
+        let (_, data) = preprocess(setups, 2).unwrap();
+        let logits_move = Array2::<f32>::zeros((2, ALL_MOVES.len()));
+        let logits_value =
+            Array2::from_shape_vec((2, 3), vec![0.0, 1.0, 6.0, 6.0, 1.0, 0.0]).unwrap();
+
+        let results = postprocess(logits_move.view(), logits_value.view(), &data);
+        for result in &results {
+            assert!(result.value_white() > 0.9);
+            let black = result.expected_score(Color::Black);
+            assert!((result.value_white() + black - 1.0).abs() < 1e-6);
+        }
+        assert!((results[0].value_white() - results[1].value_white()).abs() < 1e-6);
+        assert!(results[1].expected_score(Color::Black) < 0.1);
+    }
+
+    #[test]
+    fn played_castling_and_promotions_are_found() {
+        let cases = [
+            // Both castlings, for each color; Black's go through the
+            // mirrored position.
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", ["e1g1", "e1c1"]),
+            ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", ["e8g8", "e8c8"]),
+            ("8/P6k/8/8/8/8/2p5/K7 w - - 0 1", ["a7a8q", "a7a8n"]),
+            ("8/P6k/8/8/8/8/2p5/K7 b - - 0 1", ["c2c1q", "c2c1r"]),
+        ];
+        for (fen, played) in cases {
+            let pos: Chess = fen
+                .parse::<Fen>()
+                .unwrap()
+                .into_position(shakmaty::CastlingMode::Standard)
+                .unwrap();
+            let (_, data) = preprocess(vec![pos.to_setup(EnPassantMode::Legal)], 1).unwrap();
+            let mut logits_move = Array2::<f32>::zeros((1, ALL_MOVES.len()));
+            let boosted: UciMove = played[0].parse().unwrap();
+            let boosted = if pos.turn().is_black() {
+                boosted.to_mirrored()
+            } else {
+                boosted
+            };
+            logits_move[[0, ALL_MOVES[&boosted]]] = 2.0;
+            let logits_value = Array2::<f32>::zeros((1, 3));
+            let result = &postprocess(logits_move.view(), logits_value.view(), &data)[0];
+
+            let map = result.to_map();
+            assert_eq!(map.len(), result.policy.len());
+            for (rank, uci) in [(1, played[0]), (2, played[1])] {
+                let uci: UciMove = uci.parse().unwrap();
+                let m = uci.to_move(&pos).unwrap();
+                let probability = result.probability_of_move(&m, &pos);
+                assert!(probability.is_some(), "{fen}: {uci}");
+                assert_eq!(probability, map.get(&uci).copied());
+                assert_eq!(result.rank_of(&uci), Some(rank), "{fen}: {uci}");
+            }
+        }
+    }
+
+    #[test]
+    fn tied_moves_are_ordered_by_uci() {
+        // Promotions and captures on the same squares tie with each
+        // other; Black to move checks the order after mirroring.
+        for fen in [
+            "1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/p7/1R2K3 b - - 0 1",
+        ] {
+            let setup: Setup = fen.parse::<Fen>().unwrap().into_setup();
+            let (_, data) = preprocess(vec![setup], 1).unwrap();
+            let logits_move = Array2::<f32>::zeros((1, ALL_MOVES.len()));
+            let logits_value = Array2::<f32>::zeros((1, 3));
+
+            let result = &postprocess(logits_move.view(), logits_value.view(), &data)[0];
+            let ucis: Vec<String> = result.policy.iter().map(|m| m.uci.to_string()).collect();
+            let mut sorted = ucis.clone();
+            sorted.sort();
+            assert_eq!(ucis, sorted, "{fen}");
+        }
+    }
+
+    #[test]
+    fn uci_order_matches_strings() {
+        let mut moves: Vec<UciMove> = ALL_MOVES.keys().copied().collect();
+        moves.sort_by_key(uci_order);
+        let strings: Vec<String> = moves.iter().map(ToString::to_string).collect();
+        let mut sorted = strings.clone();
+        sorted.sort();
+        assert_eq!(strings, sorted);
+    }
+
+    #[test]
+    fn unsorted_policy_keeps_generation_order() {
+        let setup = Setup::default();
+        let (_, data) = preprocess(vec![setup.clone()], 1).unwrap();
+        let mut logits_move = Array2::<f32>::zeros((1, ALL_MOVES.len()));
+        logits_move[[0, ALL_MOVES[&"e2e4".parse().unwrap()]]] = 3.0;
+        let logits_value = Array2::<f32>::zeros((1, 3));
+        let options = EvalOptions {
+            sorted: false,
+            ..EvalOptions::default()
+        };
+
+        let result =
+            &postprocess_with_options(logits_move.view(), logits_value.view(), &data, &options)[0];
+        let pos: Chess = setup.position(shakmaty::CastlingMode::Standard).unwrap();
+        let generated: Vec<UciMove> = pos
+            .legal_moves()
+            .iter()
+            .map(|m| m.to_uci(shakmaty::CastlingMode::Standard))
+            .collect();
+        let ucis: Vec<UciMove> = result.policy.iter().map(|m| m.uci).collect();
+        assert_eq!(ucis, generated);
+
+        let sorted = &postprocess(logits_move.view(), logits_value.view(), &data)[0];
+        assert_eq!(sorted.policy[0].uci.to_string(), "e2e4");
+    }
+
+    #[test]
+    fn promotion_heavy_policies_sum_to_one() {
+        // Promotion pushes and captures on every file, and the 218-move
+        // position with nine queens.
+        for fen in [
+            "1n1n1n2/PPPPPPP1/8/7k/8/8/8/KQ4Q1 w - - 0 1",
+            "R6R/3Q4/1Q4Q1/4Q3/2Q4Q/Q4Q2/pp1Q4/kBNN1KB1 w - - 0 1",
+        ] {
+            let setup: Setup = fen.parse::<Fen>().unwrap().into_setup();
+            let (_, data) = preprocess(vec![setup], 1).unwrap();
+            // Widely spread logits, so the terms differ by many orders of
+            // magnitude.
+            let logits_move =
+                Array2::from_shape_fn((1, ALL_MOVES.len()), |(_, j)| (j % 97) as f32 * 0.37 - 15.0);
+            let logits_value = Array2::<f32>::zeros((1, 3));
+
+            let result = &postprocess(logits_move.view(), logits_value.view(), &data)[0];
+            assert!(result.policy.len() > 60, "{fen}");
+            let total: f64 = result.policy.iter().map(|m| f64::from(m.probability)).sum();
+            assert!((total - 1.0).abs() < 1e-6, "{fen}: {total}");
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Identification of official Maia model files by their SHA-256.
+//!
+//! A wrong file is a common cause of odd outputs: a Maia1 network, an
+//! older export, a truncated download. [`verify_file`] and
+//! [`Maia::verify`] hash a model and look it up in [`KNOWN_MODELS`]. This
+//! only identifies models; files missing from the registry load and run
+//! as usual.
+
+use std::{fs::File, io::Read, path::Path};
+
+use hmac_sha256::Hash;
+
+use crate::error::Error;
+#[cfg(feature = "ort")]
+use crate::{Maia, backend::OrtBackend};
+
+/// An official model export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KnownModel {
+    /// Name of the export, e.g. `"maia2-rapid"`.
+    pub name: &'static str,
+    /// SHA-256 of the `.onnx` file, in lowercase hex.
+    pub sha256: &'static str,
+}
+
+/// Official exports, in release order.
+///
+/// Each digest is that of the file as published; [`model_sha256`]
+/// computes the value for a new entry.
+pub const KNOWN_MODELS: &[KnownModel] = &[];
+
+/// Where the model of an [`OrtBackend`] came from, for
+/// [`Maia::verify`].
+#[cfg(feature = "ort")]
+#[derive(Debug, Clone, Default)]
+pub(crate) enum ModelSource {
+    /// Built from a session; nothing to hash.
+    #[default]
+    Unknown,
+    File(std::path::PathBuf),
+    /// Loaded from memory, hashed while loading.
+    Sha256(String),
+}
+
+/// SHA-256 of `bytes`, in lowercase hex.
+#[cfg(feature = "ort")]
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    hex(Hash::hash(bytes))
+}
+
+fn hex(digest: [u8; 32]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// SHA-256 of the file at `path`, in lowercase hex, as reported by
+/// `sha256sum`.
+///
+/// # Errors
+/// Returns [`Error::Io`] if the file cannot be read.
+pub fn model_sha256(path: impl AsRef<Path>) -> Result<String, Error> {
+    let mut file = File::open(path)?;
+    let mut hash = Hash::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hex(hash.finalize()));
+        }
+        hash.update(&buffer[..read]);
+    }
+}
+
+/// The entry of `registry` with digest `sha256`.
+fn identify(sha256: &str, registry: &[KnownModel]) -> Option<KnownModel> {
+    registry
+        .iter()
+        .find(|model| model.sha256.eq_ignore_ascii_case(sha256))
+        .copied()
+}
+
+/// The known model stored at `path`, `None` for any other file.
+///
+/// # Errors
+/// Returns [`Error::Io`] if the file cannot be read.
+pub fn verify_file(path: impl AsRef<Path>) -> Result<Option<KnownModel>, Error> {
+    Ok(identify(&model_sha256(path)?, KNOWN_MODELS))
+}
+
+#[cfg(feature = "ort")]
+impl Maia<OrtBackend> {
+    /// The known model this instance runs, `None` if it is not one or its
+    /// origin is unknown.
+    ///
+    /// Models loaded from a file are identified by hashing the file as it
+    /// is now on disk, models loaded from memory by the digest taken while
+    /// loading. Models built with [`from_session`](Self::from_session)
+    /// have no known origin.
+    ///
+    /// # Errors
+    /// Returns [`Error::Io`] if the model file can no longer be read.
+    pub fn verify(&self) -> Result<Option<KnownModel>, Error> {
+        match self.backend().source() {
+            ModelSource::Unknown => Ok(None),
+            ModelSource::File(path) => verify_file(path),
+            ModelSource::Sha256(digest) => Ok(identify(digest, KNOWN_MODELS)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn digests_match_sha256sum() {
+        assert_eq!(
+            hex(Hash::hash(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let path = std::env::temp_dir().join(format!("maia-registry-{}.onnx", std::process::id()));
+        // Longer than one read, so the digest spans several updates.
+        let bytes: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        fs::write(&path, &bytes).unwrap();
+        let digest = model_sha256(&path).unwrap();
+        assert_eq!(digest, hex(Hash::hash(&bytes)));
+
+        // Unknown files are not an error.
+        assert_eq!(verify_file(&path).unwrap(), None);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(verify_file(&path), Err(Error::Io(_))));
+
+        let registry = [KnownModel {
+            name: "test",
+            sha256: "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD",
+        }];
+        assert_eq!(
+            identify(&hex(Hash::hash(b"abc")), &registry),
+            Some(registry[0])
+        );
+        assert_eq!(identify(&digest, &registry), None);
+    }
+}
@@ -0,0 +1,401 @@
+//! Output self-check against built-in reference positions.
+//!
+//! The references are positions with one clearly best move, a mate in
+//! one or a free queen, where any sound Maia model plays that move and
+//! expects the side to move to score well. A model broken by
+//! quantization, a mismatched export or wrong input types fails them;
+//! subtle drift needs a comparison against the original model instead.
+//!
+//! [`Maia::self_test`] checks a symmetry instead: a position and its
+//! color-mirrored twin must get mirrored results.
+
+use std::fmt;
+
+use shakmaty::{Color, Setup, fen::Fen, uci::UciMove};
+
+use crate::{Maia, backend::InferenceBackend, error::Error};
+
+/// Rating of both sides when evaluating the references.
+pub const SANITY_ELO: f32 = 1900.0;
+
+/// `(fen, expected top move, minimum expected score of the side to move)`.
+const REFERENCES: [(&str, &str, f32); 4] = [
+    // Scholar's mate, Qxf7#.
+    (
+        "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+        "h5f7",
+        0.8,
+    ),
+    // Fool's mate, Qh4#.
+    (
+        "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2",
+        "d8h4",
+        0.8,
+    ),
+    // The queen hangs to Nxg5.
+    (
+        "rnb1kbnr/pppp1ppp/8/4p1q1/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+        "f3g5",
+        0.75,
+    ),
+    // The queen hangs to Nxg4.
+    (
+        "rnbqkb1r/pppppppp/5n2/8/4P1Q1/8/PPPP1PPP/RNB1KBNR b KQkq - 2 2",
+        "f6g4",
+        0.75,
+    ),
+];
+
+/// Outcome of one reference position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SanityCheck {
+    pub fen: &'static str,
+    pub expected_move: UciMove,
+    /// The model's most likely move.
+    pub top_move: Option<UciMove>,
+    /// Probability the model gives the expected move.
+    pub expected_probability: f32,
+    /// Expected score of the side to move.
+    pub score: f32,
+    /// Lowest acceptable `score`.
+    pub min_score: f32,
+}
+
+impl SanityCheck {
+    /// Whether the model's top move and score both match the reference.
+    pub fn passed(&self) -> bool {
+        self.top_move == Some(self.expected_move) && self.score >= self.min_score
+    }
+}
+
+/// Results of [`Maia::sanity_check`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SanityReport {
+    pub checks: Vec<SanityCheck>,
+}
+
+impl SanityReport {
+    /// Whether every reference position passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(SanityCheck::passed)
+    }
+
+    /// The references that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &SanityCheck> {
+        self.checks.iter().filter(|c| !c.passed())
+    }
+}
+
+impl fmt::Display for SanityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed = self.failures().count();
+        writeln!(
+            f,
+            "{} of {} checks passed",
+            self.checks.len() - failed,
+            self.checks.len()
+        )?;
+        for check in self.failures() {
+            let top = check.top_move.map(|m| m.to_string());
+            writeln!(
+                f,
+                "{}: top move {} (expected {}, p={:.3}), score {:.3} (min {:.2})",
+                check.fen,
+                top.as_deref().unwrap_or("none"),
+                check.expected_move,
+                check.expected_probability,
+                check.score,
+                check.min_score,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Largest discrepancy [`SelfTestReport::passed`] accepts.
+pub const SELF_TEST_TOLERANCE: f32 = 1e-4;
+
+/// Symmetry of one position and its color-mirrored twin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirrorCheck {
+    pub fen: String,
+    /// Largest difference between the probability of a move and of its
+    /// mirror image in the twin; a move without one counts with its
+    /// full probability.
+    pub policy_discrepancy: f32,
+    /// Largest difference between a value probability and its
+    /// counterpart in the twin, White's win against Black's and the
+    /// draws against each other.
+    pub value_asymmetry: f32,
+}
+
+/// Results of [`Maia::self_test`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestReport {
+    pub checks: Vec<MirrorCheck>,
+}
+
+impl SelfTestReport {
+    /// The largest policy discrepancy of any position, 0 without any.
+    pub fn max_policy_discrepancy(&self) -> f32 {
+        self.checks
+            .iter()
+            .map(|c| c.policy_discrepancy)
+            .fold(0.0, f32::max)
+    }
+
+    /// The largest value asymmetry of any position, 0 without any.
+    pub fn max_value_asymmetry(&self) -> f32 {
+        self.checks
+            .iter()
+            .map(|c| c.value_asymmetry)
+            .fold(0.0, f32::max)
+    }
+
+    /// Whether both stay within [`SELF_TEST_TOLERANCE`].
+    pub fn passed(&self) -> bool {
+        self.max_policy_discrepancy() <= SELF_TEST_TOLERANCE
+            && self.max_value_asymmetry() <= SELF_TEST_TOLERANCE
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} positions: policy discrepancy {:.2e}, value asymmetry {:.2e}",
+            self.checks.len(),
+            self.max_policy_discrepancy(),
+            self.max_value_asymmetry()
+        )?;
+        for check in &self.checks {
+            if check.policy_discrepancy.max(check.value_asymmetry) > SELF_TEST_TOLERANCE {
+                writeln!(
+                    f,
+                    "{}: policy {:.2e}, value {:.2e}",
+                    check.fen, check.policy_discrepancy, check.value_asymmetry
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<B: InferenceBackend> Maia<B> {
+    /// Evaluate the built-in reference positions at [`SANITY_ELO`] and
+    /// report, per position, whether the model finds the obvious move
+    /// and expects the side to move to score well.
+    ///
+    /// Run this after loading a quantized or converted export; a failed
+    /// report means the conversion lost too much.
+    ///
+    /// # Errors
+    /// Propagates evaluation errors.
+    pub fn sanity_check(&mut self) -> Result<SanityReport, Error> {
+        let mut setups = Vec::with_capacity(REFERENCES.len());
+        for (fen, ..) in REFERENCES {
+            let parsed = Fen::from_ascii(fen.as_bytes()).map_err(|e| Error::invalid_fen(fen, e))?;
+            setups.push(parsed.into_setup());
+        }
+        let turns: Vec<Color> = setups.iter().map(|s| s.turn).collect();
+        let elos = [SANITY_ELO; REFERENCES.len()];
+        let results = self.batch_evaluate(setups, &elos, &elos)?;
+
+        let checks = REFERENCES
+            .iter()
+            .zip(turns)
+            .zip(results)
+            .map(|((&(fen, expected, min_score), turn), result)| {
+                let expected_move: UciMove = expected.parse().expect("valid reference move");
+                let expected_probability = result
+                    .policy
+                    .iter()
+                    .find(|m| m.uci == expected_move)
+                    .map_or(0.0, |m| m.probability);
+                SanityCheck {
+                    fen,
+                    expected_move,
+                    top_move: result.best_move().map(|m| m.uci),
+                    expected_probability,
+                    score: result.expected_score(turn),
+                    min_score,
+                }
+            })
+            .collect();
+        Ok(SanityReport { checks })
+    }
+
+    /// Evaluate every position together with its color-mirrored twin
+    /// (board flipped, colors, castling rights and en passant square
+    /// swapped) in one batch and report how far their results are from
+    /// mirror images of each other.
+    ///
+    /// Both sides of each pair rate [`SANITY_ELO`]. Since the network
+    /// always sees the side to move as White, a position and its twin
+    /// are the same input; a discrepancy means the mirroring of moves
+    /// and values around the model is off, or that the provider does
+    /// not give the same input the same output within a batch, as
+    /// fp16 or TensorRT kernels may. Run it on the positions of the
+    /// intended workload after switching exports or providers.
+    ///
+    /// # Errors
+    /// Propagates evaluation errors; an invalid position fails as
+    /// [`Error::AtIndex`] with its index in the doubled batch.
+    pub fn self_test(&mut self, positions: &[Setup]) -> Result<SelfTestReport, Error> {
+        let mut setups = Vec::with_capacity(positions.len() * 2);
+        for setup in positions {
+            let mut twin = setup.clone();
+            twin.mirror();
+            setups.push(setup.clone());
+            setups.push(twin);
+        }
+        let elos = vec![SANITY_ELO; setups.len()];
+        let results = self.batch_evaluate(setups, &elos, &elos)?;
+
+        let checks = positions
+            .iter()
+            .zip(results.chunks_exact(2))
+            .map(|(setup, pair)| {
+                let (result, twin) = (&pair[0], &pair[1]);
+                let policy_discrepancy = result
+                    .policy
+                    .iter()
+                    .map(|m| {
+                        let mirrored = m.uci.to_mirrored();
+                        let twin_probability = twin
+                            .policy
+                            .iter()
+                            .find(|t| t.uci == mirrored)
+                            .map_or(0.0, |t| t.probability);
+                        (m.probability - twin_probability).abs()
+                    })
+                    .fold(0.0, f32::max);
+                let value_asymmetry = [
+                    result.white_wr - twin.black_wr,
+                    result.draw - twin.draw,
+                    result.black_wr - twin.white_wr,
+                ]
+                .into_iter()
+                .map(f32::abs)
+                .fold(0.0, f32::max);
+                MirrorCheck {
+                    fen: Fen::try_from_setup(setup.clone())
+                        .unwrap_or_else(|e| e.ignore())
+                        .to_string(),
+                    policy_discrepancy,
+                    value_asymmetry,
+                }
+            })
+            .collect();
+        Ok(SelfTestReport { checks })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array2, Array3};
+    use shakmaty::{CastlingMode, Chess};
+
+    use super::*;
+    use crate::{backend::ModelOutputs, testing::ZeroBackend};
+
+    #[test]
+    fn references_are_legal() {
+        for (fen, expected, _) in REFERENCES {
+            let pos: Chess = fen
+                .parse::<Fen>()
+                .unwrap()
+                .into_position(CastlingMode::Standard)
+                .unwrap();
+            let uci: UciMove = expected.parse().unwrap();
+            assert!(uci.to_move(&pos).is_ok(), "{fen}: {expected}");
+        }
+    }
+
+    #[test]
+    fn flat_model_fails_the_check() {
+        let mut maia = Maia::from_backend(ZeroBackend::default());
+        let report = maia.sanity_check().unwrap();
+        assert_eq!(report.checks.len(), REFERENCES.len());
+        assert!(!report.passed());
+        // An even value falls short of every minimum score.
+        assert_eq!(report.failures().count(), REFERENCES.len());
+        assert!(report.to_string().starts_with("0 of 4 checks passed"));
+    }
+
+    fn positions() -> Vec<Setup> {
+        [
+            "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/6k1/8/8/8/8/p6K/8 b - - 0 1",
+        ]
+        .into_iter()
+        .map(|fen| fen.parse::<Fen>().unwrap().into_setup())
+        .collect()
+    }
+
+    /// Logits that depend on the input only, as from a sound provider.
+    struct InputBackend;
+
+    impl InferenceBackend for InputBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            _elo_self: &[f32],
+            _elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            let batch_size = tokens.shape()[0];
+            let pieces: Vec<f32> = tokens.outer_iter().map(|t| t.sum()).collect();
+            Ok(ModelOutputs {
+                logits_move: Array2::from_shape_fn(
+                    (batch_size, crate::moves::ALL_MOVES.len()),
+                    |(b, i)| ((i * 31) % 17) as f32 / pieces[b],
+                ),
+                logits_value: Array2::from_shape_fn((batch_size, 3), |(b, i)| {
+                    i as f32 * pieces[b] / 10.0
+                }),
+            })
+        }
+    }
+
+    /// Favors the first position of each batch, like a provider whose
+    /// results depend on the row.
+    struct RowBackend;
+
+    impl InferenceBackend for RowBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            elo_self: &[f32],
+            elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            let mut outputs = InputBackend.run(tokens, elo_self, elo_oppo)?;
+            outputs
+                .logits_value
+                .row_mut(0)
+                .assign(&ndarray::arr1(&[2.0, 0.0, 0.0]));
+            Ok(outputs)
+        }
+    }
+
+    #[test]
+    fn mirrored_twins_agree() {
+        let report = Maia::from_backend(InputBackend)
+            .self_test(&positions())
+            .unwrap();
+        assert_eq!(report.checks.len(), 3);
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.max_policy_discrepancy(), 0.0);
+    }
+
+    #[test]
+    fn row_dependent_outputs_break_the_symmetry() {
+        let report = Maia::from_backend(RowBackend)
+            .self_test(&positions())
+            .unwrap();
+        assert!(!report.passed());
+        assert!(report.checks[0].value_asymmetry > 0.1);
+        assert_eq!(report.checks[1].value_asymmetry, 0.0);
+        assert_eq!(report.max_policy_discrepancy(), 0.0);
+    }
+}
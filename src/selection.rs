@@ -0,0 +1,278 @@
+//! Ways to turn an evaluation into the move to play.
+//!
+//! A [`MoveSelector`] picks one move of an [`EvaluationResult`]'s
+//! policy. The strategies here cover the usual needs:
+//!
+//! | Selector | Plays |
+//! |---|---|
+//! | [`Greedy`] | the most likely move |
+//! | [`Proportional`] | a move drawn with its policy probability |
+//! | [`Temperature`] | a move drawn from the sharpened or flattened policy |
+//! | [`EpsilonGreedy`] | the most likely move, or now and then any move |
+//! | [`TopK`] | a move drawn from the `k` most likely |
+//! | [`BestQuality`] | the move with the best backed-up value |
+//! | [`SamplingParams`] | a move drawn as a player of some rating would |
+//!
+//! Every selector draws only from the RNG it is given, so a seeded RNG
+//! makes the choice reproducible, and returns `None` for an empty
+//! policy.
+
+use rand::{Rng, RngExt};
+use shakmaty::{CastlingMode, Chess, Move, uci::UciMove};
+
+use crate::{
+    error::Error,
+    humanize::SamplingParams,
+    types::{EvaluationResult, MoveProbability, MoveQuality, play},
+};
+
+/// A strategy for choosing a move from an evaluation.
+pub trait MoveSelector {
+    /// The move to play in the position `result` evaluates, `None` if the
+    /// policy is empty.
+    fn select(&self, result: &EvaluationResult, rng: &mut dyn Rng) -> Option<UciMove>;
+
+    /// Play the selected move in `pos`, the position `result` evaluates,
+    /// returning the move and the position after it.
+    ///
+    /// # Errors
+    /// As [`EvaluationResult::apply_top_move`].
+    fn apply(
+        &self,
+        result: &EvaluationResult,
+        pos: &Chess,
+        rng: &mut dyn Rng,
+    ) -> Result<(Move, Chess), Error> {
+        play(self.select(result, rng), pos)
+    }
+}
+
+/// The most likely move.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Greedy;
+
+impl MoveSelector for Greedy {
+    fn select(&self, result: &EvaluationResult, _rng: &mut dyn Rng) -> Option<UciMove> {
+        result.best_move().map(|m| m.uci)
+    }
+}
+
+/// A move drawn with its policy probability.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Proportional;
+
+impl MoveSelector for Proportional {
+    fn select(&self, result: &EvaluationResult, rng: &mut dyn Rng) -> Option<UciMove> {
+        weighted(result.policy.iter(), |m| m.probability, rng)
+    }
+}
+
+/// A move drawn with probabilities sharpened (below 1) or flattened
+/// (above 1), as [`EvaluationResult::sample_move`]. Zero or below plays
+/// the most likely move.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Temperature(pub f32);
+
+impl MoveSelector for Temperature {
+    fn select(&self, result: &EvaluationResult, mut rng: &mut dyn Rng) -> Option<UciMove> {
+        result.sample_move(self.0, &mut rng).map(|m| m.uci)
+    }
+}
+
+/// The most likely move, except with probability `epsilon` a uniformly
+/// chosen one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EpsilonGreedy {
+    pub epsilon: f32,
+}
+
+impl MoveSelector for EpsilonGreedy {
+    fn select(&self, result: &EvaluationResult, rng: &mut dyn Rng) -> Option<UciMove> {
+        if result.policy.is_empty() {
+            return None;
+        }
+        if rng.random::<f32>() < self.epsilon {
+            let i = rng.random_range(0..result.policy.len());
+            return Some(result.policy[i].uci);
+        }
+        Greedy.select(result, rng)
+    }
+}
+
+/// A move drawn, with its policy probability, from the `k` most likely
+/// ones. `k` of 0 is treated as 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopK {
+    pub k: usize,
+}
+
+impl MoveSelector for TopK {
+    fn select(&self, result: &EvaluationResult, rng: &mut dyn Rng) -> Option<UciMove> {
+        let mut moves: Vec<&MoveProbability> = result.policy.iter().collect();
+        // Stable, so moves of equal probability keep the policy order.
+        moves.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+        moves.truncate(self.k.max(1));
+        weighted(moves.into_iter(), |m| m.probability, rng)
+    }
+}
+
+/// The move with the highest backed-up value, from
+/// [`Maia::move_qualities`](crate::Maia::move_qualities) for the same
+/// position.
+///
+/// Moves of the policy without a quality are never chosen; when none of
+/// them has one this falls back to the most likely move. Equal values go
+/// to the likelier move.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BestQuality {
+    pub qualities: Vec<MoveQuality>,
+}
+
+impl MoveSelector for BestQuality {
+    fn select(&self, result: &EvaluationResult, rng: &mut dyn Rng) -> Option<UciMove> {
+        let mut best: Option<(UciMove, f32)> = None;
+        for m in &result.policy {
+            let q = self
+                .qualities
+                .iter()
+                .find(|quality| quality.mv.to_uci(CastlingMode::Standard) == m.uci)
+                .map(|quality| quality.q);
+            if let Some(q) = q
+                && best.is_none_or(|(_, best_q)| q > best_q)
+            {
+                best = Some((m.uci, q));
+            }
+        }
+        best.map(|(uci, _)| uci)
+            .or_else(|| Greedy.select(result, rng))
+    }
+}
+
+impl MoveSelector for SamplingParams {
+    fn select(&self, result: &EvaluationResult, mut rng: &mut dyn Rng) -> Option<UciMove> {
+        self.sample(result, &mut rng).map(|m| m.uci)
+    }
+}
+
+/// One of `moves` drawn in proportion to `weight`: the last one with a
+/// positive weight if rounding leaves the draw unspent, the first one if
+/// no weight is positive.
+fn weighted<'a>(
+    moves: impl Iterator<Item = &'a MoveProbability> + Clone,
+    weight: impl Fn(&MoveProbability) -> f32,
+    rng: &mut dyn Rng,
+) -> Option<UciMove> {
+    let total: f32 = moves.clone().map(&weight).sum();
+    let mut moves = moves.peekable();
+    let first = moves.peek()?.uci;
+    if total <= 0.0 {
+        return Some(first);
+    }
+    let mut target = rng.random::<f32>() * total;
+    let mut last = first;
+    for m in moves {
+        let w = weight(m);
+        if w > 0.0 {
+            last = m.uci;
+        }
+        target -= w;
+        if target <= 0.0 && w > 0.0 {
+            return Some(m.uci);
+        }
+    }
+    Some(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::testing::white_move;
+
+    fn result(probabilities: &[(&str, f32)]) -> EvaluationResult {
+        EvaluationResult {
+            policy: probabilities
+                .iter()
+                .map(|&(uci, p)| white_move(uci, p))
+                .collect(),
+            white_wr: 0.4,
+            draw: 0.3,
+            black_wr: 0.3,
+            outcome: None,
+        }
+    }
+
+    fn selectors() -> Vec<Box<dyn MoveSelector>> {
+        vec![
+            Box::new(Greedy),
+            Box::new(Proportional),
+            Box::new(Temperature(0.5)),
+            Box::new(EpsilonGreedy { epsilon: 0.5 }),
+            Box::new(TopK { k: 2 }),
+            Box::new(BestQuality {
+                qualities: Vec::new(),
+            }),
+            Box::new(SamplingParams::temperature(1.0)),
+        ]
+    }
+
+    #[test]
+    fn selectors_are_reproducible_and_handle_empty_policies() {
+        let r = result(&[("e2e4", 0.5), ("d2d4", 0.3), ("g1f3", 0.15), ("a2a3", 0.05)]);
+        let empty = result(&[]);
+        for selector in selectors() {
+            let picks = |seed| {
+                let mut rng = StdRng::seed_from_u64(seed);
+                (0..50)
+                    .map(|_| selector.select(&r, &mut rng).unwrap())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(picks(7), picks(7));
+            assert_eq!(selector.select(&empty, &mut StdRng::seed_from_u64(7)), None);
+            assert!(matches!(
+                selector.apply(&empty, &Chess::default(), &mut StdRng::seed_from_u64(7)),
+                Err(Error::Terminal)
+            ));
+        }
+    }
+
+    #[test]
+    fn strategies_choose_from_the_right_moves() {
+        let r = result(&[("e2e4", 0.5), ("d2d4", 0.3), ("g1f3", 0.15), ("a2a3", 0.05)]);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut draws = |selector: &dyn MoveSelector| {
+            (0..500)
+                .map(|_| selector.select(&r, &mut rng).unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(draws(&Greedy).iter().all(|m| m == "e2e4"));
+        assert!(draws(&Temperature(0.0)).iter().all(|m| m == "e2e4"));
+        let top2 = draws(&TopK { k: 2 });
+        assert!(top2.iter().all(|m| m == "e2e4" || m == "d2d4"));
+        assert!(top2.iter().any(|m| m == "d2d4"));
+        assert!(draws(&Proportional).iter().any(|m| m == "a2a3"));
+        let explored = draws(&EpsilonGreedy { epsilon: 0.2 });
+        let greedy = explored.iter().filter(|m| *m == "e2e4").count();
+        // 80% plus a quarter of the 20% uniform draws.
+        assert!((greedy as f32 / 500.0 - 0.85).abs() < 0.06, "{greedy}");
+
+        let pos = Chess::default();
+        let quality = |uci: &str, q| MoveQuality {
+            mv: uci.parse::<UciMove>().unwrap().to_move(&pos).unwrap(),
+            policy_prob: 0.0,
+            q,
+            visits_equivalent: 1,
+        };
+        let best = BestQuality {
+            qualities: vec![
+                quality("e2e4", 0.5),
+                quality("g1f3", 0.6),
+                quality("d2d4", 0.6),
+            ],
+        };
+        // The tie between Nf3 and d4 goes to the likelier d4.
+        assert!(draws(&best).iter().all(|m| m == "d2d4"));
+    }
+}
@@ -0,0 +1,859 @@
+//! Games played by Maia against itself.
+//!
+//! [`play_games`] plays any number of games at once, batching the
+//! positions of all running games into one evaluation per ply. Each side
+//! plays according to a [`PlayerConfig`]: the elo pair it conditions on
+//! and the [`SamplingParams`] it draws moves with, by default those of the
+//! [`HumanizationProfile`] at its rating. [`simulate_match`] builds a
+//! match between two configurations on top of it. Games that would drag
+//! on end by resignation, draw rules or a ply cap, see
+//! [`AdjudicationConfig`].
+//!
+//! For datasets, [`replay`] plays the games of a [`GenerationConfig`]
+//! with one seeded generator per game and returns [`GameRecord`]s that
+//! carry their settings, so any set of games, or a single one with
+//! [`replay_game`], can be regenerated exactly later.
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use shakmaty::{CastlingMode, Chess, Color, EnPassantMode, KnownOutcome, Position, uci::UciMove};
+
+use crate::{
+    analysis::{DrawStatus, DrawTracker},
+    error::Error,
+    evaluator::Evaluator,
+    humanize::{HumanizationProfile, SamplingParams},
+    selection::MoveSelector,
+    types::{EvaluationResult, Terminal, score_to_centipawns},
+};
+
+/// How one side plays.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerConfig {
+    /// Rating the side plays as.
+    pub elo_self: f32,
+    /// Rating the side assumes for its opponent.
+    pub elo_oppo: f32,
+    /// How moves are drawn from the policy.
+    pub sampling: SamplingParams,
+}
+
+impl PlayerConfig {
+    /// A player of rating `elo` facing an opponent of the same rating,
+    /// sampling as the default [`HumanizationProfile`] does at `elo`.
+    pub fn new(elo: f32) -> Self {
+        Self::with_profile(elo, &HumanizationProfile::default())
+    }
+
+    /// A player of rating `elo` facing an opponent of the same rating,
+    /// sampling as `profile` does at `elo`.
+    pub fn with_profile(elo: f32, profile: &HumanizationProfile) -> Self {
+        Self {
+            elo_self: elo,
+            elo_oppo: elo,
+            sampling: profile.params(elo),
+        }
+    }
+}
+
+/// Settings of [`play_games`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct SelfPlayConfig {
+    /// Position every game starts from, the standard one by default.
+    /// Serialized as FEN.
+    #[cfg_attr(feature = "serde", serde(with = "fen_string"))]
+    pub start: Chess,
+    /// When games end before checkmate or stalemate.
+    pub adjudication: AdjudicationConfig,
+}
+
+/// Rules ending self-play games that checkmate or stalemate would not
+/// end soon.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdjudicationConfig {
+    /// Expected score of the side to move below which it considers
+    /// resigning. `None` plays every game out.
+    pub resign_threshold: Option<f32>,
+    /// Consecutive own moves the score has to stay below
+    /// `resign_threshold` before the side resigns.
+    pub resign_moves: usize,
+    /// Draw by threefold repetition, the fifty-move rule and insufficient
+    /// material.
+    pub draw_rules: bool,
+    /// Games still running after this many plies end with `at_max_plies`.
+    pub max_plies: usize,
+    pub at_max_plies: PlyCapResult,
+}
+
+impl Default for AdjudicationConfig {
+    fn default() -> Self {
+        Self {
+            resign_threshold: Some(0.05),
+            resign_moves: 3,
+            draw_rules: true,
+            max_plies: 400,
+            at_max_plies: PlyCapResult::Draw,
+        }
+    }
+}
+
+/// Result of a game reaching [`AdjudicationConfig::max_plies`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlyCapResult {
+    Draw,
+    /// A win for the side whose expected score in the last evaluated
+    /// position exceeds 0.5 by more than `margin`, a draw otherwise.
+    ByValue {
+        margin: f32,
+    },
+}
+
+/// Why a generated game ended.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    InsufficientMaterial,
+    /// Fifty moves without capture or pawn move.
+    FiftyMoves,
+    /// The same position occurred for the third time.
+    Repetition,
+    /// The side to move stayed below
+    /// [`AdjudicationConfig::resign_threshold`] and resigned.
+    Resignation,
+    /// [`AdjudicationConfig::max_plies`] was reached.
+    MaxPlies,
+}
+
+impl Termination {
+    pub const ALL: [Self; 7] = [
+        Self::Checkmate,
+        Self::Stalemate,
+        Self::InsufficientMaterial,
+        Self::FiftyMoves,
+        Self::Repetition,
+        Self::Resignation,
+        Self::MaxPlies,
+    ];
+
+    /// Lowercase description, as written to the PGN `Termination` tag.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Checkmate => "checkmate",
+            Self::Stalemate => "stalemate",
+            Self::InsufficientMaterial => "insufficient material",
+            Self::FiftyMoves => "fifty moves",
+            Self::Repetition => "repetition",
+            Self::Resignation => "resignation",
+            Self::MaxPlies => "max plies",
+        }
+    }
+}
+
+/// A finished game.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedGame {
+    /// Moves from [`SelfPlayConfig::start`].
+    pub moves: Vec<UciMove>,
+    /// Result of the game, serialized as `"1-0"`, `"0-1"` or `"1/2-1/2"`.
+    #[cfg_attr(feature = "serde", serde(with = "outcome_string"))]
+    pub outcome: KnownOutcome,
+    /// White's expected score in each evaluated position, from the start:
+    /// the position before every move, and the one a side resigned in.
+    pub values: Vec<f32>,
+    /// How the game ended.
+    pub termination: Termination,
+}
+
+/// Where each game draws its moves' randomness from.
+enum GameRngs<'a> {
+    /// One generator shared by all games, used in pairing order within
+    /// each ply.
+    Shared(&'a mut dyn Rng),
+    /// One generator per game, so a game does not depend on the others.
+    PerGame(Vec<StdRng>),
+}
+
+impl GameRngs<'_> {
+    fn get(&mut self, game: usize) -> &mut dyn Rng {
+        match self {
+            Self::Shared(rng) => &mut **rng,
+            Self::PerGame(rngs) => &mut rngs[game],
+        }
+    }
+}
+
+/// A game in progress.
+struct RunningGame {
+    position: Chess,
+    moves: Vec<UciMove>,
+    draws: DrawTracker,
+    /// Draw status of `position`.
+    status: DrawStatus,
+    /// White's expected score in each evaluated position.
+    values: Vec<f32>,
+    /// Consecutive own moves White and Black spent below the resign
+    /// threshold.
+    losing_moves: [usize; 2],
+}
+
+impl RunningGame {
+    fn new(start: &Chess) -> Self {
+        let mut draws = DrawTracker::new();
+        Self {
+            status: draws.push(start),
+            position: start.clone(),
+            moves: Vec::new(),
+            draws,
+            values: Vec::new(),
+            losing_moves: [0; 2],
+        }
+    }
+
+    fn play(&mut self, uci: UciMove, next: Chess) {
+        self.moves.push(uci);
+        self.status = self.draws.push(&next);
+        self.position = next;
+    }
+
+    fn adjudicate(&self, config: &AdjudicationConfig) -> Option<(KnownOutcome, Termination)> {
+        let pos = &self.position;
+        if let Some(terminal) = Terminal::of(pos) {
+            Some(match terminal {
+                Terminal::Checkmate => {
+                    let winner = !pos.turn();
+                    (KnownOutcome::Decisive { winner }, Termination::Checkmate)
+                }
+                Terminal::Stalemate => (KnownOutcome::Draw, Termination::Stalemate),
+            })
+        } else if config.draw_rules && pos.is_insufficient_material() {
+            Some((KnownOutcome::Draw, Termination::InsufficientMaterial))
+        } else if config.draw_rules && self.status.halfmove_clock >= 100 {
+            Some((KnownOutcome::Draw, Termination::FiftyMoves))
+        } else if config.draw_rules && self.status.repetitions >= 3 {
+            Some((KnownOutcome::Draw, Termination::Repetition))
+        } else if self.moves.len() >= config.max_plies {
+            Some((
+                self.capped_outcome(config.at_max_plies),
+                Termination::MaxPlies,
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn capped_outcome(&self, result: PlyCapResult) -> KnownOutcome {
+        match (result, self.values.last().copied()) {
+            (PlyCapResult::ByValue { margin }, Some(score)) if score > 0.5 + margin => {
+                KnownOutcome::Decisive {
+                    winner: Color::White,
+                }
+            }
+            (PlyCapResult::ByValue { margin }, Some(score)) if score < 0.5 - margin => {
+                KnownOutcome::Decisive {
+                    winner: Color::Black,
+                }
+            }
+            _ => KnownOutcome::Draw,
+        }
+    }
+
+    /// Record the evaluation of the current position; true if the side
+    /// to move resigns.
+    fn observe(&mut self, result: &EvaluationResult, config: &AdjudicationConfig) -> bool {
+        self.values.push(result.value_white());
+        let turn = self.position.turn();
+        let losing = &mut self.losing_moves[turn.fold_wb(0, 1)];
+        match config.resign_threshold {
+            Some(threshold) if result.expected_score(turn) < threshold => *losing += 1,
+            _ => *losing = 0,
+        }
+        config.resign_threshold.is_some() && *losing >= config.resign_moves.max(1)
+    }
+}
+
+/// Play one game per `(white, black)` pairing from
+/// [`SelfPlayConfig::start`].
+///
+/// All running games are evaluated together, so throughput grows with
+/// the number of pairings up to the evaluator's efficient batch size.
+/// Games end by checkmate, stalemate or the rules of
+/// [`SelfPlayConfig::adjudication`]. Results are in pairing order.
+///
+/// # Errors
+/// Propagates evaluation errors; no partial results are returned.
+pub fn play_games(
+    evaluator: &mut impl Evaluator,
+    pairings: &[(PlayerConfig, PlayerConfig)],
+    config: &SelfPlayConfig,
+    rng: &mut impl Rng,
+) -> Result<Vec<GeneratedGame>, Error> {
+    run_games(evaluator, pairings, config, GameRngs::Shared(rng))
+}
+
+fn run_games(
+    evaluator: &mut impl Evaluator,
+    pairings: &[(PlayerConfig, PlayerConfig)],
+    config: &SelfPlayConfig,
+    mut rngs: GameRngs<'_>,
+) -> Result<Vec<GeneratedGame>, Error> {
+    let rules = &config.adjudication;
+    let mut running: Vec<RunningGame> = pairings
+        .iter()
+        .map(|_| RunningGame::new(&config.start))
+        .collect();
+    let mut finished: Vec<Option<GeneratedGame>> = vec![None; pairings.len()];
+
+    loop {
+        let mut active = Vec::new();
+        for (i, game) in running.iter().enumerate() {
+            if finished[i].is_some() {
+                continue;
+            }
+            match game.adjudicate(rules) {
+                Some((outcome, termination)) => {
+                    finished[i] = Some(GeneratedGame {
+                        moves: game.moves.clone(),
+                        values: game.values.clone(),
+                        outcome,
+                        termination,
+                    });
+                }
+                None => active.push(i),
+            }
+        }
+        if active.is_empty() {
+            break;
+        }
+
+        let players: Vec<&PlayerConfig> = active
+            .iter()
+            .map(|&i| {
+                let (white, black) = &pairings[i];
+                running[i].position.turn().fold_wb(white, black)
+            })
+            .collect();
+        let setups = active
+            .iter()
+            .map(|&i| running[i].position.to_setup(EnPassantMode::Legal))
+            .collect();
+        let elo_selfs: Vec<f32> = players.iter().map(|p| p.elo_self).collect();
+        let elo_oppos: Vec<f32> = players.iter().map(|p| p.elo_oppo).collect();
+        let results = evaluator.batch_evaluate(setups, &elo_selfs, &elo_oppos)?;
+
+        for ((&i, player), result) in active.iter().zip(&players).zip(&results) {
+            let game = &mut running[i];
+            if game.observe(result, rules) {
+                finished[i] = Some(GeneratedGame {
+                    moves: game.moves.clone(),
+                    values: game.values.clone(),
+                    outcome: KnownOutcome::Decisive {
+                        winner: !game.position.turn(),
+                    },
+                    termination: Termination::Resignation,
+                });
+                continue;
+            }
+            let (m, next) = player.sampling.apply(result, &game.position, rngs.get(i))?;
+            game.play(m.to_uci(CastlingMode::Standard), next);
+        }
+    }
+
+    Ok(finished.into_iter().map(Option::unwrap).collect())
+}
+
+/// Everything needed to generate a set of self-play games again.
+///
+/// Games are played by [`replay`]; with the same configuration and the
+/// same model they come out move for move the same, so a dataset can be
+/// regenerated from the configuration stored with it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct GenerationConfig {
+    /// `(white, black)` players, one game per pairing.
+    pub pairings: Vec<(PlayerConfig, PlayerConfig)>,
+    pub settings: SelfPlayConfig,
+    /// Seed from which every game's generator is derived, see
+    /// [`game_seed`].
+    pub seed: u64,
+    /// SHA-256 of the model the games were generated with, from
+    /// [`model_sha256`](crate::model_sha256). Only recorded; [`replay`]
+    /// has no way to check it against the evaluator.
+    pub model_sha256: Option<String>,
+}
+
+/// A generated game with the settings it was played with, enough for
+/// [`replay_game`] to play it again.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct GameRecord {
+    /// Position of the game in [`GenerationConfig::pairings`].
+    pub index: usize,
+    pub white: PlayerConfig,
+    pub black: PlayerConfig,
+    /// Seed of the game's own generator.
+    pub seed: u64,
+    pub settings: SelfPlayConfig,
+    pub model_sha256: Option<String>,
+    pub game: GeneratedGame,
+}
+
+/// Seed of the generator of game `index` of a run seeded with `seed`.
+///
+/// Each game samples from its own generator, so its moves depend only on
+/// its seed, its players and the model, not on which other games it was
+/// batched with.
+pub fn game_seed(seed: u64, index: usize) -> u64 {
+    // SplitMix64 of the index-th step from `seed`.
+    let mut z = seed.wrapping_add((index as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Play the games of `config`, one record per pairing in pairing order.
+///
+/// Every game draws from a generator seeded with [`game_seed`], and
+/// games are batched and sampled in pairing order, so a deterministic
+/// evaluator always yields the same games for the same configuration.
+///
+/// # Errors
+/// Propagates evaluation errors.
+pub fn replay(
+    evaluator: &mut impl Evaluator,
+    config: &GenerationConfig,
+) -> Result<Vec<GameRecord>, Error> {
+    let seeds: Vec<u64> = (0..config.pairings.len())
+        .map(|i| game_seed(config.seed, i))
+        .collect();
+    let rngs = seeds.iter().map(|&s| StdRng::seed_from_u64(s)).collect();
+    let games = run_games(
+        evaluator,
+        &config.pairings,
+        &config.settings,
+        GameRngs::PerGame(rngs),
+    )?;
+    Ok(games
+        .into_iter()
+        .zip(&config.pairings)
+        .zip(seeds)
+        .enumerate()
+        .map(|(index, ((game, (white, black)), seed))| GameRecord {
+            index,
+            white: white.clone(),
+            black: black.clone(),
+            seed,
+            settings: config.settings.clone(),
+            model_sha256: config.model_sha256.clone(),
+            game,
+        })
+        .collect())
+}
+
+/// Play the game of `record` again, on its own.
+///
+/// # Errors
+/// Propagates evaluation errors.
+pub fn replay_game(
+    evaluator: &mut impl Evaluator,
+    record: &GameRecord,
+) -> Result<GeneratedGame, Error> {
+    let pairings = [(record.white.clone(), record.black.clone())];
+    let rngs = vec![StdRng::seed_from_u64(record.seed)];
+    let mut games = run_games(
+        evaluator,
+        &pairings,
+        &record.settings,
+        GameRngs::PerGame(rngs),
+    )?;
+    Ok(games.remove(0))
+}
+
+/// Positions as FEN strings.
+#[cfg(feature = "serde")]
+mod fen_string {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as _};
+    use shakmaty::{CastlingMode, Chess, EnPassantMode, fen::Fen};
+
+    pub fn serialize<S: Serializer>(pos: &Chess, serializer: S) -> Result<S::Ok, S::Error> {
+        Fen::from_position(pos, EnPassantMode::Legal).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Chess, D::Error> {
+        Fen::deserialize(deserializer)?
+            .into_position(CastlingMode::Standard)
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Outcomes as `"1-0"`, `"0-1"` or `"1/2-1/2"`.
+#[cfg(feature = "serde")]
+mod outcome_string {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error as _};
+    use shakmaty::KnownOutcome;
+
+    pub fn serialize<S: Serializer>(
+        outcome: &KnownOutcome,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(outcome.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<KnownOutcome, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Outcome of [`simulate_match`], counted from player A's side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MatchResult {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl MatchResult {
+    /// Number of games played.
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// A's score as a fraction of the games, counting draws as half.
+    pub fn score(&self) -> f32 {
+        if self.games() == 0 {
+            return 0.5;
+        }
+        (self.wins as f32 + 0.5 * self.draws as f32) / self.games() as f32
+    }
+
+    /// Half-width of the 95% confidence interval of [`score`](Self::score),
+    /// from the normal approximation to the per-game score distribution.
+    pub fn score_margin(&self) -> f32 {
+        let n = self.games() as f32;
+        if n < 2.0 {
+            return 0.5;
+        }
+        let mean = self.score();
+        let variance = (self.wins as f32 * (1.0 - mean).powi(2)
+            + self.draws as f32 * (0.5 - mean).powi(2)
+            + self.losses as f32 * mean.powi(2))
+            / (n - 1.0);
+        1.96 * (variance / n).sqrt()
+    }
+
+    /// Estimated elo difference of A over B with its 95% interval, as
+    /// `(estimate, lower, upper)`.
+    ///
+    /// Uses the logistic elo model; scores of 0 or 1 are clamped, so a
+    /// clean sweep reports a large but finite difference.
+    pub fn elo_difference(&self) -> (f32, f32, f32) {
+        let score = self.score();
+        let margin = self.score_margin();
+        // Elo and the centipawn scale share the same logistic curve.
+        let elo = |s: f32| score_to_centipawns(s) as f32;
+        (elo(score), elo(score - margin), elo(score + margin))
+    }
+}
+
+/// Play `n_games` between configurations A and B, alternating colors
+/// (A is White in even games), and count the results from A's side.
+///
+/// Sampling is seeded with `seed`, so a match is reproducible with a
+/// deterministic evaluator. Games use the default [`SelfPlayConfig`].
+///
+/// # Errors
+/// Propagates evaluation errors.
+pub fn simulate_match(
+    evaluator: &mut impl Evaluator,
+    config_a: &PlayerConfig,
+    config_b: &PlayerConfig,
+    n_games: usize,
+    seed: u64,
+) -> Result<MatchResult, Error> {
+    simulate_match_with(
+        evaluator,
+        config_a,
+        config_b,
+        n_games,
+        seed,
+        &SelfPlayConfig::default(),
+    )
+}
+
+/// [`simulate_match`] with games started and adjudicated per `config`.
+///
+/// # Errors
+/// Propagates evaluation errors.
+pub fn simulate_match_with(
+    evaluator: &mut impl Evaluator,
+    config_a: &PlayerConfig,
+    config_b: &PlayerConfig,
+    n_games: usize,
+    seed: u64,
+    config: &SelfPlayConfig,
+) -> Result<MatchResult, Error> {
+    let pairings: Vec<(PlayerConfig, PlayerConfig)> = (0..n_games)
+        .map(|i| match i % 2 {
+            0 => (config_a.clone(), config_b.clone()),
+            _ => (config_b.clone(), config_a.clone()),
+        })
+        .collect();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let games = play_games(evaluator, &pairings, config, &mut rng)?;
+
+    let mut result = MatchResult::default();
+    for (i, game) in games.iter().enumerate() {
+        let a_color = if i % 2 == 0 {
+            Color::White
+        } else {
+            Color::Black
+        };
+        match game.outcome {
+            KnownOutcome::Decisive { winner } if winner == a_color => result.wins += 1,
+            KnownOutcome::Decisive { .. } => result.losses += 1,
+            KnownOutcome::Draw => result.draws += 1,
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::{Role, Setup, fen::Fen};
+
+    use super::*;
+    use crate::testing::UniformEvaluator;
+
+    /// Uniform policy, with the side ahead in material nearly sure to win.
+    struct MaterialEvaluator;
+
+    impl Evaluator for MaterialEvaluator {
+        fn batch_evaluate(
+            &mut self,
+            setups: Vec<Setup>,
+            elo_selfs: &[f32],
+            elo_oppos: &[f32],
+        ) -> Result<Vec<EvaluationResult>, Error> {
+            let balances: Vec<i32> = setups
+                .iter()
+                .map(|s| {
+                    let value = |color| {
+                        [(Role::Pawn, 1), (Role::Knight, 3), (Role::Bishop, 3)]
+                            .into_iter()
+                            .chain([(Role::Rook, 5), (Role::Queen, 9)])
+                            .map(|(role, v)| v * s.board.by_piece(role.of(color)).count() as i32)
+                            .sum::<i32>()
+                    };
+                    value(Color::White) - value(Color::Black)
+                })
+                .collect();
+            let mut results = UniformEvaluator.batch_evaluate(setups, elo_selfs, elo_oppos)?;
+            for (result, balance) in results.iter_mut().zip(balances) {
+                result.white_wr = 0.5 + 0.48 * balance.signum() as f32;
+                result.draw = 0.0;
+                result.black_wr = 1.0 - result.white_wr;
+            }
+            Ok(results)
+        }
+    }
+
+    fn rook_ending() -> SelfPlayConfig {
+        SelfPlayConfig {
+            start: "8/8/4k3/8/8/8/8/R3K3 w - - 0 1"
+                .parse::<Fen>()
+                .unwrap()
+                .into_position(CastlingMode::Standard)
+                .unwrap(),
+            ..SelfPlayConfig::default()
+        }
+    }
+
+    #[test]
+    fn lost_endings_are_resigned() {
+        let pairings = vec![(PlayerConfig::new(1500.0), PlayerConfig::new(1500.0))];
+        let config = rook_ending();
+        let mut rng = StdRng::seed_from_u64(3);
+        let games = play_games(&mut MaterialEvaluator, &pairings, &config, &mut rng).unwrap();
+
+        let game = &games[0];
+        assert_eq!(game.termination, Termination::Resignation);
+        assert_eq!(
+            game.outcome,
+            KnownOutcome::Decisive {
+                winner: Color::White
+            }
+        );
+        // Black resigns instead of making its third move.
+        assert_eq!(game.moves.len(), 5);
+    }
+
+    #[test]
+    fn the_ply_cap_can_adjudicate_by_value() {
+        let pairings = vec![(PlayerConfig::new(1500.0), PlayerConfig::new(1500.0))];
+        let mut config = rook_ending();
+        config.adjudication = AdjudicationConfig {
+            resign_threshold: None,
+            max_plies: 6,
+            at_max_plies: PlyCapResult::ByValue { margin: 0.2 },
+            ..AdjudicationConfig::default()
+        };
+        let mut rng = StdRng::seed_from_u64(3);
+        let games = play_games(&mut MaterialEvaluator, &pairings, &config, &mut rng).unwrap();
+        assert_eq!(games[0].termination, Termination::MaxPlies);
+        assert_eq!(games[0].moves.len(), 6);
+        assert_eq!(
+            games[0].outcome,
+            KnownOutcome::Decisive {
+                winner: Color::White
+            }
+        );
+
+        config.adjudication.at_max_plies = PlyCapResult::Draw;
+        let games = play_games(&mut MaterialEvaluator, &pairings, &config, &mut rng).unwrap();
+        assert_eq!(games[0].outcome, KnownOutcome::Draw);
+    }
+
+    #[test]
+    fn games_end_by_rule_and_replay_with_the_same_seed() {
+        let pairings = vec![(PlayerConfig::new(1500.0), PlayerConfig::new(1900.0)); 4];
+        let config = SelfPlayConfig {
+            adjudication: AdjudicationConfig {
+                max_plies: 60,
+                ..AdjudicationConfig::default()
+            },
+            ..SelfPlayConfig::default()
+        };
+
+        let play = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            play_games(&mut UniformEvaluator, &pairings, &config, &mut rng).unwrap()
+        };
+        let games = play(7);
+
+        assert_eq!(games.len(), 4);
+        for game in &games {
+            assert!(game.moves.len() <= 60);
+            if game.termination == Termination::MaxPlies {
+                assert_eq!(game.outcome, KnownOutcome::Draw);
+            }
+        }
+        let replay = play(7);
+        assert!(games.iter().zip(&replay).all(|(a, b)| a.moves == b.moves));
+    }
+
+    #[test]
+    fn generation_replays_game_by_game() {
+        let config = GenerationConfig {
+            pairings: vec![
+                (PlayerConfig::new(1200.0), PlayerConfig::new(1600.0)),
+                (PlayerConfig::new(1900.0), PlayerConfig::new(1500.0)),
+                (PlayerConfig::new(1500.0), PlayerConfig::new(1500.0)),
+            ],
+            settings: SelfPlayConfig {
+                adjudication: AdjudicationConfig {
+                    max_plies: 40,
+                    ..AdjudicationConfig::default()
+                },
+                ..SelfPlayConfig::default()
+            },
+            seed: 11,
+            model_sha256: None,
+        };
+        let records = replay(&mut UniformEvaluator, &config).unwrap();
+        let again = replay(&mut UniformEvaluator, &config).unwrap();
+        assert!(
+            records
+                .iter()
+                .zip(&again)
+                .all(|(a, b)| a.game.moves == b.game.moves)
+        );
+        // Games sample independently, so they differ from each other and
+        // replay alone as they were played in the batch.
+        assert_ne!(records[0].game.moves, records[2].game.moves);
+        for record in &records {
+            assert_eq!(record.seed, game_seed(11, record.index));
+            assert_eq!(
+                replay_game(&mut UniformEvaluator, record).unwrap(),
+                record.game
+            );
+        }
+        let reseeded = replay(
+            &mut UniformEvaluator,
+            &GenerationConfig {
+                seed: 12,
+                ..config.clone()
+            },
+        )
+        .unwrap();
+        assert_ne!(reseeded[0].game.moves, records[0].game.moves);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn generation_configs_round_trip_through_json() {
+        let config = GenerationConfig {
+            pairings: vec![(PlayerConfig::new(1500.0), PlayerConfig::new(1700.0))],
+            settings: rook_ending(),
+            seed: 5,
+            model_sha256: Some("ab".repeat(32)),
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains("\"8/8/4k3/8/8/8/8/R3K3 w - - 0 1\""));
+        let read: GenerationConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.pairings, config.pairings);
+        assert_eq!(read.settings.adjudication, config.settings.adjudication);
+        assert_eq!(read.model_sha256, config.model_sha256);
+
+        let record = &replay(&mut MaterialEvaluator, &read).unwrap()[0];
+        let json = serde_json::to_string(record).unwrap();
+        assert!(json.contains("\"outcome\":\"1-0\""));
+        let read: GameRecord = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.game, record.game);
+        assert_eq!(
+            replay_game(&mut MaterialEvaluator, &read).unwrap(),
+            record.game
+        );
+    }
+
+    #[test]
+    fn match_statistics() {
+        let result = MatchResult {
+            wins: 6,
+            draws: 2,
+            losses: 2,
+        };
+        assert_eq!(result.games(), 10);
+        assert!((result.score() - 0.7).abs() < 1e-6);
+
+        let (elo, lower, upper) = result.elo_difference();
+        assert_eq!(elo, 147.0);
+        assert!(lower < elo && elo < upper);
+
+        let even = MatchResult {
+            wins: 5,
+            draws: 0,
+            losses: 5,
+        };
+        assert_eq!(even.elo_difference().0, 0.0);
+    }
+
+    #[test]
+    fn simulate_match_counts_every_game() {
+        let a = PlayerConfig::new(1500.0);
+        let b = PlayerConfig {
+            elo_oppo: 1900.0,
+            ..PlayerConfig::new(1500.0)
+        };
+        let result = simulate_match(&mut UniformEvaluator, &a, &b, 3, 1).unwrap();
+        assert_eq!(result.games(), 3);
+    }
+}
@@ -0,0 +1,927 @@
+//! Dynamic batching on a background thread.
+//!
+//! [`MaiaService`] moves a [`Maia`] onto a worker thread and lets any
+//! number of callers submit single positions. The worker coalesces
+//! requests that arrive close together into one `batch_evaluate` call,
+//! so many small concurrent requests (e.g. from an HTTP server) share
+//! forward passes instead of running one by one.
+//!
+//! Requests carry a [`Priority`]. Each batch takes the waiting
+//! high-priority requests first and fills the remaining room with
+//! normal, then background ones, so an interactive request overtakes a
+//! long background queue. Once a lower-priority request has been passed
+//! over for [`ServiceConfig::max_skipped_batches`] batches, every batch
+//! starts with the longest-waiting such request, so background work
+//! keeps moving under constant high-priority load.
+//!
+//! The queue is unbounded by default. With
+//! [`ServiceConfig::max_queue_depth`] set, a full queue either blocks
+//! submitters until the worker takes requests out or rejects them with
+//! [`Error::Overloaded`], per [`OverloadPolicy`]; [`MaiaService::stats`]
+//! reports how deep the queue is and has been.
+//!
+//! [`MaiaService::shutdown`] stops a service explicitly: it refuses new
+//! requests, drains or cancels the queued ones per
+//! [`ShutdownPolicy`], and joins the worker, which drops the model on
+//! its own thread before the call returns. Dropping the last handle
+//! does the same without a time limit.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Condvar, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use shakmaty::Setup;
+
+use crate::{
+    Maia, backend::InferenceBackend, error::Error, input::TryIntoSetup, types::EvaluationResult,
+};
+
+/// Batching limits for [`MaiaService`].
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    /// Largest batch handed to the model at once.
+    pub max_batch_size: usize,
+    /// How long the worker waits for more requests after the first one
+    /// of a batch arrives.
+    pub max_delay: Duration,
+    /// How many batches may pass over a waiting normal or background
+    /// request before it is given the first slot of the next one.
+    pub max_skipped_batches: usize,
+    /// Most requests waiting for a batch at once, unbounded if `None`
+    /// (the default).
+    pub max_queue_depth: Option<usize>,
+    /// What submitting to a full queue does.
+    pub overload: OverloadPolicy,
+    /// What happens to queued requests at shutdown.
+    pub shutdown: ShutdownPolicy,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 256,
+            max_delay: Duration::from_millis(2),
+            max_skipped_batches: 8,
+            max_queue_depth: None,
+            overload: OverloadPolicy::Block,
+            shutdown: ShutdownPolicy::Drain,
+        }
+    }
+}
+
+/// What [`MaiaService::submit`] does when the queue holds
+/// [`ServiceConfig::max_queue_depth`] requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    /// Wait until the worker takes requests out of the queue.
+    #[default]
+    Block,
+    /// Fail the request with [`Error::Overloaded`] right away.
+    Reject,
+}
+
+/// What [`MaiaService::shutdown`] does with the requests still queued.
+/// The batch running at the time always finishes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShutdownPolicy {
+    /// Keep evaluating them until the timeout, then cancel the rest.
+    #[default]
+    Drain,
+    /// Cancel them at once.
+    Cancel,
+}
+
+/// Queue statistics of a [`MaiaService`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServiceStats {
+    /// Requests submitted and not yet handed to the model.
+    pub queue_depth: usize,
+    /// Deepest the queue has been.
+    pub high_water_mark: usize,
+}
+
+/// Urgency of a request to a [`MaiaService`], see the
+/// [module docs](self).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Served before everything else, e.g. the position on screen.
+    High,
+    #[default]
+    Normal,
+    /// Served with the room high and normal requests leave, e.g.
+    /// whole-game analysis.
+    Background,
+}
+
+struct Job {
+    setup: Setup,
+    elo_self: f32,
+    elo_oppo: f32,
+    priority: Priority,
+    reply: Sender<Result<EvaluationResult, Error>>,
+    #[cfg(feature = "metrics")]
+    queued_at: Instant,
+}
+
+/// Cloneable handle to a background evaluation worker.
+///
+/// The worker thread exits when [`shutdown`](Self::shutdown) is called
+/// or the last handle is dropped, which waits for it as `shutdown`
+/// without a timeout does.
+#[derive(Clone)]
+pub struct MaiaService {
+    inner: Arc<ServiceHandle>,
+}
+
+/// What the handles of one service share.
+struct ServiceHandle {
+    /// `None` once the service shuts down.
+    sender: Mutex<Option<Sender<Job>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+    queue: Arc<QueueDepth>,
+    shutdown: ShutdownPolicy,
+}
+
+impl ServiceHandle {
+    /// Refuse new requests, let the worker drain the queue for
+    /// `timeout` (forever if `None`) and wait for it to exit.
+    fn shut_down(&self, timeout: Option<Duration>) {
+        let now = Instant::now();
+        let cancel_at = match self.shutdown {
+            ShutdownPolicy::Drain => timeout.and_then(|t| now.checked_add(t)),
+            ShutdownPolicy::Cancel => Some(now),
+        };
+        self.queue.close(cancel_at);
+        // Without a sender the worker sees the end of the channel once
+        // it has taken the requests sent so far.
+        self.sender.lock().unwrap().take();
+        let worker = self.worker.lock().unwrap().take();
+        if let Some(worker) = worker {
+            // A worker that panicked has already answered its requests
+            // by dropping them.
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for ServiceHandle {
+    fn drop(&mut self) {
+        self.shut_down(None);
+    }
+}
+
+/// Depth of the queue, shared by the handles and the worker.
+struct QueueDepth {
+    max: Option<usize>,
+    overload: OverloadPolicy,
+    state: Mutex<DepthState>,
+    /// Notified when requests leave the queue or the worker stops.
+    room: Condvar,
+}
+
+#[derive(Default)]
+struct DepthState {
+    stats: ServiceStats,
+    stopped: bool,
+    /// When the worker cancels what is still queued, once shutting down.
+    cancel_at: Option<Instant>,
+}
+
+impl QueueDepth {
+    /// Count a new request, waiting for room if `wait` is set.
+    fn enter(&self, wait: bool) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopped {
+                return Err(Error::ServiceStopped);
+            }
+            let depth = state.stats.queue_depth;
+            match self.max {
+                Some(max) if depth >= max && wait => {
+                    state = self.room.wait(state).unwrap();
+                }
+                Some(max) if depth >= max => {
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::SERVICE_REJECTED.add(1);
+                    return Err(Error::Overloaded { depth });
+                }
+                _ => break,
+            }
+        }
+        let stats = &mut state.stats;
+        stats.queue_depth += 1;
+        stats.high_water_mark = stats.high_water_mark.max(stats.queue_depth);
+        #[cfg(feature = "metrics")]
+        {
+            crate::metrics::SERVICE_REQUESTS.add(1);
+            crate::metrics::QUEUE_DEPTH.add(1);
+        }
+        Ok(())
+    }
+
+    /// Count `n` requests handed to the model.
+    fn leave(&self, n: usize) {
+        self.state.lock().unwrap().stats.queue_depth -= n;
+        self.room.notify_all();
+        #[cfg(feature = "metrics")]
+        crate::metrics::QUEUE_DEPTH.add(-(n as i64));
+    }
+
+    fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.room.notify_all();
+    }
+
+    /// Refuse new requests and cancel queued ones from `cancel_at`.
+    fn close(&self, cancel_at: Option<Instant>) {
+        let mut state = self.state.lock().unwrap();
+        state.stopped = true;
+        state.cancel_at = state.cancel_at.or(cancel_at);
+        drop(state);
+        self.room.notify_all();
+    }
+
+    /// Whether the worker should cancel the queue now.
+    fn cancel_due(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.cancel_at.is_some_and(|at| Instant::now() >= at)
+    }
+}
+
+/// Marks the queue stopped when the worker exits, also by a panic, so
+/// no submitter waits for room forever.
+struct StopOnDrop(Arc<QueueDepth>);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.stop();
+    }
+}
+
+/// A submitted request whose result has not been collected yet.
+pub struct PendingEvaluation {
+    receiver: Receiver<Result<EvaluationResult, Error>>,
+}
+
+impl PendingEvaluation {
+    /// A request answered with `err` without reaching the worker.
+    fn failed(err: Error) -> Self {
+        let (reply, receiver) = mpsc::channel();
+        let _ = reply.send(Err(err));
+        Self { receiver }
+    }
+
+    /// Block until the worker has evaluated the position.
+    ///
+    /// # Errors
+    /// Returns the evaluation error for this position,
+    /// [`Error::Cancelled`] if the service shut down before evaluating
+    /// it, or [`Error::ServiceStopped`] if the worker went away first.
+    pub fn wait(self) -> Result<EvaluationResult, Error> {
+        self.receiver.recv().unwrap_or(Err(Error::ServiceStopped))
+    }
+}
+
+impl MaiaService {
+    /// Move `maia` onto a new worker thread.
+    pub fn spawn<B>(maia: Maia<B>, config: ServiceConfig) -> Self
+    where
+        B: InferenceBackend + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let queue = Arc::new(QueueDepth {
+            max: config.max_queue_depth,
+            overload: config.overload,
+            state: Mutex::default(),
+            room: Condvar::new(),
+        });
+        let stop = StopOnDrop(queue.clone());
+        let shutdown = config.shutdown;
+        let worker = thread::Builder::new()
+            .name("maia-service".to_string())
+            .spawn(move || run_worker(maia, config, receiver, stop))
+            .expect("failed to spawn maia-service thread");
+        Self {
+            inner: Arc::new(ServiceHandle {
+                sender: Mutex::new(Some(sender)),
+                worker: Mutex::new(Some(worker)),
+                queue,
+                shutdown,
+            }),
+        }
+    }
+
+    /// Current and deepest size of the request queue.
+    pub fn stats(&self) -> ServiceStats {
+        self.inner.queue.state.lock().unwrap().stats
+    }
+
+    /// Stop the service for every handle and wait for its worker to
+    /// exit.
+    ///
+    /// New requests fail with [`Error::ServiceStopped`] from now on.
+    /// The queued ones are evaluated for up to `timeout` or cancelled
+    /// right away, per [`ServiceConfig::shutdown`]; those left over
+    /// resolve with [`Error::Cancelled`]. The batch running when time is
+    /// up still finishes, so the call can take that much longer. The
+    /// model is dropped on the worker thread before this returns.
+    /// Later calls return at once.
+    pub fn shutdown(&self, timeout: Duration) {
+        self.inner.shut_down(Some(timeout));
+    }
+
+    /// Queue a position at [`Priority::Normal`] without waiting for its
+    /// result.
+    pub fn submit(&self, setup: Setup, elo_self: f32, elo_oppo: f32) -> PendingEvaluation {
+        self.submit_with_priority(setup, elo_self, elo_oppo, Priority::Normal)
+    }
+
+    /// Queue a position at `priority` without waiting for its result.
+    ///
+    /// With the queue full this waits for room or returns a request
+    /// failed with [`Error::Overloaded`], per
+    /// [`ServiceConfig::overload`].
+    pub fn submit_with_priority(
+        &self,
+        setup: Setup,
+        elo_self: f32,
+        elo_oppo: f32,
+        priority: Priority,
+    ) -> PendingEvaluation {
+        let wait = self.inner.queue.overload == OverloadPolicy::Block;
+        match self.inner.queue.enter(wait) {
+            Ok(()) => self.send(setup, elo_self, elo_oppo, priority),
+            Err(err) => PendingEvaluation::failed(err),
+        }
+    }
+
+    /// Queue a position at `priority` if the queue has room, never
+    /// waiting for it.
+    ///
+    /// # Errors
+    /// Returns [`Error::Overloaded`] if the queue is full, whatever the
+    /// [`OverloadPolicy`], and [`Error::ServiceStopped`] if the worker
+    /// has stopped.
+    pub fn try_submit(
+        &self,
+        setup: Setup,
+        elo_self: f32,
+        elo_oppo: f32,
+        priority: Priority,
+    ) -> Result<PendingEvaluation, Error> {
+        self.inner.queue.enter(false)?;
+        Ok(self.send(setup, elo_self, elo_oppo, priority))
+    }
+
+    /// Send a counted request to the worker.
+    fn send(
+        &self,
+        setup: Setup,
+        elo_self: f32,
+        elo_oppo: f32,
+        priority: Priority,
+    ) -> PendingEvaluation {
+        let sender = self.inner.sender.lock().unwrap().clone();
+        let Some(sender) = sender else {
+            // Shut down since the request was counted.
+            self.inner.queue.leave(1);
+            return PendingEvaluation::failed(Error::ServiceStopped);
+        };
+        let (reply, receiver) = mpsc::channel();
+        // A send error drops `reply`, which `wait` reports as stopped.
+        let _ = sender.send(Job {
+            setup,
+            elo_self,
+            elo_oppo,
+            priority,
+            reply,
+            #[cfg(feature = "metrics")]
+            queued_at: Instant::now(),
+        });
+        PendingEvaluation { receiver }
+    }
+
+    /// Evaluate one position, batched with whatever else is in flight.
+    ///
+    /// The position may be given as any [`TryIntoSetup`] type.
+    ///
+    /// # Errors
+    /// Returns the conversion error of `input`, such as
+    /// [`Error::InvalidFen`]; otherwise see [`PendingEvaluation::wait`].
+    pub fn evaluate(
+        &self,
+        input: impl TryIntoSetup,
+        elo_self: f32,
+        elo_oppo: f32,
+    ) -> Result<EvaluationResult, Error> {
+        self.submit(input.try_into_setup()?, elo_self, elo_oppo)
+            .wait()
+    }
+
+    /// Evaluate one position like [`evaluate`](Self::evaluate), but fail
+    /// instead of waiting for room in a full queue.
+    ///
+    /// # Errors
+    /// As [`evaluate`](Self::evaluate) and [`try_submit`](Self::try_submit).
+    pub fn try_evaluate(
+        &self,
+        input: impl TryIntoSetup,
+        elo_self: f32,
+        elo_oppo: f32,
+    ) -> Result<EvaluationResult, Error> {
+        self.try_submit(
+            input.try_into_setup()?,
+            elo_self,
+            elo_oppo,
+            Priority::Normal,
+        )?
+        .wait()
+    }
+
+    /// Evaluate several positions, returning one result per position.
+    ///
+    /// Unlike [`Maia::batch_evaluate`] a bad position only fails its own
+    /// entry.
+    ///
+    /// # Panics
+    /// Panics if the three inputs have different lengths.
+    pub fn batch_evaluate(
+        &self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Vec<Result<EvaluationResult, Error>> {
+        assert_eq!(setups.len(), elo_selfs.len());
+        assert_eq!(setups.len(), elo_oppos.len());
+
+        let pending: Vec<_> = setups
+            .into_iter()
+            .zip(elo_selfs.iter().zip(elo_oppos))
+            .map(|(setup, (&elo_self, &elo_oppo))| self.submit(setup, elo_self, elo_oppo))
+            .collect();
+        pending.into_iter().map(PendingEvaluation::wait).collect()
+    }
+}
+
+fn run_worker<B: InferenceBackend>(
+    mut maia: Maia<B>,
+    config: ServiceConfig,
+    receiver: Receiver<Job>,
+    stop: StopOnDrop,
+) {
+    let max_batch_size = config.max_batch_size.max(1);
+    let mut queue = JobQueue::new(config.max_skipped_batches);
+
+    loop {
+        if stop.0.cancel_due() {
+            let cancelled: Vec<Job> = queue.drain().chain(receiver.try_iter()).collect();
+            stop.0.leave(cancelled.len());
+            for job in cancelled {
+                let _ = job.reply.send(Err(Error::Cancelled));
+            }
+            break;
+        }
+        if queue.is_empty() {
+            match receiver.recv() {
+                Ok(job) => queue.push(job),
+                Err(_) => break,
+            }
+        }
+        // Everything already sent competes for this batch; then wait a
+        // little for the batch to fill up.
+        while let Ok(job) = receiver.try_recv() {
+            queue.push(job);
+        }
+        let deadline = Instant::now() + config.max_delay;
+        while queue.len() < max_batch_size {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(timeout) {
+                Ok(job) => queue.push(job),
+                Err(_) => break,
+            }
+        }
+        let jobs = queue.next_batch(max_batch_size);
+        stop.0.leave(jobs.len());
+        #[cfg(feature = "metrics")]
+        for job in &jobs {
+            crate::metrics::QUEUE_WAIT_SECONDS.observe_duration(job.queued_at.elapsed());
+        }
+        run_batch(&mut maia, jobs);
+    }
+}
+
+/// The requests waiting in the worker, one FIFO lane per priority.
+struct JobQueue {
+    lanes: [VecDeque<(Job, usize)>; 3],
+    /// Batches assembled so far; each job keeps the count at arrival.
+    batches: usize,
+    max_skipped_batches: usize,
+}
+
+impl JobQueue {
+    fn new(max_skipped_batches: usize) -> Self {
+        Self {
+            lanes: Default::default(),
+            batches: 0,
+            max_skipped_batches,
+        }
+    }
+
+    fn push(&mut self, job: Job) {
+        self.lanes[job.priority as usize].push_back((job, self.batches));
+    }
+
+    fn len(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every queued job, high priority first.
+    fn drain(&mut self) -> impl Iterator<Item = Job> + '_ {
+        self.lanes
+            .iter_mut()
+            .flat_map(|lane| lane.drain(..).map(|(job, _)| job))
+    }
+
+    /// Up to `max` jobs: the longest-waiting starved lower-priority job
+    /// if there is one, then the others by priority.
+    fn next_batch(&mut self, max: usize) -> Vec<Job> {
+        let mut jobs = Vec::with_capacity(max.min(self.len()));
+        let starved = (1..self.lanes.len())
+            .filter_map(|lane| Some((lane, self.lanes[lane].front()?.1)))
+            .filter(|&(_, since)| self.batches - since >= self.max_skipped_batches)
+            .min_by_key(|&(_, since)| since);
+        if let Some((lane, _)) = starved {
+            jobs.extend(self.lanes[lane].pop_front().map(|(job, _)| job));
+        }
+        self.batches += 1;
+
+        for lane in &mut self.lanes {
+            let take = (max - jobs.len()).min(lane.len());
+            jobs.extend(lane.drain(..take).map(|(job, _)| job));
+        }
+        jobs
+    }
+}
+
+fn run_batch<B: InferenceBackend>(maia: &mut Maia<B>, jobs: Vec<Job>) {
+    let setups: Vec<Setup> = jobs.iter().map(|j| j.setup.clone()).collect();
+    let elo_selfs: Vec<f32> = jobs.iter().map(|j| j.elo_self).collect();
+    let elo_oppos: Vec<f32> = jobs.iter().map(|j| j.elo_oppo).collect();
+
+    match maia.batch_evaluate(setups, &elo_selfs, &elo_oppos) {
+        Ok(results) => {
+            for (job, result) in jobs.into_iter().zip(results) {
+                let _ = job.reply.send(Ok(result));
+            }
+        }
+        // Something in the batch was rejected; rerun the requests one by
+        // one so each caller receives its own error. The batch index is
+        // internal to the service, so it is dropped.
+        Err(_) if jobs.len() > 1 => {
+            for job in jobs {
+                let result = maia
+                    .batch_evaluate([job.setup], &[job.elo_self], &[job.elo_oppo])
+                    .map(|mut results| results.remove(0))
+                    .map_err(Error::without_index);
+                let _ = job.reply.send(result);
+            }
+        }
+        Err(err) => {
+            let _ = jobs[0].reply.send(Err(err.without_index()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use ndarray::{Array2, Array3};
+    use shakmaty::fen::Fen;
+
+    use super::*;
+    use crate::{backend::ModelOutputs, testing::ZeroBackend};
+
+    fn setup(fen: &str) -> Setup {
+        fen.parse::<Fen>().unwrap().into_setup()
+    }
+
+    #[test]
+    fn concurrent_requests_share_a_batch() {
+        let backend = ZeroBackend::default();
+        let config = ServiceConfig {
+            max_batch_size: 8,
+            max_delay: Duration::from_millis(200),
+            ..ServiceConfig::default()
+        };
+        let service = MaiaService::spawn(Maia::from_backend(backend.clone()), config);
+
+        let start = setup("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let results = service.batch_evaluate(vec![start; 3], &[1500.0; 3], &[1500.0; 3]);
+
+        assert!(
+            results
+                .iter()
+                .all(|r| r.as_ref().unwrap().policy.len() == 20)
+        );
+        assert_eq!(backend.batch_sizes(), vec![3]);
+    }
+
+    #[test]
+    fn invalid_position_only_fails_its_own_request() {
+        let service = MaiaService::spawn(
+            Maia::from_backend(ZeroBackend::default()),
+            ServiceConfig::default(),
+        );
+
+        let start = setup("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let no_kings = setup("8/8/8/8/8/8/8/8 w - - 0 1");
+        let results = service.batch_evaluate(vec![start, no_kings], &[1500.0; 2], &[1500.0; 2]);
+
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(Error::InvalidPosition(_))));
+    }
+
+    /// The `elo_self` inputs of every batch run.
+    type Batches = Arc<Mutex<Vec<Vec<f32>>>>;
+
+    /// Backend recording the `elo_self` inputs of every batch. The first
+    /// batch reports on `started` and then waits for `gate`.
+    struct GatedBackend {
+        started: Sender<()>,
+        gate: Option<Receiver<()>>,
+        batches: Batches,
+    }
+
+    impl InferenceBackend for GatedBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            elo_self: &[f32],
+            _elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            if let Some(gate) = self.gate.take() {
+                self.started.send(()).unwrap();
+                gate.recv().unwrap();
+            }
+            self.batches.lock().unwrap().push(elo_self.to_vec());
+            let batch_size = tokens.shape()[0];
+            Ok(ModelOutputs {
+                logits_move: Array2::zeros((batch_size, crate::moves::ALL_MOVES.len())),
+                logits_value: Array2::zeros((batch_size, 3)),
+            })
+        }
+    }
+
+    /// A service on a [`GatedBackend`] busy with a first normal request,
+    /// the sender opening the gate and the recorded batches.
+    fn busy_service(config: ServiceConfig) -> (MaiaService, Sender<()>, Batches) {
+        let (started, started_rx) = mpsc::channel();
+        let (open, gate) = mpsc::channel();
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let backend = GatedBackend {
+            started,
+            gate: Some(gate),
+            batches: batches.clone(),
+        };
+        let service = MaiaService::spawn(Maia::from_backend(backend), config);
+        service.submit(setup(START), 1500.0, 1500.0);
+        started_rx.recv().unwrap();
+        (service, open, batches)
+    }
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    /// Index of the first recorded batch with a request rated `elo`.
+    fn batch_of(batches: &Mutex<Vec<Vec<f32>>>, elo: f32) -> Option<usize> {
+        batches
+            .lock()
+            .unwrap()
+            .iter()
+            .position(|b| b.contains(&elo))
+    }
+
+    #[test]
+    fn high_priority_overtakes_background_work() {
+        let (service, open, batches) = busy_service(ServiceConfig {
+            max_batch_size: 64,
+            max_delay: Duration::ZERO,
+            ..ServiceConfig::default()
+        });
+        let background: Vec<_> = (0..1000)
+            .map(|_| {
+                service.submit_with_priority(setup(START), 1000.0, 1500.0, Priority::Background)
+            })
+            .collect();
+        let urgent = service.submit_with_priority(setup(START), 2000.0, 1500.0, Priority::High);
+        open.send(()).unwrap();
+
+        assert!(urgent.wait().is_ok());
+        // Right after the batch that was running when it arrived.
+        assert_eq!(batch_of(&batches, 2000.0), Some(1));
+        assert!(background.into_iter().all(|p| p.wait().is_ok()));
+    }
+
+    #[test]
+    fn starved_requests_get_a_slot() {
+        let (service, open, batches) = busy_service(ServiceConfig {
+            max_batch_size: 2,
+            max_delay: Duration::ZERO,
+            max_skipped_batches: 2,
+            ..ServiceConfig::default()
+        });
+        let background =
+            service.submit_with_priority(setup(START), 1000.0, 1500.0, Priority::Background);
+        let urgent: Vec<_> = (0..10)
+            .map(|_| service.submit_with_priority(setup(START), 2000.0, 1500.0, Priority::High))
+            .collect();
+        open.send(()).unwrap();
+
+        assert!(background.wait().is_ok());
+        assert!(urgent.into_iter().all(|p| p.wait().is_ok()));
+        // Passed over by two batches of high-priority requests, then
+        // served ahead of the three still waiting.
+        assert_eq!(batch_of(&batches, 1000.0), Some(3));
+        assert_eq!(batches.lock().unwrap().len(), 7);
+    }
+
+    /// Backend taking `delay` for every batch.
+    struct SlowBackend {
+        delay: Duration,
+    }
+
+    impl InferenceBackend for SlowBackend {
+        fn run(
+            &mut self,
+            tokens: Array3<f32>,
+            _elo_self: &[f32],
+            _elo_oppo: &[f32],
+        ) -> Result<ModelOutputs, Error> {
+            thread::sleep(self.delay);
+            let batch_size = tokens.shape()[0];
+            Ok(ModelOutputs {
+                logits_move: Array2::zeros((batch_size, crate::moves::ALL_MOVES.len())),
+                logits_value: Array2::zeros((batch_size, 3)),
+            })
+        }
+    }
+
+    /// Submit 200 requests on four threads to a service that drains four
+    /// every 5 ms, returning the outcomes and the final stats.
+    fn flood(overload: OverloadPolicy) -> (Vec<Result<EvaluationResult, Error>>, ServiceStats) {
+        let config = ServiceConfig {
+            max_batch_size: 4,
+            max_delay: Duration::ZERO,
+            max_queue_depth: Some(8),
+            overload,
+            ..ServiceConfig::default()
+        };
+        let backend = SlowBackend {
+            delay: Duration::from_millis(5),
+        };
+        let service = MaiaService::spawn(Maia::from_backend(backend), config);
+        let outcomes = thread::scope(|scope| {
+            let producers: Vec<_> = (0..4)
+                .map(|_| {
+                    let service = service.clone();
+                    scope.spawn(move || {
+                        let pending: Vec<_> = (0..50)
+                            .map(|_| service.submit(setup(START), 1500.0, 1500.0))
+                            .collect();
+                        pending
+                            .into_iter()
+                            .map(PendingEvaluation::wait)
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            producers
+                .into_iter()
+                .flat_map(|p| p.join().unwrap())
+                .collect()
+        });
+        (outcomes, service.stats())
+    }
+
+    #[test]
+    fn blocking_queues_stay_bounded() {
+        let (outcomes, stats) = flood(OverloadPolicy::Block);
+        assert!(outcomes.iter().all(Result::is_ok));
+        assert_eq!(stats.queue_depth, 0);
+        assert_eq!(stats.high_water_mark, 8);
+    }
+
+    #[test]
+    fn rejecting_queues_fail_the_excess() {
+        let (outcomes, stats) = flood(OverloadPolicy::Reject);
+        let rejected = outcomes
+            .iter()
+            .filter(|r| matches!(r, Err(Error::Overloaded { depth: 8 })))
+            .count();
+        assert!(rejected > 0);
+        assert_eq!(
+            outcomes.iter().filter(|r| r.is_ok()).count() + rejected,
+            200
+        );
+        assert!(stats.high_water_mark <= 8);
+        assert_eq!(stats.queue_depth, 0);
+    }
+
+    #[test]
+    fn try_evaluate_never_waits_for_room() {
+        let (service, open, _) = busy_service(ServiceConfig {
+            max_queue_depth: Some(2),
+            ..ServiceConfig::default()
+        });
+        let queued: Vec<_> = (0..2)
+            .map(|_| service.submit(setup(START), 1500.0, 1500.0))
+            .collect();
+        assert_eq!(service.stats().queue_depth, 2);
+        assert!(matches!(
+            service.try_evaluate(START, 1500.0, 1500.0),
+            Err(Error::Overloaded { depth: 2 })
+        ));
+
+        open.send(()).unwrap();
+        assert!(queued.into_iter().all(|p| p.wait().is_ok()));
+        assert!(service.try_evaluate(START, 1500.0, 1500.0).is_ok());
+        assert_eq!(service.stats().high_water_mark, 2);
+    }
+
+    #[test]
+    fn shutdown_resolves_every_request() {
+        let config = ServiceConfig {
+            max_batch_size: 4,
+            max_delay: Duration::ZERO,
+            ..ServiceConfig::default()
+        };
+        let backend = SlowBackend {
+            delay: Duration::from_millis(10),
+        };
+        let service = MaiaService::spawn(Maia::from_backend(backend), config);
+        let pending: Vec<_> = (0..100)
+            .map(|_| service.submit(setup(START), 1500.0, 1500.0))
+            .collect();
+        service.shutdown(Duration::from_millis(30));
+
+        let outcomes: Vec<_> = pending.into_iter().map(PendingEvaluation::wait).collect();
+        let done = outcomes.iter().filter(|r| r.is_ok()).count();
+        let cancelled = outcomes
+            .iter()
+            .filter(|r| matches!(r, Err(Error::Cancelled)))
+            .count();
+        assert!(
+            done > 0 && cancelled > 0,
+            "{done} done, {cancelled} cancelled"
+        );
+        assert_eq!(done + cancelled, 100);
+        assert_eq!(service.stats().queue_depth, 0);
+        assert!(matches!(
+            service.evaluate(START, 1500.0, 1500.0),
+            Err(Error::ServiceStopped)
+        ));
+        // A second shutdown, and dropping the handle, return at once.
+        service.shutdown(Duration::ZERO);
+    }
+
+    #[test]
+    fn cancelling_shutdown_finishes_the_running_batch() {
+        let (service, open, batches) = busy_service(ServiceConfig {
+            shutdown: ShutdownPolicy::Cancel,
+            ..ServiceConfig::default()
+        });
+        let queued: Vec<_> = (0..5)
+            .map(|_| service.submit(setup(START), 1500.0, 1500.0))
+            .collect();
+        thread::scope(|scope| {
+            let stopping = scope.spawn(|| service.shutdown(Duration::from_secs(60)));
+            // Wait until the service refuses requests, so the queued
+            // ones are cancelled when the running batch finishes.
+            while service
+                .try_submit(setup(START), 1500.0, 1500.0, Priority::Normal)
+                .is_ok()
+            {
+                thread::yield_now();
+            }
+            open.send(()).unwrap();
+            stopping.join().unwrap();
+        });
+
+        assert!(
+            queued
+                .into_iter()
+                .all(|p| matches!(p.wait(), Err(Error::Cancelled)))
+        );
+        assert_eq!(batches.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,299 @@
+//! Aggregate statistics over many evaluations.
+//!
+//! [`summarize`] condenses a slice of results into a [`BatchSummary`]:
+//! the distribution of White's expected score
+//! ([`EvaluationResult::value_white`]), the mean policy entropy, how
+//! often the model is confident in one move and the moves it most often
+//! picks. [`BatchSummaryBuilder`] computes the same from results that
+//! arrive in chunks, in constant memory: values are counted in
+//! [`VALUE_RESOLUTION`] bins rather than kept, so the quantiles are
+//! exact to within one bin.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::EvaluationResult;
+
+/// Bins of [`BatchSummary::value_histogram`], of equal width over
+/// `[0, 1]`.
+pub const VALUE_BINS: usize = 10;
+
+/// Bins the values are counted in for the quantiles.
+pub const VALUE_RESOLUTION: usize = 1000;
+
+/// Quantiles of [`BatchSummary::value_quantiles`].
+pub const SUMMARY_QUANTILES: [f32; 5] = [0.05, 0.25, 0.5, 0.75, 0.95];
+
+/// Moves listed in [`BatchSummary::top_moves`].
+pub const TOP_MOVES: usize = 10;
+
+/// How much the most likely move must have for a position to count
+/// towards [`BatchSummary::confident_fraction`].
+pub const CONFIDENT_PROBABILITY: f32 = 0.5;
+
+/// Aggregates of a set of evaluations. Means, quantiles and fractions
+/// are 0 for an empty set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchSummary {
+    pub positions: u64,
+    /// Mean of White's expected score.
+    pub mean_value: f64,
+    pub min_value: f32,
+    pub max_value: f32,
+    /// White's expected score at each of [`SUMMARY_QUANTILES`].
+    pub value_quantiles: [f32; 5],
+    /// Positions by White's expected score, in [`VALUE_BINS`] bins from
+    /// 0 to 1; a score of exactly 1 counts in the last.
+    pub value_histogram: [u64; VALUE_BINS],
+    /// Mean Shannon entropy of the policies in bits, see
+    /// [`EvaluationResult::entropy`].
+    pub mean_entropy: f64,
+    /// Fraction of positions whose most likely move has more than
+    /// [`CONFIDENT_PROBABILITY`].
+    pub confident_fraction: f64,
+    /// The [`TOP_MOVES`] moves most often the most likely one, most
+    /// frequent first.
+    pub top_moves: Vec<MoveCount>,
+}
+
+/// How often a move was the most likely one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveCount {
+    pub uci: String,
+    pub count: u64,
+}
+
+/// Summarize `results`.
+pub fn summarize(results: &[EvaluationResult]) -> BatchSummary {
+    let mut builder = BatchSummaryBuilder::new();
+    builder.extend(results);
+    builder.build()
+}
+
+/// Running aggregates for a [`BatchSummary`], fed one result or chunk
+/// at a time.
+#[derive(Debug, Clone)]
+pub struct BatchSummaryBuilder {
+    positions: u64,
+    value_sum: f64,
+    min_value: f32,
+    max_value: f32,
+    /// Counts of values in [`VALUE_RESOLUTION`] bins.
+    values: Vec<u64>,
+    entropy_sum: f64,
+    confident: u64,
+    top_moves: HashMap<String, u64>,
+}
+
+impl Default for BatchSummaryBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BatchSummaryBuilder {
+    pub fn new() -> Self {
+        Self {
+            positions: 0,
+            value_sum: 0.0,
+            min_value: f32::INFINITY,
+            max_value: f32::NEG_INFINITY,
+            values: vec![0; VALUE_RESOLUTION],
+            entropy_sum: 0.0,
+            confident: 0,
+            top_moves: HashMap::new(),
+        }
+    }
+
+    /// Count `result`.
+    pub fn add(&mut self, result: &EvaluationResult) {
+        let value = result.value_white().clamp(0.0, 1.0);
+        self.positions += 1;
+        self.value_sum += f64::from(value);
+        self.min_value = self.min_value.min(value);
+        self.max_value = self.max_value.max(value);
+        self.values[bin(value, VALUE_RESOLUTION)] += 1;
+        self.entropy_sum += f64::from(result.entropy());
+        if let Some(best) = result.best_move() {
+            if best.probability > CONFIDENT_PROBABILITY {
+                self.confident += 1;
+            }
+            *self.top_moves.entry(best.uci.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Count every result of `results`, e.g. one chunk of a stream.
+    pub fn extend<'a>(&mut self, results: impl IntoIterator<Item = &'a EvaluationResult>) {
+        for result in results {
+            self.add(result);
+        }
+    }
+
+    /// Count everything `other` has counted, e.g. the results of
+    /// another thread.
+    pub fn merge(&mut self, other: &BatchSummaryBuilder) {
+        self.positions += other.positions;
+        self.value_sum += other.value_sum;
+        self.min_value = self.min_value.min(other.min_value);
+        self.max_value = self.max_value.max(other.max_value);
+        for (count, other) in self.values.iter_mut().zip(&other.values) {
+            *count += other;
+        }
+        self.entropy_sum += other.entropy_sum;
+        self.confident += other.confident;
+        for (uci, count) in &other.top_moves {
+            *self.top_moves.entry(uci.clone()).or_default() += count;
+        }
+    }
+
+    /// How many results have been counted.
+    pub fn positions(&self) -> u64 {
+        self.positions
+    }
+
+    /// The summary of everything counted so far.
+    pub fn build(&self) -> BatchSummary {
+        let n = self.positions;
+        let mean = |sum: f64| if n == 0 { 0.0 } else { sum / n as f64 };
+
+        let mut value_histogram = [0; VALUE_BINS];
+        for (i, count) in self.values.iter().enumerate() {
+            value_histogram[i * VALUE_BINS / VALUE_RESOLUTION] += count;
+        }
+
+        let mut top_moves: Vec<MoveCount> = self
+            .top_moves
+            .iter()
+            .map(|(uci, &count)| MoveCount {
+                uci: uci.clone(),
+                count,
+            })
+            .collect();
+        top_moves.sort_unstable_by(|a, b| b.count.cmp(&a.count).then_with(|| a.uci.cmp(&b.uci)));
+        top_moves.truncate(TOP_MOVES);
+
+        BatchSummary {
+            positions: n,
+            mean_value: mean(self.value_sum),
+            min_value: if n == 0 { 0.0 } else { self.min_value },
+            max_value: if n == 0 { 0.0 } else { self.max_value },
+            value_quantiles: SUMMARY_QUANTILES.map(|q| self.quantile(q)),
+            value_histogram,
+            mean_entropy: mean(self.entropy_sum),
+            confident_fraction: mean(self.confident as f64),
+            top_moves,
+        }
+    }
+
+    /// The value below which a fraction `q` of the counted values lie,
+    /// to within a bin.
+    fn quantile(&self, q: f32) -> f32 {
+        if self.positions == 0 {
+            return 0.0;
+        }
+        let rank = ((f64::from(q) * self.positions as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.values.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let mid = (i as f32 + 0.5) / VALUE_RESOLUTION as f32;
+                return mid.clamp(self.min_value, self.max_value);
+            }
+        }
+        self.max_value
+    }
+}
+
+/// The bin of `value` among `bins` of equal width over `[0, 1]`.
+fn bin(value: f32, bins: usize) -> usize {
+    ((value * bins as f32) as usize).min(bins - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::uci::UciMove;
+
+    use super::*;
+    use crate::types::MoveProbability;
+
+    /// A result with White's expected score `value` and the policy
+    /// `moves`.
+    fn result(value: f32, moves: &[(&str, f32)]) -> EvaluationResult {
+        EvaluationResult {
+            policy: moves
+                .iter()
+                .map(|&(uci, probability)| MoveProbability {
+                    uci: uci.parse::<UciMove>().unwrap(),
+                    probability,
+                    index: 0,
+                })
+                .collect(),
+            white_wr: value,
+            draw: 0.0,
+            black_wr: 1.0 - value,
+            outcome: None,
+        }
+    }
+
+    #[test]
+    fn batches_are_summarized() {
+        let results = [
+            result(0.1, &[("e2e4", 0.9), ("d2d4", 0.1)]),
+            result(0.5, &[("e2e4", 0.5), ("d2d4", 0.5)]),
+            result(0.55, &[("d2d4", 0.6), ("e2e4", 0.4)]),
+            result(1.0, &[("g1f3", 1.0)]),
+        ];
+        let summary = summarize(&results);
+        assert_eq!(summary.positions, 4);
+        assert!((summary.mean_value - 0.5375).abs() < 1e-6);
+        assert_eq!((summary.min_value, summary.max_value), (0.1, 1.0));
+        assert_eq!(summary.value_histogram, [0, 1, 0, 0, 0, 2, 0, 0, 0, 1]);
+        let bin_width = 1.0 / VALUE_RESOLUTION as f32;
+        for (quantile, expected) in summary
+            .value_quantiles
+            .into_iter()
+            .zip([0.1, 0.1, 0.5, 0.55, 1.0])
+        {
+            assert!(
+                (quantile - expected).abs() <= bin_width,
+                "{quantile} vs {expected}"
+            );
+        }
+        assert!((summary.mean_entropy - (0.469 + 1.0 + 0.971) / 4.0).abs() < 1e-3);
+        assert_eq!(summary.confident_fraction, 0.75);
+        let top: Vec<_> = summary
+            .top_moves
+            .iter()
+            .map(|m| (m.uci.as_str(), m.count))
+            .collect();
+        assert_eq!(top, [("e2e4", 2), ("d2d4", 1), ("g1f3", 1)]);
+    }
+
+    #[test]
+    fn chunks_merge_into_the_same_summary() {
+        let results: Vec<_> = (0..50)
+            .map(|i| result(i as f32 / 50.0, &[(["e2e4", "d2d4"][i % 2], 0.7)]))
+            .collect();
+        let mut streamed = BatchSummaryBuilder::new();
+        let mut other = BatchSummaryBuilder::new();
+        for chunk in results.chunks(16) {
+            streamed.extend(chunk);
+        }
+        other.extend(&results[..10]);
+        let mut merged = BatchSummaryBuilder::new();
+        merged.extend(&results[10..]);
+        merged.merge(&other);
+        assert_eq!(streamed.positions(), 50);
+        assert_eq!(streamed.build(), summarize(&results));
+        assert_eq!(
+            merged.build().value_histogram,
+            summarize(&results).value_histogram
+        );
+        assert_eq!(merged.build().top_moves, summarize(&results).top_moves);
+
+        let empty = BatchSummaryBuilder::new().build();
+        assert_eq!((empty.positions, empty.mean_value), (0, 0.0));
+        assert!(empty.top_moves.is_empty());
+    }
+}
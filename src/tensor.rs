@@ -1,92 +1,601 @@
-use ndarray::{Array3, ArrayViewMut2, Axis};
-use shakmaty::{CastlingMode, Chess, Role, Setup, Square};
+use ndarray::{Array3, ArrayView2, ArrayViewMut2, Axis};
+use shakmaty::{
+    Bitboard, Board, CastlingMode, Chess, Color, EnPassantMode, Move, Piece, Position,
+    PositionError, PositionErrorKinds, Role, Setup, Square, fen::Fen,
+};
 
-use crate::error::Error;
+use crate::{error::Error, moves::ALL_MOVES, types::Terminal};
+
+/// A channel of the `[B, 64, 12]` token tensor: one piece type of one
+/// color, from the perspective the network sees (White to move).
+///
+/// The tokens have no channels for the side to move, castling rights or
+/// the en passant square. Positions with Black to move are mirrored
+/// first, and the model does not see the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BoardChannel {
+    WhitePawn,
+    WhiteKnight,
+    WhiteBishop,
+    WhiteRook,
+    WhiteQueen,
+    WhiteKing,
+    BlackPawn,
+    BlackKnight,
+    BlackBishop,
+    BlackRook,
+    BlackQueen,
+    BlackKing,
+}
+
+impl BoardChannel {
+    /// Every channel, in tensor order.
+    pub const ALL: [BoardChannel; 12] = [
+        BoardChannel::WhitePawn,
+        BoardChannel::WhiteKnight,
+        BoardChannel::WhiteBishop,
+        BoardChannel::WhiteRook,
+        BoardChannel::WhiteQueen,
+        BoardChannel::WhiteKing,
+        BoardChannel::BlackPawn,
+        BoardChannel::BlackKnight,
+        BoardChannel::BlackBishop,
+        BoardChannel::BlackRook,
+        BoardChannel::BlackQueen,
+        BoardChannel::BlackKing,
+    ];
+
+    /// Position of the channel in the last tensor axis.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The channel of `piece`.
+    pub fn of(piece: Piece) -> Self {
+        let role = match piece.role {
+            Role::Pawn => 0,
+            Role::Knight => 1,
+            Role::Bishop => 2,
+            Role::Rook => 3,
+            Role::Queen => 4,
+            Role::King => 5,
+        };
+        Self::ALL[if piece.color.is_white() { 0 } else { 6 } + role]
+    }
+
+    /// The piece the channel marks.
+    pub fn piece(self) -> Piece {
+        let i = self.index();
+        Piece {
+            color: if i < 6 { Color::White } else { Color::Black },
+            role: Role::ALL[i % 6],
+        }
+    }
+}
+
+/// The `[B, 64]` cells of `channel` in `tokens`, squares in the order
+/// a1, b1, …, h8.
+///
+/// # Panics
+/// Panics if `tokens` does not have 12 channels.
+pub fn channel_view(tokens: &Array3<f32>, channel: BoardChannel) -> ArrayView2<'_, f32> {
+    tokens.index_axis(Axis(2), channel.index())
+}
+
+/// The setups `tokens` encode, one per batch row: the inverse of
+/// [`preprocess`] as far as the tokens go.
+///
+/// The boards are the ones the network saw, so positions with Black to
+/// move come back mirrored. Every setup has White to move and no
+/// castling rights or en passant square, which the tokens do not hold.
+/// A cell counts as set above 0.5; of several channels set on one
+/// square the first wins.
+///
+/// # Panics
+/// Panics if `tokens` does not have shape `[B, 64, 12]`.
+pub fn decode_tokens(tokens: &Array3<f32>) -> Vec<Setup> {
+    assert_eq!(
+        &tokens.shape()[1..],
+        &[64, 12],
+        "tokens must be [B, 64, 12]"
+    );
+    tokens
+        .outer_iter()
+        .map(|row| {
+            let mut board = Board::empty();
+            for sq in Square::ALL {
+                let cells = row.index_axis(Axis(0), square_index(sq));
+                if let Some(channel) = BoardChannel::ALL
+                    .into_iter()
+                    .find(|c| cells[c.index()] > 0.5)
+                {
+                    board.set_piece_at(sq, channel.piece());
+                }
+            }
+            let mut setup = Setup::empty();
+            setup.board = board;
+            setup
+        })
+        .collect()
+}
+
+/// The tokens of one position, `[64, 12]`, as one 8×8 grid per channel
+/// with the channel's name, rank 8 at the top.
+///
+/// Cells read `1` or `0`; any other value shows as `?`, which is what to
+/// look for when the input is wrong.
+///
+/// # Panics
+/// Panics if `tokens` does not have shape `[64, 12]`.
+pub fn format_board_tensor(tokens: ArrayView2<f32>) -> String {
+    assert_eq!(tokens.shape(), &[64, 12], "tokens must be [64, 12]");
+    let mut out = String::new();
+    for channel in BoardChannel::ALL {
+        out.push_str(&format!("{channel:?}\n"));
+        out.push_str(&grid(|sq| {
+            match tokens[[square_index(sq), channel.index()]] {
+                1.0 => '1',
+                0.0 => '0',
+                _ => '?',
+            }
+        }));
+    }
+    out
+}
+
+/// The tokens of one position, `[64, 12]`, with the piece channels
+/// overlaid on one board in FEN letters, rank 8 at the top.
+///
+/// Empty squares show as `.`; squares with several channels set or with
+/// a value other than 0 and 1 show as `?`.
+///
+/// # Panics
+/// Panics if `tokens` does not have shape `[64, 12]`.
+pub fn format_board_compact(tokens: ArrayView2<f32>) -> String {
+    assert_eq!(tokens.shape(), &[64, 12], "tokens must be [64, 12]");
+    grid(|sq| {
+        let cells = tokens.row(square_index(sq));
+        if cells.iter().any(|&v| v != 0.0 && v != 1.0) {
+            return '?';
+        }
+        let mut set = BoardChannel::ALL
+            .into_iter()
+            .filter(|c| cells[c.index()] == 1.0);
+        match (set.next(), set.next()) {
+            (None, _) => '.',
+            (Some(channel), None) => channel.piece().char(),
+            (Some(_), Some(_)) => '?',
+        }
+    })
+}
+
+/// Eight lines of the characters `cell` gives the squares, rank 8 first.
+fn grid(cell: impl Fn(Square) -> char) -> String {
+    let mut out = String::with_capacity(8 * 9);
+    for rank in (0..8).rev() {
+        for file in 0..8 {
+            out.push(cell(Square::new(rank * 8 + file)));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// How strictly setups are checked before evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Validation {
+    /// Reject every setup shakmaty considers impossible.
+    #[default]
+    Strict,
+    /// Evaluate impossible but usable setups, such as composed studies or
+    /// generated puzzles, and report what was waived as
+    /// [`ValidationWarning`]s. Setups without both kings, with too many
+    /// kings or with the side not to move in check are still rejected.
+    Relaxed,
+}
+
+/// A rule waived for a setup evaluated with [`Validation::Relaxed`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValidationWarning {
+    /// Pawns stand on the first or eighth rank. The network sees them,
+    /// but shakmaty cannot generate moves with them on the board, so
+    /// they have no moves in the policy and do not attack anything.
+    BackrankPawns,
+    /// Castling rights without the king and rook in place were dropped.
+    InvalidCastlingRights,
+    /// An en passant square that cannot be right was dropped.
+    InvalidEnPassantSquare,
+    /// More material than promotions can produce.
+    TooMuchMaterial,
+    /// A check no legal move can give, e.g. by three pieces.
+    ImpossibleCheck,
+}
+
+/// Rules [`Validation::Relaxed`] lets shakmaty waive.
+const WAIVABLE: [(PositionErrorKinds, ValidationWarning); 4] = [
+    (
+        PositionErrorKinds::INVALID_CASTLING_RIGHTS,
+        ValidationWarning::InvalidCastlingRights,
+    ),
+    (
+        PositionErrorKinds::INVALID_EP_SQUARE,
+        ValidationWarning::InvalidEnPassantSquare,
+    ),
+    (
+        PositionErrorKinds::TOO_MUCH_MATERIAL,
+        ValidationWarning::TooMuchMaterial,
+    ),
+    (
+        PositionErrorKinds::IMPOSSIBLE_CHECK,
+        ValidationWarning::ImpossibleCheck,
+    ),
+];
+
+/// The position of `setup` under `validation`, with the rules waived to
+/// build it.
+///
+/// # Errors
+/// Returns [`Error::InvalidPosition`] if `setup` is unusable even so.
+pub(crate) fn validate(
+    setup: &Setup,
+    validation: Validation,
+) -> Result<(Chess, Vec<ValidationWarning>), Error> {
+    let mut setup = setup.clone();
+    let mut warnings = Vec::new();
+    if validation == Validation::Strict {
+        return Ok((setup.position(CastlingMode::Standard)?, warnings));
+    }
+
+    let backrank = setup.board.pawns() & Bitboard::BACKRANKS;
+    if backrank.any() {
+        for sq in backrank {
+            setup.board.discard_piece_at(sq);
+        }
+        warnings.push(ValidationWarning::BackrankPawns);
+    }
+    let err = match setup.position(CastlingMode::Standard) {
+        Ok(position) => return Ok((position, warnings)),
+        Err(err) => err,
+    };
+    let kinds = err.kinds();
+    warnings.extend(
+        WAIVABLE
+            .iter()
+            .filter(|(kind, _)| kinds.contains(*kind))
+            .map(|&(_, warning)| warning),
+    );
+    let position = err
+        .ignore_invalid_castling_rights()
+        .or_else(PositionError::ignore_invalid_ep_square)
+        .or_else(PositionError::ignore_too_much_material)
+        .or_else(PositionError::ignore_impossible_check)?;
+    Ok((position, warnings))
+}
 
 /// Data produced by the preprocessing step, ready for model consumption.
 ///
 /// - `mirrored` tracks which positions were mirrored to
 ///   force the model's perspective to always be White.
-/// - `chess_positions` holds the corresponding `Chess` objects after
-///   any mirroring has been applied.  This is needed when converting
-///   the model's output back into human-readable UCI moves.
+/// - The legal moves of every position, as vocabulary indices of the
+///   possibly mirrored position, are what postprocessing needs to map
+///   the model's output back to moves. They are stored in one flat
+///   buffer of two bytes per move rather than as positions.
+/// - Positions without legal moves record whether they are checkmate
+///   or stalemate.
+/// - Positions preprocessed with [`Validation::Relaxed`] record the
+///   rules that were waived for them.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PreprocessedData {
     /// Maia3 positions are represented from white-to-move perspective.
     pub mirrored: Vec<bool>,
-    /// Potentially mirrored chess positions.
-    pub chess_positions: Vec<Chess>,
+    /// Legal move indices of all positions, concatenated.
+    move_indices: Vec<u16>,
+    /// Start of each position's moves in `move_indices`, followed by
+    /// the total.
+    offsets: Vec<u32>,
+    /// Terminal state of each position.
+    outcomes: Vec<Option<Terminal>>,
+    /// Waived rules by position index, for the few positions that have
+    /// any.
+    warnings: Vec<(u32, ValidationWarning)>,
+}
+
+impl PreprocessedData {
+    fn with_capacity(batch_size: usize) -> Self {
+        let mut offsets = Vec::with_capacity(batch_size + 1);
+        offsets.push(0);
+        Self {
+            mirrored: Vec::with_capacity(batch_size),
+            // Typical positions have 30 to 40 legal moves.
+            move_indices: Vec::with_capacity(batch_size * 40),
+            offsets,
+            outcomes: Vec::with_capacity(batch_size),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Record the next position, already mirrored if `mirrored`.
+    fn push(&mut self, position: &Chess, mirrored: bool) {
+        self.mirrored.push(mirrored);
+        let legal = position.legal_moves();
+        // Mirroring keeps the state, which is relative to the side to
+        // move anyway.
+        self.outcomes
+            .push(legal.is_empty().then(|| Terminal::of(position)).flatten());
+        for m in &legal {
+            let uci = m.to_uci(CastlingMode::Standard);
+            // Moves outside the vocabulary cannot be scored and are left
+            // out of the policy.
+            if let Some(&idx) = ALL_MOVES.get(&uci) {
+                self.move_indices.push(idx as u16);
+            }
+        }
+        self.offsets.push(self.move_indices.len() as u32);
+    }
+
+    /// Number of positions.
+    pub fn len(&self) -> usize {
+        self.mirrored.len()
+    }
+
+    /// Whether there are no positions.
+    pub fn is_empty(&self) -> bool {
+        self.mirrored.is_empty()
+    }
+
+    /// Vocabulary indices of the legal moves of position `i`, in
+    /// legal-move-generation order, from the perspective the network
+    /// saw (mirrored when Black is to move).
+    pub fn legal_move_indices(&self, i: usize) -> &[u16] {
+        let (start, end) = (self.offsets[i], self.offsets[i + 1]);
+        &self.move_indices[start as usize..end as usize]
+    }
+
+    /// Rules waived for position `i`, empty unless it was preprocessed
+    /// with [`Validation::Relaxed`].
+    pub fn warnings(&self, i: usize) -> Vec<ValidationWarning> {
+        let i = i as u32;
+        let start = self.warnings.partition_point(|&(j, _)| j < i);
+        let end = self.warnings.partition_point(|&(j, _)| j <= i);
+        self.warnings[start..end].iter().map(|&(_, w)| w).collect()
+    }
+
+    /// Terminal state of position `i`, `None` if it has legal moves.
+    pub fn outcome(&self, i: usize) -> Option<Terminal> {
+        self.outcomes[i]
+    }
+
+    /// What position `i` of the batch `tokens` were encoded with looks
+    /// like to the network, for debugging: its board as
+    /// [`format_board_compact`] draws it, whether it was mirrored, its
+    /// number of legal moves, its outcome and warnings.
+    ///
+    /// # Panics
+    /// Panics if `i` is out of range for the data or the tokens.
+    pub fn describe(&self, tokens: &Array3<f32>, i: usize) -> String {
+        let mut out = format!(
+            "position {i}: {}, {} legal moves",
+            if self.mirrored[i] {
+                "mirrored"
+            } else {
+                "as given"
+            },
+            self.legal_move_indices(i).len()
+        );
+        if let Some(outcome) = self.outcome(i) {
+            out.push_str(&format!(", {outcome:?}"));
+        }
+        let warnings = self.warnings(i);
+        if !warnings.is_empty() {
+            out.push_str(&format!(", waived {warnings:?}"));
+        }
+        out.push('\n');
+        out.push_str(&format_board_compact(tokens.index_axis(Axis(0), i)));
+        out
+    }
 }
 
 /// Transform an iterator of `Setup`s into the input tensors
 /// expected by the Maia3 model.
 ///
-/// `batch_size` must match the number of setups provided; mismatches
-/// will panic.  This function also records whether each position was
-/// mirrored and returns the possibly‑mirrored `Chess` objects for
-/// later use.
+/// `batch_size` must match the number of setups provided. This function
+/// also records whether each position was mirrored and the legal moves postprocessing maps the outputs onto.
 /// `tokens` has shape `[B, 64, 12]` where `B` is the batch size. Each
-/// square stores one-hot piece channels in the order:
-/// white P,N,B,R,Q,K then black p,n,b,r,q,k.
+/// square stores one-hot piece channels in the order of
+/// [`BoardChannel::ALL`]: white P,N,B,R,Q,K then black p,n,b,r,q,k.
+///
+/// # Errors
+/// Returns [`Error::AtIndex`] with the index and FEN of the first
+/// setup that is not a legal position, wrapping its
+/// [`Error::InvalidPosition`], and [`Error::BatchSizeMismatch`] if there
+/// are more or fewer setups than `batch_size`.
 pub fn preprocess(
     setups: impl IntoIterator<Item = Setup>,
     batch_size: usize,
+) -> Result<(Array3<f32>, PreprocessedData), Error> {
+    preprocess_with(setups, batch_size, Validation::Strict)
+}
+
+/// [`preprocess`] checking setups according to `validation`. The rules
+/// waived for each position are available from
+/// [`PreprocessedData::warnings`]; board cells are encoded as given,
+/// including pawns left out of move generation.
+///
+/// # Errors
+/// As [`preprocess`], for setups unusable under `validation`.
+pub fn preprocess_with(
+    setups: impl IntoIterator<Item = Setup>,
+    batch_size: usize,
+    validation: Validation,
+) -> Result<(Array3<f32>, PreprocessedData), Error> {
+    preprocess_inspecting(setups, batch_size, validation, |_, _| {})
+}
+
+/// [`preprocess_with`] passing each checked position, as the network
+/// sees it, and whether it was mirrored to `inspect`.
+pub(crate) fn preprocess_inspecting(
+    setups: impl IntoIterator<Item = Setup>,
+    batch_size: usize,
+    validation: Validation,
+    mut inspect: impl FnMut(&Chess, bool),
 ) -> Result<(Array3<f32>, PreprocessedData), Error> {
     let mut tokens = Array3::<f32>::zeros((batch_size, 64, 12));
-    let mut mirrored_vec = Vec::with_capacity(batch_size);
-    let mut chess_positions = Vec::with_capacity(batch_size);
-    let mut last_index = 0;
+    let mut data = PreprocessedData::with_capacity(batch_size);
+    let mut setups = setups.into_iter();
+    let mut count = 0;
 
-    for (i, mut setup) in setups.into_iter().enumerate() {
-        last_index = i;
+    while let Some(mut setup) = setups.next() {
+        let i = count;
+        count += 1;
         if i >= batch_size {
-            panic!("More setups provided than batch size");
+            return Err(Error::BatchSizeMismatch {
+                expected: batch_size,
+                actual: count + setups.count(),
+            });
         }
 
         // If it's Black's turn we mirror so the network always sees
         // White-to-move positions.
         let mirrored = setup.turn.is_black();
-        mirrored_vec.push(mirrored);
-
         if mirrored {
             setup.mirror();
         }
 
         board_to_tokens(&setup, tokens.index_axis_mut(Axis(0), i));
-        let position: Chess = setup.position(CastlingMode::Standard)?;
-        chess_positions.push(position);
+        let (position, warnings) = validate(&setup, validation).map_err(|err| {
+            // Report the position as given, not as the network sees it.
+            if mirrored {
+                setup.mirror();
+            }
+            let fen = Fen::try_from_setup(setup).unwrap_or_else(|e| e.ignore());
+            Error::AtIndex {
+                index: i,
+                fen: Some(fen.to_string()),
+                source: Box::new(err),
+            }
+        })?;
+        inspect(&position, mirrored);
+        data.push(&position, mirrored);
+        data.warnings
+            .extend(warnings.into_iter().map(|w| (i as u32, w)));
     }
 
-    if last_index + 1 != batch_size {
-        panic!("Fewer setups provided than batch size");
+    if count != batch_size {
+        return Err(Error::BatchSizeMismatch {
+            expected: batch_size,
+            actual: count,
+        });
     }
 
-    Ok((
-        tokens,
-        PreprocessedData {
-            chess_positions,
-            mirrored: mirrored_vec,
-        },
-    ))
+    // Release the unused part of the move buffer's estimate.
+    data.move_indices.shrink_to_fit();
+    Ok((tokens, data))
 }
 
 fn board_to_tokens(setup: &Setup, mut tokens: ArrayViewMut2<f32>) {
     for sq in Square::ALL {
         if let Some(piece) = setup.board.piece_at(sq) {
-            let piece_idx = (if piece.color.is_white() { 0 } else { 6 })
-                + match piece.role {
-                Role::Pawn => 0,
-                Role::Knight => 1,
-                Role::Bishop => 2,
-                Role::Rook => 3,
-                Role::Queen => 4,
-                Role::King => 5,
-            };
+            tokens[[square_index(sq), BoardChannel::of(piece).index()]] = 1.0;
+        }
+    }
+}
+
+/// Maia3 square index matches rank-major layout:
+/// a1 => 0, b1 => 1, ..., h8 => 63.
+fn square_index(sq: Square) -> usize {
+    (sq.rank() as usize) * 8 + (sq.file() as usize)
+}
+
+/// Token encoder that follows a game move by move.
+///
+/// Both perspectives are kept up to date: the board as is, used when
+/// White is to move, and the mirrored board used when Black is to move.
+/// A move only toggles the cells of the pieces it moves, captures or
+/// promotes, so encoding a game costs a few cell writes per ply instead
+/// of a full [`preprocess`] call each.
+///
+/// [`tokens`](Self::tokens) always equals the `[1, 64, 12]` tensor
+/// [`preprocess`] produces for the current position.
+pub struct IncrementalEncoder {
+    position: Chess,
+    white_view: Array3<f32>,
+    black_view: Array3<f32>,
+}
+
+impl IncrementalEncoder {
+    /// Start from `setup`.
+    ///
+    /// # Errors
+    /// Returns [`Error::InvalidPosition`] if `setup` is not a legal
+    /// position.
+    pub fn new(setup: Setup) -> Result<Self, Error> {
+        let mut white_view = Array3::zeros((1, 64, 12));
+        let mut black_view = Array3::zeros((1, 64, 12));
+        board_to_tokens(&setup, white_view.index_axis_mut(Axis(0), 0));
+        let mut mirrored = setup.clone();
+        mirrored.mirror();
+        board_to_tokens(&mirrored, black_view.index_axis_mut(Axis(0), 0));
+
+        Ok(Self {
+            position: setup.position(CastlingMode::Standard)?,
+            white_view,
+            black_view,
+        })
+    }
+
+    /// The current position.
+    pub fn position(&self) -> &Chess {
+        &self.position
+    }
+
+    /// Tokens of the current position from the side to move.
+    pub fn tokens(&self) -> &Array3<f32> {
+        match self.position.turn() {
+            Color::White => &self.white_view,
+            Color::Black => &self.black_view,
+        }
+    }
+
+    /// Play `m` and return the tokens of the resulting position.
+    ///
+    /// # Errors
+    /// Returns [`Error::IllegalMove`] if `m` is not legal, leaving the
+    /// encoder unchanged.
+    pub fn push_move(&mut self, m: &Move) -> Result<&Array3<f32>, Error> {
+        if !self.position.is_legal(*m) {
+            let uci = m.to_uci(CastlingMode::Standard);
+            return Err(Error::IllegalMove(uci.to_string()));
+        }
+        let before = self.position.board().clone();
+        self.position.play_unchecked(*m);
+        let after = self.position.board();
 
-            // Maia3 square index matches rank-major layout:
-            // a1 => 0, b1 => 1, ..., h8 => 63.
-            let square_idx = (sq.rank() as usize) * 8 + (sq.file() as usize);
-            tokens[[square_idx, piece_idx]] = 1.0;
+        for color in Color::ALL {
+            for role in Role::ALL {
+                let piece = Piece { color, role };
+                let flipped = Piece {
+                    color: !color,
+                    role,
+                };
+                for sq in before.by_piece(piece) ^ after.by_piece(piece) {
+                    let white = [0, square_index(sq), BoardChannel::of(piece).index()];
+                    let black = [
+                        0,
+                        square_index(sq.flip_vertical()),
+                        BoardChannel::of(flipped).index(),
+                    ];
+                    self.white_view[white] = 1.0 - self.white_view[white];
+                    self.black_view[black] = 1.0 - self.black_view[black];
+                }
+            }
         }
+        Ok(self.tokens())
+    }
+
+    /// The current position as a [`Setup`], e.g. for postprocessing.
+    pub fn setup(&self) -> Setup {
+        self.position.to_setup(EnPassantMode::Legal)
     }
 }
 
@@ -110,4 +619,148 @@ mod tests {
         let e4_idx = (3 * 8) + 4;
         assert_eq!(tensor[[0, e4_idx, 0]], 1.0);
     }
+
+    /// Play random legal moves and compare the incremental tensors with
+    /// encoding from scratch after every ply.
+    #[test]
+    fn incremental_encoding_matches_preprocess() {
+        use rand::{RngExt, SeedableRng, rngs::StdRng};
+
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            // Promotions and en passant on the board.
+            "8/1P4k1/8/3pP3/8/8/6K1/8 w - d6 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1",
+        ];
+        let mut rng = StdRng::seed_from_u64(3);
+        for fen in fens {
+            let setup = fen.parse::<Fen>().unwrap().into_setup();
+            let mut encoder = IncrementalEncoder::new(setup).unwrap();
+            for _ in 0..150 {
+                let legal = encoder.position().legal_moves();
+                if legal.is_empty() {
+                    break;
+                }
+                let m = legal[rng.random_range(0..legal.len())];
+                let tokens = encoder.push_move(&m).unwrap().clone();
+                let (expected, _) = preprocess([encoder.setup()], 1).unwrap();
+                assert_eq!(tokens, expected, "after {m} in {fen}");
+            }
+        }
+    }
+
+    #[test]
+    fn channels_follow_the_piece_order() {
+        for (i, channel) in BoardChannel::ALL.into_iter().enumerate() {
+            assert_eq!(channel.index(), i);
+            assert_eq!(BoardChannel::of(channel.piece()), channel);
+        }
+        let (tensor, _) = preprocess(vec![Setup::default()], 1).unwrap();
+        let kings = channel_view(&tensor, BoardChannel::BlackKing);
+        assert_eq!(kings.shape(), &[1, 64]);
+        assert_eq!(kings.sum(), 1.0);
+        assert_eq!(kings[[0, square_index(Square::E8)]], 1.0);
+    }
+
+    #[test]
+    fn boards_are_drawn_as_the_network_sees_them() {
+        // 1. e4, mirrored because Black is to move.
+        let setup = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_setup();
+        let (mut tensor, data) = preprocess(vec![setup], 1).unwrap();
+        assert_eq!(
+            data.describe(&tensor, 0),
+            "position 0: mirrored, 20 legal moves\n\
+             rnbqkbnr\n\
+             pppp.ppp\n\
+             ........\n\
+             ....p...\n\
+             ........\n\
+             ........\n\
+             PPPPPPPP\n\
+             RNBQKBNR\n"
+        );
+
+        tensor[[0, square_index(Square::A1), BoardChannel::WhiteKing.index()]] = 0.5;
+        let tokens = tensor.index_axis(Axis(0), 0);
+        assert!(format_board_compact(tokens).ends_with("\n?NBQKBNR\n"));
+        let channels = format_board_tensor(tokens);
+        assert_eq!(channels.lines().count(), 12 * 9);
+        assert!(channels.contains("WhiteKing\n00000000\n"));
+        assert!(channels.contains("\n?0001000\nBlackPawn\n"));
+    }
+
+    /// Encode the positions of random games and decode them again: the
+    /// boards come back as the network saw them.
+    #[test]
+    fn decoding_inverts_preprocess() {
+        use rand::{RngExt, SeedableRng, rngs::StdRng};
+
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut setups = Vec::new();
+        for _ in 0..20 {
+            let mut position = Chess::default();
+            for _ in 0..rng.random_range(0..120) {
+                let legal = position.legal_moves();
+                if legal.is_empty() {
+                    break;
+                }
+                position.play_unchecked(legal[rng.random_range(0..legal.len())]);
+            }
+            setups.push(position.to_setup(EnPassantMode::Legal));
+        }
+
+        let (tensor, data) = preprocess(setups.clone(), setups.len()).unwrap();
+        let decoded = decode_tokens(&tensor);
+        for ((mut expected, decoded), mirrored) in
+            setups.into_iter().zip(&decoded).zip(data.mirrored)
+        {
+            if mirrored {
+                expected.mirror();
+            }
+            assert_eq!(decoded.board, expected.board);
+            assert_eq!(decoded.turn, Color::White);
+        }
+        let (reencoded, _) = preprocess(decoded, tensor.shape()[0]).unwrap();
+        assert_eq!(reencoded, tensor);
+    }
+
+    #[test]
+    fn incremental_encoder_rejects_illegal_moves() {
+        let mut encoder = IncrementalEncoder::new(Setup::default()).unwrap();
+        let m = shakmaty::uci::UciMove::from_ascii(b"e2e4")
+            .unwrap()
+            .to_move(encoder.position())
+            .unwrap();
+        encoder.push_move(&m).unwrap();
+        assert!(matches!(
+            encoder.push_move(&m),
+            Err(Error::IllegalMove(uci)) if uci == "e2e4"
+        ));
+    }
+
+    #[test]
+    fn relaxed_validation_keeps_backrank_pawns_in_the_tokens() {
+        let setup = "4k3/8/8/8/8/8/8/P3K3 w - - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_setup();
+        assert!(preprocess(vec![setup.clone()], 1).is_err());
+
+        let (tensor, data) = preprocess_with(vec![setup], 1, Validation::Relaxed).unwrap();
+        assert_eq!(tensor[[0, square_index(Square::A1), 0]], 1.0);
+        assert_eq!(data.warnings(0), vec![ValidationWarning::BackrankPawns]);
+        assert_eq!(data.legal_move_indices(0).len(), 5);
+    }
+
+    #[test]
+    fn relaxed_validation_still_needs_both_kings() {
+        let setup = "8/8/8/8/8/8/8/4K3 w - - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_setup();
+        assert!(validate(&setup, Validation::Relaxed).is_err());
+    }
 }
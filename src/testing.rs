@@ -0,0 +1,129 @@
+//! Test doubles for code generic over [`Evaluator`] or
+//! [`InferenceBackend`], such as the crate's own unit tests and binaries.
+//! They are deterministic and need no model file.
+
+use std::sync::{Arc, Mutex};
+
+use ndarray::{Array2, Array3};
+use shakmaty::{CastlingMode, Chess, Position, Setup};
+
+use crate::{
+    backend::{InferenceBackend, ModelOutputs},
+    error::Error,
+    evaluator::Evaluator,
+    moves::{ALL_MOVES, vocabulary_index},
+    types::{EvaluationResult, MoveProbability, Terminal},
+};
+
+/// Policy entry for `uci` played by White.
+#[cfg(test)]
+pub(crate) fn white_move(uci: &str, probability: f32) -> MoveProbability {
+    let uci = uci.parse().unwrap();
+    MoveProbability {
+        index: vocabulary_index(&uci, shakmaty::Color::White).unwrap(),
+        uci,
+        probability,
+    }
+}
+
+/// Evaluator spreading the policy evenly over the legal moves, with a
+/// fixed 40/30/30 White/draw/Black value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UniformEvaluator;
+
+impl Evaluator for UniformEvaluator {
+    fn batch_evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        _elo_selfs: &[f32],
+        _elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        setups
+            .into_iter()
+            .map(|setup| {
+                let pos: Chess = setup.position(CastlingMode::Standard)?;
+                let legal = pos.legal_moves();
+                let policy = legal
+                    .iter()
+                    .map(|m| {
+                        let uci = m.to_uci(CastlingMode::Standard);
+                        MoveProbability {
+                            index: vocabulary_index(&uci, pos.turn()).unwrap(),
+                            uci,
+                            probability: 1.0 / legal.len() as f32,
+                        }
+                    })
+                    .collect();
+                Ok(EvaluationResult {
+                    policy,
+                    white_wr: 0.4,
+                    draw: 0.3,
+                    black_wr: 0.3,
+                    outcome: Terminal::of(&pos),
+                })
+            })
+            .collect()
+    }
+}
+
+/// [`UniformEvaluator`] with White's win probability given per ply,
+/// counted from the start of the game by the setup's move number, and no
+/// draws.
+///
+/// # Panics
+/// Evaluating a position past the end of the script panics.
+#[derive(Debug, Clone)]
+pub struct ScriptedEvaluator(pub Vec<f32>);
+
+impl Evaluator for ScriptedEvaluator {
+    fn batch_evaluate(
+        &mut self,
+        setups: Vec<Setup>,
+        elo_selfs: &[f32],
+        elo_oppos: &[f32],
+    ) -> Result<Vec<EvaluationResult>, Error> {
+        let plies: Vec<usize> = setups
+            .iter()
+            .map(|s| 2 * (s.fullmoves.get() as usize - 1) + s.turn.fold_wb(0, 1))
+            .collect();
+        let mut results = UniformEvaluator.batch_evaluate(setups, elo_selfs, elo_oppos)?;
+        for (result, ply) in results.iter_mut().zip(plies) {
+            result.white_wr = self.0[ply];
+            result.draw = 0.0;
+            result.black_wr = 1.0 - self.0[ply];
+        }
+        Ok(results)
+    }
+}
+
+/// Backend returning all-zero logits, so a uniform policy over the legal
+/// moves and a uniform value, as from a model with no signal left. Clones
+/// share the record of batch sizes, so a clone kept outside a service
+/// sees the batches its worker runs.
+#[derive(Debug, Clone, Default)]
+pub struct ZeroBackend {
+    batch_sizes: Arc<Mutex<Vec<usize>>>,
+}
+
+impl ZeroBackend {
+    /// The size of every batch run so far, in order.
+    pub fn batch_sizes(&self) -> Vec<usize> {
+        self.batch_sizes.lock().unwrap().clone()
+    }
+}
+
+impl InferenceBackend for ZeroBackend {
+    fn run(
+        &mut self,
+        tokens: Array3<f32>,
+        _elo_self: &[f32],
+        _elo_oppo: &[f32],
+    ) -> Result<ModelOutputs, Error> {
+        let batch_size = tokens.shape()[0];
+        self.batch_sizes.lock().unwrap().push(batch_size);
+        Ok(ModelOutputs {
+            logits_move: Array2::zeros((batch_size, ALL_MOVES.len())),
+            logits_value: Array2::zeros((batch_size, 3)),
+        })
+    }
+}
@@ -0,0 +1,279 @@
+//! Evaluation of position trees with transposition deduplication.
+//!
+//! A [`TreeEvaluator`] expands trees wave by wave: all nodes of one depth
+//! are evaluated in a single batch, then an [`ExpansionPolicy`] picks the
+//! moves to follow from each of them. Positions are keyed by their
+//! zobrist hash and elo pair, so a position reached along several move
+//! orders is evaluated and expanded only once.
+//!
+//! The transposition table lives as long as the `TreeEvaluator`, which is
+//! meant to be created per job; wrap a caching evaluator to share results
+//! across jobs.
+
+use std::collections::{HashMap, HashSet};
+
+use shakmaty::{CastlingMode, Chess, Color, EnPassantMode, Move, Position, zobrist::Zobrist64};
+
+use crate::{error::Error, evaluator::Evaluator, types::EvaluationResult};
+
+/// Chooses the moves to expand from a node.
+pub trait ExpansionPolicy {
+    /// Moves to follow from `pos`, evaluated as `result`, at `depth`
+    /// (0 for the roots). Return no moves to make the node a leaf.
+    fn expand(&mut self, pos: &Chess, result: &EvaluationResult, depth: usize) -> Vec<Move>;
+}
+
+impl<F> ExpansionPolicy for F
+where
+    F: FnMut(&Chess, &EvaluationResult, usize) -> Vec<Move>,
+{
+    fn expand(&mut self, pos: &Chess, result: &EvaluationResult, depth: usize) -> Vec<Move> {
+        self(pos, result, depth)
+    }
+}
+
+/// Expand the `count` most likely moves of every node above `max_depth`.
+#[derive(Debug, Clone, Copy)]
+pub struct TopMoves {
+    pub count: usize,
+    pub max_depth: usize,
+}
+
+impl ExpansionPolicy for TopMoves {
+    fn expand(&mut self, pos: &Chess, result: &EvaluationResult, depth: usize) -> Vec<Move> {
+        if depth >= self.max_depth {
+            return Vec::new();
+        }
+        result
+            .policy
+            .iter()
+            .take(self.count)
+            .filter_map(|m| m.uci.to_move(pos).ok())
+            .collect()
+    }
+}
+
+/// One node of an evaluated tree.
+#[derive(Debug, Clone)]
+pub struct TreeNode {
+    pub position: Chess,
+    /// Index of the parent node, `None` for roots.
+    pub parent: Option<usize>,
+    /// Move leading here from the parent.
+    pub mv: Option<Move>,
+    /// Distance from the root.
+    pub depth: usize,
+    /// The position was already reached elsewhere in the job, so it was
+    /// not expanded again.
+    pub transposition: bool,
+    pub result: EvaluationResult,
+}
+
+/// Node counts of a [`TreeEvaluator`] over its lifetime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TreeStats {
+    /// Nodes visited, including transpositions.
+    pub total: usize,
+    /// Positions actually sent to the evaluator.
+    pub unique: usize,
+}
+
+/// Zobrist hash and the bit patterns of `(elo_self, elo_oppo)`.
+type Key = (Zobrist64, u32, u32);
+
+/// Tree expansion on top of an [`Evaluator`] with a per-job
+/// transposition table.
+pub struct TreeEvaluator<E> {
+    evaluator: E,
+    table: HashMap<Key, EvaluationResult>,
+    stats: TreeStats,
+}
+
+impl<E: Evaluator> TreeEvaluator<E> {
+    /// An empty transposition table in front of `evaluator`.
+    pub fn new(evaluator: E) -> Self {
+        Self {
+            evaluator,
+            table: HashMap::new(),
+            stats: TreeStats::default(),
+        }
+    }
+
+    /// Deduplication counts so far.
+    pub fn stats(&self) -> TreeStats {
+        self.stats
+    }
+
+    /// Unwrap, dropping the transposition table.
+    pub fn into_inner(self) -> E {
+        self.evaluator
+    }
+
+    /// Expand trees from `roots`, returning every node in breadth-first
+    /// order. Each position is evaluated with the rating of the side to
+    /// move as `elo_self`.
+    ///
+    /// # Errors
+    /// Returns [`Error::IllegalMove`] if the policy picks an illegal move
+    /// and propagates evaluation errors.
+    pub fn evaluate_tree(
+        &mut self,
+        roots: &[Chess],
+        white_elo: f32,
+        black_elo: f32,
+        policy: &mut impl ExpansionPolicy,
+    ) -> Result<Vec<TreeNode>, Error> {
+        let elos = |pos: &Chess| match pos.turn() {
+            Color::White => (white_elo, black_elo),
+            Color::Black => (black_elo, white_elo),
+        };
+        let mut nodes = Vec::new();
+        let mut expanded = HashSet::new();
+        let mut wave: Vec<(Chess, Option<usize>, Option<Move>)> =
+            roots.iter().map(|p| (p.clone(), None, None)).collect();
+        let mut depth = 0;
+
+        while !wave.is_empty() {
+            let keys: Vec<Key> = wave
+                .iter()
+                .map(|(pos, ..)| {
+                    let (elo_self, elo_oppo) = elos(pos);
+                    let hash = pos.zobrist_hash(EnPassantMode::Legal);
+                    (hash, elo_self.to_bits(), elo_oppo.to_bits())
+                })
+                .collect();
+            self.evaluate_unseen(&wave, &keys, elos)?;
+            self.stats.total += wave.len();
+
+            let mut next = Vec::new();
+            for ((position, parent, mv), key) in wave.into_iter().zip(keys) {
+                let result = self.table[&key].clone();
+                let transposition = !expanded.insert(key);
+                let index = nodes.len();
+                if !transposition {
+                    for m in policy.expand(&position, &result, depth) {
+                        if !position.is_legal(m) {
+                            let uci = m.to_uci(CastlingMode::Standard);
+                            return Err(Error::IllegalMove(uci.to_string()));
+                        }
+                        let mut child = position.clone();
+                        child.play_unchecked(m);
+                        next.push((child, Some(index), Some(m)));
+                    }
+                }
+                nodes.push(TreeNode {
+                    position,
+                    parent,
+                    mv,
+                    depth,
+                    transposition,
+                    result,
+                });
+            }
+            wave = next;
+            depth += 1;
+        }
+        Ok(nodes)
+    }
+
+    /// Evaluate the positions of a wave missing from the table, each
+    /// distinct key once.
+    fn evaluate_unseen(
+        &mut self,
+        wave: &[(Chess, Option<usize>, Option<Move>)],
+        keys: &[Key],
+        elos: impl Fn(&Chess) -> (f32, f32),
+    ) -> Result<(), Error> {
+        let mut queued = HashSet::new();
+        let unseen: Vec<(&Chess, Key)> = wave
+            .iter()
+            .zip(keys)
+            .filter(|&(_, key)| !self.table.contains_key(key) && queued.insert(*key))
+            .map(|((pos, ..), key)| (pos, *key))
+            .collect();
+        if unseen.is_empty() {
+            return Ok(());
+        }
+
+        let setups = unseen
+            .iter()
+            .map(|(pos, _)| pos.to_setup(EnPassantMode::Legal))
+            .collect();
+        let (elo_selfs, elo_oppos): (Vec<f32>, Vec<f32>) =
+            unseen.iter().map(|(pos, _)| elos(pos)).unzip();
+        let results = self
+            .evaluator
+            .batch_evaluate(setups, &elo_selfs, &elo_oppos)?;
+        self.stats.unique += unseen.len();
+        for ((_, key), result) in unseen.into_iter().zip(results) {
+            self.table.insert(key, result);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::uci::UciMove;
+
+    use super::*;
+    use crate::testing::UniformEvaluator;
+
+    /// Knight development only, which transposes after three plies.
+    fn knights(pos: &Chess, _: &EvaluationResult, depth: usize) -> Vec<Move> {
+        if depth >= 3 {
+            return Vec::new();
+        }
+        ["g1f3", "b1c3", "g8f6"]
+            .iter()
+            .filter_map(|uci| uci.parse::<UciMove>().unwrap().to_move(pos).ok())
+            .collect()
+    }
+
+    #[test]
+    fn transpositions_are_evaluated_once() {
+        let mut tree = TreeEvaluator::new(UniformEvaluator);
+        let roots = [Chess::default()];
+        let nodes = tree
+            .evaluate_tree(&roots, 1500.0, 1500.0, &mut knights)
+            .unwrap();
+
+        // Root, two white moves, two replies, and two move orders that
+        // reach the same position.
+        assert_eq!(nodes.len(), 7);
+        assert_eq!(
+            tree.stats(),
+            TreeStats {
+                total: 7,
+                unique: 6
+            }
+        );
+        assert_eq!(nodes.iter().filter(|n| n.transposition).count(), 1);
+        assert_eq!(nodes[6].depth, 3);
+        assert_eq!(nodes[nodes[6].parent.unwrap()].depth, 2);
+
+        // A second job on the same table costs no evaluations.
+        tree.evaluate_tree(&roots, 1500.0, 1500.0, &mut knights)
+            .unwrap();
+        assert_eq!(tree.stats().unique, 6);
+
+        // Other ratings are different nodes.
+        tree.evaluate_tree(&roots, 1500.0, 1900.0, &mut knights)
+            .unwrap();
+        assert_eq!(tree.stats().unique, 12);
+    }
+
+    #[test]
+    fn top_moves_expands_to_depth() {
+        let mut tree = TreeEvaluator::new(UniformEvaluator);
+        let mut policy = TopMoves {
+            count: 2,
+            max_depth: 2,
+        };
+        let nodes = tree
+            .evaluate_tree(&[Chess::default()], 1500.0, 1500.0, &mut policy)
+            .unwrap();
+        assert_eq!(nodes.len(), 1 + 2 + 4);
+        assert!(nodes.iter().all(|n| n.depth <= 2));
+    }
+}
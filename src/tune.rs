@@ -0,0 +1,151 @@
+//! Measuring the throughput of batch sizes on the current hardware.
+
+use std::time::Instant;
+
+use rand::{RngExt, SeedableRng, rngs::StdRng};
+use shakmaty::{Chess, EnPassantMode, Position, Setup};
+
+use crate::{Maia, backend::InferenceBackend, error::Error};
+
+/// Timed runs per candidate; the median is reported.
+const TRIALS: usize = 3;
+
+/// Throughput of one candidate batch size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchTiming {
+    pub batch_size: usize,
+    /// Median over the trials.
+    pub positions_per_second: f64,
+}
+
+/// Results of [`Maia::tune_batch_size`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TuneReport {
+    /// One entry per candidate, in the order given.
+    pub timings: Vec<BatchTiming>,
+    /// The candidate with the highest throughput, the smallest one on
+    /// ties. Suitable as
+    /// [`ServiceConfig::max_batch_size`](crate::ServiceConfig::max_batch_size).
+    pub recommended: usize,
+}
+
+/// `count` positions reached by short random playouts from the start
+/// position, so batches mix openings and middlegames.
+fn synthetic_positions(count: usize) -> Vec<Setup> {
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..count)
+        .map(|_| {
+            let mut pos = Chess::default();
+            for _ in 0..rng.random_range(0..40) {
+                let legal = pos.legal_moves();
+                if legal.is_empty() {
+                    break;
+                }
+                pos.play_unchecked(legal[rng.random_range(0..legal.len())]);
+            }
+            pos.to_setup(EnPassantMode::Legal)
+        })
+        .collect()
+}
+
+impl<B: InferenceBackend> Maia<B> {
+    /// Time every batch size in `candidates` on synthetic positions and
+    /// recommend the fastest.
+    ///
+    /// Each candidate first runs one warm-up batch, so allocations and
+    /// runtime optimizations specific to its shape happen outside the
+    /// measurement. Then it evaluates `positions_per_trial` positions in
+    /// batches of that size, a few times, and reports the median
+    /// throughput.
+    ///
+    /// # Panics
+    /// Panics if `candidates` is empty or holds a zero.
+    ///
+    /// # Errors
+    /// Propagates evaluation errors.
+    pub fn tune_batch_size(
+        &mut self,
+        candidates: &[usize],
+        positions_per_trial: usize,
+    ) -> Result<TuneReport, Error> {
+        assert!(!candidates.is_empty(), "no batch sizes to tune");
+        assert!(
+            candidates.iter().all(|&b| b > 0),
+            "batch sizes must be positive"
+        );
+        let largest = candidates.iter().copied().max().unwrap_or(1);
+        let pool = synthetic_positions(largest);
+        let elos = vec![1500.0; largest];
+
+        let mut timings = Vec::with_capacity(candidates.len());
+        for &batch_size in candidates {
+            let batch = &pool[..batch_size];
+            let elos = &elos[..batch_size];
+            self.batch_evaluate(batch.to_vec(), elos, elos)?;
+
+            let mut trials = Vec::with_capacity(TRIALS);
+            for _ in 0..TRIALS {
+                let mut evaluated = 0;
+                let start = Instant::now();
+                while evaluated < positions_per_trial.max(1) {
+                    self.batch_evaluate(batch.to_vec(), elos, elos)?;
+                    evaluated += batch_size;
+                }
+                let seconds = start.elapsed().as_secs_f64().max(f64::EPSILON);
+                trials.push(evaluated as f64 / seconds);
+            }
+            trials.sort_by(f64::total_cmp);
+            timings.push(BatchTiming {
+                batch_size,
+                positions_per_second: trials[TRIALS / 2],
+            });
+        }
+
+        let recommended = timings
+            .iter()
+            .max_by(|a, b| {
+                a.positions_per_second
+                    .total_cmp(&b.positions_per_second)
+                    .then(b.batch_size.cmp(&a.batch_size))
+            })
+            .map_or(candidates[0], |t| t.batch_size);
+        Ok(TuneReport {
+            timings,
+            recommended,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ZeroBackend;
+
+    #[test]
+    fn every_candidate_is_warmed_up_and_timed() {
+        let mut maia = Maia::from_backend(ZeroBackend::default());
+        let report = maia.tune_batch_size(&[4, 1, 8], 16).unwrap();
+
+        let sizes: Vec<usize> = report.timings.iter().map(|t| t.batch_size).collect();
+        assert_eq!(sizes, vec![4, 1, 8]);
+        assert!(sizes.contains(&report.recommended));
+        assert!(report.timings.iter().all(|t| t.positions_per_second > 0.0));
+
+        // One warm-up batch plus three trials of 16 positions each.
+        let batches = maia.backend().batch_sizes();
+        assert_eq!(batches.iter().filter(|&&b| b == 4).count(), 1 + 3 * 4);
+        assert_eq!(batches.iter().filter(|&&b| b == 1).count(), 1 + 3 * 16);
+        assert_eq!(batches.iter().filter(|&&b| b == 8).count(), 1 + 3 * 2);
+    }
+
+    #[test]
+    fn synthetic_positions_are_legal() {
+        for setup in synthetic_positions(32) {
+            assert!(
+                setup
+                    .position::<Chess>(shakmaty::CastlingMode::Standard)
+                    .is_ok()
+            );
+        }
+    }
+}
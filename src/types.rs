@@ -1,28 +1,681 @@
-use shakmaty::uci::UciMove;
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+};
+
+use rand::{Rng, RngExt};
+use shakmaty::{CastlingMode, Chess, Color, Move, Position, Role, uci::UciMove};
+
+use crate::{error::Error, tensor::ValidationWarning};
 
 /// A move paired with the model's estimated probability of being the
 /// best choice.
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MoveProbability {
     /// Move in UCI notation.
     pub uci: UciMove,
     /// Probability (0.0–1.0) assigned by the policy head after
     /// softmax normalization.
     pub probability: f32,
+    /// Index of the policy logit the probability was computed from.
+    ///
+    /// This is the index of the move as the network saw it, so when
+    /// Black is to move it belongs to `uci.to_mirrored()`, not to `uci`;
+    /// [`mirrored_index`](crate::mirrored_index) converts between the
+    /// two and [`vocabulary_index`](crate::vocabulary_index) computes it
+    /// from a move.
+    pub index: u16,
 }
 
 /// Output returned by the Maia evaluator.
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct EvaluationResult {
     /// Policy head results: legal moves sorted by descending
-    /// probability.
+    /// probability. Moves with exactly equal probabilities follow in
+    /// ascending order of their UCI strings, so the order is the same
+    /// on every run and platform.
+    ///
+    /// The order is only guaranteed with
+    /// [`EvalOptions::sorted`](crate::EvalOptions::sorted) enabled (the
+    /// default); otherwise moves come in legal-move-generation order.
     pub policy: Vec<MoveProbability>,
-    /// White win rate, normalized to [0, 1].
+    /// White win rate, normalized to [0, 1], whichever side is to
+    /// move.
     pub white_wr: f32,
     /// Draw probability, normalized to [0, 1].
     pub draw: f32,
-    /// Black win rate, normalized to [0, 1].
+    /// Black win rate, normalized to [0, 1], whichever side is to
+    /// move.
     pub black_wr: f32,
+    /// Whether the position is over, `None` while it has legal moves.
+    /// An empty `policy` alone does not tell, since moves missing from
+    /// the model's vocabulary are left out of it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub outcome: Option<Terminal>,
+}
+
+/// Why a position has no legal moves, as seen by the side to move.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminal {
+    /// The side to move is checkmated.
+    Checkmate,
+    /// The side to move has no legal move but is not in check.
+    Stalemate,
+}
+
+impl Terminal {
+    /// The terminal state of `pos`, `None` if it has a legal move.
+    ///
+    /// Every part of the crate that treats finished positions specially
+    /// decides through this function.
+    pub fn of(pos: &Chess) -> Option<Self> {
+        if pos.is_checkmate() {
+            Some(Self::Checkmate)
+        } else if pos.is_stalemate() {
+            Some(Self::Stalemate)
+        } else {
+            None
+        }
+    }
+
+    /// Exact score of the side to move: 0 when checkmated, half a point
+    /// when stalemated.
+    pub fn score(self) -> f32 {
+        match self {
+            Self::Checkmate => 0.0,
+            Self::Stalemate => 0.5,
+        }
+    }
+}
+
+/// Where an [`EvaluationResult`] came from, returned alongside the
+/// results by [`Maia::batch_evaluate_with_meta`](crate::Maia::batch_evaluate_with_meta).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvaluationMeta {
+    /// Polyglot hash of the position as given, before any mirroring, see
+    /// [`polyglot_key`](crate::export::polyglot_key). Pawns reported by
+    /// [`ValidationWarning::BackrankPawns`] are not part of it.
+    pub key: u64,
+    /// The network saw the position mirrored, because Black was to move.
+    pub mirrored: bool,
+    /// Rating fed to the network for the side to move.
+    pub elo_self: f32,
+    /// Rating fed to the network for the opponent.
+    pub elo_oppo: f32,
+    /// Rules waived to evaluate the position, empty unless
+    /// [`EvalOptions::validation`](crate::EvalOptions::validation) is
+    /// relaxed.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub warnings: Vec<ValidationWarning>,
+}
+
+/// Probability mass of a policy by kind of move, see
+/// [`EvaluationResult::breakdown`].
+///
+/// The role and region buckets each split the classified mass, so they
+/// add up to [`total`](Self::total). The move types overlap: a capturing
+/// check counts as a capture and as a check, a promotion with capture as
+/// both. Only [`quiet`](Self::quiet) excludes the others.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PolicyBreakdown {
+    /// Mass of every moving piece, indexed pawn to king; castling is a
+    /// king move. See [`role`](Self::role).
+    pub by_role: [f32; 6],
+    pub captures: f32,
+    /// Moves giving check, including mates.
+    pub checks: f32,
+    pub castling: f32,
+    pub promotions: f32,
+    /// Moves that are none of the above.
+    pub quiet: f32,
+    /// Moves to the mover's own half of the board, ranks 1 to 4 for
+    /// White.
+    pub own_half: f32,
+    /// Moves into the opponent's half.
+    pub opponent_half: f32,
+    /// Mass of the moves classified, about 1 unless policy moves were
+    /// not legal in the position.
+    pub total: f32,
+}
+
+impl PolicyBreakdown {
+    /// Mass on moves of `role`.
+    pub fn role(&self, role: Role) -> f32 {
+        self.by_role[role as usize - 1]
+    }
+}
+
+/// How far [`Maia::move_qualities`](crate::Maia::move_qualities) backs
+/// values up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backup {
+    /// The value of each child.
+    OnePly,
+    /// The value after the opponent's `replies` most likely answers,
+    /// weighted by their probabilities renormalized over those replies.
+    TwoPly { replies: usize },
+}
+
+/// A move's policy probability next to its backed-up value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveQuality {
+    pub mv: Move,
+    /// Probability of the move in the policy of the position.
+    pub policy_prob: f32,
+    /// Expected score of the side to move after playing the move, exact
+    /// for a move that ends the game.
+    pub q: f32,
+    /// Positions whose values were averaged into `q`: 1 for a one-ply
+    /// backup or a finished game, the number of replies followed
+    /// otherwise.
+    pub visits_equivalent: u32,
+}
+
+impl EvaluationResult {
+    /// Expected score for `color`, counting a draw as half a point.
+    pub fn expected_score(&self, color: Color) -> f32 {
+        let win = match color {
+            Color::White => self.white_wr,
+            Color::Black => self.black_wr,
+        };
+        win + 0.5 * self.draw
+    }
+
+    /// White's expected score, counting a draw as half a point.
+    ///
+    /// The network scores positions for the side to move, and sees
+    /// Black-to-move positions mirrored so that Black plays as White.
+    /// Postprocessing undoes both, so the win rates and this value refer
+    /// to the real colors and need no correction for the turn:
+    /// `value_white()` equals `1.0 - expected_score(Color::Black)`, and
+    /// the network's side-to-move value is
+    /// [`expected_score(turn)`](Self::expected_score).
+    pub fn value_white(&self) -> f32 {
+        self.expected_score(Color::White)
+    }
+
+    /// Expected score for `color` expressed in centipawns, see
+    /// [`score_to_centipawns`].
+    pub fn centipawns(&self, color: Color) -> i32 {
+        score_to_centipawns(self.expected_score(color))
+    }
+
+    /// The most likely move, `None` without legal moves. On ties the
+    /// move listed first wins, so with a sorted policy this is
+    /// `policy.first()`.
+    pub fn best_move(&self) -> Option<&MoveProbability> {
+        self.policy.iter().reduce(|best, m| {
+            if m.probability > best.probability {
+                m
+            } else {
+                best
+            }
+        })
+    }
+
+    /// The policy as a map from move to probability.
+    pub fn to_map(&self) -> HashMap<UciMove, f32> {
+        self.policy.iter().map(|m| (m.uci, m.probability)).collect()
+    }
+
+    /// Probability of `m` played in `pos`, the position this result
+    /// evaluates. Castling is looked up in the standard king-to-g1/c1
+    /// notation the policy uses whatever the move's internal form.
+    /// `None` if `m` is illegal in `pos` or not in the policy.
+    pub fn probability_of_move(&self, m: &Move, pos: &Chess) -> Option<f32> {
+        if !pos.is_legal(*m) {
+            return None;
+        }
+        let uci = m.to_uci(CastlingMode::Standard);
+        self.policy
+            .iter()
+            .find(|p| p.uci == uci)
+            .map(|p| p.probability)
+    }
+
+    /// 1-based rank of `uci` by probability, counting the moves more
+    /// likely than it, so tied moves share a rank. `None` if the move
+    /// is not in the policy. Castling must be given king-to-g1/c1, e.g.
+    /// `e1g1`.
+    pub fn rank_of(&self, uci: &UciMove) -> Option<usize> {
+        let probability = self.policy.iter().find(|m| m.uci == *uci)?.probability;
+        let better = self
+            .policy
+            .iter()
+            .filter(|m| m.probability > probability)
+            .count();
+        Some(better + 1)
+    }
+
+    /// Shannon entropy of the policy in bits. Moves of zero probability
+    /// contribute nothing and an empty policy has entropy 0.
+    pub fn entropy(&self) -> f32 {
+        -self
+            .policy
+            .iter()
+            .filter(|m| m.probability > 0.0)
+            .map(|m| m.probability * m.probability.log2())
+            .sum::<f32>()
+    }
+
+    /// Effective number of moves, `2^entropy`: `n` for a policy spread
+    /// evenly over `n` moves, 1 for a single forced-looking move, and 0
+    /// for an empty policy.
+    pub fn perplexity(&self) -> f32 {
+        if self.policy.is_empty() {
+            return 0.0;
+        }
+        self.entropy().exp2()
+    }
+
+    /// Probability of the most likely move minus that of the second,
+    /// `None` with fewer than two moves. Does not rely on the policy
+    /// being sorted.
+    pub fn top_gap(&self) -> Option<f32> {
+        let mut top = [f32::NEG_INFINITY; 2];
+        for m in &self.policy {
+            if m.probability > top[0] {
+                top = [m.probability, top[0]];
+            } else if m.probability > top[1] {
+                top[1] = m.probability;
+            }
+        }
+        (self.policy.len() >= 2).then(|| top[0] - top[1])
+    }
+
+    /// Smallest number of most likely moves whose probabilities add up
+    /// to at least `p`, 0 if `p` is not positive. Sums within `1e-6` of
+    /// `p` count, so `p = 1.0` is reached despite rounding. Returns
+    /// `None` if the whole policy falls short, which includes any
+    /// positive `p` for an empty policy.
+    pub fn moves_to_cover(&self, p: f32) -> Option<usize> {
+        if p <= 0.0 {
+            return Some(0);
+        }
+        let mut probabilities: Vec<f32> = self.policy.iter().map(|m| m.probability).collect();
+        probabilities.sort_by(|a, b| b.total_cmp(a));
+        let mut covered = 0.0;
+        for (k, probability) in probabilities.into_iter().enumerate() {
+            covered += probability;
+            if covered >= p - 1e-6 {
+                return Some(k + 1);
+            }
+        }
+        None
+    }
+
+    /// Probability mass by moving piece, move type and target half of
+    /// the board, classifying the moves against `pos`, the position this
+    /// result evaluates. Moves not legal in `pos` are left out.
+    pub fn breakdown(&self, pos: &Chess) -> PolicyBreakdown {
+        let mover = pos.turn();
+        let mut breakdown = PolicyBreakdown::default();
+        for p in &self.policy {
+            let Ok(m) = p.uci.to_move(pos) else {
+                continue;
+            };
+            let mass = p.probability;
+            breakdown.total += mass;
+            breakdown.by_role[m.role() as usize - 1] += mass;
+
+            let mut after = pos.clone();
+            after.play_unchecked(m);
+            let check = after.is_check();
+            if m.is_capture() {
+                breakdown.captures += mass;
+            }
+            if check {
+                breakdown.checks += mass;
+            }
+            if m.is_castle() {
+                breakdown.castling += mass;
+            }
+            if m.is_promotion() {
+                breakdown.promotions += mass;
+            }
+            if !(m.is_capture() || check || m.is_castle() || m.is_promotion()) {
+                breakdown.quiet += mass;
+            }
+
+            let rank = u32::from(m.to().rank());
+            if mover.fold_wb(rank < 4, rank >= 4) {
+                breakdown.own_half += mass;
+            } else {
+                breakdown.opponent_half += mass;
+            }
+        }
+        breakdown
+    }
+
+    /// Pick the top move, or sample with probabilities sharpened
+    /// (`temperature < 1`) or flattened (`temperature > 1`) when
+    /// `temperature` is positive. Returns `None` without legal moves.
+    pub fn sample_move(&self, temperature: f32, rng: &mut impl Rng) -> Option<&MoveProbability> {
+        if temperature <= 0.0 {
+            return self.best_move();
+        }
+
+        let weights: Vec<f32> = self
+            .policy
+            .iter()
+            .map(|m| m.probability.powf(1.0 / temperature))
+            .collect();
+        let mut target = rng.random::<f32>() * weights.iter().sum::<f32>();
+        for (m, w) in self.policy.iter().zip(&weights) {
+            target -= w;
+            if target <= 0.0 {
+                return Some(m);
+            }
+        }
+        self.policy.last()
+    }
+}
+
+impl EvaluationResult {
+    /// Play the most likely move in `pos`, the position this result
+    /// evaluates, returning the move and the position after it.
+    ///
+    /// # Errors
+    /// Returns [`Error::Terminal`] if the policy is empty and
+    /// [`Error::IllegalMove`] if the move is illegal in `pos`, which
+    /// means `pos` is not the evaluated position.
+    pub fn apply_top_move(&self, pos: &Chess) -> Result<(Move, Chess), Error> {
+        play(self.best_move().map(|m| m.uci), pos)
+    }
+
+    /// Play a move chosen with [`sample_move`](Self::sample_move) in
+    /// `pos`, returning the move and the position after it.
+    ///
+    /// # Errors
+    /// See [`apply_top_move`](Self::apply_top_move).
+    pub fn apply_sampled_move(
+        &self,
+        pos: &Chess,
+        temperature: f32,
+        rng: &mut impl Rng,
+    ) -> Result<(Move, Chess), Error> {
+        play(self.sample_move(temperature, rng).map(|m| m.uci), pos)
+    }
+}
+
+/// Play `chosen` in `pos`, checking that it is legal there.
+pub(crate) fn play(chosen: Option<UciMove>, pos: &Chess) -> Result<(Move, Chess), Error> {
+    let uci = chosen.ok_or(Error::Terminal)?;
+    let m = uci
+        .to_move(pos)
+        .map_err(|_| Error::IllegalMove(uci.to_string()))?;
+    let mut next = pos.clone();
+    next.play_unchecked(m);
+    Ok((m, next))
+}
+
+/// Moves shown by the `Display` implementation of [`EvaluationResult`].
+const DISPLAY_MOVES: usize = 5;
+
+impl EvaluationResult {
+    /// One line with White's expected score and the first `n` moves of
+    /// the policy, e.g. `value=0.62 | e2e4 41.2% d2d4 22.5% ...`.
+    pub fn summary(&self, n: usize) -> String {
+        let mut line = format!("value={:.2} |", self.value_white());
+        match self.outcome {
+            Some(Terminal::Checkmate) => line.push_str(" checkmate"),
+            Some(Terminal::Stalemate) => line.push_str(" stalemate"),
+            None => {
+                for m in self.policy.iter().take(n) {
+                    let _ = write!(line, " {m}");
+                }
+                if self.policy.len() > n {
+                    line.push_str(" ...");
+                }
+            }
+        }
+        line
+    }
+}
+
+/// The [`summary`](EvaluationResult::summary) of the top five moves.
+/// The alternate form `{:#}` lists the win rates and then the whole
+/// policy, one ranked move per line.
+impl fmt::Display for EvaluationResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !f.alternate() {
+            return f.write_str(&self.summary(DISPLAY_MOVES));
+        }
+        write!(
+            f,
+            "value={:.2} (white {:.3}, draw {:.3}, black {:.3})",
+            self.value_white(),
+            self.white_wr,
+            self.draw,
+            self.black_wr
+        )?;
+        match self.outcome {
+            Some(Terminal::Checkmate) => write!(f, "\ncheckmate")?,
+            Some(Terminal::Stalemate) => write!(f, "\nstalemate")?,
+            None => {}
+        }
+        for (rank, m) in self.policy.iter().enumerate() {
+            write!(f, "\n{:>3}. {m}", rank + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// The move and its probability as a percentage, e.g. `e2e4 41.2%`.
+impl fmt::Display for MoveProbability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:.1}%", self.uci, 100.0 * self.probability)
+    }
+}
+
+/// Centipawn value of a forced mate, beyond the range of
+/// [`score_to_centipawns`].
+pub const MATE_CENTIPAWNS: i32 = 10_000;
+
+/// Convert an expected score in [0, 1] into a centipawn-style value
+/// using the logistic model `score = 1 / (1 + 10^(-cp / 400))`.
+///
+/// Scores are clamped away from 0 and 1 so the result stays finite
+/// (roughly ±1600 cp).
+pub fn score_to_centipawns(score: f32) -> i32 {
+    let score = score.clamp(1e-4, 1.0 - 1e-4);
+    (400.0 * (score / (1.0 - score)).log10()).round() as i32
+}
+
+/// Convert a centipawn-style value back into an expected score, the
+/// inverse of [`score_to_centipawns`].
+pub fn centipawns_to_score(cp: i32) -> f32 {
+    1.0 / (1.0 + 10f32.powf(-cp as f32 / 400.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::testing::white_move;
+
+    fn result(moves: &[(&str, f32)]) -> EvaluationResult {
+        EvaluationResult {
+            policy: moves
+                .iter()
+                .map(|&(uci, probability)| white_move(uci, probability))
+                .collect(),
+            white_wr: 0.5,
+            draw: 0.24,
+            black_wr: 0.26,
+            outcome: None,
+        }
+    }
+
+    #[test]
+    fn breakdown_classifies_moves_against_the_position() {
+        let pos: Chess = "4k3/P7/8/3q4/8/8/3R4/4K2R w K - 0 1"
+            .parse::<shakmaty::fen::Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let result = result(&[
+            // Promotes with check along the eighth rank.
+            ("a7a8q", 0.4),
+            ("d2d5", 0.2),
+            ("d2e2", 0.1),
+            ("e1g1", 0.1),
+            ("e1d1", 0.1),
+            // Not legal here.
+            ("e1e3", 0.1),
+        ]);
+        let b = result.breakdown(&pos);
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-6;
+
+        assert!(close(b.total, 0.9));
+        assert!(close(b.role(Role::Pawn), 0.4));
+        assert!(close(b.role(Role::Rook), 0.3));
+        assert!(close(b.role(Role::King), 0.2));
+        assert_eq!(b.role(Role::Knight), 0.0);
+
+        assert!(close(b.captures, 0.2));
+        assert!(close(b.checks, 0.5));
+        assert!(close(b.castling, 0.1));
+        assert!(close(b.promotions, 0.4));
+        assert!(close(b.quiet, 0.1));
+
+        assert!(close(b.own_half, 0.3));
+        assert!(close(b.opponent_half, 0.6));
+    }
+
+    #[test]
+    fn display_shows_value_and_top_moves() {
+        let moves = [
+            ("e2e4", 0.412),
+            ("d2d4", 0.225),
+            ("g1f3", 0.141),
+            ("c2c4", 0.1),
+            ("e2e3", 0.07),
+            ("b1c3", 0.052),
+        ];
+        let result = result(&moves);
+        assert_eq!(
+            result.to_string(),
+            "value=0.62 | e2e4 41.2% d2d4 22.5% g1f3 14.1% c2c4 10.0% e2e3 7.0% ..."
+        );
+        assert_eq!(result.summary(2), "value=0.62 | e2e4 41.2% d2d4 22.5% ...");
+        assert_eq!(
+            result.summary(6),
+            format!("{}", result).replace(" ...", " b1c3 5.2%")
+        );
+
+        let full = format!("{result:#}");
+        let lines: Vec<&str> = full.lines().collect();
+        assert_eq!(lines.len(), 1 + moves.len());
+        assert_eq!(
+            lines[0],
+            "value=0.62 (white 0.500, draw 0.240, black 0.260)"
+        );
+        assert_eq!(lines[1], "  1. e2e4 41.2%");
+        assert_eq!(lines[6], "  6. b1c3 5.2%");
+    }
+
+    #[test]
+    fn policy_statistics() {
+        let uniform = result(&[
+            ("e2e4", 0.25),
+            ("d2d4", 0.25),
+            ("g1f3", 0.25),
+            ("c2c4", 0.25),
+        ]);
+        assert!((uniform.entropy() - 2.0).abs() < 1e-6);
+        assert!((uniform.perplexity() - 4.0).abs() < 1e-5);
+        assert_eq!(uniform.top_gap(), Some(0.0));
+        assert_eq!(uniform.moves_to_cover(0.5), Some(2));
+        assert_eq!(uniform.moves_to_cover(1.0), Some(4));
+
+        // Unsorted, with a zero entry and a sum just short of 1.
+        let skewed = result(&[
+            ("d2d4", 0.2),
+            ("e2e4", 0.7),
+            ("a2a3", 0.0),
+            ("g1f3", 0.0999999),
+        ]);
+        assert!((skewed.entropy() - 1.1568).abs() < 1e-3);
+        assert!((skewed.top_gap().unwrap() - 0.5).abs() < 1e-6);
+        assert_eq!(skewed.moves_to_cover(0.7), Some(1));
+        assert_eq!(skewed.moves_to_cover(0.75), Some(2));
+        assert_eq!(skewed.moves_to_cover(1.0), Some(3));
+        assert_eq!(skewed.moves_to_cover(0.0), Some(0));
+
+        let single = result(&[("e2e4", 1.0)]);
+        assert_eq!(single.entropy(), 0.0);
+        assert_eq!(single.perplexity(), 1.0);
+        assert_eq!(single.top_gap(), None);
+
+        let empty = result(&[]);
+        assert_eq!(empty.entropy(), 0.0);
+        assert_eq!(empty.perplexity(), 0.0);
+        assert_eq!(empty.top_gap(), None);
+        assert_eq!(empty.moves_to_cover(0.5), None);
+        assert_eq!(empty.moves_to_cover(0.0), Some(0));
+    }
+
+    fn position(fen: &str) -> Chess {
+        fen.parse::<shakmaty::fen::Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap()
+    }
+
+    #[test]
+    fn top_move_castles_and_promotes() {
+        let pos = position("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let (m, next) = result(&[("e1c1", 0.4), ("e1g1", 0.6)])
+            .apply_top_move(&pos)
+            .unwrap();
+        assert!(m.is_castle());
+        assert_eq!(
+            next.board().king_of(Color::White),
+            Some(shakmaty::Square::G1)
+        );
+        assert_eq!(next.turn(), Color::Black);
+
+        let pos = position("8/P6k/8/8/8/8/2p5/K7 w - - 0 1");
+        let (m, next) = result(&[("a7a8n", 0.7), ("a7a8q", 0.3)])
+            .apply_top_move(&pos)
+            .unwrap();
+        assert_eq!(m.promotion(), Some(shakmaty::Role::Knight));
+        assert_eq!(
+            next.board().piece_at(shakmaty::Square::A8),
+            Some(shakmaty::Piece::from_char('N').unwrap())
+        );
+    }
+
+    #[test]
+    fn sampled_move_is_checked_against_the_position() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let castles = result(&[("e1g1", 0.5), ("e1c1", 0.5)]);
+        let pos = position("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let (m, _) = castles.apply_sampled_move(&pos, 1.0, &mut rng).unwrap();
+        assert!(m.is_castle());
+
+        // The result belongs to another position.
+        assert!(matches!(
+            castles.apply_sampled_move(&Chess::default(), 1.0, &mut rng),
+            Err(Error::IllegalMove(uci)) if uci.starts_with("e1")
+        ));
+        assert!(matches!(
+            result(&[]).apply_top_move(&Chess::default()),
+            Err(Error::Terminal)
+        ));
+    }
+
+    #[test]
+    fn display_names_terminal_positions() {
+        let mut mated = result(&[]);
+        mated.outcome = Some(Terminal::Checkmate);
+        assert_eq!(mated.to_string(), "value=0.62 | checkmate");
+        assert!(format!("{mated:#}").ends_with("\ncheckmate"));
+    }
 }